@@ -0,0 +1,208 @@
+use crate::kmeans::Calculate;
+
+use rand::Rng;
+
+/// Points as arrays of `N` components, such as embeddings or feature vectors.
+///
+/// Components are expected to span about `-1.0..1.0`, as in normalized
+/// embeddings, which sets the range of random centroids and `CONVERGE`.
+impl<const N: usize> Calculate for [f32; N] {
+    const CONVERGE: f32 = 0.0025;
+    const DIMENSIONS: usize = N;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Self], centroids: &[Self], indices: &mut Vec<u32>) {
+        for point in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(point, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Self],
+        centroids: &mut [Self],
+        indices: &[u32],
+    ) {
+        // Accumulate the sum and count of each cluster in a single pass, in
+        // `f64` so large clusters don't lose precision
+        let mut sums: Vec<([f64; N], u64)> = centroids.iter().map(|_| ([0.0; N], 0)).collect();
+        for (&idx, point) in indices.iter().zip(buf) {
+            if let Some((sum, count)) = sums.get_mut(idx as usize) {
+                if point.iter().all(|x| x.is_finite()) {
+                    for (s, &x) in sum.iter_mut().zip(point) {
+                        *s += f64::from(x);
+                    }
+                    *count += 1;
+                }
+            }
+        }
+
+        for (cent, (sum, count)) in centroids.iter_mut().zip(sums) {
+            if count != 0 {
+                let count = count as f64;
+                *cent = sum.map(|x| (x / count) as f32);
+            } else {
+                *cent = Self::create_random(&mut rng);
+            }
+        }
+    }
+
+    fn check_loop(centroids: &[Self], old_centroids: &[Self]) -> f32 {
+        let mut temp = [0.0f32; N];
+        for (c0, c1) in centroids.iter().zip(old_centroids) {
+            for ((t, a), b) in temp.iter_mut().zip(c0).zip(c1) {
+                *t += a - b;
+            }
+        }
+
+        temp.iter().map(|x| x * x).sum()
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Self {
+        std::array::from_fn(|_| rng.gen_range(-1.0..=1.0))
+    }
+
+    #[inline]
+    fn difference(c1: &Self, c2: &Self) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
+        c1.iter().zip(c2).map(|(a, b)| (a - b) * (a - b)).sum()
+    }
+}
+
+/// Maximum number of random directions points are projected onto.
+const MAX_PROJECTIONS: usize = 16;
+
+/// Random projections of a buffer of arrays onto a few directions, for
+/// finding candidates for the nearest centroid without comparing every
+/// component.
+pub(crate) struct Projection<const N: usize> {
+    /// Random directions with components in `-1.0..1.0`.
+    directions: Vec<[f32; N]>,
+    /// Projection of each point, `directions.len()` values per point.
+    points: Vec<f32>,
+}
+
+impl<const N: usize> Projection<N> {
+    /// Project the points of `buf` onto `min(N, 16)` random directions.
+    pub(crate) fn new(rng: &mut impl Rng, buf: &[[f32; N]]) -> Self {
+        let directions: Vec<[f32; N]> = (0..N.min(MAX_PROJECTIONS))
+            .map(|_| <[f32; N]>::create_random(rng))
+            .collect();
+        let mut points = Vec::with_capacity(buf.len() * directions.len());
+        for point in buf {
+            points.extend(directions.iter().map(|d| dot(point, d)));
+        }
+
+        Projection { directions, points }
+    }
+
+    /// Index each point with its nearest centroid among the `candidates`
+    /// centroids nearest to it in the projection, comparing the candidates
+    /// with every component. The lowest index is used for equally near
+    /// candidates, and non-finite points are indexed to the first centroid.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn get_closest_centroid(
+        &self,
+        buf: &[[f32; N]],
+        centroids: &[[f32; N]],
+        candidates: usize,
+        indices: &mut Vec<u32>,
+    ) {
+        let dims = self.directions.len();
+        let projected: Vec<f32> = centroids
+            .iter()
+            .flat_map(|c| self.directions.iter().map(move |d| dot(c, d)))
+            .collect();
+        let candidates = candidates.clamp(1, centroids.len().max(1));
+        let mut near: Vec<(f32, usize)> = Vec::with_capacity(centroids.len());
+
+        for (point, proj) in buf.iter().zip(self.points.chunks_exact(dims)) {
+            if !proj.iter().all(|x| x.is_finite()) {
+                indices.push(0);
+                continue;
+            }
+            near.clear();
+            near.extend(
+                projected
+                    .chunks_exact(dims)
+                    .map(|c| c.iter().zip(proj).map(|(a, b)| (a - b) * (a - b)).sum())
+                    .zip(0..),
+            );
+            if candidates < near.len() {
+                let _ = near.select_nth_unstable_by(candidates - 1, |a, b| a.0.total_cmp(&b.0));
+                near.truncate(candidates);
+            }
+
+            let mut index = 0;
+            let mut min = f32::MAX;
+            for &(_, idx) in &near {
+                let diff = <[f32; N]>::difference(point, &centroids[idx]);
+                if diff < min || (diff == min && idx < index) {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+}
+
+/// Dot product of two arrays.
+fn dot<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{get_kmeans, get_kmeans_approx};
+
+    /// Points around `k` well separated centers in 32 dimensions.
+    #[allow(clippy::cast_precision_loss)]
+    fn clusters(k: usize) -> Vec<[f32; 32]> {
+        (0..k * 20)
+            .map(|i| {
+                let center = i % k;
+                std::array::from_fn(|j| {
+                    let offset = ((i * 31 + j * 17) % 13) as f32 / 200.0;
+                    if j % k == center {
+                        0.8 + offset
+                    } else {
+                        offset
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn approx_with_every_candidate_is_exact() {
+        let buf = clusters(6);
+        let exact = get_kmeans(6, 20, 0.0, false, &buf, 0);
+        let approx = get_kmeans_approx(6, 20, 0.0, false, &buf, 0, 6);
+        assert_eq!(approx.centroids, exact.centroids);
+        assert_eq!(approx.indices, exact.indices);
+    }
+
+    #[test]
+    fn approx_finds_separated_clusters() {
+        let buf = clusters(8);
+        let exact = get_kmeans(8, 20, 0.0, false, &buf, 1);
+        let approx = get_kmeans_approx(8, 20, 0.0, false, &buf, 1, 2);
+        assert_eq!(approx.effective_k(), exact.effective_k());
+        assert!((approx.score - exact.score).abs() < 1e-3);
+        assert!(approx.indices.iter().all(|&i| i < 8));
+    }
+}
@@ -1,154 +1,742 @@
-use crate::args::Opt;
-use crate::filename::{create_filename, create_filename_palette};
-use crate::utils::{cached_srgba_to_lab, print_colors, save_image, save_image_alpha, save_palette};
+use crate::args::{CodeFormat, Opt, Space, WhitePoint};
+use crate::cache::{cache_path, load_cached};
+use crate::err::CliError;
+use crate::filename::{create_filename, create_filename_palette, create_filename_report};
+use crate::model::Model;
+use crate::palette_file::SwatchOptions;
+use crate::report::{save_report, ReportEntry};
+use crate::svg::save_svg;
+use crate::utils::{
+    adjust_tone, barcode_stripe, composite_background, content_seed, expand_palette, hue_excluded,
+    image_edge_weights, lab_data_to_srgb, lab_to_linear, lightness_excluded, order_colors,
+    parse_color, print_accent, print_background_split, print_bootstrap, print_channel_stats,
+    print_code, print_colors, print_delta_e_histogram, print_error_summary, print_hue_histogram,
+    print_iteration_json, print_regions, print_runs, print_stability, print_template,
+    read_file_list, saturation_excluded, save_barcode, save_gradient, save_image, save_image_alpha,
+    PrintFormat,
+};
 
-use fxhash::FxHashMap;
-use kmeans_colors::{get_kmeans, get_kmeans_hamerly, Calculate, Kmeans, MapColor, Sort};
-use palette::cast::{AsComponents, ComponentsAs};
-use palette::{white_point::D65, FromColor, IntoColor, Lab, LinSrgba, Srgb, Srgba};
+use kmeans_colors::{
+    average_color, average_color_weighted, default_converge, despeckle, get_kmeans,
+    get_kmeans_best, get_kmeans_best_from, get_kmeans_shrink, get_kmeans_tiled, Calculate,
+    ChromaOnly, ConversionCache, ConvertSrgb, Hamerly, HueOnly, Kmeans, KmeansConfig, MapColor,
+    Sort, Weighted,
+};
+use palette::cast::{ArrayCast, AsComponents, ComponentsAs, ComponentsAsMut};
+use palette::white_point::{A, D50, D55, D65, D75, E};
+use palette::{FromColor, Lab, Oklch, Srgb, Srgba};
+use rand::SeedableRng;
+use std::path::PathBuf;
 
 pub fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
+    run_cached(opt, &mut Caches::new())
+}
+
+/// Cached results of Srgb<u8> -> Lab conversions for each white point, shared
+/// between runs.
+pub struct Caches {
+    a: ConversionCache<Lab<A, f32>>,
+    d50: ConversionCache<Lab<D50, f32>>,
+    d55: ConversionCache<Lab<D55, f32>>,
+    d65: ConversionCache<Lab<D65, f32>>,
+    d75: ConversionCache<Lab<D75, f32>>,
+    e: ConversionCache<Lab<E, f32>>,
+}
+
+impl Caches {
+    pub fn new() -> Self {
+        Caches {
+            a: ConversionCache::new(),
+            d50: ConversionCache::new(),
+            d55: ConversionCache::new(),
+            d65: ConversionCache::new(),
+            d75: ConversionCache::new(),
+            e: ConversionCache::new(),
+        }
+    }
+}
+
+/// Runs the main command, reusing conversions cached by previous runs.
+pub fn run_cached(mut opt: Opt, caches: &mut Caches) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(list) = &opt.files_from {
+        opt.input.extend(read_file_list(list)?);
+    }
+
+    match opt.white_point {
+        WhitePoint::A => run_with(opt, &mut caches.a),
+        WhitePoint::D50 => run_with(opt, &mut caches.d50),
+        WhitePoint::D55 => run_with(opt, &mut caches.d55),
+        WhitePoint::D65 => run_with(opt, &mut caches.d65),
+        WhitePoint::D75 => run_with(opt, &mut caches.d75),
+        WhitePoint::E => run_with(opt, &mut caches.e),
+    }
+}
+
+/// Result of mapping the pixels to the centroids of a loaded model instead of
+/// clustering.
+fn model_kmeans<C: Calculate>(centroids: Vec<C>, pixels: &[C]) -> Kmeans<C> {
+    let mut indices = Vec::with_capacity(pixels.len());
+    C::get_closest_centroid(pixels, &centroids, &mut indices);
+    let score = pixels
+        .iter()
+        .zip(&indices)
+        .map(|(x, &i)| C::difference(x, &centroids[i as usize]))
+        .sum();
+
+    Kmeans {
+        score,
+        centroids,
+        indices,
+        runs: Vec::new(),
+    }
+}
+
+/// Returns `true` if every iteration is printed as a json line with `-vv
+/// --format json`.
+fn trace_iterations(opt: &Opt) -> bool {
+    opt.verbose > 1 && opt.format == Some(CodeFormat::Json)
+}
+
+/// Returns `true` if the k-means functions print their scores to standard
+/// output, which is left out while tracing so the json output stays valid.
+fn verbose_kmeans(opt: &Opt) -> bool {
+    opt.verbose > 0 && !trace_iterations(opt)
+}
+
+/// Settings of the k-means calculation.
+fn kmeans_config(opt: &Opt, converge: f32, seed: u64) -> KmeansConfig {
+    KmeansConfig {
+        k: opt.k as usize,
+        max_iter: opt.max_iter,
+        converge,
+        runs: opt.runs,
+        seed,
+        verbose: verbose_kmeans(opt),
+        prune: opt.prune,
+        trace: trace_iterations(opt).then_some(print_iteration_json),
+    }
+}
+
+/// Returns `true` if a pixel with `alpha` is painted in the vectorized output,
+/// which is only fully opaque pixels unless `--keep-alpha` is used.
+fn visible(opt: &Opt, alpha: u8) -> bool {
+    alpha == 255 || (opt.keep_alpha && alpha > 0)
+}
+
+/// Returns `true` if the options exclude pixels from the k-means calculation
+/// by their color.
+fn excludes_pixels(opt: &Opt) -> bool {
+    !opt.exclude_hue.is_empty() || opt.min_saturation.is_some() || opt.lightness_range.is_some()
+}
+
+/// Returns `true` if the pixel is excluded from the k-means calculation by its
+/// hue, saturation, or lightness.
+fn pixel_excluded(opt: &Opt, color: &Srgba<u8>) -> bool {
+    hue_excluded(&opt.exclude_hue, color)
+        || saturation_excluded(opt.min_saturation, color)
+        || lightness_excluded(opt.lightness_range, color)
+}
+
+/// Colors of the pixels used in the k-means calculation, skipping transparent
+/// pixels with `--transparent` and excluded colors.
+fn kept_colors<'a>(opt: &'a Opt, pixels: &'a [Srgba<u8>]) -> impl Iterator<Item = Srgb<u8>> + 'a {
+    let filter = opt.transparent || excludes_pixels(opt);
+    pixels
+        .iter()
+        .filter(move |x| {
+            !filter || ((!opt.transparent || x.alpha == 255) && !pixel_excluded(opt, x))
+        })
+        .map(|x| x.color)
+}
+
+/// Weights of the pixels used in the k-means calculation for `--edge-weight`,
+/// in the order of [`kept_colors`].
+fn edge_weights(opt: &Opt, pixels: &[Srgba<u8>], width: u32, height: u32) -> Option<Vec<f32>> {
+    opt.edge_weight.map(|strength| {
+        image_edge_weights(pixels, width, height, strength, |x| {
+            (!opt.transparent || x.alpha == 255) && !pixel_excluded(opt, x)
+        })
+    })
+}
+
+/// Result of clustering the pixels with `k = 1`, their average color.
+fn average_kmeans<C>(pixels: &[C], weights: Option<&[f32]>) -> Kmeans<C>
+where
+    C: Calculate + ArrayCast<Array = [f32; 3]> + Copy,
+{
+    let average = match weights {
+        Some(weights) => average_color_weighted(pixels, weights),
+        None => average_color(pixels),
+    };
+
+    match average {
+        Some(average) => Kmeans {
+            score: 0.0,
+            centroids: vec![average],
+            indices: vec![0; pixels.len()],
+            runs: Vec::new(),
+        },
+        None => Kmeans::new(),
+    }
+}
+
+/// Runs the main command with `Lab` colors using the white point `Wp`.
+fn run_with<Wp: palette::white_point::WhitePoint<f32> + Send + Sync>(
+    opt: Opt,
+    lab_cache: &mut ConversionCache<Lab<Wp, f32>>,
+) -> Result<(), Box<dyn std::error::Error>> {
     if opt.input.is_empty() {
-        eprintln!("No input files specified.")
+        return Err(CliError::NoInput.into());
     }
 
     let seed = opt.seed.unwrap_or(0);
 
-    // Cached results of Srgb<u8> -> Lab conversions; not cleared between runs
-    let mut lab_cache = FxHashMap::default();
     // Vec of pixels converted to Lab; cleared and reused between runs
-    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+    let mut lab_pixels: Vec<Lab<Wp, f32>> = Vec::new();
     // Vec of pixels converted to Srgb<f32>; cleared and reused between runs
     let mut rgb_pixels: Vec<Srgb<f32>> = Vec::new();
+    // Centroids of the first file, the starting point of the next files with
+    // --warm-start
+    let mut warm_start: Option<Vec<[f32; 3]>> = None;
+    // Colors of each input file for barcode output
+    let mut barcode = Vec::with_capacity(opt.input.len());
+    // Summaries of each input file for the report
+    let mut report = Vec::with_capacity(opt.input.len());
 
-    for file in &opt.input {
-        if opt.verbose {
+    if opt.gamma.is_some_and(|x| x.is_nan() || x <= 0.0) {
+        return Err(CliError::Usage("Gamma must be greater than 0".into()).into());
+    }
+    if opt.blur.is_some_and(|x| x.is_nan() || x <= 0.0) {
+        return Err(CliError::Usage("Blur sigma must be greater than 0".into()).into());
+    }
+
+    let k_range = opt.k_min.zip(opt.k_max);
+    if let Some((k_min, k_max)) = k_range {
+        if k_min == 0 || k_min > k_max {
+            return Err(
+                CliError::Usage("--k-min must be at least 1 and at most --k-max".into()).into(),
+            );
+        }
+    }
+    if opt.k_threshold.is_nan() || opt.k_threshold < 0.0 {
+        return Err(CliError::Usage("--k-threshold must be at least 0".into()).into());
+    }
+
+    // Modes which don't start from centroids, where --warm-start would do nothing
+    if opt.warm_start {
+        let mode = if opt.load_model.is_some() {
+            Some("--load-model")
+        } else if k_range.is_some() {
+            Some("--k-min")
+        } else if opt.k == 1 {
+            Some("-k 1")
+        } else if opt.shrink {
+            Some("--shrink")
+        } else if opt.tiles.is_some() {
+            Some("--tiles")
+        } else if !opt.rgb && opt.space != Space::Lab {
+            Some("--space hue|ab")
+        } else {
+            None
+        };
+        if let Some(mode) = mode {
+            return Err(CliError::Usage(format!("--warm-start can't be used with {mode}")).into());
+        }
+    }
+
+    if let Some(dir) = &opt.cache_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    // Centroids to map the images to instead of clustering
+    let model = opt.load_model.as_deref().map(Model::load).transpose()?;
+    if let Some(model) = &model {
+        if model.rgb != opt.rgb {
+            return Err(CliError::Usage(
+                "Model color space doesn't match, check the --rgb flag".into(),
+            )
+            .into());
+        }
+        if !model.rgb && model.white_point != opt.white_point {
+            return Err(
+                CliError::Usage("Model white point doesn't match --white-point".into()).into(),
+            );
+        }
+    }
+
+    let background = opt
+        .background
+        .as_deref()
+        .map(|c| parse_color(c.trim_start_matches('#')))
+        .transpose()?;
+
+    // Runs the command on one file, collecting errors to continue with the
+    // remaining files
+    let mut run_file = |file: &PathBuf| -> Result<(), Box<dyn std::error::Error>> {
+        if opt.verbose > 0 {
             println!("{}", &file.to_string_lossy());
         }
-        let img = image::open(file)?.into_rgba8();
+        let mut img = image::open(file)?.into_rgba8();
         let (imgx, imgy) = img.dimensions();
-        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
-        let converge = opt.factor.unwrap_or(if !opt.rgb { 5.0 } else { 0.0025 });
+        let seed = if opt.seed_from_content {
+            let seed = content_seed(imgx, imgy, img.as_raw());
+            if opt.verbose > 0 {
+                println!("Seed: {seed}");
+            }
+            seed
+        } else {
+            seed
+        };
+        if opt.exposure.is_some() || opt.gamma.is_some() {
+            adjust_tone(
+                img.as_mut().components_as_mut(),
+                opt.exposure.unwrap_or(0.0),
+                opt.gamma.unwrap_or(1.0),
+            );
+        }
+        if let Some(background) = background {
+            composite_background(img.as_mut().components_as_mut(), background);
+        }
+        // Pixels of the output image, which aren't blurred
+        let out_vec: &[Srgba<u8>] = img.as_raw().components_as();
+        let blurred = opt.blur.map(|sigma| image::imageops::blur(&img, sigma));
+        let img_vec: &[Srgba<u8>] = match &blurred {
+            Some(blurred) => blurred.as_raw().components_as(),
+            None => out_vec,
+        };
+        if let Some(format) = opt.hue_histogram {
+            return print_hue_histogram(
+                format,
+                img_vec
+                    .iter()
+                    .filter(|x| !opt.transparent || x.alpha == 255)
+                    .map(|x| x.color),
+            );
+        }
+        // Cluster the pixels of a thumbnail instead of the whole image
+        let thumbnail = opt
+            .cluster_on_thumbnail
+            .filter(|&size| imgx.max(imgy) > size)
+            .map(|size| {
+                let scale = size as f32 / imgx.max(imgy) as f32;
+                let w = ((imgx as f32 * scale).round() as u32).max(1);
+                let h = ((imgy as f32 * scale).round() as u32).max(1);
+                image::imageops::thumbnail(blurred.as_ref().unwrap_or(&img), w, h)
+            });
+        let (cluster_vec, clusterx, clustery): (&[Srgba<u8>], u32, u32) = match &thumbnail {
+            Some(thumbnail) => (
+                thumbnail.as_raw().components_as(),
+                thumbnail.width(),
+                thumbnail.height(),
+            ),
+            None => (img_vec, imgx, imgy),
+        };
+        let converge = opt.factor.unwrap_or_else(|| {
+            if !opt.rgb {
+                default_converge::<Lab<Wp, f32>>(opt.k as usize, cluster_vec.len())
+            } else {
+                default_converge::<Srgb>(opt.k as usize, cluster_vec.len())
+            }
+        });
+
+        // Reuse the centroids of a previous run on the same pixels and settings
+        let cache = opt
+            .cache_dir
+            .as_deref()
+            .filter(|_| model.is_none())
+            .map(|dir| {
+                cache_path(
+                    dir,
+                    &opt,
+                    converge,
+                    seed,
+                    warm_start.as_deref(),
+                    imgx,
+                    imgy,
+                    img_vec.as_components(),
+                )
+            });
+        let cached = cache.as_deref().and_then(|path| load_cached(path, opt.rgb));
+        if opt.verbose > 0 && cached.is_some() {
+            println!(
+                "Cached colors: {}",
+                cache.as_deref().unwrap().to_string_lossy()
+            );
+        }
 
         // Defaults to Lab, first case.
         if !opt.rgb {
             lab_pixels.clear();
 
             // Convert Srgb image buffer to Lab for kmeans
-            if !opt.transparent {
-                cached_srgba_to_lab(img_vec.iter(), &mut lab_cache, &mut lab_pixels);
+            lab_cache.extend(kept_colors(&opt, cluster_vec), &mut lab_pixels);
+
+            // Weight pixels near edges, skipping the same pixels as above
+            let mut weights = edge_weights(&opt, cluster_vec, clusterx, clustery);
+
+            // Number of colors chosen with --k-min
+            let mut chosen_k = None;
+            // Iterate over amount of runs keeping best results
+            let mut result = if let Some(model) = model.as_ref().or(cached.as_ref()) {
+                let centroids = model
+                    .centroids
+                    .iter()
+                    .map(|&[l, a, b]| Lab::new(l, a, b))
+                    .collect();
+                model_kmeans(centroids, &lab_pixels)
+            } else if opt.space == Space::Hue {
+                reduced_kmeans::<Wp, HueOnly>(&opt, converge, seed, &lab_pixels)
+            } else if opt.space == Space::Ab {
+                reduced_kmeans::<Wp, ChromaOnly>(&opt, converge, seed, &lab_pixels)
+            } else if let Some((k_min, k_max)) = k_range {
+                let (result, k) =
+                    range_kmeans(&opt, k_min, k_max, seed, &lab_pixels, weights.as_deref());
+                chosen_k = Some(k);
+                result
+            } else if opt.k == 1 {
+                average_kmeans(&lab_pixels, weights.as_deref())
+            } else if opt.shrink && weights.is_none() {
+                shrink_kmeans(&opt, converge, seed, &lab_pixels)
+            } else if let Some(tiles) = opt.tiles.filter(|_| weights.is_none()) {
+                get_kmeans_tiled(
+                    &kmeans_config(&opt, converge, seed),
+                    &lab_pixels,
+                    lab_pixels.len().div_ceil(tiles.max(1)),
+                    std::thread::available_parallelism().map_or(1, |x| x.get()),
+                )
+            } else if let Some(centroids) = warm_start.as_ref() {
+                let centroids: Vec<_> = centroids
+                    .iter()
+                    .map(|&[l, a, b]| Lab::new(l, a, b))
+                    .collect();
+                get_kmeans_best_from(
+                    &kmeans_config(&opt, converge, seed),
+                    &centroids,
+                    &lab_pixels,
+                    weights.as_deref(),
+                )
             } else {
-                cached_srgba_to_lab(
-                    img_vec.iter().filter(|x: &&Srgba<u8>| x.alpha == 255),
-                    &mut lab_cache,
-                    &mut lab_pixels,
+                get_kmeans_best(
+                    &kmeans_config(&opt, converge, seed),
+                    &lab_pixels,
+                    weights.as_deref(),
+                )
+            };
+
+            if thumbnail.is_some() {
+                // Index the pixels of the whole image with the colors found
+                lab_pixels.clear();
+                lab_cache.extend(kept_colors(&opt, img_vec), &mut lab_pixels);
+                weights = edge_weights(&opt, img_vec, imgx, imgy);
+                result.indices.clear();
+                Lab::<Wp, f32>::get_closest_centroid(
+                    &lab_pixels,
+                    &result.centroids,
+                    &mut result.indices,
                 );
+            }
+
+            if let (Some(path), None) = (&cache, &cached) {
+                Model {
+                    rgb: false,
+                    white_point: opt.white_point,
+                    centroids: result.centroids.iter().map(|x| [x.l, x.a, x.b]).collect(),
+                }
+                .save(path)?;
+            }
+            if opt.warm_start && warm_start.is_none() {
+                warm_start = Some(result.centroids.iter().map(|x| [x.l, x.a, x.b]).collect());
+            }
+
+            // Number of colors chosen with --k-min, used in file names
+            let k = match chosen_k {
+                Some(k) => {
+                    eprintln!("{}: k: {k}", file.to_string_lossy());
+                    k
+                }
+                None => opt.k,
             };
 
-            // Iterate over amount of runs keeping best results
-            let mut result = Kmeans::new();
-            if opt.k > 1 {
-                for i in 0..opt.runs {
-                    let run_result = get_kmeans_hamerly(
-                        opt.k as usize,
-                        opt.max_iter,
-                        converge,
-                        opt.verbose,
+            if opt.verbose > 0 && result.runs.len() > 1 {
+                print_runs(&result.runs);
+            }
+
+            if let Some(seeds) = opt.stability.filter(|_| model.is_none()) {
+                if opt.space == Space::Lab {
+                    print_stability(
+                        &kmeans_config(&opt, converge, seed),
+                        seeds,
                         &lab_pixels,
-                        seed + i as u64,
+                        weights.as_deref(),
+                        &result.centroids,
+                        |&x| Srgb::from_linear(lab_to_linear(x)),
                     );
-                    if run_result.score < result.score {
-                        result = run_result;
-                    }
                 }
-            } else {
-                for i in 0..opt.runs {
-                    let run_result = get_kmeans(
-                        opt.k as usize,
-                        opt.max_iter,
-                        converge,
-                        opt.verbose,
-                        &lab_pixels,
-                        seed + i as u64,
+            }
+
+            if let Some(grid) = opt.snap {
+                result.snap_centroids(&lab_pixels, |&x| {
+                    Lab::from_srgb(grid.snap(Srgb::from_linear(lab_to_linear(x))))
+                });
+            }
+
+            if opt.save_model.is_some() {
+                Model {
+                    rgb: false,
+                    white_point: opt.white_point,
+                    centroids: result.centroids.iter().map(|x| [x.l, x.a, x.b]).collect(),
+                }
+                .save(&create_filename(
+                    &opt.input,
+                    &opt.save_model,
+                    "json",
+                    Some(k),
+                    file,
+                )?)?;
+            }
+
+            if let Some(format) = opt.histogram {
+                print_delta_e_histogram(
+                    format,
+                    lab_pixels
+                        .iter()
+                        .zip(&result.indices)
+                        .map(|(x, &i)| Lab::difference(x, &result.centroids[i as usize]).sqrt()),
+                )?;
+            }
+
+            if let Some(format) = opt.stats {
+                let colors: Vec<Srgb<u8>> = result
+                    .centroids
+                    .iter()
+                    .map(|&x| Srgb::from_linear(lab_to_linear(x)))
+                    .collect();
+                print_channel_stats(
+                    format,
+                    ["l", "a", "b"],
+                    lab_pixels
+                        .iter()
+                        .zip(&result.indices)
+                        .map(|(x, &i)| ([x.l, x.a, x.b], i)),
+                    &colors,
+                )?;
+            }
+
+            if opt.split_bg || opt.regions.is_some() {
+                // Filtered pixels weren't indexed, find the closest centroids
+                // of the whole image
+                let full = (lab_pixels.len() != img_vec.len()).then(|| {
+                    let mut all = Vec::with_capacity(img_vec.len());
+                    lab_cache.extend(img_vec.iter().map(|x| x.color), &mut all);
+                    let mut full = Kmeans {
+                        score: result.score,
+                        centroids: result.centroids.clone(),
+                        indices: Vec::with_capacity(img_vec.len()),
+                        runs: Vec::new(),
+                    };
+                    Lab::<Wp, f32>::get_closest_centroid(&all, &full.centroids, &mut full.indices);
+                    full
+                });
+                let full = full.as_ref().unwrap_or(&result);
+
+                if opt.split_bg {
+                    let split = full.split_background(imgx as usize, imgy as usize);
+                    print_background_split(
+                        split
+                            .background
+                            .map(|x| Srgb::from_linear(lab_to_linear(x))),
+                        split
+                            .foreground
+                            .map(|x| Srgb::from_linear(lab_to_linear(x))),
                     );
-                    if run_result.score < result.score {
-                        result = run_result;
-                    }
+                }
+
+                if let Some(largest) = opt.regions {
+                    let colors: Vec<Srgb<u8>> = full
+                        .centroids
+                        .iter()
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
+                        .collect();
+                    print_regions(
+                        &full.regions(imgx as usize, imgy as usize),
+                        &colors,
+                        largest,
+                    )?;
                 }
             }
 
             // Print and/or sort results, output to palette
-            if opt.print || opt.percentage || opt.palette {
-                let mut res =
-                    Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
-                if opt.sort {
-                    res.sort_unstable_by(|a, b| (b.percentage).total_cmp(&a.percentage));
+            if opt.print
+                || opt.percentage
+                || opt.cmyk
+                || opt.accent
+                || opt.palette
+                || opt.barcode.is_some()
+                || opt.gradient.is_some()
+                || opt.template.is_some()
+                || opt.format.is_some()
+                || opt.report.is_some()
+            {
+                let mut res = lab_data_to_srgb(&Lab::<Wp, f32>::sort_indexed_colors(
+                    &result.centroids,
+                    &result.indices,
+                ));
+                order_colors(&mut res, opt.order, opt.sort_lightness, Oklch::from_color);
+                if let Some(n) = opt.expand {
+                    res = expand_palette(&res, n, opt.interpolation);
                 }
 
-                if opt.print || opt.percentage {
-                    print_colors(opt.percentage, &res)?;
+                let format = PrintFormat {
+                    pct_format: opt.pct_format,
+                    delimiter: &opt.delimiter,
+                    pixels: result.indices.len(),
+                };
+                if let Some(template) = &opt.template {
+                    print_template(template, format, &res)?;
+                } else if opt.print || opt.percentage || opt.cmyk {
+                    print_colors(opt.percentage, opt.cmyk, format, &res)?;
                 }
 
-                if opt.palette {
-                    save_palette(
+                if let Some(format) = opt.format {
+                    print_code(format, &res)?;
+                }
+
+                if let Some(resamples) = opt.bootstrap {
+                    print_bootstrap(&res, &result.indices, resamples, seed)?;
+                }
+
+                if opt.accent {
+                    print_accent(&res, opt.accent_min)?;
+                }
+
+                if opt.barcode.is_some() {
+                    barcode.push(barcode_stripe(&res));
+                }
+
+                if opt.report.is_some() {
+                    report.push(ReportEntry::new(file, &img, barcode_stripe(&res))?);
+                }
+
+                if opt.gradient.is_some() {
+                    save_gradient(
                         &res,
-                        opt.proportional,
+                        opt.interpolation,
                         opt.height,
                         opt.width,
-                        &create_filename_palette(
-                            &opt.input,
-                            &opt.palette_output,
-                            opt.rgb,
-                            Some(opt.k),
-                            file,
-                        )?,
+                        &create_filename(&opt.input, &opt.gradient, "png", Some(k), file)?,
                     )?;
                 }
+
+                if opt.palette {
+                    let title = create_filename_palette(
+                        &opt.input,
+                        &opt.palette_output,
+                        opt.rgb,
+                        Some(k),
+                        file,
+                        opt.palette_format.extension(),
+                    )?;
+                    let options = SwatchOptions {
+                        proportional: opt.proportional,
+                        height: opt.height,
+                        width: opt.width,
+                        normalized: opt.normalized,
+                    };
+                    opt.palette_format.0.save(&res, &options, &title)?;
+                }
             }
 
             // Don't allocate image buffer if no-file
             if opt.no_file {
-                continue;
+                return Ok(());
             }
 
             // Convert indexed colors to Srgb colors to output as final result
-            if !opt.transparent {
+            if !opt.transparent && !opt.keep_alpha {
                 // Convert centroids to Srgb<u8> before mapping to buffer
                 let centroids = &result
                     .centroids
                     .iter()
-                    .map(|&x| Srgb::from_linear(x.into_color()))
+                    .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                     .collect::<Vec<Srgb<u8>>>();
-                let rgb: Vec<Srgb<u8>> = Srgb::map_indices_to_centroids(centroids, &result.indices);
+
+                // Excluded pixels weren't indexed and blurred pixels aren't
+                // output, find the closest centroids of the output pixels
+                let mut indices = Vec::new();
+                if excludes_pixels(&opt) || blurred.is_some() {
+                    lab_pixels.clear();
+                    lab_cache.extend(out_vec.iter().map(|x| x.color), &mut lab_pixels);
+                    Lab::<Wp, f32>::get_closest_centroid(
+                        &lab_pixels,
+                        &result.centroids,
+                        &mut indices,
+                    );
+                } else {
+                    indices = result.indices;
+                }
+                if let Some(min_size) = opt.despeckle {
+                    despeckle(&mut indices, imgx as usize, imgy as usize, min_size);
+                }
+                if opt.vectorize {
+                    let indices: Vec<Option<u32>> = indices.iter().map(|&x| Some(x)).collect();
+                    return save_svg(
+                        &indices,
+                        imgx,
+                        imgy,
+                        centroids,
+                        &create_filename(&opt.input, &opt.output, "svg", Some(k), file)?,
+                    );
+                }
+                let rgb: Vec<Srgb<u8>> = Srgb::map_indices_to_centroids(centroids, &indices);
 
                 save_image(
                     rgb.as_components(),
                     imgx,
                     imgy,
-                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(opt.k), file)?,
+                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(k), file)?,
                     false,
                 )?;
             } else {
                 // For transparent images, we get_closest_centroid based
                 // on the centroids we calculated and only paint in the pixels
-                // that have a full alpha
-                let mut indices = Vec::with_capacity(img_vec.len());
+                // that have a full alpha, or keep the alpha of every pixel
+                let mut indices = Vec::with_capacity(out_vec.len());
 
                 lab_pixels.clear();
-                cached_srgba_to_lab(img_vec.iter(), &mut lab_cache, &mut lab_pixels);
-                Lab::<D65, f32>::get_closest_centroid(&lab_pixels, &result.centroids, &mut indices);
+                lab_cache.extend(out_vec.iter().map(|x| x.color), &mut lab_pixels);
+                Lab::<Wp, f32>::get_closest_centroid(&lab_pixels, &result.centroids, &mut indices);
 
                 let centroids = &result
                     .centroids
                     .iter()
-                    .map(|&x| Srgba::<f32>::from_linear(LinSrgba::from_color(x)).into_format())
+                    .map(|&x| Srgba::from(Srgb::<f32>::from_linear(lab_to_linear(x))).into_format())
                     .collect::<Vec<Srgba<u8>>>();
 
+                if let Some(min_size) = opt.despeckle {
+                    despeckle(&mut indices, imgx as usize, imgy as usize, min_size);
+                }
+                if opt.vectorize {
+                    let indices: Vec<Option<u32>> = indices
+                        .iter()
+                        .zip(out_vec)
+                        .map(|(&x, orig)| visible(&opt, orig.alpha).then_some(x))
+                        .collect();
+                    let colors: Vec<Srgb<u8>> = centroids.iter().map(|x| x.color).collect();
+                    return save_svg(
+                        &indices,
+                        imgx,
+                        imgy,
+                        &colors,
+                        &create_filename(&opt.input, &opt.output, "svg", Some(k), file)?,
+                    );
+                }
                 let rgba: Vec<Srgba<u8>> = Srgba::map_indices_to_centroids(centroids, &indices)
                     .iter()
-                    .zip(img_vec)
+                    .zip(out_vec)
                     .map(|(x, orig)| {
-                        if orig.alpha == 255 {
+                        if opt.keep_alpha {
+                            Srgba::new(x.red, x.green, x.blue, orig.alpha)
+                        } else if orig.alpha == 255 {
                             *x
                         } else {
                             Srgba::new(0u8, 0, 0, 0)
@@ -159,119 +747,345 @@ pub fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
                     rgba.as_components(),
                     imgx,
                     imgy,
-                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(opt.k), file)?,
+                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(k), file)?,
                 )?;
             }
         } else {
             rgb_pixels.clear();
 
             // Read image buffer into Srgb format
-            if !opt.transparent {
-                rgb_pixels.extend(
-                    img_vec
-                        .iter()
-                        .map(|x| Srgb::<f32>::from_color(x.into_format::<_, f32>())),
-                );
+            rgb_pixels.extend(kept_colors(&opt, cluster_vec).map(|x| x.into_format()));
+
+            // Weight pixels near edges, skipping the same pixels as above
+            let mut weights = edge_weights(&opt, cluster_vec, clusterx, clustery);
+
+            // Number of colors chosen with --k-min
+            let mut chosen_k = None;
+            // Iterate over amount of runs keeping best results
+            let mut result = if let Some(model) = model.as_ref().or(cached.as_ref()) {
+                let centroids = model
+                    .centroids
+                    .iter()
+                    .map(|&[r, g, b]| Srgb::new(r, g, b))
+                    .collect();
+                model_kmeans(centroids, &rgb_pixels)
+            } else if let Some((k_min, k_max)) = k_range {
+                let (result, k) =
+                    range_kmeans(&opt, k_min, k_max, seed, &rgb_pixels, weights.as_deref());
+                chosen_k = Some(k);
+                result
+            } else if opt.k == 1 {
+                average_kmeans(&rgb_pixels, weights.as_deref())
+            } else if opt.shrink && weights.is_none() {
+                shrink_kmeans(&opt, converge, seed, &rgb_pixels)
+            } else if let Some(tiles) = opt.tiles.filter(|_| weights.is_none()) {
+                get_kmeans_tiled(
+                    &kmeans_config(&opt, converge, seed),
+                    &rgb_pixels,
+                    rgb_pixels.len().div_ceil(tiles.max(1)),
+                    std::thread::available_parallelism().map_or(1, |x| x.get()),
+                )
+            } else if let Some(centroids) = warm_start.as_ref() {
+                let centroids: Vec<_> = centroids
+                    .iter()
+                    .map(|&[r, g, b]| Srgb::new(r, g, b))
+                    .collect();
+                get_kmeans_best_from(
+                    &kmeans_config(&opt, converge, seed),
+                    &centroids,
+                    &rgb_pixels,
+                    weights.as_deref(),
+                )
             } else {
-                rgb_pixels.extend(
-                    img_vec
+                get_kmeans_best(
+                    &kmeans_config(&opt, converge, seed),
+                    &rgb_pixels,
+                    weights.as_deref(),
+                )
+            };
+
+            if thumbnail.is_some() {
+                // Index the pixels of the whole image with the colors found
+                rgb_pixels.clear();
+                rgb_pixels.extend(kept_colors(&opt, img_vec).map(|x| x.into_format()));
+                weights = edge_weights(&opt, img_vec, imgx, imgy);
+                result.indices.clear();
+                Srgb::<f32>::get_closest_centroid(
+                    &rgb_pixels,
+                    &result.centroids,
+                    &mut result.indices,
+                );
+            }
+
+            if let (Some(path), None) = (&cache, &cached) {
+                Model {
+                    rgb: true,
+                    white_point: opt.white_point,
+                    centroids: result
+                        .centroids
                         .iter()
-                        .filter(|x| x.alpha == 255)
-                        .map(|x| Srgb::<f32>::from_color(x.into_format::<_, f32>())),
+                        .map(|x| [x.red, x.green, x.blue])
+                        .collect(),
+                }
+                .save(path)?;
+            }
+            if opt.warm_start && warm_start.is_none() {
+                warm_start = Some(
+                    result
+                        .centroids
+                        .iter()
+                        .map(|x| [x.red, x.green, x.blue])
+                        .collect(),
                 );
             }
 
-            // Iterate over amount of runs keeping best results
-            let mut result = Kmeans::new();
-            if opt.k > 1 {
-                for i in 0..opt.runs {
-                    let run_result = get_kmeans_hamerly(
-                        opt.k as usize,
-                        opt.max_iter,
-                        converge,
-                        opt.verbose,
-                        &rgb_pixels,
-                        seed + i as u64,
-                    );
-                    if run_result.score < result.score {
-                        result = run_result;
-                    }
+            // Number of colors chosen with --k-min, used in file names
+            let k = match chosen_k {
+                Some(k) => {
+                    eprintln!("{}: k: {k}", file.to_string_lossy());
+                    k
                 }
-            } else {
-                for i in 0..opt.runs {
-                    let run_result = get_kmeans(
-                        opt.k as usize,
-                        opt.max_iter,
-                        converge,
-                        opt.verbose,
-                        &rgb_pixels,
-                        seed + i as u64,
-                    );
-                    if run_result.score < result.score {
-                        result = run_result;
-                    }
+                None => opt.k,
+            };
+
+            if opt.verbose > 0 && result.runs.len() > 1 {
+                print_runs(&result.runs);
+            }
+
+            if let Some(seeds) = opt.stability.filter(|_| model.is_none()) {
+                print_stability(
+                    &kmeans_config(&opt, converge, seed),
+                    seeds,
+                    &rgb_pixels,
+                    weights.as_deref(),
+                    &result.centroids,
+                    |&x| x,
+                );
+            }
+
+            if let Some(grid) = opt.snap {
+                result.snap_centroids(&rgb_pixels, |x| grid.snap(x.into_format()).into_format());
+            }
+
+            if opt.save_model.is_some() {
+                Model {
+                    rgb: true,
+                    white_point: opt.white_point,
+                    centroids: result
+                        .centroids
+                        .iter()
+                        .map(|x| [x.red, x.green, x.blue])
+                        .collect(),
+                }
+                .save(&create_filename(
+                    &opt.input,
+                    &opt.save_model,
+                    "json",
+                    Some(k),
+                    file,
+                )?)?;
+            }
+
+            if let Some(format) = opt.histogram {
+                let centroids: Vec<Lab<D65, f32>> = result
+                    .centroids
+                    .iter()
+                    .map(|&x| Lab::from_color(x))
+                    .collect();
+                print_delta_e_histogram(
+                    format,
+                    rgb_pixels.iter().zip(&result.indices).map(|(&x, &i)| {
+                        Lab::difference(&Lab::from_color(x), &centroids[i as usize]).sqrt()
+                    }),
+                )?;
+            }
+
+            if let Some(format) = opt.stats {
+                let colors: Vec<Srgb<u8>> =
+                    result.centroids.iter().map(|x| x.into_format()).collect();
+                print_channel_stats(
+                    format,
+                    ["r", "g", "b"],
+                    rgb_pixels
+                        .iter()
+                        .zip(&result.indices)
+                        .map(|(x, &i)| ([x.red, x.green, x.blue], i)),
+                    &colors,
+                )?;
+            }
+
+            if opt.split_bg || opt.regions.is_some() {
+                // Filtered pixels weren't indexed, find the closest centroids
+                // of the whole image
+                let full = (rgb_pixels.len() != img_vec.len()).then(|| {
+                    let all: Vec<Srgb<f32>> = img_vec
+                        .iter()
+                        .map(|x| Srgb::<f32>::from_color(x.into_format::<_, f32>()))
+                        .collect();
+                    let mut full = Kmeans {
+                        score: result.score,
+                        centroids: result.centroids.clone(),
+                        indices: Vec::with_capacity(img_vec.len()),
+                        runs: Vec::new(),
+                    };
+                    Srgb::get_closest_centroid(&all, &full.centroids, &mut full.indices);
+                    full
+                });
+                let full = full.as_ref().unwrap_or(&result);
+
+                if opt.split_bg {
+                    let split = full.split_background(imgx as usize, imgy as usize);
+                    print_background_split(split.background, split.foreground);
+                }
+
+                if let Some(largest) = opt.regions {
+                    let colors: Vec<Srgb<u8>> =
+                        full.centroids.iter().map(|x| x.into_format()).collect();
+                    print_regions(
+                        &full.regions(imgx as usize, imgy as usize),
+                        &colors,
+                        largest,
+                    )?;
                 }
             }
 
             // Print and/or sort results, output to palette
-            if opt.print || opt.percentage || opt.palette {
+            if opt.print
+                || opt.percentage
+                || opt.cmyk
+                || opt.accent
+                || opt.palette
+                || opt.barcode.is_some()
+                || opt.gradient.is_some()
+                || opt.template.is_some()
+                || opt.format.is_some()
+                || opt.report.is_some()
+            {
                 let mut res = Srgb::sort_indexed_colors(&result.centroids, &result.indices);
-                if opt.sort {
-                    res.sort_unstable_by(|a, b| (b.percentage).total_cmp(&a.percentage));
+                order_colors(&mut res, opt.order, opt.sort_lightness, Oklch::from_color);
+                if let Some(n) = opt.expand {
+                    res = expand_palette(&res, n, opt.interpolation);
                 }
 
-                if opt.print || opt.percentage {
-                    print_colors(opt.percentage, &res)?;
+                let format = PrintFormat {
+                    pct_format: opt.pct_format,
+                    delimiter: &opt.delimiter,
+                    pixels: result.indices.len(),
+                };
+                if let Some(template) = &opt.template {
+                    print_template(template, format, &res)?;
+                } else if opt.print || opt.percentage || opt.cmyk {
+                    print_colors(opt.percentage, opt.cmyk, format, &res)?;
                 }
 
-                if opt.palette {
-                    save_palette(
+                if let Some(format) = opt.format {
+                    print_code(format, &res)?;
+                }
+
+                if let Some(resamples) = opt.bootstrap {
+                    print_bootstrap(&res, &result.indices, resamples, seed)?;
+                }
+
+                if opt.accent {
+                    print_accent(&res, opt.accent_min)?;
+                }
+
+                if opt.barcode.is_some() {
+                    barcode.push(barcode_stripe(&res));
+                }
+
+                if opt.report.is_some() {
+                    report.push(ReportEntry::new(file, &img, barcode_stripe(&res))?);
+                }
+
+                if opt.gradient.is_some() {
+                    save_gradient(
                         &res,
-                        opt.proportional,
+                        opt.interpolation,
                         opt.height,
                         opt.width,
-                        &create_filename_palette(
-                            &opt.input,
-                            &opt.palette_output,
-                            opt.rgb,
-                            Some(opt.k),
-                            file,
-                        )?,
+                        &create_filename(&opt.input, &opt.gradient, "png", Some(k), file)?,
+                    )?;
+                }
+
+                if opt.palette {
+                    let title = create_filename_palette(
+                        &opt.input,
+                        &opt.palette_output,
+                        opt.rgb,
+                        Some(k),
+                        file,
+                        opt.palette_format.extension(),
                     )?;
+                    let options = SwatchOptions {
+                        proportional: opt.proportional,
+                        height: opt.height,
+                        width: opt.width,
+                        normalized: opt.normalized,
+                    };
+                    opt.palette_format.0.save(&res, &options, &title)?;
                 }
             }
 
             // Don't allocate image buffer if no-file
             if opt.no_file {
-                continue;
+                return Ok(());
             }
 
             // Convert indexed colors to Srgb colors to output as final result
-            if !opt.transparent {
+            if !opt.transparent && !opt.keep_alpha {
                 // Pre-convert centroids into output format
                 let centroids = &result
                     .centroids
                     .iter()
                     .map(|x| x.into_format())
                     .collect::<Vec<Srgb<u8>>>();
-                let rgb: Vec<Srgb<u8>> = Srgb::map_indices_to_centroids(centroids, &result.indices);
+
+                // Excluded pixels weren't indexed and blurred pixels aren't
+                // output, find the closest centroids of the output pixels
+                let mut indices = Vec::new();
+                if excludes_pixels(&opt) || blurred.is_some() {
+                    rgb_pixels.clear();
+                    rgb_pixels.extend(
+                        out_vec
+                            .iter()
+                            .map(|x| Srgb::<f32>::from_color(x.into_format::<_, f32>())),
+                    );
+                    Srgb::get_closest_centroid(&rgb_pixels, &result.centroids, &mut indices);
+                } else {
+                    indices = result.indices;
+                }
+                if let Some(min_size) = opt.despeckle {
+                    despeckle(&mut indices, imgx as usize, imgy as usize, min_size);
+                }
+                if opt.vectorize {
+                    let indices: Vec<Option<u32>> = indices.iter().map(|&x| Some(x)).collect();
+                    return save_svg(
+                        &indices,
+                        imgx,
+                        imgy,
+                        centroids,
+                        &create_filename(&opt.input, &opt.output, "svg", Some(k), file)?,
+                    );
+                }
+                let rgb: Vec<Srgb<u8>> = Srgb::map_indices_to_centroids(centroids, &indices);
 
                 save_image(
                     rgb.as_components(),
                     imgx,
                     imgy,
-                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(opt.k), file)?,
+                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(k), file)?,
                     false,
                 )?;
             } else {
                 // For transparent images, we get_closest_centroid based
                 // on the centroids we calculated and only paint in the pixels
-                // that have a full alpha
-                let mut indices = Vec::with_capacity(img_vec.len());
+                // that have a full alpha, or keep the alpha of every pixel
+                let mut indices = Vec::with_capacity(out_vec.len());
 
                 rgb_pixels.clear();
                 rgb_pixels.extend(
-                    img_vec
+                    out_vec
                         .iter()
                         .map(|x| Srgb::<f32>::from_color(x.into_format::<_, f32>())),
                 );
@@ -283,11 +1097,31 @@ pub fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
                     .map(|x| x.into_format().into())
                     .collect::<Vec<Srgba<u8>>>();
 
+                if let Some(min_size) = opt.despeckle {
+                    despeckle(&mut indices, imgx as usize, imgy as usize, min_size);
+                }
+                if opt.vectorize {
+                    let indices: Vec<Option<u32>> = indices
+                        .iter()
+                        .zip(out_vec)
+                        .map(|(&x, orig)| visible(&opt, orig.alpha).then_some(x))
+                        .collect();
+                    let colors: Vec<Srgb<u8>> = centroids.iter().map(|x| x.color).collect();
+                    return save_svg(
+                        &indices,
+                        imgx,
+                        imgy,
+                        &colors,
+                        &create_filename(&opt.input, &opt.output, "svg", Some(k), file)?,
+                    );
+                }
                 let rgb: Vec<Srgba<u8>> = Srgba::map_indices_to_centroids(centroids, &indices)
                     .iter()
-                    .zip(img_vec)
+                    .zip(out_vec)
                     .map(|(x, orig)| {
-                        if orig.alpha == 255 {
+                        if opt.keep_alpha {
+                            Srgba::new(x.red, x.green, x.blue, orig.alpha)
+                        } else if orig.alpha == 255 {
                             *x
                         } else {
                             Srgba::new(0u8, 0, 0, 0)
@@ -298,11 +1132,156 @@ pub fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
                     rgb.as_components(),
                     imgx,
                     imgy,
-                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(opt.k), file)?,
+                    &create_filename(&opt.input, &opt.output, &opt.extension, Some(k), file)?,
                 )?;
             }
         }
+
+        Ok(())
+    };
+
+    let mut errors = Vec::new();
+    for file in &opt.input {
+        if let Err(err) = run_file(file) {
+            let err = CliError::Input(file.clone(), err);
+            if opt.fail_fast || opt.input.len() == 1 {
+                return Err(err.into());
+            }
+            eprintln!("kmeans_colors: {err}");
+            errors.push(err);
+        }
+    }
+
+    if let Some(title) = &opt.barcode {
+        save_barcode(
+            &barcode,
+            opt.proportional,
+            opt.stripe_width,
+            opt.height,
+            title,
+        )?;
+    }
+
+    if let Some(format) = opt.report {
+        save_report(
+            &report,
+            &create_filename_report(&opt.report_output, format.extension())?,
+        )?;
+    }
+
+    if !errors.is_empty() {
+        print_error_summary(&errors, opt.input.len());
+        return Err(format!("{} of {} input files failed", errors.len(), opt.input.len()).into());
     }
 
     Ok(())
 }
+
+/// Find the k-means of the pixels with each `k` from `k_min` up to `k_max`,
+/// keeping the last result before an extra cluster reduces the distance of the
+/// pixels to their centroids by less than `--k-threshold` of the distance.
+/// Returns the result and its `k`.
+fn range_kmeans<C: Hamerly + Weighted + Clone>(
+    opt: &Opt,
+    k_min: u32,
+    k_max: u32,
+    seed: u64,
+    pixels: &[C],
+    weights: Option<&[f32]>,
+) -> (Kmeans<C>, u32) {
+    let mut previous: Option<(Kmeans<C>, f32, u32)> = None;
+    for k in k_min..=k_max {
+        let converge = opt
+            .factor
+            .unwrap_or_else(|| default_converge::<C>(k as usize, pixels.len()));
+        let config = KmeansConfig {
+            k: k as usize,
+            ..kmeans_config(opt, converge, seed)
+        };
+        let result = get_kmeans_best(&config, pixels, weights);
+        let distance: f32 = pixels
+            .iter()
+            .zip(&result.indices)
+            .enumerate()
+            .map(|(i, (x, &idx))| {
+                weights.map_or(1.0, |w| w[i]) * C::difference(x, &result.centroids[idx as usize])
+            })
+            .sum();
+        if opt.verbose > 0 {
+            println!("k: {k}, distance: {distance}");
+        }
+
+        if let Some((best, best_distance, best_k)) = previous.take() {
+            if best_distance - distance <= opt.k_threshold * best_distance {
+                return (best, best_k);
+            }
+        }
+        previous = Some((result, distance, k));
+    }
+
+    previous.map_or_else(|| (Kmeans::new(), 0), |(result, _, k)| (result, k))
+}
+
+/// Find the k-means of the pixels with `get_kmeans_shrink`, keeping the best
+/// of the runs.
+fn shrink_kmeans<C: Calculate + Clone>(
+    opt: &Opt,
+    converge: f32,
+    seed: u64,
+    pixels: &[C],
+) -> Kmeans<C> {
+    let mut result = Kmeans::new();
+    for i in 0..opt.runs {
+        let run_result = get_kmeans_shrink(
+            opt.k as usize,
+            opt.max_iter,
+            converge,
+            verbose_kmeans(opt),
+            pixels,
+            seed + i as u64,
+        );
+        result.keep_best(run_result, seed + i as u64);
+    }
+
+    result
+}
+
+/// Find the k-means of `Lab` pixels in the reduced space `R`, such as hue
+/// alone, returning the average `Lab` color of each cluster as the centroids.
+fn reduced_kmeans<Wp, R>(
+    opt: &Opt,
+    converge: f32,
+    seed: u64,
+    lab_pixels: &[Lab<Wp, f32>],
+) -> Kmeans<Lab<Wp, f32>>
+where
+    Wp: palette::white_point::WhitePoint<f32>,
+    R: Calculate + Clone + From<Lab<Wp, f32>>,
+{
+    let reduced: Vec<R> = lab_pixels.iter().map(|&x| R::from(x)).collect();
+
+    // Iterate over amount of runs keeping best results
+    let mut result = Kmeans::new();
+    for i in 0..opt.runs {
+        let run_result = get_kmeans(
+            opt.k as usize,
+            opt.max_iter,
+            converge,
+            verbose_kmeans(opt),
+            &reduced,
+            seed + i as u64,
+        );
+        result.keep_best(run_result, seed + i as u64);
+    }
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids = vec![Lab::new(0.0, 0.0, 0.0); result.centroids.len()];
+    Lab::<Wp, f32>::recalculate_centroids(&mut rng, lab_pixels, &mut centroids, &result.indices);
+
+    Kmeans {
+        score: result.score,
+        centroids,
+        indices: result.indices,
+        runs: result.runs,
+    }
+}
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
+use crate::palette_file::{PaletteWriter, WRITERS};
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "kmeans-colors",
@@ -18,6 +20,24 @@ pub struct Opt {
     )]
     pub input: Vec<PathBuf>,
 
+    /// Stop at the first input file that fails instead of continuing with
+    /// the remaining files and printing a json summary of the errors.
+    #[structopt(long = "fail-fast")]
+    pub fail_fast: bool,
+
+    /// Read more input files from this file, one path per line, or from
+    /// standard input with `-`.
+    #[structopt(long = "files-from", parse(from_os_str), conflicts_with("command"))]
+    pub files_from: Option<PathBuf>,
+
+    /// Start from the options of a preset workflow: thumbnail for a quick
+    /// printed palette, poster for a flat posterized image, pixel-art for an
+    /// Aseprite palette, or print for CMYK values of the colors by coverage.
+    /// Options given explicitly override the preset, and flags it adds can
+    /// be turned off with their negation, such as `--no-print` or `--file`.
+    #[structopt(long)]
+    pub preset: Option<Preset>,
+
     /// Number of clusters.
     ///
     /// `RGB` tends to have more "appealing" contrast at lower number of
@@ -31,7 +51,24 @@ pub struct Opt {
     /// which aren't the "best" answer. In these cases, the algorithm should be
     /// run multiple times and the best result chosen.
     #[structopt(short, long, default_value = "8", required = false)]
-    pub k: u8,
+    pub k: u32,
+
+    /// Choose the number of clusters for each image, from this many up to
+    /// `--k-max`, adding a cluster while it brings the pixels closer to their
+    /// colors by at least `--k-threshold`. The chosen `k` is printed to
+    /// standard error after the file name, as in `photo.jpg: k: 5`. Replaces
+    /// `-k`; ignored with `--space hue|ab`.
+    #[structopt(long = "k-min", requires("k-max"))]
+    pub k_min: Option<u32>,
+
+    /// Largest number of clusters tried with `--k-min`.
+    #[structopt(long = "k-max", requires("k-min"))]
+    pub k_max: Option<u32>,
+
+    /// Smallest improvement, as a fraction of the total distance of the
+    /// pixels to their colors, for `--k-min` to add another cluster.
+    #[structopt(long = "k-threshold", default_value = "0.1")]
+    pub k_threshold: f32,
 
     /// Maximum number of iterations.
     ///
@@ -40,11 +77,20 @@ pub struct Opt {
     /// the previous iteration.
     ///
     /// `RGB` tends to converge within 10 iterations while `Lab` can take many
-    /// more.
-    #[structopt(short, long = "iterations", default_value = "20", required = false)]
+    /// more. Use "auto" to stop once the convergence score has improved by
+    /// less than 5% for 3 iterations in a row, up to 1000 iterations.
+    #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
     pub max_iter: usize,
 
-    /// Convergence factor. Defaults to "10.0" for Lab and "0.0025" for RGB.
+    /// Convergence factor. Defaults to "5.0" for Lab and "0.0025" for RGB at
+    /// `k = 8` on an image of 512x512 pixels, scaled up for higher `k` and
+    /// smaller images and down for lower `k` and larger images.
     ///
     /// One of the thresholds for halting calculation of k-means. The other is
     /// a limit on total iterations. Decrease the factor for a higher quality
@@ -59,14 +105,57 @@ pub struct Opt {
     pub factor: Option<f32>,
 
     /// Number of times to run the algorithm on the image, keeping the lowest
-    /// score.
+    /// score. With `--verbose`, the seed and score of each run are printed to
+    /// show how much the result varies.
     #[structopt(short, long, default_value = "1", required = false)]
     pub runs: usize,
 
+    /// Abandon a run after 3 iterations if its pixels are this many times
+    /// further from their colors than in the best completed run, e.g. 1.1.
+    ///
+    /// Runs that start behind rarely catch up, so pruning them saves time
+    /// spent on extra runs that wouldn't be kept.
+    #[structopt(long)]
+    pub prune: Option<f32>,
+
+    /// Split the image into this many bands of rows, cluster them in parallel
+    /// on every core, then cluster the colors of all bands for the final
+    /// colors. Much faster on large images, with results close to clustering
+    /// the whole image. Ignored with `--edge-weight` and `--space hue|ab`.
+    #[structopt(long)]
+    pub tiles: Option<usize>,
+
+    /// Find the colors of a thumbnail with this many pixels on its longest
+    /// side, e.g. `256`, then map every pixel of the image to those colors.
+    /// Much faster on large photos, with nearly the same colors.
+    #[structopt(long = "cluster-on-thumbnail")]
+    pub cluster_on_thumbnail: Option<u32>,
+
+    /// Merge the two closest colors whenever the calculation stalls, if the
+    /// merged colors fit the image better accounting for the number of colors,
+    /// so images with fewer natural colors than `k` get fewer colors. Works
+    /// best with `--rgb`. Ignored with `--edge-weight` and `--space hue|ab`.
+    #[structopt(long)]
+    pub shrink: bool,
+
+    /// Rerun the k-means with this many seeds and print how consistently each
+    /// color appears, matching the colors of each run to the closest result
+    /// colors. Colors found in fewer than 80% of the seeds within a delta E of
+    /// 5 are unstable, suggesting more runs or a different `k`. Ignored with
+    /// `--load-model` and `--space hue|ab`.
+    #[structopt(long)]
+    pub stability: Option<usize>,
+
     /// Seed for the random number generator.
     #[structopt(long)]
     pub seed: Option<u64>,
 
+    /// Derive the seed for the random number generator from the pixels of
+    /// each image, so the same image always produces the same palette without
+    /// choosing a seed.
+    #[structopt(long = "seed-from-content", conflicts_with("seed"))]
+    pub seed_from_content: bool,
+
     /// File extension of output.
     #[structopt(short, long = "ext", default_value = "png", required = false)]
     pub extension: String,
@@ -79,38 +168,253 @@ pub struct Opt {
     #[structopt(short, long)]
     pub print: bool,
 
+    /// Don't print the colors, overriding `--print` from a `--preset`.
+    #[structopt(long = "no-print")]
+    pub no_print: bool,
+
     /// Print the percentage of each color in the image.
     #[structopt(long = "pct")]
     pub percentage: bool,
 
+    /// Don't print the percentages, overriding `--pct` from a `--preset`.
+    #[structopt(long = "no-pct")]
+    pub no_percentage: bool,
+
+    /// Format of the `--pct` output: fraction for `0.1234`, a percentage
+    /// with the given decimals such as `0.1%` for `12.3%`, or count for the
+    /// number of pixels.
+    #[structopt(long = "pct-format", default_value = "fraction")]
+    pub pct_format: PctFormat,
+
+    /// Separator between values on each line of printed colors.
+    #[structopt(long, default_value = ",")]
+    pub delimiter: String,
+
+    /// Print a line per color from a template instead of the `--print`,
+    /// `--pct`, and `--cmyk` output, e.g. `"{hex} {pct}"`. Placeholders are
+    /// hex, rgb, lab, pct in the `--pct-format`, count, index of the
+    /// centroid, and name for a rough description like `dark red`. Use `{{`
+    /// and `}}` for literal braces.
+    #[structopt(long)]
+    pub template: Option<Template>,
+
+    /// Print the colors as a constant array to paste into code: rust,
+    /// python, js, or json.
+    #[structopt(long)]
+    pub format: Option<CodeFormat>,
+
+    /// Resample the pixels this many times to print a 95% confidence interval
+    /// of the percentage of each color, as `low-high` in the order of the
+    /// printed colors, e.g. `--bootstrap 200`.
+    #[structopt(long)]
+    pub bootstrap: Option<usize>,
+
+    /// Print naive CMYK approximations of each color as `c/m/y/k` percentages.
+    /// No color profile is applied, so values are only a starting point for
+    /// print work.
+    #[structopt(long)]
+    pub cmyk: bool,
+
+    /// Don't print CMYK values, overriding `--cmyk` from a `--preset`.
+    #[structopt(long = "no-cmyk")]
+    pub no_cmyk: bool,
+
+    /// Print the dominant color followed by the accent color, the most
+    /// saturated color covering at least `--accent-min` of the image.
+    #[structopt(long)]
+    pub accent: bool,
+
+    /// Minimum fraction of the image an accent color must cover.
+    #[structopt(long = "accent-min", default_value = "0.05")]
+    pub accent_min: f32,
+
     /// Perform the k-means in `RGB` color space.
     #[structopt(long)]
     pub rgb: bool,
 
+    /// Perform the k-means in `Lab` color space, the default, overriding
+    /// `--rgb` from a `--preset`.
+    #[structopt(long)]
+    pub lab: bool,
+
+    /// Brighten or darken the image by this many stops in linear light before
+    /// clustering, e.g. `1.5` for an underexposed photo. Output colors come
+    /// from the adjusted image.
+    #[structopt(long, allow_hyphen_values = true)]
+    pub exposure: Option<f32>,
+
+    /// Apply this gamma to the image in linear light before clustering, after
+    /// `--exposure`. Values above 1 brighten shadows and midtones, values
+    /// below 1 darken them.
+    #[structopt(long)]
+    pub gamma: Option<f32>,
+
+    /// Blend transparent and semi-transparent pixels onto this hex color
+    /// before clustering, after `--exposure` and `--gamma`, e.g. `"#ffffff"`
+    /// for logos. The output image is opaque.
+    #[structopt(long)]
+    pub background: Option<String>,
+
+    /// Blur the image with a Gaussian blur of this sigma before clustering,
+    /// e.g. `1.0`, to keep JPEG noise and dithering patterns from forming
+    /// their own colors. The output image maps the unblurred pixels.
+    #[structopt(long)]
+    pub blur: Option<f32>,
+
+    /// Give pixels on edges more weight in the k-means calculation, so that
+    /// detailed regions aren't outweighed by large flat areas. Pixels are
+    /// weighted from 1 in flat areas up to 1 plus this strength on the
+    /// strongest edge. Ignored with `--space hue|ab`.
+    #[structopt(long = "edge-weight")]
+    pub edge_weight: Option<f32>,
+
+    /// Print the dominant background color followed by the dominant subject
+    /// color, classifying colors by how often they appear along the border
+    /// of the image.
+    #[structopt(long = "split-bg")]
+    pub split_bg: bool,
+
+    /// Print the number of contiguous regions of each color and the sizes of
+    /// its largest regions, up to this many, as `color,regions,sizes...`.
+    /// Many small regions suggest a color is speckle noise rather than an
+    /// object.
+    #[structopt(long)]
+    pub regions: Option<usize>,
+
+    /// Space of the `Lab` k-means calculation: lab, hue to group colors by
+    /// hue alone, or ab to ignore lightness. Output colors are the average
+    /// `Lab` color of each group. Ignored with `--rgb`.
+    #[structopt(long, default_value = "lab")]
+    pub space: Space,
+
+    /// Snap the colors to a grid after the k-means calculation and reassign
+    /// pixels to them: websafe, 4bit, or a step between channel values from
+    /// 1 to 255.
+    #[structopt(long)]
+    pub snap: Option<SnapGrid>,
+
+    /// Merge contiguous regions of the output image smaller than this many
+    /// pixels into the color surrounding them, for cleaner posterized images.
+    #[structopt(long)]
+    pub despeckle: Option<usize>,
+
+    /// Experimental: save the output image as an SVG file instead, tracing
+    /// the pixels of each color into a filled path for scalable flat-color
+    /// artwork. Pairs well with `--despeckle`.
+    #[structopt(long)]
+    pub vectorize: bool,
+
+    /// Print a histogram of the delta E between each pixel and its color, as
+    /// text or json, to show how much detail the colors preserve. Delta E is
+    /// the `Lab` distance, calculated in `Lab` for `--rgb` too.
+    #[structopt(long)]
+    pub histogram: Option<HistogramFormat>,
+
+    /// Print the minimum, maximum, mean, and standard deviation of each
+    /// channel, in `Lab` or `RGB` as used for the k-means calculation, for the
+    /// whole image and for the pixels of each color, as text or json. Text
+    /// lines are `color,channel,min,max,mean,std` where color is `image` or
+    /// the hex of the color.
+    #[structopt(long)]
+    pub stats: Option<HistogramFormat>,
+
+    /// Print a histogram of the hues of the pixels, as text or json, instead
+    /// of calculating k-means. Pixels are counted in 12 hue families 30
+    /// degrees wide, with nearly gray pixels counted separately, followed by
+    /// the peak hues from most to least common.
+    #[structopt(long = "hue-histogram")]
+    pub hue_histogram: Option<HistogramFormat>,
+
+    /// Expand the palette to this many colors interpolated between the
+    /// sorted k-means colors, for a larger smooth palette.
+    #[structopt(long)]
+    pub expand: Option<usize>,
+
+    /// Color space used to interpolate colors for `--expand` and
+    /// `--gradient`: oklab, or lch to keep colors saturated between distant
+    /// hues.
+    #[structopt(long, default_value = "oklab")]
+    pub interpolation: InterpolationSpace,
+
+    /// Exclude pixels with a hue in this range of degrees from the k-means
+    /// calculation, e.g. `20..50`. Ranges may wrap around, like `340..20`, and
+    /// the option may be repeated. Gray pixels have no hue and are kept.
+    #[structopt(long = "exclude-hue", number_of_values = 1)]
+    pub exclude_hue: Vec<HueRange>,
+
+    /// Exclude pixels with a lower `HSV` saturation, from 0 to 1, from the
+    /// k-means calculation, e.g. `0.15` to ignore gray pixels.
+    #[structopt(long = "min-saturation")]
+    pub min_saturation: Option<f32>,
+
+    /// Exclude pixels with a `Lab` lightness outside of this range, from 0 to
+    /// 100, from the k-means calculation, e.g. `10..95` to ignore near-black
+    /// and near-white pixels.
+    #[structopt(long = "lightness-range")]
+    pub lightness_range: Option<LightnessRange>,
+
+    /// Reference white point used for `Lab` conversion: a, d50, d55, d65,
+    /// d75, or e. Colors are chromatically adapted from the sRGB D65 white
+    /// point with the Bradford method.
+    #[structopt(long = "white-point", default_value = "d65")]
+    pub white_point: WhitePoint,
+
     /// Disable outputting the image. Used in combination with printing
     /// colors as output.
     #[structopt(long = "no-file")]
     pub no_file: bool,
 
+    /// Output the image, overriding `--no-file` from a `--preset`.
+    #[structopt(long)]
+    pub file: bool,
+
     /// Enable printing the convergence distance and other internal
-    /// information, such as iteration count.
-    #[structopt(short, long)]
-    pub verbose: bool,
+    /// information, such as iteration count. Pass twice with `--format json`
+    /// to print the iteration, score, number of re-initialized colors, and
+    /// time of every iteration as json lines on standard error instead of the
+    /// scores.
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
 
     /// Save color palette of image to file. Defaults to `40 * k width x 40
     /// height`.
     #[structopt(long)]
     pub palette: bool,
 
-    /// Display colors in order from highest to lowest percentage in the image.
-    /// Applies to console and `--palette` image output.
-    #[structopt(long)]
-    pub sort: bool,
+    /// Don't save the color palette, overriding `--palette` from a
+    /// `--preset`.
+    #[structopt(long = "no-palette")]
+    pub no_palette: bool,
+
+    /// Order of the printed colors, the `--palette`, and other palette
+    /// output: luminance from darkest to lightest, percentage from most to
+    /// least frequent, hue with grays first, or input for the order k-means
+    /// found the colors in.
+    #[structopt(long, default_value = "luminance")]
+    pub order: Order,
+
+    /// Lightness used to order colors from darkest to lightest: lab for `L*`,
+    /// or oklab, which ranks saturated colors closer to how bright they
+    /// appear next to grays.
+    #[structopt(long = "sort-lightness", default_value = "lab")]
+    pub sort_lightness: SortLightness,
+
+    /// File format of the `--palette` output: png for an image, svg for a
+    /// vector image, a palette file for editors: aseprite, paintnet for
+    /// paint.net `.txt`, jasc for Paint Shop Pro `.pal`, or gpl for GIMP, or
+    /// json with the percentages, or hex for one hex code per line.
+    #[structopt(long = "palette-format", default_value = "png")]
+    pub palette_format: PaletteFormat,
 
     /// Color palette output will be proportionally scaled.
     #[structopt(long)]
     pub proportional: bool,
 
+    /// Draw a second strip below the `--palette` image with the colors at
+    /// their mean lightness, to compare hues independent of brightness.
+    #[structopt(long)]
+    pub normalized: bool,
+
     /// Height of color palette image. If width is omitted, palette will be
     /// `height * k` pixels wide.
     #[structopt(long, default_value = "40")]
@@ -130,6 +434,60 @@ pub struct Opt {
     #[structopt(long = "op", parse(from_os_str))]
     pub palette_output: Option<PathBuf>,
 
+    /// Save a smooth gradient through the colors to this path, using the
+    /// interpolation space of `--interpolation`. When input is multiple files,
+    /// the filename is prefixed with the name of each file.
+    #[structopt(long, parse(from_os_str))]
+    pub gradient: Option<PathBuf>,
+
+    /// Save the centroids to this json file, to map other images to the same
+    /// colors with `--load-model`. When input is multiple files, the filename
+    /// is prefixed with the name of each file.
+    #[structopt(long = "save-model", parse(from_os_str))]
+    pub save_model: Option<PathBuf>,
+
+    /// Cache the colors of each image in this directory, keyed by a hash of
+    /// the pixels and the settings that change the result, so running again
+    /// only recalculates images that changed. Cached colors are mapped to the
+    /// image like `--load-model`.
+    #[structopt(long = "cache-dir", parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Skip clustering and map the image(s) to the centroids of a json file
+    /// saved with `--save-model`, for consistent recoloring against one
+    /// reference palette. The color space must match the saved model.
+    #[structopt(long = "load-model", parse(from_os_str))]
+    pub load_model: Option<PathBuf>,
+
+    /// When input is multiple files, start the k-means of each file from the
+    /// colors of the first file instead of a new random initialization. This
+    /// converges in far fewer iterations for similar images like video frames
+    /// or burst shots. Can't be used with `--load-model`, `--k-min`, `-k 1`,
+    /// `--shrink`, `--tiles`, or `--space hue|ab`.
+    #[structopt(long = "warm-start")]
+    pub warm_start: bool,
+
+    /// Save a "barcode" of all input files to this path, one vertical stripe
+    /// per input in the order given. Stripes are filled with the dominant
+    /// color of each file, or all colors when used with `--proportional`.
+    #[structopt(long, parse(from_os_str))]
+    pub barcode: Option<PathBuf>,
+
+    /// Width of each stripe in the barcode image.
+    #[structopt(long = "stripe-width", default_value = "4")]
+    pub stripe_width: u32,
+
+    /// Save a printable report of all input files, a contact sheet with the
+    /// thumbnail, palette, hex codes, and percentages of each image. Only
+    /// `pdf` is supported.
+    #[structopt(long)]
+    pub report: Option<ReportFormat>,
+
+    /// Path of the report. Defaults to `report-<timestamp>` with the
+    /// extension of the report format.
+    #[structopt(long = "report-output", parse(from_os_str))]
+    pub report_output: Option<PathBuf>,
+
     /// Maps the image to the user supplied colors.
     #[structopt(subcommand, name = "command")]
     pub cmd: Option<Command>,
@@ -138,6 +496,11 @@ pub struct Opt {
     /// transparent output image.
     #[structopt(long)]
     pub transparent: bool,
+
+    /// Keep the alpha channel of each input pixel in the output image. Alpha
+    /// isn't part of the k-means calculation unless `--transparent` is used.
+    #[structopt(long = "keep-alpha")]
+    pub keep_alpha: bool,
 }
 
 #[derive(StructOpt, Debug)]
@@ -164,7 +527,7 @@ pub enum Command {
             short,
             long,
             min_values = 2,
-            max_values = 255,
+            max_values = 65536,
             value_delimiter = ",",
             required = true
         )]
@@ -174,8 +537,14 @@ pub enum Command {
         #[structopt(long)]
         replace: bool,
 
-        /// Maximum number of iterations.
-        #[structopt(short, long = "iterations", default_value = "20", required = false)]
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
         max_iter: usize,
 
         /// Convergence factor.
@@ -196,10 +565,59 @@ pub enum Command {
         #[structopt(short, long = "pct")]
         percentage: bool,
 
+        /// Format of the `--pct` output: fraction, a percentage with the
+        /// given decimals such as `0.1%`, or count for the number of pixels.
+        #[structopt(long = "pct-format", default_value = "fraction")]
+        pct_format: PctFormat,
+
+        /// Separator between values on each line of printed colors.
+        #[structopt(long, default_value = ",")]
+        delimiter: String,
+
+        /// Print naive CMYK approximations of each color as `c/m/y/k`
+        /// percentages.
+        #[structopt(long)]
+        cmyk: bool,
+
+        /// Order of the printed colors: luminance, percentage, hue, or input
+        /// for the order of `--colors`. With `--replace`, the colors are paired
+        /// with the k-means colors in this order.
+        #[structopt(long, default_value = "luminance")]
+        order: Order,
+
+        /// Print the mean and maximum delta E between each color and the
+        /// pixels matched to it, as `mean/max`, to show how well the colors fit
+        /// the image. Ignored with `--replace`.
+        #[structopt(long = "delta-e")]
+        delta_e: bool,
+
+        /// Leave pixels with a delta E farther than this from every color
+        /// unmatched instead of forcing them to the nearest color, and print
+        /// the fraction of unmatched pixels. Ignored with `--replace`.
+        #[structopt(long = "max-distance")]
+        max_distance: Option<f32>,
+
+        /// How to draw unmatched pixels: keep their original color, magenta, or
+        /// transparent.
+        #[structopt(long, default_value = "keep")]
+        unmatched: Unmatched,
+
+        /// Map the pixels to the colors with Floyd-Steinberg error diffusion
+        /// and save an indexed png whose palette is exactly the colors, in
+        /// order. Supports up to 256 colors; can't be used with `--replace`,
+        /// `--max-distance`, or `--transparent`.
+        #[structopt(long)]
+        dither: bool,
+
         /// Perform the k-means operations in `RGB` color space.
         #[structopt(long)]
         rgb: bool,
 
+        /// Reference white point used for `Lab` conversion: a, d50, d55, d65,
+        /// d75, or e.
+        #[structopt(long = "white-point", default_value = "d65")]
+        white_point: WhitePoint,
+
         /// Enable printing the convergence distance and other internal
         /// information, such as iteration count.
         #[structopt(short, long)]
@@ -215,4 +633,924 @@ pub enum Command {
         #[structopt(long)]
         transparent: bool,
     },
+
+    /// Estimate the white balance of an image.
+    ///
+    /// The k-means colors are calculated in `Lab` and the near-neutral color
+    /// with the lowest chroma is treated as gray. Its `a` and `b` components
+    /// are printed as the color cast of the image, and can optionally be
+    /// removed from every pixel to write a corrected image.
+    Wb {
+        /// Input file(s), separated by commas.
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            value_delimiter = ",",
+            required = true
+        )]
+        input: Vec<PathBuf>,
+
+        /// Number of clusters.
+        #[structopt(short, long, default_value = "8", required = false)]
+        k: u32,
+
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
+        max_iter: usize,
+
+        /// Convergence factor.
+        #[structopt(short, long, default_value = "5.0", required = false)]
+        factor: f32,
+
+        /// Number of times to run the algorithm on the image, keeping the lowest
+        /// score.
+        #[structopt(short, long, default_value = "1", required = false)]
+        runs: usize,
+
+        /// Seed for the random number generator.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Maximum chroma of a color to be considered neutral.
+        #[structopt(long = "max-chroma", default_value = "12.0")]
+        max_chroma: f32,
+
+        /// Save an image with the color cast removed.
+        #[structopt(long)]
+        correct: bool,
+
+        /// Enable printing the convergence distance and other internal
+        /// information, such as iteration count.
+        #[structopt(short, long)]
+        verbose: bool,
+
+        /// Output file for the corrected image. When input is multiple files,
+        /// this string will be appended to the filename.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Apply a duotone or tritone effect to an image.
+    ///
+    /// The lightness of each pixel is mapped onto a smooth ramp through 2 or 3
+    /// colors, from shadows to highlights. The colors are the k-means colors
+    /// of the image sorted by lightness, or can be supplied.
+    Duotone {
+        /// Input file(s), separated by commas.
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            value_delimiter = ",",
+            required = true
+        )]
+        input: Vec<PathBuf>,
+
+        /// Colors of the ramp in order from shadows to highlights, instead of
+        /// the k-means colors.
+        #[structopt(short, long, min_values = 2, max_values = 3, value_delimiter = ",")]
+        colors: Vec<String>,
+
+        /// Number of k-means colors in the ramp, 2 or 3. Ignored when colors
+        /// are supplied.
+        #[structopt(short, long, default_value = "2", required = false)]
+        tones: u8,
+
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
+        max_iter: usize,
+
+        /// Convergence factor.
+        #[structopt(short, long, default_value = "5.0", required = false)]
+        factor: f32,
+
+        /// Number of times to run the algorithm on the image, keeping the lowest
+        /// score.
+        #[structopt(short, long, default_value = "3", required = false)]
+        runs: usize,
+
+        /// Seed for the random number generator.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Color space used to interpolate the ramp: oklab, or lch to keep
+        /// colors saturated between distant hues.
+        #[structopt(long, default_value = "oklab")]
+        interpolation: InterpolationSpace,
+
+        /// Enable printing the convergence distance and other internal
+        /// information, such as iteration count.
+        #[structopt(short, long)]
+        verbose: bool,
+
+        /// Output file. When input is multiple files, this string will be appended
+        /// to the filename. File type extension can be declared here for `.jpg`.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Posterize an image by reducing each channel to a number of evenly
+    /// spaced levels, without k-means.
+    ///
+    /// This is the naive form of color reduction, for comparing against the
+    /// k-means colors of the default command.
+    Posterize {
+        /// Input file(s), separated by commas.
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            value_delimiter = ",",
+            required = true
+        )]
+        input: Vec<PathBuf>,
+
+        /// Number of levels of each channel, from 2 to 255.
+        #[structopt(short, long, default_value = "4", required = false)]
+        levels: u8,
+
+        /// Keep the alpha channel of the input in the output image.
+        #[structopt(long)]
+        transparent: bool,
+
+        /// Output file. When input is multiple files, this string will be appended
+        /// to the filename. File type extension can be declared here for `.jpg`.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Time the k-means algorithms on an image across several values of k.
+    ///
+    /// Prints a table of the mean time of a run with Lloyd's algorithm, the
+    /// Hamerly algorithm, the Elkan algorithm, and Lloyd's algorithm with a
+    /// k-d tree, to help pick the fastest algorithm and parameters for an
+    /// image.
+    Bench {
+        /// Input file(s), separated by commas.
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            value_delimiter = ",",
+            required = true
+        )]
+        input: Vec<PathBuf>,
+
+        /// Numbers of clusters to time, separated by commas.
+        #[structopt(short, long, value_delimiter = ",", default_value = "2,4,8,16,32")]
+        k: Vec<u32>,
+
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
+        max_iter: usize,
+
+        /// Convergence factor.
+        #[structopt(short, long)]
+        factor: Option<f32>,
+
+        /// Number of times to run each algorithm, averaging the time.
+        #[structopt(short, long, default_value = "3", required = false)]
+        runs: usize,
+
+        /// Seed for the random number generator.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Perform the k-means operations in `RGB` color space.
+        #[structopt(long)]
+        rgb: bool,
+    },
+
+    /// Print a color for `<meta name="theme-color">` from an image.
+    ///
+    /// The dominant k-means color of the image is darkened or lightened just
+    /// enough to reach a minimum contrast ratio against white or black text,
+    /// as defined by WCAG.
+    ThemeColor {
+        /// Input file(s), separated by commas.
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            value_delimiter = ",",
+            required = true
+        )]
+        input: Vec<PathBuf>,
+
+        /// Number of clusters.
+        #[structopt(short, long, default_value = "4", required = false)]
+        k: u32,
+
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
+        max_iter: usize,
+
+        /// Convergence factor.
+        #[structopt(short, long, default_value = "5.0", required = false)]
+        factor: f32,
+
+        /// Number of times to run the algorithm on the image, keeping the lowest
+        /// score.
+        #[structopt(short, long, default_value = "3", required = false)]
+        runs: usize,
+
+        /// Seed for the random number generator.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Minimum contrast ratio against the text color, from 1 to 21. WCAG
+        /// asks for 4.5 for normal text and 3 for large text.
+        #[structopt(long, default_value = "4.5")]
+        contrast: f32,
+
+        /// Color of the text shown on the theme color: white, black, or auto
+        /// for whichever contrasts more with the dominant color.
+        #[structopt(long, default_value = "auto")]
+        text: TextColor,
+
+        /// Print the text color and the contrast ratio after the color.
+        #[structopt(short, long)]
+        verbose: bool,
+    },
+
+    /// Compare two palettes and print the colors that were added, removed, or
+    /// shifted between them.
+    ///
+    /// Each palette is a json file saved with `--save-model`, or an image to
+    /// find the colors of. Colors are paired by their distance in `Lab`,
+    /// closest first, and each line is `status,before,after,delta E` where
+    /// status is same, shifted, removed, or added. Useful to track changes of
+    /// brand colors or assets over time.
+    Diff {
+        /// Palette before the changes.
+        #[structopt(parse(from_os_str))]
+        before: PathBuf,
+
+        /// Palette after the changes.
+        #[structopt(parse(from_os_str))]
+        after: PathBuf,
+
+        /// Number of clusters to find in images.
+        #[structopt(short, long, default_value = "8", required = false)]
+        k: u32,
+
+        /// Maximum number of iterations, or "auto".
+        #[structopt(
+        short,
+        long = "iterations",
+        default_value = "20",
+        required = false,
+        parse(try_from_str = parse_max_iter)
+    )]
+        max_iter: usize,
+
+        /// Convergence factor. Defaults to a factor scaled for `k` and the
+        /// size of each image.
+        #[structopt(short, long)]
+        factor: Option<f32>,
+
+        /// Number of times to run the algorithm on each image, keeping the
+        /// lowest score.
+        #[structopt(short, long, default_value = "3", required = false)]
+        runs: usize,
+
+        /// Seed for the random number generator.
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Largest delta E between paired colors that are reported as the
+        /// same color.
+        #[structopt(long, default_value = "2.3")]
+        tolerance: f32,
+
+        /// Largest delta E between paired colors that are reported as a
+        /// shifted color. Colors further apart are reported as one color
+        /// removed and another added.
+        #[structopt(long = "max-shift", default_value = "25")]
+        max_shift: f32,
+    },
+
+    /// Run the jobs of a manifest file, each with its own settings.
+    ///
+    /// The manifest is a toml file with a `[[job]]` table for each run of the
+    /// main command. Keys are the long option names of the main command, such
+    /// as `input`, `k`, `rgb`, or `output`; arrays are joined with commas and
+    /// `true` passes a flag. Keys of an optional `[defaults]` table apply to
+    /// every job which doesn't set them. Jobs run on a pool of threads, each
+    /// reusing its `Lab` conversions between jobs.
+    Batch {
+        /// Manifest file listing the jobs.
+        #[structopt(parse(from_os_str))]
+        manifest: PathBuf,
+
+        /// Number of threads to run jobs on. Defaults to the available
+        /// parallelism. Output of jobs may interleave with more than one
+        /// thread.
+        #[structopt(short, long)]
+        threads: Option<usize>,
+
+        /// Print a json summary of the input, outcome and duration of every
+        /// job once all jobs finish.
+        #[structopt(long)]
+        summary: bool,
+    },
+}
+
+/// Text colors the `theme-color` subcommand ensures contrast against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextColor {
+    Auto,
+    White,
+    Black,
+}
+
+impl std::str::FromStr for TextColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(TextColor::Auto),
+            "white" => Ok(TextColor::White),
+            "black" => Ok(TextColor::Black),
+            _ => Err(format!("Unsupported text color: {s}")),
+        }
+    }
+}
+
+/// How the `find` subcommand draws pixels that don't match any color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unmatched {
+    Keep,
+    Magenta,
+    Transparent,
+}
+
+impl std::str::FromStr for Unmatched {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(Unmatched::Keep),
+            "magenta" => Ok(Unmatched::Magenta),
+            "transparent" => Ok(Unmatched::Transparent),
+            _ => Err(format!("Unsupported unmatched mode: {s}")),
+        }
+    }
+}
+
+/// Spaces available for clustering `Lab` colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    Lab,
+    Hue,
+    Ab,
+}
+
+impl std::str::FromStr for Space {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lab" => Ok(Space::Lab),
+            "hue" => Ok(Space::Hue),
+            "ab" => Ok(Space::Ab),
+            _ => Err(format!("Unsupported space: {s}")),
+        }
+    }
+}
+
+/// Output formats of the delta E histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistogramFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for HistogramFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(HistogramFormat::Text),
+            "json" => Ok(HistogramFormat::Json),
+            _ => Err(format!("Unsupported histogram format: {s}")),
+        }
+    }
+}
+
+/// File format of the palette output, one of the `PaletteWriter`s.
+#[derive(Clone, Copy)]
+pub struct PaletteFormat(pub &'static dyn PaletteWriter);
+
+impl PaletteFormat {
+    /// Default file extension of the format.
+    pub fn extension(self) -> &'static str {
+        self.0.extension()
+    }
+}
+
+impl std::fmt::Debug for PaletteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PaletteFormat")
+            .field(&self.0.names()[0])
+            .finish()
+    }
+}
+
+impl std::str::FromStr for PaletteFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        WRITERS
+            .iter()
+            .find(|writer| writer.names().contains(&name.as_str()))
+            .map(|&writer| PaletteFormat(writer))
+            .ok_or_else(|| format!("Unsupported palette format: {s}"))
+    }
+}
+
+/// File formats of the report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Pdf,
+}
+
+impl ReportFormat {
+    /// Default file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pdf" => Ok(ReportFormat::Pdf),
+            _ => Err(format!("Unsupported report format: {s}")),
+        }
+    }
+}
+
+/// Color spaces available for interpolating between colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterpolationSpace {
+    Oklab,
+    Lch,
+}
+
+impl From<InterpolationSpace> for kmeans_colors::Interpolation {
+    fn from(space: InterpolationSpace) -> Self {
+        match space {
+            InterpolationSpace::Oklab => kmeans_colors::Interpolation::Oklab,
+            InterpolationSpace::Lch => kmeans_colors::Interpolation::Lch,
+        }
+    }
+}
+
+impl std::str::FromStr for InterpolationSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "oklab" => Ok(InterpolationSpace::Oklab),
+            "lch" => Ok(InterpolationSpace::Lch),
+            _ => Err(format!("Unsupported interpolation space: {s}")),
+        }
+    }
+}
+
+/// Formats of printed color percentages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PctFormat {
+    /// A fraction with four decimals.
+    Fraction,
+    /// A percentage with this many decimals.
+    Percent(usize),
+    /// The number of pixels.
+    Count,
+}
+
+impl std::str::FromStr for PctFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fraction" => Ok(PctFormat::Fraction),
+            "count" => Ok(PctFormat::Count),
+            _ => {
+                // A sample percentage like `0%` or `0.01%` sets the decimals
+                let err = || format!("Unsupported percentage format: {s}");
+                let number = s.strip_suffix('%').ok_or_else(err)?;
+                let (whole, decimals) = number.split_once('.').unwrap_or((number, ""));
+                if whole.is_empty()
+                    || !(whole.bytes().chain(decimals.bytes())).all(|b| b.is_ascii_digit())
+                {
+                    return Err(err());
+                }
+                Ok(PctFormat::Percent(decimals.len()))
+            }
+        }
+    }
+}
+
+/// Bundles of options for common workflows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    Thumbnail,
+    Poster,
+    PixelArt,
+    Print,
+}
+
+impl Preset {
+    /// The options of the preset as the name of the argument, its flag, and
+    /// its value.
+    fn options(self) -> &'static [(&'static str, &'static str, Option<&'static str>)] {
+        match self {
+            // A handful of colors printed quickly
+            Preset::Thumbnail => &[
+                ("k", "-k", Some("5")),
+                ("runs", "--runs", Some("1")),
+                ("print", "--print", None),
+                ("no-file", "--no-file", None),
+            ],
+            // Flat areas of color like a screen print
+            Preset::Poster => &[
+                ("k", "-k", Some("6")),
+                ("rgb", "--rgb", None),
+                ("runs", "--runs", Some("3")),
+                ("blur", "--blur", Some("1.0")),
+            ],
+            // A limited palette for editing in Aseprite
+            Preset::PixelArt => &[
+                ("k", "-k", Some("16")),
+                ("rgb", "--rgb", None),
+                ("runs", "--runs", Some("3")),
+                ("palette", "--palette", None),
+                ("palette-format", "--palette-format", Some("aseprite")),
+            ],
+            // Starting values for print work, by coverage
+            Preset::Print => &[
+                ("k", "-k", Some("8")),
+                ("runs", "--runs", Some("3")),
+                ("print", "--print", None),
+                ("percentage", "--pct", None),
+                ("pct-format", "--pct-format", Some("0.1%")),
+                ("cmyk", "--cmyk", None),
+                ("order", "--order", Some("percentage")),
+                ("palette", "--palette", None),
+            ],
+        }
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "thumbnail" => Ok(Preset::Thumbnail),
+            "poster" => Ok(Preset::Poster),
+            "pixel-art" => Ok(Preset::PixelArt),
+            "print" => Ok(Preset::Print),
+            _ => Err(format!("Unsupported preset: {s}")),
+        }
+    }
+}
+
+/// Programming languages for printing the colors as code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeFormat {
+    Rust,
+    Python,
+    Js,
+    Json,
+}
+
+impl std::str::FromStr for CodeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rust" => Ok(CodeFormat::Rust),
+            "python" => Ok(CodeFormat::Python),
+            "js" | "javascript" => Ok(CodeFormat::Js),
+            "json" => Ok(CodeFormat::Json),
+            _ => Err(format!("Unsupported code format: {s}")),
+        }
+    }
+}
+
+/// A line of text with placeholders for the values of a color.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template(pub Vec<TemplatePart>);
+
+/// Literal text or a placeholder of a `Template`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplatePart {
+    Text(String),
+    Hex,
+    Rgb,
+    Lab,
+    Pct,
+    Count,
+    Index,
+    Name,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    text.push('{');
+                    let _ = chars.next();
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    text.push('}');
+                    let _ = chars.next();
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in template: {s}"))?;
+                    let part = match &rest[..end] {
+                        "hex" => TemplatePart::Hex,
+                        "rgb" => TemplatePart::Rgb,
+                        "lab" => TemplatePart::Lab,
+                        "pct" => TemplatePart::Pct,
+                        "count" => TemplatePart::Count,
+                        "index" => TemplatePart::Index,
+                        "name" => TemplatePart::Name,
+                        name => return Err(format!("Unsupported placeholder: {{{name}}}")),
+                    };
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched `}}` in template: {s}")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        Ok(Template(parts))
+    }
+}
+
+/// Lightness scales for ordering colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortLightness {
+    Lab,
+    Oklab,
+}
+
+impl std::str::FromStr for SortLightness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lab" => Ok(SortLightness::Lab),
+            "oklab" => Ok(SortLightness::Oklab),
+            _ => Err(format!("Unsupported lightness: {s}")),
+        }
+    }
+}
+
+/// Orders of the palette colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Luminance,
+    Percentage,
+    Hue,
+    Input,
+}
+
+impl std::str::FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "luminance" => Ok(Order::Luminance),
+            "percentage" => Ok(Order::Percentage),
+            "hue" => Ok(Order::Hue),
+            "input" => Ok(Order::Input),
+            _ => Err(format!("Unsupported order: {s}")),
+        }
+    }
+}
+
+/// Reference white points available for `Lab` conversion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhitePoint {
+    A,
+    D50,
+    D55,
+    D65,
+    D75,
+    E,
+}
+
+impl std::str::FromStr for WhitePoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Ok(WhitePoint::A),
+            "d50" => Ok(WhitePoint::D50),
+            "d55" => Ok(WhitePoint::D55),
+            "d65" => Ok(WhitePoint::D65),
+            "d75" => Ok(WhitePoint::D75),
+            "e" => Ok(WhitePoint::E),
+            _ => Err(format!("Unsupported white point: {s}")),
+        }
+    }
+}
+
+/// A range of hue in degrees, inclusive on both ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HueRange {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl HueRange {
+    /// Returns `true` if the hue lies within the range, wrapping around 360.
+    pub fn contains(&self, hue: f32) -> bool {
+        if self.start <= self.end {
+            hue >= self.start && hue <= self.end
+        } else {
+            hue >= self.start || hue <= self.end
+        }
+    }
+}
+
+impl std::str::FromStr for HueRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid hue range, expected `start..end` in degrees: {s}");
+        let (start, end) = s.split_once("..").ok_or_else(err)?;
+        let start: f32 = start.trim().parse().map_err(|_| err())?;
+        let end: f32 = end.trim().parse().map_err(|_| err())?;
+
+        Ok(HueRange {
+            start: start.rem_euclid(360.0),
+            end: end.rem_euclid(360.0),
+        })
+    }
+}
+
+/// A range of `Lab` lightness, inclusive on both ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightnessRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl LightnessRange {
+    /// Returns `true` if the lightness lies within the range.
+    pub fn contains(&self, lightness: f32) -> bool {
+        lightness >= self.min && lightness <= self.max
+    }
+}
+
+impl std::str::FromStr for LightnessRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid lightness range, expected `min..max` from 0 to 100: {s}");
+        let (min, max) = s.split_once("..").ok_or_else(err)?;
+        let min: f32 = min.trim().parse().map_err(|_| err())?;
+        let max: f32 = max.trim().parse().map_err(|_| err())?;
+        if min > max {
+            return Err(err());
+        }
+
+        Ok(LightnessRange { min, max })
+    }
+}
+
+/// Grid of 8-bit colors that centroids can be snapped to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapGrid {
+    pub step: u8,
+}
+
+impl SnapGrid {
+    /// Returns the nearest color on the grid.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn snap(&self, color: palette::Srgb<u8>) -> palette::Srgb<u8> {
+        let step = f32::from(self.step);
+        let snap = |x: u8| ((f32::from(x) / step).round() * step).min(255.0) as u8;
+        palette::Srgb::new(snap(color.red), snap(color.green), snap(color.blue))
+    }
+}
+
+impl std::str::FromStr for SnapGrid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let step = match s.to_ascii_lowercase().as_str() {
+            "websafe" => 51,
+            "4bit" => 17,
+            x => x
+                .parse()
+                .map_err(|_| format!("Invalid snap grid, expected websafe, 4bit, or 1-255: {s}"))?,
+        };
+        if step == 0 {
+            return Err("Snap grid step must be greater than 0".to_string());
+        }
+
+        Ok(SnapGrid { step })
+    }
+}
+
+impl Opt {
+    /// Turns off the flags whose negation was given, such as `--print` with
+    /// `--no-print`, so flags added by a preset can be disabled.
+    fn negate_flags(mut self) -> Self {
+        self.print &= !self.no_print;
+        self.percentage &= !self.no_percentage;
+        self.cmyk &= !self.no_cmyk;
+        self.rgb &= !self.lab;
+        self.no_file &= !self.file;
+        self.palette &= !self.no_palette;
+        self
+    }
+}
+
+/// Parses the options of the main command, adding the options of the
+/// `--preset` which weren't given explicitly.
+pub fn parse_opt<I>(args: I) -> Result<Opt, structopt::clap::Error>
+where
+    I: IntoIterator,
+    I::Item: Into<std::ffi::OsString>,
+{
+    let mut args: Vec<std::ffi::OsString> = args.into_iter().map(Into::into).collect();
+    let matches = Opt::clap().get_matches_from_safe(&args)?;
+    let opt = Opt::from_clap(&matches);
+    let preset = match opt.preset {
+        Some(preset) if opt.cmd.is_none() => preset,
+        _ => return Ok(opt.negate_flags()),
+    };
+
+    for &(name, flag, value) in preset.options() {
+        if matches.occurrences_of(name) == 0 {
+            args.push(flag.into());
+            args.extend(value.map(Into::into));
+        }
+    }
+    Opt::from_iter_safe(args).map(Opt::negate_flags)
+}
+
+/// Parses an iteration limit, where "auto" stops once the score stops
+/// improving.
+fn parse_max_iter(s: &str) -> Result<usize, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(kmeans_colors::AUTO_MAX_ITER);
+    }
+
+    s.parse()
+        .map_err(|_| format!("Invalid iterations, expected a number or auto: {s}"))
 }
@@ -0,0 +1,386 @@
+use std::sync::Mutex;
+
+use crate::app::{run_cached, Caches};
+use crate::args::{parse_opt, Command};
+use crate::err::CliError;
+use crate::utils::json_string;
+
+/// Value of a manifest key.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    /// A string or number, passed as written.
+    Text(String),
+    List(Vec<String>),
+}
+
+/// Keys and values of a `[[job]]` table, in order.
+type Job = Vec<(String, Value)>;
+
+/// Run each job of a manifest with the main command.
+pub fn batch(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Batch {
+        manifest,
+        threads,
+        summary,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    let (defaults, jobs) = parse_manifest(&std::fs::read_to_string(&manifest)?)
+        .map_err(|e| CliError::Usage(format!("{}: {e}", manifest.to_string_lossy())))?;
+    if jobs.is_empty() {
+        return Err(CliError::NoInput.into());
+    }
+    // Parse every job before running any so mistakes are caught up front
+    let opts = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| {
+            parse_opt(job_args(&defaults, job))
+                .map_err(|e| CliError::Usage(format!("job {}: {}", i + 1, e.message)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let inputs: Vec<Vec<String>> = opts
+        .iter()
+        .map(|opt| {
+            opt.input
+                .iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect()
+        })
+        .collect();
+
+    let threads = threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()))
+        .clamp(1, opts.len().max(1));
+    let queue = Mutex::new(opts.into_iter().enumerate());
+    let results = Mutex::new(vec![JobResult::default(); inputs.len()]);
+
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                // Conversions are reused between the jobs of each thread
+                let mut caches = Caches::new();
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((i, opt)) = next else {
+                        break;
+                    };
+                    let start = std::time::Instant::now();
+                    let error = run_cached(opt, &mut caches).err().map(|e| e.to_string());
+                    results.lock().unwrap()[i] = JobResult {
+                        seconds: start.elapsed().as_secs_f64(),
+                        error,
+                    };
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    if summary {
+        print_summary(&inputs, &results);
+    }
+
+    let errors: Vec<String> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, x)| x.error.as_ref().map(|e| format!("job {}: {e}", i + 1)))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+/// Outcome of running a job.
+#[derive(Clone, Debug, Default)]
+struct JobResult {
+    seconds: f64,
+    error: Option<String>,
+}
+
+/// Print the outcome of every job as json.
+fn print_summary(inputs: &[Vec<String>], results: &[JobResult]) {
+    let failed = results.iter().filter(|x| x.error.is_some()).count();
+    let jobs: Vec<String> = inputs
+        .iter()
+        .zip(results)
+        .enumerate()
+        .map(|(i, (input, result))| {
+            let input: Vec<String> = input.iter().map(|x| json_string(x)).collect();
+            let error = match &result.error {
+                Some(e) => format!(",\"error\":{}", json_string(e)),
+                None => String::new(),
+            };
+            format!(
+                "{{\"job\":{},\"input\":[{}],\"ok\":{},\"seconds\":{:.3}{error}}}",
+                i + 1,
+                input.join(","),
+                result.error.is_none(),
+                result.seconds,
+            )
+        })
+        .collect();
+
+    println!(
+        "{{\"jobs\":[{}],\"succeeded\":{},\"failed\":{failed}}}",
+        jobs.join(","),
+        results.len() - failed,
+    );
+}
+
+/// Command line arguments of the main command for a job, using the defaults
+/// for keys the job doesn't set.
+fn job_args(defaults: &Job, job: &Job) -> Vec<String> {
+    let mut args = vec![String::from("kmeans_colors")];
+    let defaults = defaults
+        .iter()
+        .filter(|(key, _)| !job.iter().any(|(k, _)| k == key));
+    for (key, value) in defaults.chain(job) {
+        let flag = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Bool(true) => args.push(flag),
+            Value::Bool(false) => {}
+            Value::Text(x) => args.extend([flag, x.clone()]),
+            Value::List(x) => args.extend([flag, x.join(",")]),
+        }
+    }
+    args
+}
+
+/// Parse the `[defaults]` and `[[job]]` tables of a manifest. Supports the
+/// subset of toml needed for options: basic and literal strings, numbers,
+/// booleans, and single-line arrays.
+fn parse_manifest(text: &str) -> Result<(Job, Vec<Job>), String> {
+    let mut defaults: Job = Vec::new();
+    let mut jobs: Vec<Job> = Vec::new();
+    // Whether keys belong to the defaults rather than the last job
+    let mut in_defaults = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let err = |msg: &str| format!("line {}: {msg}", n + 1);
+        if line.is_empty() {
+            continue;
+        }
+        if line == "[[job]]" {
+            jobs.push(Vec::new());
+            in_defaults = false;
+            continue;
+        }
+        if line == "[defaults]" {
+            if !jobs.is_empty() {
+                return Err(err("[defaults] must come before the jobs"));
+            }
+            in_defaults = true;
+            continue;
+        }
+        let job = if in_defaults {
+            &mut defaults
+        } else {
+            jobs.last_mut()
+                .ok_or_else(|| err("expected [defaults] or [[job]]"))?
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err("expected key = value"))?;
+        let key = key.trim().trim_matches('"');
+        let value = parse_value(value.trim()).ok_or_else(|| err("invalid value"))?;
+        job.push((key.to_string(), value));
+    }
+
+    Ok((defaults, jobs))
+}
+
+/// Byte offsets of the characters of `line` which are outside strings. Basic
+/// strings in `"` may escape characters with `\`, literal strings in `'` are
+/// taken as written.
+fn unquoted(line: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    line.char_indices().filter(move |&(_, c)| match quote {
+        Some('"') if escaped => {
+            escaped = false;
+            false
+        }
+        Some('"') if c == '\\' => {
+            escaped = true;
+            false
+        }
+        Some(q) => {
+            if c == q {
+                quote = None;
+            }
+            false
+        }
+        None => {
+            if c == '"' || c == '\'' {
+                quote = Some(c);
+            }
+            true
+        }
+    })
+}
+
+/// Remove a `#` comment which isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    match unquoted(line).find(|&(_, c)| c == '#') {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+/// Parse a boolean, string, number, or array of strings and numbers.
+fn parse_value(value: &str) -> Option<Value> {
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ if value.starts_with('[') => {
+            let inner = value.strip_prefix('[')?.strip_suffix(']')?;
+            let mut items = Vec::new();
+            let mut start = 0;
+            for (i, c) in unquoted(inner) {
+                if c == ',' {
+                    items.push(&inner[start..i]);
+                    start = i + 1;
+                }
+            }
+            items.push(&inner[start..]);
+            // A trailing comma is allowed
+            if items.last().is_some_and(|x| x.trim().is_empty()) {
+                items.pop();
+            }
+            items
+                .into_iter()
+                .map(|x| parse_scalar(x.trim()))
+                .collect::<Option<_>>()
+                .map(Value::List)
+        }
+        _ => parse_scalar(value).map(Value::Text),
+    }
+}
+
+/// Parse a string or number.
+fn parse_scalar(value: &str) -> Option<String> {
+    if let Some(x) = value.strip_prefix('\'') {
+        let x = x.strip_suffix('\'')?;
+        (!x.contains('\'')).then(|| x.to_string())
+    } else if let Some(x) = value.strip_prefix('"') {
+        unescape(x.strip_suffix('"')?)
+    } else if value.parse::<f64>().is_ok() {
+        Some(value.to_string())
+    } else {
+        None
+    }
+}
+
+/// Replace the escapes of a basic string, returning `None` for an unknown
+/// escape or an unescaped quote.
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(match chars.next()? {
+                '\\' => '\\',
+                '"' => '"',
+                'n' => '\n',
+                't' => '\t',
+                _ => return None,
+            }),
+            '"' => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{job_args, parse_manifest, Value};
+
+    #[test]
+    fn defaults_and_overrides() {
+        let manifest = r#"
+            # Shared options
+            [defaults]
+            k = 8
+            rgb = true
+            runs = 3
+
+            [[job]]
+            input = ["a.png", "b.png"]
+            k = 4 # fewer colors
+
+            [[job]]
+            input = ["c.png"]
+            rgb = false
+        "#;
+        let (defaults, jobs) = parse_manifest(manifest).unwrap();
+        assert_eq!(defaults.len(), 3);
+        assert_eq!(jobs.len(), 2);
+
+        assert_eq!(
+            job_args(&defaults, &jobs[0]),
+            [
+                "kmeans_colors",
+                "--rgb",
+                "--runs",
+                "3",
+                "--input",
+                "a.png,b.png",
+                "--k",
+                "4",
+            ]
+        );
+        assert_eq!(
+            job_args(&defaults, &jobs[1]),
+            [
+                "kmeans_colors",
+                "--k",
+                "8",
+                "--runs",
+                "3",
+                "--input",
+                "c.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let manifest = r##"
+            [[job]]
+            template = "{hex} # \"{pct}\"" # comment
+            list = ["a, b", 'c\d', "e\\", 2]
+            escaped = "\\\"#"
+        "##;
+        let (_, jobs) = parse_manifest(manifest).unwrap();
+        assert_eq!(
+            jobs[0],
+            [
+                ("template".into(), Value::Text("{hex} # \"{pct}\"".into())),
+                (
+                    "list".into(),
+                    Value::List(vec!["a, b".into(), "c\\d".into(), "e\\".into(), "2".into()])
+                ),
+                ("escaped".into(), Value::Text("\\\"#".into())),
+            ]
+        );
+
+        for invalid in [
+            "x = \"a\\q\"",
+            "x = \"a\" b\"",
+            "x = [\"a\", b]",
+            "x = 'a'b'",
+            "x = yes",
+        ] {
+            assert!(parse_manifest(&format!("[[job]]\n{invalid}")).is_err());
+        }
+        assert!(parse_manifest("k = 1").is_err());
+        assert!(parse_manifest("[[job]]\n[defaults]").is_err());
+    }
+}
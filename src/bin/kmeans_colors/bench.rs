@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+use palette::cast::ComponentsAs;
+use palette::{Lab, Srgb, Srgba};
+
+use crate::args::Command;
+use kmeans_colors::{
+    default_converge, get_kmeans, get_kmeans_elkan, get_kmeans_hamerly, get_kmeans_kdtree, Axes,
+    ConversionCache, Elkan, Hamerly,
+};
+
+/// Settings shared by every timed run.
+struct Settings {
+    max_iter: usize,
+    factor: Option<f32>,
+    runs: usize,
+    seed: u64,
+}
+
+/// Time the k-means algorithms on each image and print a table of the results.
+pub fn bench(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Bench {
+        input,
+        k,
+        max_iter,
+        factor,
+        runs,
+        seed,
+        rgb,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    let settings = Settings {
+        max_iter,
+        factor,
+        runs: runs.max(1),
+        seed: seed.unwrap_or(0),
+    };
+    let mut lab_cache = ConversionCache::new();
+
+    for file in &input {
+        let img = image::open(file)?.into_rgba8();
+        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+        println!("{}", &file.to_string_lossy());
+        println!(
+            "{:>5}  {:>12}  {:>12}  {:>12}  {:>12}",
+            "k", "lloyd", "hamerly", "elkan", "kdtree"
+        );
+
+        if !rgb {
+            let mut lab_pixels: Vec<Lab> = Vec::with_capacity(img_vec.len());
+            lab_cache.extend(img_vec.iter().map(|x| x.color), &mut lab_pixels);
+            print_times(&k, &lab_pixels, &settings);
+        } else {
+            let rgb_pixels: Vec<Srgb> = img_vec.iter().map(|x| x.color.into_format()).collect();
+            print_times(&k, &rgb_pixels, &settings);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a row of the table for each k.
+fn print_times<C: Hamerly + Elkan + Axes + Clone>(k: &[u32], buf: &[C], settings: &Settings) {
+    for &k in k {
+        let times = time_algorithms(k as usize, buf, settings);
+        let [lloyd, hamerly, elkan, kdtree] = times.map(|x| format!("{:.2}ms", millis(x)));
+        println!("{k:>5}  {lloyd:>12}  {hamerly:>12}  {elkan:>12}  {kdtree:>12}");
+    }
+}
+
+/// Mean time of a run of each algorithm: Lloyd's, Hamerly, Elkan, and k-d
+/// tree.
+fn time_algorithms<C: Hamerly + Elkan + Axes + Clone>(
+    k: usize,
+    buf: &[C],
+    settings: &Settings,
+) -> [Duration; 4] {
+    let time = |f: &dyn Fn(u64)| {
+        let start = Instant::now();
+        for i in 0..settings.runs {
+            f(settings.seed + i as u64);
+        }
+        start.elapsed() / settings.runs as u32
+    };
+    let max_iter = settings.max_iter;
+    let converge = settings
+        .factor
+        .unwrap_or_else(|| default_converge::<C>(k, buf.len()));
+
+    [
+        time(&|seed| {
+            get_kmeans(k, max_iter, converge, false, buf, seed);
+        }),
+        time(&|seed| {
+            get_kmeans_hamerly(k, max_iter, converge, false, buf, seed);
+        }),
+        time(&|seed| {
+            get_kmeans_elkan(k, max_iter, converge, false, buf, seed);
+        }),
+        time(&|seed| {
+            get_kmeans_kdtree(k, max_iter, converge, false, buf, seed);
+        }),
+    ]
+}
+
+/// Duration in fractional milliseconds.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use crate::args::Opt;
+use crate::model::Model;
+use crate::utils::{content_seed, fnv1a};
+
+/// Path of the cached centroids of a `width` by `height` image, named by a
+/// hash of the pixels and the settings of `opt` that change the k-means
+/// result, including the `--warm-start` centroids the calculation starts from.
+///
+/// The version is part of the key so results of other versions aren't reused.
+#[allow(clippy::too_many_arguments)]
+pub fn cache_path(
+    dir: &Path,
+    opt: &Opt,
+    converge: f32,
+    seed: u64,
+    warm_start: Option<&[[f32; 3]]>,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> PathBuf {
+    let settings = format!(
+        "{} k={} k_min={:?} k_max={:?} k_threshold={} tiles={:?} shrink={} cluster_on_thumbnail={:?} rgb={} space={:?} white_point={:?} seed={} runs={} max_iter={} \
+         converge={} prune={:?} transparent={} exclude_hue={:?} min_saturation={:?} \
+         lightness_range={:?} edge_weight={:?} warm_start={:?}",
+        env!("CARGO_PKG_VERSION"),
+        opt.k,
+        opt.k_min,
+        opt.k_max,
+        opt.k_threshold,
+        opt.tiles,
+        opt.shrink,
+        opt.cluster_on_thumbnail,
+        opt.rgb,
+        opt.space,
+        opt.white_point,
+        seed,
+        opt.runs,
+        opt.max_iter,
+        converge,
+        opt.prune,
+        opt.transparent,
+        opt.exclude_hue,
+        opt.min_saturation,
+        opt.lightness_range,
+        opt.edge_weight,
+        warm_start,
+    );
+    let hash = fnv1a(content_seed(width, height, pixels), settings.as_bytes());
+
+    dir.join(format!("{hash:016x}.json"))
+}
+
+/// Load cached centroids, treating unreadable or mismatched files as missing
+/// so they're recalculated and overwritten.
+pub fn load_cached(path: &Path, rgb: bool) -> Option<Model> {
+    Model::load(path).ok().filter(|model| model.rgb == rgb)
+}
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::path::Path;
+
+use palette::cast::ComponentsAs;
+use palette::white_point::{A, D50, D55, D65, D75, E};
+use palette::{FromColor, Lab, Srgb, Srgba};
+
+use crate::args::{Command, WhitePoint};
+use crate::err::CliError;
+use crate::model::Model;
+use crate::utils::lab_to_linear;
+use kmeans_colors::{
+    default_converge, get_kmeans_best, match_centroids, Calculate, ConversionCache, KmeansConfig,
+    Sort,
+};
+
+/// Print the differences between the colors of two palettes.
+pub fn diff(command: Command) -> Result<(), Box<dyn Error>> {
+    let Command::Diff {
+        before,
+        after,
+        k,
+        max_iter,
+        factor,
+        runs,
+        seed,
+        tolerance,
+        max_shift,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    if tolerance.is_nan() || tolerance < 0.0 || max_shift.is_nan() || max_shift < tolerance {
+        return Err(CliError::Usage(
+            "--tolerance must be at least 0 and at most --max-shift".into(),
+        )
+        .into());
+    }
+    let config = KmeansConfig {
+        k: k as usize,
+        max_iter,
+        runs,
+        seed: seed.unwrap_or(0),
+        ..Default::default()
+    };
+
+    // Cached results of Srgb<u8> -> Lab conversions, shared by both images
+    let mut lab_cache = ConversionCache::new();
+    let before = palette_colors(&before, &config, factor, &mut lab_cache)?;
+    let after = palette_colors(&after, &config, factor, &mut lab_cache)?;
+
+    let hex = |x: Lab<D65, f32>| format!("{:x}", Srgb::<u8>::from_linear(lab_to_linear(x)));
+    let mut added = vec![true; after.len()];
+    let mut removed = Vec::new();
+    for (&color, paired) in before.iter().zip(match_centroids(&before, &after)) {
+        let Some(j) = paired else {
+            removed.push(color);
+            continue;
+        };
+        let delta_e = Lab::difference(&color, &after[j]).sqrt();
+        if delta_e > max_shift {
+            removed.push(color);
+            continue;
+        }
+        added[j] = false;
+        let status = if delta_e <= tolerance {
+            "same"
+        } else {
+            "shifted"
+        };
+        println!("{status},{},{},{delta_e:.2}", hex(color), hex(after[j]));
+    }
+    for color in removed {
+        println!("removed,{},,", hex(color));
+    }
+    for (&color, _) in after.iter().zip(&added).filter(|x| *x.1) {
+        println!("added,,{},", hex(color));
+    }
+
+    Ok(())
+}
+
+/// Colors of a palette as `Lab` with the D65 white point, read from a model
+/// file with a `.json` extension, or found with k-means from an image and
+/// ordered from most to least common.
+fn palette_colors(
+    path: &Path,
+    config: &KmeansConfig,
+    factor: Option<f32>,
+    lab_cache: &mut ConversionCache<Lab<D65, f32>>,
+) -> Result<Vec<Lab<D65, f32>>, Box<dyn Error>> {
+    let is_model = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("json"));
+    if is_model {
+        let model = Model::load(path)?;
+        if model.rgb {
+            return Ok(model
+                .centroids
+                .iter()
+                .map(|&[r, g, b]| Lab::from_color(Srgb::new(r, g, b).into_linear()))
+                .collect());
+        }
+        return Ok(match model.white_point {
+            WhitePoint::A => model_colors::<A>(&model),
+            WhitePoint::D50 => model_colors::<D50>(&model),
+            WhitePoint::D55 => model_colors::<D55>(&model),
+            WhitePoint::D65 => model_colors::<D65>(&model),
+            WhitePoint::D75 => model_colors::<D75>(&model),
+            WhitePoint::E => model_colors::<E>(&model),
+        });
+    }
+
+    let img = image::open(path)?.into_rgba8();
+    let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+    let mut pixels = Vec::with_capacity(img_vec.len());
+    lab_cache.extend(img_vec.iter().map(|x| x.color), &mut pixels);
+
+    let config = KmeansConfig {
+        converge: factor
+            .unwrap_or_else(|| default_converge::<Lab<D65, f32>>(config.k, pixels.len())),
+        ..*config
+    };
+    let result = get_kmeans_best(&config, &pixels, None);
+    let mut res = Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
+    res.sort_unstable_by(|a, b| b.percentage.total_cmp(&a.percentage));
+
+    Ok(res.into_iter().map(|x| x.centroid).collect())
+}
+
+/// Convert the `Lab` centroids of a model with the white point `Wp` to D65.
+fn model_colors<Wp: palette::white_point::WhitePoint<f32>>(model: &Model) -> Vec<Lab<D65, f32>> {
+    model
+        .centroids
+        .iter()
+        .map(|&[l, a, b]| Lab::from_color(lab_to_linear(Lab::<Wp, f32>::new(l, a, b))))
+        .collect()
+}
@@ -0,0 +1,44 @@
+use std::ops::{Add, Mul, Sub};
+
+use kmeans_colors::{Axes, CentroidTree};
+
+/// Map pixels to the nearest color with Floyd-Steinberg error diffusion,
+/// returning the index of each pixel's color.
+///
+/// `pixels` must be a full image in row-major order, `width` pixels wide. The
+/// error of each pixel is spread to its unvisited neighbors in the color space
+/// of `C`.
+pub fn dither_indices<C>(pixels: &[C], colors: &[C], width: usize) -> Vec<u32>
+where
+    C: Axes + Copy + Add<Output = C> + Sub<Output = C> + Mul<f32, Output = C>,
+{
+    let tree = CentroidTree::new(colors);
+    let mut buf = pixels.to_vec();
+    let mut indices = Vec::with_capacity(pixels.len());
+    let spread = |buf: &mut [C], i: usize, error: C, weight: f32| {
+        if let Some(x) = buf.get_mut(i) {
+            *x = *x + error * weight;
+        }
+    };
+
+    for i in 0..buf.len() {
+        let pixel = buf[i];
+        let nearest = tree.nearest(&pixel);
+        indices.push(nearest as u32);
+
+        let error = pixel - colors[nearest];
+        let x = i % width;
+        if x + 1 < width {
+            spread(&mut buf, i + 1, error, 7.0 / 16.0);
+        }
+        if x > 0 {
+            spread(&mut buf, i + width - 1, error, 3.0 / 16.0);
+        }
+        spread(&mut buf, i + width, error, 5.0 / 16.0);
+        if x + 1 < width {
+            spread(&mut buf, i + width + 1, error, 1.0 / 16.0);
+        }
+    }
+
+    indices
+}
@@ -0,0 +1,114 @@
+use palette::cast::{AsComponents, ComponentsAs};
+use palette::{white_point::D65, Lab, Srgb, Srgba};
+
+use crate::args::Command;
+use crate::err::CliError;
+use crate::filename::create_filename;
+use crate::utils::{lab_to_linear, parse_color, save_image};
+use kmeans_colors::{get_kmeans_hamerly, interpolate_colors, ConversionCache, Kmeans, Sort};
+
+/// Number of steps in the color ramp that lightness is mapped onto.
+const RAMP_STEPS: usize = 256;
+
+/// Map the lightness of each pixel onto a ramp through 2 or 3 colors.
+pub fn duotone(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Duotone {
+        input,
+        colors,
+        tones,
+        max_iter,
+        factor,
+        runs,
+        seed,
+        interpolation,
+        verbose,
+        output,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    let seed = seed.unwrap_or(0);
+    let tones = tones.clamp(2, 3);
+
+    // Supplied colors are used for every image
+    let supplied: Vec<Srgb> = colors
+        .iter()
+        .map(|c| parse_color(c.trim_start_matches('#')).map(|x| x.into_format()))
+        .collect::<Result<_, CliError>>()?;
+
+    // Cached results of Srgb<u8> -> Lab conversions; not cleared between runs
+    let mut lab_cache = ConversionCache::new();
+    // Vec of pixels converted to Lab; cleared and reused between runs
+    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+
+    for file in &input {
+        if input.len() > 1 {
+            println!("{}", &file.to_string_lossy());
+        }
+
+        let img = image::open(file)?.into_rgba8();
+        let (imgx, imgy) = img.dimensions();
+        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+
+        lab_pixels.clear();
+        lab_cache.extend(img_vec.iter().map(|x| x.color), &mut lab_pixels);
+
+        let ramp = if supplied.is_empty() {
+            // Iterate over amount of runs keeping best results
+            let mut result = Kmeans::new();
+            for i in 0..runs {
+                let run_result = get_kmeans_hamerly(
+                    tones as usize,
+                    max_iter,
+                    factor,
+                    verbose,
+                    &lab_pixels,
+                    seed + i as u64,
+                );
+                result.keep_best(run_result, seed + i as u64);
+            }
+
+            // Colors are sorted from darkest to lightest
+            Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices)
+                .iter()
+                .map(|x| Srgb::from_linear(lab_to_linear(x.centroid)))
+                .collect()
+        } else {
+            supplied.clone()
+        };
+
+        let ramp_u8: Vec<Srgb<u8>> = ramp.iter().map(|x| x.into_format()).collect();
+        println!(
+            "{}",
+            ramp_u8
+                .iter()
+                .map(|x| format!("{x:x}"))
+                .collect::<Vec<String>>()
+                .join(",")
+        );
+
+        // Look up each pixel's lightness in the interpolated ramp
+        let steps: Vec<Srgb<u8>> = interpolate_colors(&ramp, RAMP_STEPS, interpolation.into())
+            .into_iter()
+            .map(|x| x.into_format())
+            .collect();
+        let rgb: Vec<Srgb<u8>> = lab_pixels
+            .iter()
+            .map(|x| {
+                let t = (x.l / 100.0).clamp(0.0, 1.0);
+                steps[(t * (RAMP_STEPS - 1) as f32).round() as usize]
+            })
+            .collect();
+
+        save_image(
+            rgb.as_components(),
+            imgx,
+            imgy,
+            &create_filename(&input, &output, "png", None, file)?,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,9 +1,33 @@
+/// Exit code when one or more inputs failed, or for any other error.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code when there were no input files to process.
+pub const EXIT_NO_INPUT: i32 = 3;
+
 #[derive(Debug)]
 pub enum CliError {
     File(std::io::Error),
     Parse(std::num::ParseIntError),
     Time(std::time::SystemTimeError),
     InvalidHex,
+    /// An error while processing an input file.
+    Input(std::path::PathBuf, Box<dyn std::error::Error>),
+    /// Invalid or conflicting arguments.
+    Usage(String),
+    /// No input files were given.
+    NoInput,
+}
+
+impl CliError {
+    /// Process exit code for an error returned from the command.
+    pub fn exit_code(err: &(dyn std::error::Error + 'static)) -> i32 {
+        match err.downcast_ref::<CliError>() {
+            Some(CliError::Usage(_)) => EXIT_USAGE,
+            Some(CliError::NoInput) => EXIT_NO_INPUT,
+            _ => EXIT_FAILURE,
+        }
+    }
 }
 
 impl From<std::io::Error> for CliError {
@@ -31,6 +55,9 @@ impl std::fmt::Display for CliError {
             CliError::Parse(err) => write!(f, "{err}"),
             CliError::Time(err) => write!(f, "{err}"),
             CliError::InvalidHex => write!(f, "Invalid hex color, must be 3 or 6 digts"),
+            CliError::Input(path, err) => write!(f, "{}: {err}", path.to_string_lossy()),
+            CliError::Usage(msg) => write!(f, "{msg}"),
+            CliError::NoInput => write!(f, "No input files specified."),
         }
     }
 }
@@ -42,6 +69,8 @@ impl std::error::Error for CliError {
             CliError::Parse(err) => Some(err),
             CliError::Time(err) => Some(err),
             CliError::InvalidHex => None,
+            CliError::Input(_, err) => Some(err.as_ref()),
+            CliError::Usage(_) | CliError::NoInput => None,
         }
     }
 }
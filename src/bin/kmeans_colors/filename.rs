@@ -9,7 +9,7 @@ pub fn create_filename(
     input: &[PathBuf],
     output: &Option<PathBuf>,
     extension: &str,
-    k: Option<u8>,
+    k: Option<u32>,
     file: &Path,
 ) -> Result<PathBuf, CliError> {
     let title = if input.len() == 1 {
@@ -62,10 +62,10 @@ pub fn create_filename_palette(
     input: &[PathBuf],
     output: &Option<PathBuf>,
     rgb: bool,
-    k: Option<u8>,
+    k: Option<u32>,
     file: &Path,
+    extension: &str,
 ) -> Result<PathBuf, CliError> {
-    let extension = "png";
     let title = if input.len() == 1 {
         match output {
             Some(x) => {
@@ -111,8 +111,24 @@ pub fn create_filename_palette(
     Ok(title)
 }
 
+/// Creates a `PathBuf` to save the report of all input files.
+pub fn create_filename_report(
+    output: &Option<PathBuf>,
+    extension: &str,
+) -> Result<PathBuf, CliError> {
+    let mut temp = match output {
+        Some(x) => x.clone(),
+        None => PathBuf::from(generate_filename(Path::new("report"), None)?),
+    };
+    if temp.extension().is_none() {
+        temp.set_extension(extension);
+    }
+
+    Ok(temp)
+}
+
 /// Appends a timestamp to an input filename to be used as output filename.
-fn generate_filename(path: &Path, k: Option<u8>) -> Result<String, CliError> {
+fn generate_filename(path: &Path, k: Option<u32>) -> Result<String, CliError> {
     let filename = path.file_stem().unwrap().to_str().unwrap().to_string();
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
     let secs = now.as_secs();
@@ -124,7 +140,7 @@ fn generate_filename(path: &Path, k: Option<u8>) -> Result<String, CliError> {
 }
 
 /// Appends a timestamp to an input filename to be used as a palette filename.
-fn generate_filename_palette(path: &Path, k: u8, rgb: bool) -> Result<String, CliError> {
+fn generate_filename_palette(path: &Path, k: u32, rgb: bool) -> Result<String, CliError> {
     let filename = path.file_stem().unwrap().to_str().unwrap().to_string();
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
     let secs = now.as_secs();
@@ -1,17 +1,44 @@
-use fxhash::FxHashMap;
+use std::path::Path;
+
 use palette::cast::{AsComponents, ComponentsAs};
-use palette::{white_point::D65, FromColor, IntoColor, Lab, Srgb, Srgba};
+use palette::white_point::{A, D50, D55, D65, D75, E};
+use palette::{FromColor, Lab, Oklch, Srgb, Srgba};
 
-use crate::args::Command;
+use crate::args::{Command, SortLightness, Unmatched, WhitePoint};
+use crate::dither::dither_indices;
 use crate::err::CliError;
 use crate::filename::create_filename;
-use crate::utils::{cached_srgba_to_lab, parse_color, print_colors, save_image, save_image_alpha};
-use kmeans_colors::{get_kmeans, get_kmeans_hamerly, Calculate, Kmeans, MapColor, Sort};
+use crate::utils::{
+    lab_data_to_srgb, lab_to_linear, order_colors, parse_color, print_colors, print_delta_e_stats,
+    save_image, save_image_alpha, save_indexed_png, PrintFormat,
+};
+use kmeans_colors::{
+    default_converge, get_kmeans, get_kmeans_hamerly, Calculate, CentroidTree, ConversionCache,
+    ConvertSrgb, Kmeans, MapColor, Sort,
+};
 
 /// Find the image pixels which closest match the supplied colors and save that
 /// image as output.
-pub fn find_colors(
-    Command::Find {
+pub fn find_colors(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let white_point = match &command {
+        Command::Find { white_point, .. } => *white_point,
+        _ => unreachable!(),
+    };
+    match white_point {
+        WhitePoint::A => find_colors_with::<A>(command),
+        WhitePoint::D50 => find_colors_with::<D50>(command),
+        WhitePoint::D55 => find_colors_with::<D55>(command),
+        WhitePoint::D65 => find_colors_with::<D65>(command),
+        WhitePoint::D75 => find_colors_with::<D75>(command),
+        WhitePoint::E => find_colors_with::<E>(command),
+    }
+}
+
+/// Runs the `find` subcommand with `Lab` colors using the white point `Wp`.
+fn find_colors_with<Wp: palette::white_point::WhitePoint<f32>>(
+    command: Command,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Find {
         input,
         colors,
         replace,
@@ -19,35 +46,57 @@ pub fn find_colors(
         factor,
         runs,
         percentage,
+        pct_format,
+        delimiter,
+        cmyk,
+        order,
+        delta_e,
+        max_distance,
+        unmatched,
+        dither,
         rgb,
         verbose,
         output,
         seed,
         transparent,
-    }: Command,
-) -> Result<(), Box<dyn std::error::Error>> {
+        ..
+    } = command
+    else {
+        unreachable!()
+    };
+
     // Print filename if multiple files and percentage is set
-    let display_filename = (input.len() > 1) && (percentage);
-    let converge = factor.unwrap_or(if !rgb { 5.0 } else { 0.0025 });
+    let display_filename =
+        (input.len() > 1) && (percentage || cmyk || delta_e || max_distance.is_some());
 
     let seed = seed.unwrap_or(0);
 
+    if dither && (replace || max_distance.is_some() || transparent) {
+        return Err(CliError::Usage(
+            "--dither can't be used with --replace, --max-distance, or --transparent".into(),
+        )
+        .into());
+    }
+    if dither && colors.len() > 256 {
+        return Err(CliError::Usage("--dither supports at most 256 colors".into()).into());
+    }
+    // The colors as supplied, used as the palette of dithered images
+    let palette: Vec<Srgb<u8>> = colors
+        .iter()
+        .map(|c| parse_color(c.trim_start_matches('#')))
+        .collect::<Result<_, CliError>>()?;
+
     // Cached results of Srgb<u8> -> Lab conversions; not cleared between runs
-    let mut lab_cache = FxHashMap::default();
+    let mut lab_cache = ConversionCache::new();
     // Vec of pixels converted to Lab; cleared and reused between runs
-    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+    let mut lab_pixels: Vec<Lab<Wp, f32>> = Vec::new();
     // Vec of pixels converted to Srgb<f32>; cleared and reused between runs
     let mut rgb_pixels: Vec<Srgb<f32>> = Vec::new();
 
     // Default to Lab colors
     if !rgb {
         // Initialize user centroids
-        let centroids: Vec<Lab<D65, f32>> = colors
-            .iter()
-            .map(|c| {
-                parse_color(c.trim_start_matches('#')).map(|c| c.into_linear::<f32>().into_color())
-            })
-            .collect::<Result<_, CliError>>()?;
+        let centroids: Vec<Lab<Wp, f32>> = palette.iter().map(|&x| Lab::from_srgb(x)).collect();
 
         for file in &input {
             if display_filename {
@@ -61,46 +110,79 @@ pub fn find_colors(
             lab_pixels.clear();
 
             if !transparent {
-                cached_srgba_to_lab(img_vec.iter(), &mut lab_cache, &mut lab_pixels);
+                lab_cache.extend(img_vec.iter().map(|x| x.color), &mut lab_pixels);
             } else {
-                cached_srgba_to_lab(
-                    img_vec.iter().filter(|x: &&Srgba<u8>| x.alpha == 255),
-                    &mut lab_cache,
+                lab_cache.extend(
+                    img_vec
+                        .iter()
+                        .filter(|x: &&Srgba<u8>| x.alpha == 255)
+                        .map(|x| x.color),
                     &mut lab_pixels,
                 );
             }
 
             if !replace {
                 let mut indices = Vec::with_capacity(img_vec.len());
+                let far =
+                    max_distance.map(|dist| far_pixels(&mut lab_cache, img_vec, &centroids, dist));
+
+                if dither {
+                    indices = dither_indices(&lab_pixels, &centroids, imgx as usize);
+                } else if centroids.len() > 256 {
+                    // Comparing every pixel with every color of large palettes
+                    // is slow, a k-d tree finds the same nearest colors
+                    CentroidTree::new(&centroids).get_closest_centroid(&lab_pixels, &mut indices);
+                } else {
+                    // We only need to do one pass of getting the closest colors to
+                    // the custom centroids
+                    Lab::<Wp, f32>::get_closest_centroid(&lab_pixels, &centroids, &mut indices);
+                }
 
-                // We only need to do one pass of getting the closest colors to the
-                // custom centroids
-                Lab::<D65, f32>::get_closest_centroid(&lab_pixels, &centroids, &mut indices);
-
-                if percentage {
-                    let res = Lab::<D65, f32>::sort_indexed_colors(&centroids, &indices);
-                    print_colors(percentage, &res)?;
+                if percentage || cmyk || delta_e {
+                    let mut res = lab_data_to_srgb(&Lab::<Wp, f32>::sort_indexed_colors(
+                        &centroids, &indices,
+                    ));
+                    order_colors(&mut res, order, SortLightness::Lab, Oklch::from_color);
+                    let format = PrintFormat {
+                        pct_format,
+                        delimiter: &delimiter,
+                        pixels: indices.len(),
+                    };
+                    print_colors(percentage, cmyk, format, &res)?;
+                    if delta_e {
+                        print_delta_e_stats(
+                            &res.iter().map(|x| x.index as usize).collect::<Vec<_>>(),
+                            lab_pixels.iter().zip(&indices).map(|(x, &i)| {
+                                let i = i as usize;
+                                (i, Lab::difference(x, &centroids[i]).sqrt())
+                            }),
+                        )?;
+                    }
                 }
 
-                if !transparent {
+                if dither {
+                    let title = create_filename(&input, &output, "png", None, file)?;
+                    save_indexed_png(&indices, &palette, imgx, imgy, &title)?;
+                } else if !transparent {
                     let rgb_centroids = &centroids
                         .iter()
-                        .map(|&x| Srgb::from_linear(x.into_color()))
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                         .collect::<Vec<Srgb<u8>>>();
                     let lab: Vec<Srgb<u8>> =
                         Srgb::map_indices_to_centroids(rgb_centroids, &indices);
-
-                    save_image(
-                        lab.as_components(),
-                        imgx,
-                        imgy,
-                        &create_filename(&input, &output, "png", None, file)?,
-                        false,
-                    )?;
+                    let title = create_filename(&input, &output, "png", None, file)?;
+
+                    if let Some(far) = &far {
+                        let mut rgba: Vec<Srgba<u8>> = lab.into_iter().map(Srgba::from).collect();
+                        mark_unmatched(&mut rgba, img_vec, far, unmatched, transparent);
+                        save_rgba(&rgba, unmatched, imgx, imgy, &title)?;
+                    } else {
+                        save_image(lab.as_components(), imgx, imgy, &title, false)?;
+                    }
                 } else {
                     let rgb_centroids = &centroids
                         .iter()
-                        .map(|&x| Srgb::from_linear(x.into_color()))
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                         .collect::<Vec<Srgb>>();
 
                     let mut indices = Vec::with_capacity(img_vec.len());
@@ -117,17 +199,21 @@ pub fn find_colors(
                         .map(|x| Srgba::from(*x).into_format())
                         .collect::<Vec<Srgba<u8>>>();
 
-                    let rgba: Vec<Srgba<u8>> = Srgba::map_indices_to_centroids(centroids, &indices)
-                        .iter()
-                        .zip(img_vec)
-                        .map(|(x, orig)| {
-                            if orig.alpha == 255 {
-                                *x
-                            } else {
-                                Srgba::new(0u8, 0, 0, 0)
-                            }
-                        })
-                        .collect();
+                    let mut rgba: Vec<Srgba<u8>> =
+                        Srgba::map_indices_to_centroids(centroids, &indices)
+                            .iter()
+                            .zip(img_vec)
+                            .map(|(x, orig)| {
+                                if orig.alpha == 255 {
+                                    *x
+                                } else {
+                                    Srgba::new(0u8, 0, 0, 0)
+                                }
+                            })
+                            .collect();
+                    if let Some(far) = &far {
+                        mark_unmatched(&mut rgba, img_vec, far, unmatched, transparent);
+                    }
 
                     save_image_alpha(
                         rgba.as_components(),
@@ -140,6 +226,8 @@ pub fn find_colors(
                 // Replace the k-means colors case
                 let mut result = Kmeans::new();
                 let k = centroids.len();
+                let converge =
+                    factor.unwrap_or_else(|| default_converge::<Lab<Wp, f32>>(k, img_vec.len()));
                 if k > 1 {
                     for i in 0..runs {
                         let run_result = get_kmeans_hamerly(
@@ -150,9 +238,7 @@ pub fn find_colors(
                             &lab_pixels,
                             seed + i as u64,
                         );
-                        if run_result.score < result.score {
-                            result = run_result;
-                        }
+                        result.keep_best(run_result, seed + i as u64);
                     }
                 } else {
                     for i in 0..runs {
@@ -164,9 +250,7 @@ pub fn find_colors(
                             &lab_pixels,
                             seed + i as u64,
                         );
-                        if run_result.score < result.score {
-                            result = run_result;
-                        }
+                        result.keep_best(run_result, seed + i as u64);
                     }
                 }
 
@@ -174,28 +258,36 @@ pub fn find_colors(
                 let cloned_res = result.centroids.clone();
 
                 // We want to sort the user centroids based on the kmeans colors
-                // sorted by luminosity using the u8 returned in `sorted`. This
-                // corresponds to the index of the colors from darkest to lightest.
-                // We replace the colors in `sorted` with our centroids for printing
-                // purposes.
+                // in `--order` using the u8 returned in `sorted`. This
+                // corresponds to the index of the kmeans color each user color
+                // replaces. We replace the colors in `sorted` with our centroids
+                // for printing purposes.
                 let mut res =
-                    Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
+                    Lab::<Wp, f32>::sort_indexed_colors(&result.centroids, &result.indices);
+                order_colors(&mut res, order, SortLightness::Lab, |x| {
+                    Oklch::from_color(lab_to_linear(x))
+                });
                 res.iter_mut()
                     .zip(&centroids)
                     .for_each(|(s, c)| s.centroid = *c);
 
-                if percentage {
-                    print_colors(percentage, &res)?;
+                if percentage || cmyk {
+                    let format = PrintFormat {
+                        pct_format,
+                        delimiter: &delimiter,
+                        pixels: result.indices.len(),
+                    };
+                    print_colors(percentage, cmyk, format, &lab_data_to_srgb(&res))?;
                 }
 
                 // Sorting the centroids now
-                res.sort_unstable_by(|a, b| (a.index).cmp(&b.index));
-                let sorted: Vec<Lab<D65, f32>> = res.iter().map(|x| x.centroid).collect();
+                res.sort_unstable_by_key(|a| a.index);
+                let sorted: Vec<Lab<Wp, f32>> = res.iter().map(|x| x.centroid).collect();
 
                 if !transparent {
                     let rgb_centroids = &sorted
                         .iter()
-                        .map(|&x| Srgb::from_linear(x.into_color()))
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                         .collect::<Vec<Srgb<u8>>>();
                     let rgb: Vec<Srgb<u8>> =
                         Srgb::map_indices_to_centroids(rgb_centroids, &result.indices);
@@ -209,7 +301,7 @@ pub fn find_colors(
                 } else {
                     let rgb_centroids = &sorted
                         .iter()
-                        .map(|&x| Srgb::from_linear(x.into_color()))
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                         .collect::<Vec<Srgb>>();
 
                     let mut indices = Vec::with_capacity(img_vec.len());
@@ -221,7 +313,7 @@ pub fn find_colors(
                     );
                     let temp_centroids = cloned_res
                         .iter()
-                        .map(|&x| Srgb::from_linear(x.into_color()))
+                        .map(|&x| Srgb::from_linear(lab_to_linear(x)))
                         .collect::<Vec<Srgb>>();
                     Srgb::get_closest_centroid(&rgb_pixels, &temp_centroids, &mut indices);
 
@@ -255,10 +347,12 @@ pub fn find_colors(
     // Rgb case
     } else {
         // Initialize user centroids
-        let mut centroids: Vec<Srgb> = Vec::with_capacity(colors.len());
-        for c in colors {
-            centroids.push((parse_color(c.trim_start_matches('#'))?).into_format());
-        }
+        let centroids: Vec<Srgb> = palette.iter().map(|x| x.into_format()).collect();
+        // Colors in `Lab` for finding unmatched pixels
+        let lab_colors: Vec<Lab<Wp, f32>> = centroids
+            .iter()
+            .map(|x| Lab::from_srgb(x.into_format()))
+            .collect();
 
         for file in &input {
             if display_filename {
@@ -287,31 +381,63 @@ pub fn find_colors(
 
             if !replace {
                 let mut indices = Vec::with_capacity(img_vec.len());
+                let far =
+                    max_distance.map(|dist| far_pixels(&mut lab_cache, img_vec, &lab_colors, dist));
+
+                if dither {
+                    indices = dither_indices(&rgb_pixels, &centroids, imgx as usize);
+                } else if centroids.len() > 256 {
+                    // Comparing every pixel with every color of large palettes
+                    // is slow, a k-d tree finds the same nearest colors
+                    CentroidTree::new(&centroids).get_closest_centroid(&rgb_pixels, &mut indices);
+                } else {
+                    // We only need to do one pass of getting the closest colors to
+                    // the custom centroids
+                    Srgb::get_closest_centroid(&rgb_pixels, &centroids, &mut indices);
+                }
 
-                // We only need to do one pass of getting the closest colors to the
-                // custom centroids
-                Srgb::get_closest_centroid(&rgb_pixels, &centroids, &mut indices);
-
-                if percentage {
-                    let res = Srgb::sort_indexed_colors(&centroids, &indices);
-                    print_colors(percentage, &res)?;
+                if percentage || cmyk || delta_e {
+                    let mut res = Srgb::sort_indexed_colors(&centroids, &indices);
+                    order_colors(&mut res, order, SortLightness::Lab, Oklch::from_color);
+                    let format = PrintFormat {
+                        pct_format,
+                        delimiter: &delimiter,
+                        pixels: indices.len(),
+                    };
+                    print_colors(percentage, cmyk, format, &res)?;
+                    if delta_e {
+                        let lab_centroids: Vec<Lab<D65, f32>> =
+                            centroids.iter().map(|&x| Lab::from_color(x)).collect();
+                        print_delta_e_stats(
+                            &res.iter().map(|x| x.index as usize).collect::<Vec<_>>(),
+                            rgb_pixels.iter().zip(&indices).map(|(&x, &i)| {
+                                let i = i as usize;
+                                let lab: Lab<D65, f32> = Lab::from_color(x);
+                                (i, Lab::difference(&lab, &lab_centroids[i]).sqrt())
+                            }),
+                        )?;
+                    }
                 }
 
-                if !transparent {
+                if dither {
+                    let title = create_filename(&input, &output, "png", None, file)?;
+                    save_indexed_png(&indices, &palette, imgx, imgy, &title)?;
+                } else if !transparent {
                     let rgb_centroids = &centroids
                         .iter()
                         .map(|x| x.into_format())
                         .collect::<Vec<Srgb<u8>>>();
                     let rgb: Vec<Srgb<u8>> =
                         Srgb::map_indices_to_centroids(rgb_centroids, &indices);
-
-                    save_image(
-                        rgb.as_components(),
-                        imgx,
-                        imgy,
-                        &create_filename(&input, &output, "png", None, file)?,
-                        false,
-                    )?;
+                    let title = create_filename(&input, &output, "png", None, file)?;
+
+                    if let Some(far) = &far {
+                        let mut rgba: Vec<Srgba<u8>> = rgb.into_iter().map(Srgba::from).collect();
+                        mark_unmatched(&mut rgba, img_vec, far, unmatched, transparent);
+                        save_rgba(&rgba, unmatched, imgx, imgy, &title)?;
+                    } else {
+                        save_image(rgb.as_components(), imgx, imgy, &title, false)?;
+                    }
                 } else {
                     let rgb_centroids = &centroids
                         .iter()
@@ -332,17 +458,21 @@ pub fn find_colors(
                         .map(|x| Srgba::from(*x).into_format())
                         .collect::<Vec<Srgba<u8>>>();
 
-                    let rgb: Vec<Srgba<u8>> = Srgba::map_indices_to_centroids(centroids, &indices)
-                        .iter()
-                        .zip(img_vec)
-                        .map(|(x, orig)| {
-                            if orig.alpha == 255 {
-                                *x
-                            } else {
-                                Srgba::new(0u8, 0, 0, 0)
-                            }
-                        })
-                        .collect();
+                    let mut rgb: Vec<Srgba<u8>> =
+                        Srgba::map_indices_to_centroids(centroids, &indices)
+                            .iter()
+                            .zip(img_vec)
+                            .map(|(x, orig)| {
+                                if orig.alpha == 255 {
+                                    *x
+                                } else {
+                                    Srgba::new(0u8, 0, 0, 0)
+                                }
+                            })
+                            .collect();
+                    if let Some(far) = &far {
+                        mark_unmatched(&mut rgb, img_vec, far, unmatched, transparent);
+                    }
 
                     save_image_alpha(
                         rgb.as_components(),
@@ -355,6 +485,7 @@ pub fn find_colors(
                 // Replace the k-means colors case
                 let mut result = Kmeans::new();
                 let k = centroids.len();
+                let converge = factor.unwrap_or_else(|| default_converge::<Srgb>(k, img_vec.len()));
                 if k > 1 {
                     for i in 0..runs {
                         let run_result = get_kmeans_hamerly(
@@ -365,9 +496,7 @@ pub fn find_colors(
                             &rgb_pixels,
                             seed + i as u64,
                         );
-                        if run_result.score < result.score {
-                            result = run_result;
-                        }
+                        result.keep_best(run_result, seed + i as u64);
                     }
                 } else {
                     for i in 0..runs {
@@ -379,30 +508,34 @@ pub fn find_colors(
                             &rgb_pixels,
                             seed + i as u64,
                         );
-                        if run_result.score < result.score {
-                            result = run_result;
-                        }
+                        result.keep_best(run_result, seed + i as u64);
                     }
                 }
 
                 let cloned_res = result.centroids.clone();
 
                 // We want to sort the user centroids based on the kmeans colors
-                // sorted by luminosity using the u8 returned in `sorted`. This
-                // corresponds to the index of the colors from darkest to lightest.
-                // We replace the colors in `sorted` with our centroids for printing
-                // purposes.
+                // in `--order` using the u8 returned in `sorted`. This
+                // corresponds to the index of the kmeans color each user color
+                // replaces. We replace the colors in `sorted` with our centroids
+                // for printing purposes.
                 let mut res = Srgb::sort_indexed_colors(&result.centroids, &result.indices);
+                order_colors(&mut res, order, SortLightness::Lab, Oklch::from_color);
                 res.iter_mut()
                     .zip(&centroids)
                     .for_each(|(s, c)| s.centroid = *c);
 
-                if percentage {
-                    print_colors(percentage, &res)?;
+                if percentage || cmyk {
+                    let format = PrintFormat {
+                        pct_format,
+                        delimiter: &delimiter,
+                        pixels: result.indices.len(),
+                    };
+                    print_colors(percentage, cmyk, format, &res)?;
                 }
 
                 // Sorting the centroids now
-                res.sort_unstable_by(|a, b| (a.index).cmp(&b.index));
+                res.sort_unstable_by_key(|a| a.index);
                 let sorted: Vec<Srgb> = res.iter().map(|x| x.centroid).collect();
 
                 if !transparent {
@@ -465,3 +598,69 @@ pub fn find_colors(
 
     Ok(())
 }
+
+/// Returns whether each pixel has a delta E greater than `max_distance` from
+/// every color.
+fn far_pixels<Wp: palette::white_point::WhitePoint<f32>>(
+    cache: &mut ConversionCache<Lab<Wp, f32>>,
+    img_vec: &[Srgba<u8>],
+    colors: &[Lab<Wp, f32>],
+    max_distance: f32,
+) -> Vec<bool> {
+    // Compare squared distances to avoid a square root for every pixel
+    let max = max_distance * max_distance;
+    let tree = CentroidTree::new(colors);
+    img_vec
+        .iter()
+        .map(|x| {
+            let lab = cache.convert(x.color);
+            Lab::difference(&lab, &colors[tree.nearest(&lab)]) > max
+        })
+        .collect()
+}
+
+/// Draw the pixels that didn't match any color and print the fraction of
+/// unmatched pixels. Pixels with any transparency aren't counted when
+/// `transparent` is set.
+fn mark_unmatched(
+    rgba: &mut [Srgba<u8>],
+    img_vec: &[Srgba<u8>],
+    far: &[bool],
+    unmatched: Unmatched,
+    transparent: bool,
+) {
+    let (mut count, mut total) = (0usize, 0usize);
+    for ((pixel, orig), &far) in rgba.iter_mut().zip(img_vec).zip(far) {
+        if transparent && orig.alpha != 255 {
+            continue;
+        }
+        total += 1;
+        if far {
+            count += 1;
+            *pixel = match unmatched {
+                Unmatched::Keep => *orig,
+                Unmatched::Magenta => Srgba::new(255, 0, 255, 255),
+                Unmatched::Transparent => Srgba::new(0, 0, 0, 0),
+            };
+        }
+    }
+
+    println!("unmatched,{:0.4}", count as f32 / total.max(1) as f32);
+}
+
+/// Save an image with alpha if unmatched pixels are transparent, otherwise
+/// without.
+fn save_rgba(
+    rgba: &[Srgba<u8>],
+    unmatched: Unmatched,
+    imgx: u32,
+    imgy: u32,
+    title: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if unmatched == Unmatched::Transparent {
+        save_image_alpha(rgba.as_components(), imgx, imgy, title)
+    } else {
+        let rgb: Vec<Srgb<u8>> = rgba.iter().map(|x| x.color).collect();
+        save_image(rgb.as_components(), imgx, imgy, title, false)
+    }
+}
@@ -1,22 +1,50 @@
 #![warn(rust_2018_idioms, unsafe_code)]
 mod app;
 mod args;
+mod batch;
+mod bench;
+mod cache;
+mod diff;
+mod dither;
+mod duotone;
 mod err;
 mod filename;
 mod find;
+mod model;
+mod palette_file;
+mod posterize;
+mod report;
+mod svg;
+mod theme;
 mod utils;
+mod wb;
 
 fn main() {
     if let Err(e) = try_main() {
         eprintln!("kmeans_colors: {e}");
-        std::process::exit(1);
+        std::process::exit(err::CliError::exit_code(e.as_ref()));
     }
 }
 
 fn try_main() -> Result<(), Box<dyn std::error::Error>> {
-    let opt: args::Opt = structopt::StructOpt::from_args();
+    let opt = match args::parse_opt(std::env::args_os()) {
+        Ok(opt) => opt,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            std::process::exit(err::EXIT_USAGE);
+        }
+        // Help and version messages
+        Err(e) => e.exit(),
+    };
     match opt.cmd {
         Some(command @ args::Command::Find { .. }) => find::find_colors(command)?,
+        Some(command @ args::Command::Wb { .. }) => wb::white_balance(command)?,
+        Some(command @ args::Command::Duotone { .. }) => duotone::duotone(command)?,
+        Some(command @ args::Command::Posterize { .. }) => posterize::posterize(command)?,
+        Some(command @ args::Command::Batch { .. }) => batch::batch(command)?,
+        Some(command @ args::Command::Bench { .. }) => bench::bench(command)?,
+        Some(command @ args::Command::ThemeColor { .. }) => theme::theme_color(command)?,
+        Some(command @ args::Command::Diff { .. }) => diff::diff(command)?,
         _ => app::run(opt)?,
     }
 
@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::args::WhitePoint;
+
+/// Centroids of a previous run in the color space they were calculated in,
+/// saved as json to map other images to the same colors.
+///
+/// ```json
+/// {"space":"lab","white_point":"d65","centroids":[[32.1,1.5,-2.0],...]}
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+    /// Whether the centroids are `Srgb` instead of `Lab`.
+    pub rgb: bool,
+    /// White point of the `Lab` conversion.
+    pub white_point: WhitePoint,
+    /// Components of each centroid.
+    pub centroids: Vec<[f32; 3]>,
+}
+
+impl Model {
+    /// Read a model saved by `save`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let invalid = || format!("Invalid model file: {}", path.to_string_lossy());
+
+        let rgb = match string_field(&text, "space").ok_or_else(invalid)? {
+            "lab" => false,
+            "rgb" => true,
+            space => return Err(format!("Unsupported model space: {space}").into()),
+        };
+        let white_point = string_field(&text, "white_point")
+            .ok_or_else(invalid)?
+            .parse()?;
+
+        // Every number in the centroids array, grouped into colors
+        let start = text.find("\"centroids\"").ok_or_else(invalid)?;
+        let array = &text[start + "\"centroids\"".len()..];
+        let end = array.find("]]").map_or(array.len(), |x| x + 2);
+        let numbers = array[..end]
+            .split(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .filter(|x| !x.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<f32>, _>>()?;
+        if numbers.is_empty() || numbers.len() % 3 != 0 {
+            return Err(invalid().into());
+        }
+        let centroids = numbers.chunks(3).map(|x| [x[0], x[1], x[2]]).collect();
+
+        Ok(Model {
+            rgb,
+            white_point,
+            centroids,
+        })
+    }
+
+    /// Write the model as json.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut out = String::new();
+        write!(
+            &mut out,
+            "{{\"space\":\"{}\",\"white_point\":\"{}\",\"centroids\":[",
+            if self.rgb { "rgb" } else { "lab" },
+            format!("{:?}", self.white_point).to_ascii_lowercase()
+        )?;
+        for (i, [x, y, z]) in self.centroids.iter().enumerate() {
+            let sep = if i + 1 < self.centroids.len() {
+                ","
+            } else {
+                ""
+            };
+            write!(&mut out, "[{x},{y},{z}]{sep}")?;
+        }
+        writeln!(&mut out, "]}}")?;
+        std::fs::write(path, out)?;
+
+        Ok(())
+    }
+}
+
+/// Value of a string field of a flat json object.
+fn string_field<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let rest = &text[text.find(&format!("\"{name}\""))? + name.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    Some(&rest[..rest.find('"')?])
+}
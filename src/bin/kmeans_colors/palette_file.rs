@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use palette::Srgb;
+
+use crate::utils::save_palette;
+use kmeans_colors::{centroids_to_srgb, CentroidData};
+
+/// Layout of palette images, from the `--palette` options.
+#[derive(Clone, Copy, Debug)]
+pub struct SwatchOptions {
+    pub proportional: bool,
+    pub height: u32,
+    pub width: Option<u32>,
+    pub normalized: bool,
+}
+
+/// A file format the `--palette` can be saved in.
+pub trait PaletteWriter: Sync {
+    /// Names of the format accepted by `--palette-format`.
+    fn names(&self) -> &'static [&'static str];
+
+    /// Default file extension of the format.
+    fn extension(&self) -> &'static str;
+
+    /// Save the colors to a file, in order.
+    fn save(
+        &self,
+        res: &[CentroidData<Srgb>],
+        options: &SwatchOptions,
+        title: &Path,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Every palette format, looked up by name for `--palette-format`.
+pub const WRITERS: &[&dyn PaletteWriter] =
+    &[&Png, &ASEPRITE, &PAINT_NET, &JASC, &GIMP, &SVG, &JSON, &HEX];
+
+/// Image of the colors side by side, or any image format `save_image`
+/// supports if the output has another extension.
+struct Png;
+
+impl PaletteWriter for Png {
+    fn names(&self) -> &'static [&'static str] {
+        &["png"]
+    }
+
+    fn extension(&self) -> &'static str {
+        "png"
+    }
+
+    fn save(
+        &self,
+        res: &[CentroidData<Srgb>],
+        options: &SwatchOptions,
+        title: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        save_palette(
+            res,
+            options.proportional,
+            options.height,
+            options.width,
+            options.normalized,
+            title,
+        )
+    }
+}
+
+/// Encodes the colors as the bytes of a palette file.
+type Encode = fn(&[CentroidData<Srgb>], &SwatchOptions) -> Result<Vec<u8>, Box<dyn Error>>;
+
+/// A palette format encoded from the colors alone.
+struct FileFormat {
+    names: &'static [&'static str],
+    extension: &'static str,
+    encode: Encode,
+}
+
+impl PaletteWriter for FileFormat {
+    fn names(&self) -> &'static [&'static str] {
+        self.names
+    }
+
+    fn extension(&self) -> &'static str {
+        self.extension
+    }
+
+    fn save(
+        &self,
+        res: &[CentroidData<Srgb>],
+        options: &SwatchOptions,
+        title: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let bytes = (self.encode)(res, options)?;
+        let mut w = BufWriter::new(File::create(title)?);
+        w.write_all(&bytes)?;
+        w.flush()?;
+
+        Ok(())
+    }
+}
+
+const ASEPRITE: FileFormat = FileFormat {
+    names: &["aseprite", "ase"],
+    extension: "aseprite",
+    encode: |res, _| Ok(aseprite(&centroids_to_srgb(res))),
+};
+
+const PAINT_NET: FileFormat = FileFormat {
+    names: &["paintnet", "paint.net"],
+    extension: "txt",
+    encode: |res, _| Ok(paint_net(&centroids_to_srgb(res))?.into_bytes()),
+};
+
+const JASC: FileFormat = FileFormat {
+    names: &["jasc", "pal"],
+    extension: "pal",
+    encode: |res, _| Ok(jasc(&centroids_to_srgb(res))?.into_bytes()),
+};
+
+const GIMP: FileFormat = FileFormat {
+    names: &["gpl", "gimp"],
+    extension: "gpl",
+    encode: |res, _| Ok(gimp(&centroids_to_srgb(res))?.into_bytes()),
+};
+
+const SVG: FileFormat = FileFormat {
+    names: &["svg"],
+    extension: "svg",
+    encode: |res, options| Ok(swatches(res, options)?.into_bytes()),
+};
+
+const JSON: FileFormat = FileFormat {
+    names: &["json"],
+    extension: "json",
+    encode: |res, _| Ok(json(res)?.into_bytes()),
+};
+
+const HEX: FileFormat = FileFormat {
+    names: &["hex"],
+    extension: "hex",
+    encode: |res, _| Ok(hex(&centroids_to_srgb(res))?.into_bytes()),
+};
+
+/// Aseprite file with a single empty frame holding the colors in a palette
+/// chunk, which Aseprite and compatible editors can load as a palette.
+///
+/// See <https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md>.
+fn aseprite(colors: &[Srgb<u8>]) -> Vec<u8> {
+    let len = colors.len() as u32;
+
+    // Palette chunk: entry count, first and last index, reserved bytes, then
+    // flags and RGBA for each entry
+    let mut chunk = Vec::new();
+    chunk.extend_from_slice(&len.to_le_bytes());
+    chunk.extend_from_slice(&0u32.to_le_bytes());
+    chunk.extend_from_slice(&len.saturating_sub(1).to_le_bytes());
+    chunk.extend_from_slice(&[0; 8]);
+    for color in colors {
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(&[color.red, color.green, color.blue, 255]);
+    }
+
+    // Frame header: size, magic number, chunk count, duration, reserved bytes,
+    // and the new chunk count
+    let chunk_size = 6 + chunk.len() as u32;
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&(16 + chunk_size).to_le_bytes());
+    frame.extend_from_slice(&0xF1FAu16.to_le_bytes());
+    frame.extend_from_slice(&1u16.to_le_bytes());
+    frame.extend_from_slice(&100u16.to_le_bytes());
+    frame.extend_from_slice(&[0; 2]);
+    frame.extend_from_slice(&1u32.to_le_bytes());
+    frame.extend_from_slice(&chunk_size.to_le_bytes());
+    frame.extend_from_slice(&0x2019u16.to_le_bytes());
+    frame.extend_from_slice(&chunk);
+
+    // File header of 128 bytes for a 32-bit RGBA sprite with one pixel per
+    // color
+    let mut file = Vec::with_capacity(128 + frame.len());
+    file.extend_from_slice(&(128 + frame.len() as u32).to_le_bytes());
+    file.extend_from_slice(&0xA5E0u16.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&(len.max(1) as u16).to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&32u16.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&100u16.to_le_bytes());
+    file.extend_from_slice(&[0; 8]);
+    // Transparent index and reserved bytes
+    file.extend_from_slice(&[0; 4]);
+    file.extend_from_slice(&(len as u16).to_le_bytes());
+    // Pixel ratio of 1:1
+    file.extend_from_slice(&[1, 1]);
+    // Grid position and size
+    file.extend_from_slice(&0i16.to_le_bytes());
+    file.extend_from_slice(&0i16.to_le_bytes());
+    file.extend_from_slice(&16u16.to_le_bytes());
+    file.extend_from_slice(&16u16.to_le_bytes());
+    file.resize(128, 0);
+    file.extend_from_slice(&frame);
+
+    file
+}
+
+/// paint.net palette of `AARRGGBB` hex colors, one per line.
+fn paint_net(colors: &[Srgb<u8>]) -> Result<String, std::fmt::Error> {
+    let mut out = String::from(
+        "; paint.net Palette File\r\n; Colors are written as 8-digit hexadecimal numbers: aarrggbb\r\n",
+    );
+    for color in colors {
+        write!(out, "FF{:X}\r\n", color)?;
+    }
+    Ok(out)
+}
+
+/// JASC palette used by Paint Shop Pro, with decimal `r g b` colors.
+fn jasc(colors: &[Srgb<u8>]) -> Result<String, std::fmt::Error> {
+    let mut out = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+    for color in colors {
+        write!(out, "{} {} {}\r\n", color.red, color.green, color.blue)?;
+    }
+    Ok(out)
+}
+
+/// GIMP palette with decimal `r g b` colors named by their hex codes, which
+/// Inkscape and Krita also read.
+fn gimp(colors: &[Srgb<u8>]) -> Result<String, std::fmt::Error> {
+    let mut out = String::from("GIMP Palette\nName: kmeans_colors\nColumns: 0\n#\n");
+    for color in colors {
+        writeln!(
+            out,
+            "{:3} {:3} {:3}\t#{:x}",
+            color.red, color.green, color.blue, color
+        )?;
+    }
+    Ok(out)
+}
+
+/// SVG of the colors side by side, sized like the png palette.
+fn swatches(
+    res: &[CentroidData<Srgb>],
+    options: &SwatchOptions,
+) -> Result<String, std::fmt::Error> {
+    let len = res.len() as u32;
+    let width = options.width.map_or(options.height * len, |x| x.max(len));
+    let height = options.height;
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" shape-rendering=\"crispEdges\">"
+    )?;
+    let mut x = 0.0;
+    for (elem, color) in res.iter().zip(centroids_to_srgb(res)) {
+        let w = if options.proportional {
+            elem.percentage * width as f32
+        } else {
+            width as f32 / len as f32
+        };
+        writeln!(
+            out,
+            "<rect x=\"{x}\" width=\"{w}\" height=\"{height}\" fill=\"#{color:x}\"/>"
+        )?;
+        x += w;
+    }
+    writeln!(out, "</svg>")?;
+    Ok(out)
+}
+
+/// JSON array of the colors with their hex codes, `[r, g, b]` values, and
+/// percentages.
+fn json(res: &[CentroidData<Srgb>]) -> Result<String, std::fmt::Error> {
+    let mut out = String::from("[\n");
+    for (i, (elem, color)) in res.iter().zip(centroids_to_srgb(res)).enumerate() {
+        let comma = if i + 1 < res.len() { "," } else { "" };
+        writeln!(
+            out,
+            "    {{\"hex\": \"#{color:x}\", \"rgb\": [{}, {}, {}], \"percentage\": {:.4}}}{comma}",
+            color.red, color.green, color.blue, elem.percentage
+        )?;
+    }
+    out.push_str("]\n");
+    Ok(out)
+}
+
+/// Hex codes without `#`, one per line, as used by Lospec.
+fn hex(colors: &[Srgb<u8>]) -> Result<String, std::fmt::Error> {
+    let mut out = String::new();
+    for color in colors {
+        writeln!(out, "{color:x}")?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,52 @@
+use palette::cast::{AsComponents, ComponentsAs};
+use palette::{Srgb, Srgba, WithAlpha};
+
+use crate::args::Command;
+use crate::filename::create_filename;
+use crate::utils::{save_image, save_image_alpha};
+
+/// Reduce each channel of an image to evenly spaced levels.
+pub fn posterize(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Posterize {
+        input,
+        levels,
+        transparent,
+        output,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    // Lookup table from a channel value to its nearest level
+    let step = 255.0 / f32::from(levels.max(2) - 1);
+    let table: Vec<u8> = (0..=255u8)
+        .map(|x| ((f32::from(x) / step).round() * step).round().min(255.0) as u8)
+        .collect();
+    let posterize = |x: Srgb<u8>| {
+        Srgb::new(
+            table[x.red as usize],
+            table[x.green as usize],
+            table[x.blue as usize],
+        )
+    };
+
+    for file in &input {
+        let img = image::open(file)?.into_rgba8();
+        let (imgx, imgy) = img.dimensions();
+        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+        let title = create_filename(&input, &output, "png", Some(u32::from(levels)), file)?;
+
+        if transparent {
+            let rgba: Vec<Srgba<u8>> = img_vec
+                .iter()
+                .map(|x| posterize(x.color).with_alpha(x.alpha))
+                .collect();
+            save_image_alpha(rgba.as_components(), imgx, imgy, &title)?;
+        } else {
+            let rgb: Vec<Srgb<u8>> = img_vec.iter().map(|x| posterize(x.color)).collect();
+            save_image(rgb.as_components(), imgx, imgy, &title, false)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, RgbaImage};
+use palette::Srgb;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+
+/// Width and height of an A4 page in points.
+const PAGE: (f32, f32) = (595.0, 842.0);
+const MARGIN: f32 = 40.0;
+/// Longest side of a thumbnail in points.
+const THUMBNAIL: f32 = 140.0;
+/// Height of each row of the color table.
+const LINE: f32 = 12.0;
+/// Colors listed before the table is split into two columns.
+const COLUMN_ROWS: usize = 10;
+const COLUMN_WIDTH: f32 = 175.0;
+/// Characters of the file name shown before cutting off the beginning.
+const NAME_LENGTH: usize = 50;
+
+/// Summary of one input file for the report.
+pub struct ReportEntry {
+    file: PathBuf,
+    width: u32,
+    height: u32,
+    /// JPEG of the image scaled down for the thumbnail, and its dimensions.
+    thumbnail: (Vec<u8>, u32, u32),
+    colors: Vec<(Srgb<u8>, f32)>,
+}
+
+impl ReportEntry {
+    /// Summarize an image with its colors and their percentages, in the order
+    /// they are listed. Transparent pixels of the thumbnail are blended onto
+    /// white.
+    pub fn new(
+        file: &Path,
+        img: &RgbaImage,
+        colors: Vec<(Srgb<u8>, f32)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (width, height) = img.dimensions();
+        // Twice as many pixels as points to keep thumbnails sharp in print
+        let scale = (2.0 * THUMBNAIL / width.max(height) as f32).min(1.0);
+        let w = ((width as f32 * scale).round() as u32).max(1);
+        let h = ((height as f32 * scale).round() as u32).max(1);
+        let small = image::imageops::thumbnail(img, w, h);
+        let rgb: Vec<u8> = small
+            .pixels()
+            .flat_map(|p| {
+                let alpha = u16::from(p.0[3]);
+                [p.0[0], p.0[1], p.0[2]]
+                    .map(|x| ((u16::from(x) * alpha + 255 * (255 - alpha) + 127) / 255) as u8)
+            })
+            .collect();
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90).encode(&rgb, w, h, ColorType::Rgb8)?;
+
+        Ok(ReportEntry {
+            file: file.to_path_buf(),
+            width,
+            height,
+            thumbnail: (jpeg, w, h),
+            colors,
+        })
+    }
+
+    /// Size of the thumbnail in points.
+    fn thumbnail_size(&self) -> (f32, f32) {
+        let (_, w, h) = self.thumbnail;
+        let scale = THUMBNAIL / w.max(h) as f32;
+        (w as f32 * scale, h as f32 * scale)
+    }
+
+    /// Number of columns and rows of the color table, listing as many colors
+    /// as fit on a page.
+    fn table(&self) -> (usize, usize) {
+        let max_rows = ((PAGE.1 - 3.0 * MARGIN) / LINE) as usize - 4;
+        let columns = if self.colors.len() > COLUMN_ROWS {
+            2
+        } else {
+            1
+        };
+        let rows = self.colors.len().div_ceil(columns).min(max_rows);
+        (columns, rows)
+    }
+
+    /// Height of the entry on the page in points.
+    fn layout_height(&self) -> f32 {
+        let (_, rows) = self.table();
+        // File name and dimensions, then the table with a line for leftovers
+        let text = 36.0 + (rows + 1) as f32 * LINE;
+        // Thumbnail and palette strip
+        let thumbnail = self.thumbnail_size().1 + 18.0;
+        text.max(thumbnail)
+    }
+
+    /// Draw the entry below `top`, referencing the thumbnail as `image`.
+    fn draw(&self, content: &mut Content, top: f32, image: Name<'_>) {
+        let (tw, th) = self.thumbnail_size();
+        content
+            .save_state()
+            .transform([tw, 0.0, 0.0, th, MARGIN, top - th])
+            .x_object(image)
+            .restore_state();
+
+        // Palette strip under the thumbnail, proportional to the percentages
+        let strip_y = top - th - 18.0;
+        let mut x = MARGIN;
+        if let Some((last, colors)) = self.colors.split_last() {
+            for &(color, percentage) in colors {
+                let w = (percentage * tw).min(MARGIN + tw - x);
+                fill_rgb(content, color);
+                content.rect(x, strip_y, w, 12.0).fill_nonzero();
+                x += w;
+            }
+            fill_rgb(content, last.0);
+            content
+                .rect(x, strip_y, MARGIN + tw - x, 12.0)
+                .fill_nonzero();
+        }
+
+        let x0 = MARGIN + THUMBNAIL + 20.0;
+        // Keep the end of long paths, which fits the width of the column
+        let name = self.file.to_string_lossy();
+        let name = match name.char_indices().rev().nth(NAME_LENGTH) {
+            Some((i, _)) => format!("...{}", &name[i..]),
+            None => name.into_owned(),
+        };
+        let info = format!(
+            "{} x {} px, {} colors",
+            self.width,
+            self.height,
+            self.colors.len()
+        );
+        content.set_fill_gray(0.0);
+        text(content, Name(b"F2"), 11.0, x0, top - 11.0, &name);
+        text(content, Name(b"F1"), 9.0, x0, top - 25.0, &info);
+
+        let (columns, rows) = self.table();
+        for (i, &(color, percentage)) in self.colors.iter().take(columns * rows).enumerate() {
+            let x = x0 + (i / rows) as f32 * COLUMN_WIDTH;
+            let y = top - 36.0 - (i % rows + 1) as f32 * LINE;
+            fill_rgb(content, color);
+            content
+                .set_stroke_gray(0.6)
+                .set_line_width(0.5)
+                .rect(x, y - 1.0, 9.0, 9.0)
+                .fill_nonzero_and_stroke();
+            content.set_fill_gray(0.0);
+            let rgb = format!("rgb({}, {}, {})", color.red, color.green, color.blue);
+            let percentage = format!("{:.2}%", percentage * 100.0);
+            text(
+                content,
+                Name(b"F1"),
+                9.0,
+                x + 14.0,
+                y,
+                &format!("#{color:x}"),
+            );
+            text(content, Name(b"F1"), 9.0, x + 56.0, y, &rgb);
+            text(content, Name(b"F1"), 9.0, x + 138.0, y, &percentage);
+        }
+        let leftover = self.colors.len().saturating_sub(columns * rows);
+        if leftover > 0 {
+            let y = top - 36.0 - (rows + 1) as f32 * LINE;
+            text(
+                content,
+                Name(b"F1"),
+                9.0,
+                x0,
+                y,
+                &format!("and {leftover} more colors"),
+            );
+        }
+    }
+}
+
+/// Save a PDF contact sheet of the report entries, as many per A4 page as fit.
+pub fn save_report(entries: &[ReportEntry], title: &Path) -> Result<(), Box<dyn Error>> {
+    let catalog_id = Ref::new(1);
+    let tree_id = Ref::new(2);
+    let info_id = Ref::new(3);
+    let font_id = Ref::new(4);
+    let bold_id = Ref::new(5);
+    let mut next_id = Ref::new(6);
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(tree_id);
+    pdf.document_info(info_id)
+        .title(TextStr("Palette report"))
+        .creator(TextStr("kmeans_colors"));
+    pdf.type1_font(font_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(bold_id)
+        .base_font(Name(b"Helvetica-Bold"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    let image_ids: Vec<Ref> = entries
+        .iter()
+        .map(|entry| {
+            let id = next_id.bump();
+            let (data, w, h) = &entry.thumbnail;
+            let mut image = pdf.image_xobject(id, data);
+            image.filter(Filter::DctDecode);
+            image.width(*w as i32);
+            image.height(*h as i32);
+            image.color_space().device_rgb();
+            image.bits_per_component(8);
+            image.finish();
+            id
+        })
+        .collect();
+
+    // Lay out the entries, starting a page when the next one doesn't fit
+    let mut pages: Vec<Vec<(usize, f32)>> = vec![Vec::new()];
+    let mut top = PAGE.1 - MARGIN - 30.0;
+    for (i, entry) in entries.iter().enumerate() {
+        let height = entry.layout_height();
+        if top - height < 2.0 * MARGIN && !pages.last().unwrap().is_empty() {
+            pages.push(Vec::new());
+            top = PAGE.1 - MARGIN;
+        }
+        pages.last_mut().unwrap().push((i, top));
+        top -= height + 24.0;
+    }
+
+    let mut page_ids = Vec::with_capacity(pages.len());
+    for (number, page) in pages.iter().enumerate() {
+        let names: Vec<String> = page.iter().map(|(i, _)| format!("Im{i}")).collect();
+        let mut content = Content::new();
+        if number == 0 {
+            content.set_fill_gray(0.0);
+            text(
+                &mut content,
+                Name(b"F2"),
+                16.0,
+                MARGIN,
+                PAGE.1 - MARGIN - 12.0,
+                "Palette report",
+            );
+        }
+        for (&(i, top), name) in page.iter().zip(&names) {
+            entries[i].draw(&mut content, top, Name(name.as_bytes()));
+        }
+        content.set_fill_gray(0.4);
+        let footer = format!("Page {} of {}", number + 1, pages.len());
+        text(
+            &mut content,
+            Name(b"F1"),
+            8.0,
+            PAGE.0 / 2.0 - 20.0,
+            MARGIN / 2.0,
+            &footer,
+        );
+
+        let page_id = next_id.bump();
+        let content_id = next_id.bump();
+        pdf.stream(content_id, &content.finish());
+
+        let mut pdf_page = pdf.page(page_id);
+        pdf_page
+            .media_box(Rect::new(0.0, 0.0, PAGE.0, PAGE.1))
+            .parent(tree_id)
+            .contents(content_id);
+        let mut resources = pdf_page.resources();
+        resources
+            .fonts()
+            .pair(Name(b"F1"), font_id)
+            .pair(Name(b"F2"), bold_id);
+        let mut x_objects = resources.x_objects();
+        for (&(i, _), name) in page.iter().zip(&names) {
+            x_objects.pair(Name(name.as_bytes()), image_ids[i]);
+        }
+        x_objects.finish();
+        resources.finish();
+        pdf_page.finish();
+        page_ids.push(page_id);
+    }
+
+    pdf.pages(tree_id)
+        .count(page_ids.len() as i32)
+        .kids(page_ids);
+
+    std::fs::write(title, pdf.finish())?;
+
+    Ok(())
+}
+
+/// Set the fill color of the content.
+fn fill_rgb(content: &mut Content, color: Srgb<u8>) {
+    let color: Srgb = color.into_format();
+    content.set_fill_rgb(color.red, color.green, color.blue);
+}
+
+/// Show a line of text, replacing characters outside of ASCII since the
+/// standard fonts aren't embedded.
+fn text(content: &mut Content, font: Name<'_>, size: f32, x: f32, y: f32, text: &str) {
+    let bytes: Vec<u8> = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c as u8
+            } else {
+                b'?'
+            }
+        })
+        .collect();
+    content
+        .begin_text()
+        .set_font(font, size)
+        .next_line(x, y)
+        .show(Str(&bytes))
+        .end_text();
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use palette::Srgb;
+
+/// Save the regions of each color of a `width` by `height` image as filled
+/// SVG paths, tracing the pixel edges between colors. Pixels without an index
+/// are left empty.
+pub fn save_svg(
+    indices: &[Option<u32>],
+    width: u32,
+    height: u32,
+    colors: &[Srgb<u8>],
+    title: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut svg = String::new();
+    writeln!(
+        &mut svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" shape-rendering=\"crispEdges\">"
+    )?;
+    for (i, color) in colors.iter().enumerate() {
+        let path = trace(indices, width as usize, height as usize, i as u32);
+        if !path.is_empty() {
+            writeln!(&mut svg, "<path fill=\"#{color:x}\" d=\"{path}\"/>")?;
+        }
+    }
+    writeln!(&mut svg, "</svg>")?;
+
+    let mut w = BufWriter::new(File::create(title)?);
+    w.write_all(svg.as_bytes())?;
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Trace the outlines of the pixels with `index` into path data.
+///
+/// Every pixel edge between `index` and another index or the image border is
+/// an edge of the outline, directed clockwise around the pixel, so chaining
+/// the edges gives closed loops with holes wound the other way. Loops are
+/// filled with the default nonzero rule.
+fn trace(indices: &[Option<u32>], width: usize, height: usize, index: u32) -> String {
+    let at = |x: isize, y: isize| -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < width
+            && (y as usize) < height
+            && indices.get(y as usize * width + x as usize) == Some(&Some(index))
+    };
+
+    // Outgoing edges from each grid corner
+    let mut edges: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+    let mut add = |from: (usize, usize), to: (usize, usize)| {
+        edges.entry(from).or_default().push(to);
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            if !at(xi, yi) {
+                continue;
+            }
+            if !at(xi, yi - 1) {
+                add((x, y), (x + 1, y));
+            }
+            if !at(xi + 1, yi) {
+                add((x + 1, y), (x + 1, y + 1));
+            }
+            if !at(xi, yi + 1) {
+                add((x + 1, y + 1), (x, y + 1));
+            }
+            if !at(xi - 1, yi) {
+                add((x, y + 1), (x, y));
+            }
+        }
+    }
+
+    let mut path = String::new();
+    let mut starts: Vec<(usize, usize)> = edges.keys().copied().collect();
+    starts.sort_unstable_by_key(|&(x, y)| (y, x));
+    for start in starts {
+        while let Some(mut next) = edges.get_mut(&start).and_then(Vec::pop) {
+            let _ = write!(&mut path, "M{} {}", start.0, start.1);
+            let mut prev = start;
+            let mut corner = start;
+            // Only write the corners where the outline turns
+            while let Some(following) = edges.get_mut(&next).and_then(Vec::pop) {
+                let straight = (prev.0 == next.0 && next.0 == following.0)
+                    || (prev.1 == next.1 && next.1 == following.1);
+                if !straight {
+                    write_line(&mut path, corner, next);
+                    corner = next;
+                }
+                prev = next;
+                next = following;
+            }
+            // The walk ends back at the start, which closing the path joins
+            path.push('Z');
+        }
+    }
+
+    path
+}
+
+/// Write a horizontal or vertical line to a corner.
+fn write_line(path: &mut String, from: (usize, usize), to: (usize, usize)) {
+    let _ = if from.1 == to.1 {
+        write!(path, "H{}", to.0)
+    } else {
+        write!(path, "V{}", to.1)
+    };
+}
@@ -0,0 +1,141 @@
+use palette::cast::ComponentsAs;
+use palette::{white_point::D65, Clamp, Lab, LinSrgb, Mix, Srgb, Srgba};
+
+use crate::args::{Command, TextColor};
+use crate::err::CliError;
+use crate::utils::lab_to_linear;
+use kmeans_colors::{get_kmeans, get_kmeans_hamerly, ConversionCache, Kmeans, Sort};
+
+/// Steps of the binary search for the smallest adjustment reaching the
+/// contrast ratio.
+const SEARCH_STEPS: usize = 24;
+
+/// Print the dominant color of each image, adjusted to contrast with text.
+pub fn theme_color(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::ThemeColor {
+        input,
+        k,
+        max_iter,
+        factor,
+        runs,
+        seed,
+        contrast,
+        text,
+        verbose,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    if !(1.0..=21.0).contains(&contrast) {
+        return Err(CliError::Usage("--contrast must be between 1 and 21".into()).into());
+    }
+    let seed = seed.unwrap_or(0);
+
+    // Cached results of Srgb<u8> -> Lab conversions; not cleared between runs
+    let mut lab_cache = ConversionCache::new();
+    // Vec of pixels converted to Lab; cleared and reused between runs
+    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+
+    for file in &input {
+        if input.len() > 1 {
+            println!("{}", &file.to_string_lossy());
+        }
+
+        let img = image::open(file)?.into_rgba8();
+        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+
+        lab_pixels.clear();
+        lab_cache.extend(img_vec.iter().map(|x| x.color), &mut lab_pixels);
+
+        // Iterate over amount of runs keeping best results
+        let mut result = Kmeans::new();
+        for i in 0..runs {
+            let run_result = if k > 1 {
+                get_kmeans_hamerly(
+                    k as usize,
+                    max_iter,
+                    factor,
+                    false,
+                    &lab_pixels,
+                    seed + i as u64,
+                )
+            } else {
+                get_kmeans(
+                    k as usize,
+                    max_iter,
+                    factor,
+                    false,
+                    &lab_pixels,
+                    seed + i as u64,
+                )
+            };
+            result.keep_best(run_result, seed + i as u64);
+        }
+
+        let res = Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
+        let Some(dominant) = Lab::get_dominant_color(&res) else {
+            eprintln!("No colors found in {}", file.to_string_lossy());
+            continue;
+        };
+
+        let white = contrast_ratio(to_linear(dominant), 1.0);
+        let black = contrast_ratio(to_linear(dominant), 0.0);
+        let on_white = match text {
+            TextColor::White => true,
+            TextColor::Black => false,
+            TextColor::Auto => white >= black,
+        };
+        // Darken toward black under white text, lighten toward white under
+        // black text
+        let (target, text_luminance) = if on_white {
+            (Lab::new(0.0, 0.0, 0.0), 1.0)
+        } else {
+            (Lab::new(100.0, 0.0, 0.0), 0.0)
+        };
+        let ratio = |t: f32| contrast_ratio(to_linear(dominant.mix(target, t)), text_luminance);
+
+        let mut t = 0.0;
+        if ratio(0.0) < contrast {
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..SEARCH_STEPS {
+                let mid = (low + high) / 2.0;
+                if ratio(mid) < contrast {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            t = high;
+        }
+
+        let color = dominant.mix(target, t);
+        print!("{:x}", Srgb::<u8>::from_linear(to_linear(color)));
+        if verbose {
+            let name = if on_white { "white" } else { "black" };
+            print!(",{name},{:.2}", ratio(t));
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Converts a `Lab` color to linear `Srgb`, clamped to the gamut.
+fn to_linear(color: Lab<D65, f32>) -> LinSrgb {
+    lab_to_linear(color).clamp()
+}
+
+/// Calculates the WCAG contrast ratio between a color and text of the given
+/// relative luminance.
+fn contrast_ratio(color: LinSrgb, text_luminance: f32) -> f32 {
+    // Round to the 8-bit color that is printed
+    let color: LinSrgb = Srgb::<u8>::from_linear(color).into_linear();
+    let luminance = 0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue;
+    let (light, dark) = if luminance > text_luminance {
+        (luminance, text_luminance)
+    } else {
+        (text_luminance, luminance)
+    };
+    (light + 0.05) / (dark + 0.05)
+}
@@ -2,14 +2,25 @@ use std::error::Error;
 use std::fmt::Write;
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use image::ImageEncoder;
-use palette::{white_point::D65, IntoColor, Lab, Srgb, Srgba};
+use palette::chromatic_adaptation::AdaptFrom;
+use palette::white_point::{WhitePoint, D65};
+use palette::{Clamp, Hsv, IntoColor, Lab, LinSrgb, Oklch, Srgb, Srgba};
+use rand::{Rng, SeedableRng};
 
+use crate::args::{
+    CodeFormat, HistogramFormat, HueRange, InterpolationSpace, LightnessRange, Order, PctFormat,
+    SortLightness, Template, TemplatePart,
+};
 use crate::err::CliError;
-use kmeans_colors::{Calculate, CentroidData};
+use kmeans_colors::{
+    average_color, centroids_to_hex, centroids_to_srgb, edge_weights, get_kmeans_best,
+    interpolate_colors, match_centroids, Calculate, CentroidData, Hamerly, Iteration, KmeansConfig,
+    Regions, RunSummary, Weighted,
+};
 
 /// Parse hex string to Rgb color.
 pub fn parse_color(c: &str) -> Result<Srgb<u8>, CliError> {
@@ -19,37 +30,827 @@ pub fn parse_color(c: &str) -> Result<Srgb<u8>, CliError> {
     })
 }
 
+/// Read a list of paths, one per line, from a file or from standard input if
+/// the path is `-`. Empty lines are skipped.
+pub fn read_file_list(path: &Path) -> Result<Vec<PathBuf>, CliError> {
+    let text = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path)?
+    };
+
+    Ok(text
+        .lines()
+        .map(str::trim_end)
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// How `print_colors` writes each line.
+#[derive(Clone, Copy, Debug)]
+pub struct PrintFormat<'a> {
+    pub pct_format: PctFormat,
+    pub delimiter: &'a str,
+    /// Number of pixels the percentages are fractions of, for
+    /// `PctFormat::Count`.
+    pub pixels: usize,
+}
+
+impl PrintFormat<'_> {
+    /// Writes a percentage in the chosen format.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn write_percentage(&self, out: &mut String, percentage: f32) -> std::fmt::Result {
+        match self.pct_format {
+            PctFormat::Fraction => write!(out, "{percentage:0.4}"),
+            PctFormat::Percent(decimals) => write!(out, "{:.*}%", decimals, percentage * 100.0),
+            PctFormat::Count => write!(
+                out,
+                "{}",
+                (f64::from(percentage) * self.pixels as f64).round() as u64
+            ),
+        }
+    }
+}
+
 /// Prints colors and percentage of their appearance in an image buffer.
 pub fn print_colors<C: Calculate + Copy + IntoColor<Srgb>>(
     show_percentage: bool,
+    show_cmyk: bool,
+    format: PrintFormat<'_>,
     colors: &[CentroidData<C>],
 ) -> Result<(), Box<dyn Error>> {
-    let mut col = String::new();
+    let mut col = centroids_to_hex(colors).join(format.delimiter);
     let mut freq = String::new();
-    if let Some((last, elements)) = colors.split_last() {
-        for elem in elements {
-            write!(
-                &mut col,
-                "{:x},",
-                elem.centroid.into_color().into_format::<u8>()
-            )?;
-            write!(&mut freq, "{:0.4},", elem.percentage)?;
+    let mut cmyk = String::new();
+    for (i, elem) in colors.iter().enumerate() {
+        if i > 0 {
+            freq.push_str(format.delimiter);
+            cmyk.push_str(format.delimiter);
         }
-        writeln!(
-            &mut col,
-            "{:x}",
-            last.centroid.into_color().into_format::<u8>()
-        )?;
-        writeln!(&mut freq, "{:0.4}", last.percentage)?;
+        format.write_percentage(&mut freq, elem.percentage)?;
+        let [c, m, y, k] = srgb_to_cmyk(elem.centroid.into_color());
+        write!(&mut cmyk, "{c:.0}/{m:.0}/{y:.0}/{k:.0}")?;
+    }
+    if !colors.is_empty() {
+        col.push('\n');
+        freq.push('\n');
+        cmyk.push('\n');
     }
     print!("{}", col);
     if show_percentage {
         print!("{}", freq);
     }
+    if show_cmyk {
+        print!("{}", cmyk);
+    }
+
+    Ok(())
+}
+
+/// Prints a line for each color from a template.
+pub fn print_template<C: Calculate + Copy + IntoColor<Srgb>>(
+    template: &Template,
+    format: PrintFormat<'_>,
+    colors: &[CentroidData<C>],
+) -> Result<(), Box<dyn Error>> {
+    let mut out = String::new();
+    for elem in colors {
+        let srgb: Srgb = elem.centroid.into_color();
+        let rgb = srgb.into_format::<u8>();
+        for part in &template.0 {
+            match part {
+                TemplatePart::Text(text) => out.push_str(text),
+                TemplatePart::Hex => write!(&mut out, "{rgb:x}")?,
+                TemplatePart::Rgb => write!(&mut out, "{},{},{}", rgb.red, rgb.green, rgb.blue)?,
+                TemplatePart::Lab => {
+                    let lab: Lab = srgb.into_linear().into_color();
+                    write!(&mut out, "{:.2},{:.2},{:.2}", lab.l, lab.a, lab.b)?;
+                }
+                TemplatePart::Pct => format.write_percentage(&mut out, elem.percentage)?,
+                TemplatePart::Count => PrintFormat {
+                    pct_format: PctFormat::Count,
+                    ..format
+                }
+                .write_percentage(&mut out, elem.percentage)?,
+                TemplatePart::Index => write!(&mut out, "{}", elem.index)?,
+                TemplatePart::Name => out.push_str(&color_name(srgb)),
+            }
+        }
+        out.push('\n');
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Prints the colors as a constant array of `[r, g, b]` values in a
+/// programming language, commented with the hex value of each color.
+pub fn print_code<C: Calculate + Copy + IntoColor<Srgb>>(
+    format: CodeFormat,
+    colors: &[CentroidData<C>],
+) -> Result<(), Box<dyn Error>> {
+    let mut out = String::new();
+    match format {
+        CodeFormat::Rust => writeln!(&mut out, "const PALETTE: [[u8; 3]; {}] = [", colors.len())?,
+        CodeFormat::Python => writeln!(&mut out, "PALETTE = [")?,
+        CodeFormat::Js => writeln!(&mut out, "const PALETTE = [")?,
+        CodeFormat::Json => writeln!(&mut out, "[")?,
+    }
+    for (i, rgb) in centroids_to_srgb(colors).into_iter().enumerate() {
+        let (red, green, blue) = (rgb.red, rgb.green, rgb.blue);
+        match format {
+            CodeFormat::Rust | CodeFormat::Js => {
+                writeln!(&mut out, "    [{red}, {green}, {blue}], // #{rgb:x}")?;
+            }
+            CodeFormat::Python => writeln!(&mut out, "    ({red}, {green}, {blue}),  # #{rgb:x}")?,
+            // No comments or trailing comma in json
+            CodeFormat::Json => {
+                let comma = if i + 1 < colors.len() { "," } else { "" };
+                writeln!(&mut out, "    [{red}, {green}, {blue}]{comma}")?;
+            }
+        }
+    }
+    match format {
+        CodeFormat::Rust | CodeFormat::Js => writeln!(&mut out, "];")?,
+        CodeFormat::Python | CodeFormat::Json => writeln!(&mut out, "]")?,
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Print the diagnostics of a k-means iteration as a json line on standard
+/// error.
+pub fn print_iteration_json(iteration: &Iteration) {
+    // json has no infinity, which the score of a diverging run can reach
+    let score = if iteration.score.is_finite() {
+        iteration.score.to_string()
+    } else {
+        "null".into()
+    };
+    eprintln!(
+        "{{\"seed\":{},\"iteration\":{},\"score\":{},\"reseeded\":{},\"ms\":{:.3}}}",
+        iteration.seed,
+        iteration.iteration,
+        score,
+        iteration.reseeded,
+        iteration.elapsed.as_secs_f64() * 1000.0,
+    );
+}
+
+/// Describes a color by its hue family and lightness, e.g. `dark red` or
+/// `light gray`.
+fn color_name(color: Srgb) -> String {
+    let lab: Lab = color.into_linear().into_color();
+    match hue_family(color) {
+        None if lab.l < 15.0 => "black".into(),
+        None if lab.l > 90.0 => "white".into(),
+        family => {
+            let name = family.map_or("gray", |i| HUE_FAMILIES[i]);
+            if lab.l < 35.0 {
+                format!("dark {name}")
+            } else if lab.l > 70.0 {
+                format!("light {name}")
+            } else {
+                name.into()
+            }
+        }
+    }
+}
+
+/// Upper bounds of the delta E histogram bins, with a final bin for larger
+/// differences. A delta E around 2.3 is barely noticeable.
+const DELTA_E_BINS: [f32; 6] = [1.0, 2.0, 3.0, 5.0, 10.0, 20.0];
+
+/// Prints a histogram of the delta E between each pixel and its color.
+pub fn print_delta_e_histogram(
+    format: HistogramFormat,
+    deltas: impl Iterator<Item = f32>,
+) -> Result<(), Box<dyn Error>> {
+    let mut counts = [0u64; DELTA_E_BINS.len() + 1];
+    let (mut total, mut sum, mut max) = (0u64, 0.0f64, 0.0f32);
+    for delta in deltas.filter(|x| x.is_finite()) {
+        let bin = DELTA_E_BINS
+            .iter()
+            .position(|&x| delta < x)
+            .unwrap_or(DELTA_E_BINS.len());
+        counts[bin] += 1;
+        total += 1;
+        sum += f64::from(delta);
+        max = max.max(delta);
+    }
+    let fraction = |count: u64| count as f64 / total.max(1) as f64;
+    let mean = sum / total.max(1) as f64;
+
+    let mut out = String::new();
+    match format {
+        HistogramFormat::Text => {
+            let mut lower = 0.0;
+            for (upper, &count) in DELTA_E_BINS.iter().zip(&counts) {
+                writeln!(&mut out, "{lower}-{upper},{count},{:0.4}", fraction(count))?;
+                lower = *upper;
+            }
+            let count = counts[DELTA_E_BINS.len()];
+            writeln!(&mut out, "{lower}+,{count},{:0.4}", fraction(count))?;
+            writeln!(&mut out, "mean,{mean:0.4}")?;
+            writeln!(&mut out, "max,{max:0.4}")?;
+        }
+        HistogramFormat::Json => {
+            let mut lower = 0.0;
+            let mut bins = Vec::with_capacity(counts.len());
+            for (i, &count) in counts.iter().enumerate() {
+                let upper = match DELTA_E_BINS.get(i) {
+                    Some(x) => x.to_string(),
+                    None => "null".to_string(),
+                };
+                bins.push(format!(
+                    "{{\"min\":{lower},\"max\":{upper},\"pixels\":{count},\"percentage\":{:0.4}}}",
+                    fraction(count)
+                ));
+                lower = DELTA_E_BINS.get(i).copied().unwrap_or(lower);
+            }
+            writeln!(
+                &mut out,
+                "{{\"bins\":[{}],\"mean\":{mean:0.4},\"max\":{max:0.4}}}",
+                bins.join(",")
+            )?;
+        }
+    }
+    print!("{}", out);
 
     Ok(())
 }
 
+/// Running minimum, maximum, mean, and variance of the channels of colors.
+#[derive(Clone, Copy)]
+struct ChannelStats {
+    count: u64,
+    min: [f32; 3],
+    max: [f32; 3],
+    sum: [f64; 3],
+    sum_sq: [f64; 3],
+}
+
+impl ChannelStats {
+    fn new() -> Self {
+        ChannelStats {
+            count: 0,
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            sum: [0.0; 3],
+            sum_sq: [0.0; 3],
+        }
+    }
+
+    fn add(&mut self, color: [f32; 3]) {
+        self.count += 1;
+        for (i, &x) in color.iter().enumerate() {
+            self.min[i] = self.min[i].min(x);
+            self.max[i] = self.max[i].max(x);
+            self.sum[i] += f64::from(x);
+            self.sum_sq[i] += f64::from(x) * f64::from(x);
+        }
+    }
+
+    /// Returns the minimum, maximum, mean, and standard deviation of a
+    /// channel.
+    #[allow(clippy::cast_precision_loss)]
+    fn channel(&self, i: usize) -> [f64; 4] {
+        if self.count == 0 {
+            return [0.0; 4];
+        }
+        let n = self.count as f64;
+        let mean = self.sum[i] / n;
+        let variance = (self.sum_sq[i] / n - mean * mean).max(0.0);
+        [
+            f64::from(self.min[i]),
+            f64::from(self.max[i]),
+            mean,
+            variance.sqrt(),
+        ]
+    }
+}
+
+/// Prints the minimum, maximum, mean, and standard deviation of each channel
+/// of the pixels, for the whole image and for the pixels of each color.
+///
+/// Pixels are paired with the index of their color in `colors`, which label
+/// the statistics of each cluster.
+pub fn print_channel_stats(
+    format: HistogramFormat,
+    channels: [&str; 3],
+    pixels: impl Iterator<Item = ([f32; 3], u32)>,
+    colors: &[Srgb<u8>],
+) -> Result<(), Box<dyn Error>> {
+    let mut image = ChannelStats::new();
+    let mut clusters = vec![ChannelStats::new(); colors.len()];
+    for (color, i) in pixels {
+        if color.iter().all(|x| x.is_finite()) {
+            image.add(color);
+            if let Some(cluster) = clusters.get_mut(i as usize) {
+                cluster.add(color);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    match format {
+        HistogramFormat::Text => {
+            let mut write_stats = |name: &str, stats: &ChannelStats| -> std::fmt::Result {
+                for (i, channel) in channels.iter().enumerate() {
+                    let [min, max, mean, std] = stats.channel(i);
+                    writeln!(
+                        &mut out,
+                        "{name},{channel},{min:0.4},{max:0.4},{mean:0.4},{std:0.4}"
+                    )?;
+                }
+                Ok(())
+            };
+            write_stats("image", &image)?;
+            for (color, stats) in colors.iter().zip(&clusters) {
+                write_stats(&format!("{color:x}"), stats)?;
+            }
+        }
+        HistogramFormat::Json => {
+            let json = |stats: &ChannelStats| {
+                let channels: Vec<String> = channels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, channel)| {
+                        let [min, max, mean, std] = stats.channel(i);
+                        format!(
+                            "{{\"channel\":\"{channel}\",\"min\":{min:0.4},\"max\":{max:0.4},\"mean\":{mean:0.4},\"std\":{std:0.4}}}"
+                        )
+                    })
+                    .collect();
+                format!(
+                    "\"pixels\":{},\"channels\":[{}]",
+                    stats.count,
+                    channels.join(",")
+                )
+            };
+            let clusters: Vec<String> = colors
+                .iter()
+                .zip(&clusters)
+                .map(|(color, stats)| format!("{{\"color\":\"{color:x}\",{}}}", json(stats)))
+                .collect();
+            writeln!(
+                &mut out,
+                "{{\"image\":{{{}}},\"clusters\":[{}]}}",
+                json(&image),
+                clusters.join(",")
+            )?;
+        }
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Prints a 95% confidence interval of the percentage of each color, in the
+/// order of `colors`, from `resamples` bootstrap resamples of the pixels.
+///
+/// Each resample draws as many pixel indices as there are pixels, with
+/// replacement, and the intervals are the 2.5th and 97.5th percentiles of the
+/// resampled percentages.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+pub fn print_bootstrap<C: Calculate>(
+    colors: &[CentroidData<C>],
+    indices: &[u32],
+    resamples: usize,
+    seed: u64,
+) -> Result<(), Box<dyn Error>> {
+    let k = colors
+        .iter()
+        .map(|x| x.index as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut samples = vec![Vec::with_capacity(resamples); k];
+    let mut counts = vec![0u64; k];
+    for _ in 0..resamples {
+        counts.iter_mut().for_each(|x| *x = 0);
+        for _ in 0..indices.len() {
+            let idx = indices[rng.gen_range(0..indices.len())] as usize;
+            if let Some(count) = counts.get_mut(idx) {
+                *count += 1;
+            }
+        }
+        for (sample, &count) in samples.iter_mut().zip(&counts) {
+            sample.push(count as f32 / indices.len() as f32);
+        }
+    }
+
+    let mut out = Vec::with_capacity(colors.len());
+    for color in colors {
+        let sample = &mut samples[color.index as usize];
+        sample.sort_by(f32::total_cmp);
+        let percentile = |p: f32| {
+            let i = (p * (sample.len() - 1) as f32).round() as usize;
+            sample.get(i).copied().unwrap_or(color.percentage)
+        };
+        out.push(format!(
+            "{:0.4}-{:0.4}",
+            percentile(0.025),
+            percentile(0.975)
+        ));
+    }
+    println!("{}", out.join(","));
+
+    Ok(())
+}
+
+/// Largest delta E between a result color and the matched color of another
+/// seed for the color to appear in that seed.
+const STABLE_DELTA_E: f32 = 5.0;
+
+/// Minimum fraction of seeds a color must appear in to be stable.
+const STABLE_FRACTION: f32 = 0.8;
+
+/// Reruns the k-means of `config` with `seeds` seeds and prints how often each
+/// color of `reference` appears in the other results, with the mean delta E of
+/// its matched colors, followed by whether the palette is stable.
+#[allow(clippy::cast_precision_loss)]
+pub fn print_stability<C: Hamerly + Weighted + Clone>(
+    config: &KmeansConfig,
+    seeds: usize,
+    pixels: &[C],
+    weights: Option<&[f32]>,
+    reference: &[C],
+    to_srgb: impl Fn(&C) -> Srgb,
+) {
+    let to_lab = |x: &C| -> Lab<D65, f32> { to_srgb(x).into_linear().into_color() };
+    let reference_lab: Vec<_> = reference.iter().map(to_lab).collect();
+    let mut appears = vec![0usize; reference.len()];
+    let mut delta_e = vec![0.0f32; reference.len()];
+    for i in 0..seeds {
+        let run_config = KmeansConfig {
+            runs: 1,
+            seed: config.seed + i as u64,
+            verbose: false,
+            prune: None,
+            trace: None,
+            ..*config
+        };
+        let run = get_kmeans_best(&run_config, pixels, weights);
+        for (r, matched) in match_centroids(reference, &run.centroids)
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(j) = matched {
+                let delta = Lab::difference(&reference_lab[r], &to_lab(&run.centroids[j])).sqrt();
+                delta_e[r] += delta;
+                if delta <= STABLE_DELTA_E {
+                    appears[r] += 1;
+                }
+            } else {
+                delta_e[r] += f32::INFINITY;
+            }
+        }
+    }
+
+    let mut stable = true;
+    for ((color, &count), &delta) in reference.iter().zip(&appears).zip(&delta_e) {
+        let fraction = count as f32 / seeds.max(1) as f32;
+        stable &= fraction >= STABLE_FRACTION;
+        println!(
+            "{:x},{fraction:0.4},{:0.4}",
+            to_srgb(color).into_format::<u8>(),
+            delta / seeds.max(1) as f32
+        );
+    }
+    println!("{}", if stable { "stable" } else { "unstable" });
+}
+
+/// Names of the hue histogram bins, each 30 degrees wide and centered on a
+/// multiple of 30 degrees, starting from red at 0 degrees.
+const HUE_FAMILIES: [&str; 12] = [
+    "red",
+    "orange",
+    "yellow",
+    "chartreuse",
+    "green",
+    "spring",
+    "cyan",
+    "azure",
+    "blue",
+    "violet",
+    "magenta",
+    "rose",
+];
+
+/// Colors with a lower `Hsv` chroma, the product of saturation and value, have
+/// no meaningful hue and are counted as gray.
+const HUE_MIN_CHROMA: f32 = 0.1;
+
+/// Minimum fraction of the pixels in a hue family for it to be a peak.
+const HUE_MIN_PEAK: f64 = 0.01;
+
+/// Returns the index of the hue family of a color in `HUE_FAMILIES`, or
+/// `None` for grays.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn hue_family(color: Srgb) -> Option<usize> {
+    let hsv: Hsv = color.into_color();
+    if hsv.saturation * hsv.value < HUE_MIN_CHROMA {
+        return None;
+    }
+    let bin = ((hsv.hue.into_positive_degrees() + 15.0) / 30.0) as usize;
+    Some(bin % HUE_FAMILIES.len())
+}
+
+/// Prints a histogram of the hue families of the colors and the peak hues, the
+/// families with at least 1% of the colors that are more common than their
+/// neighbors, from most to least common.
+#[allow(clippy::cast_precision_loss)]
+pub fn print_hue_histogram(
+    format: HistogramFormat,
+    colors: impl Iterator<Item = Srgb<u8>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut counts = [0u64; HUE_FAMILIES.len()];
+    let (mut gray, mut total) = (0u64, 0u64);
+    for color in colors {
+        match hue_family(color.into_format()) {
+            Some(family) => counts[family] += 1,
+            None => gray += 1,
+        }
+        total += 1;
+    }
+    let fraction = |count: u64| count as f64 / total.max(1) as f64;
+
+    let n = counts.len();
+    let mut peaks: Vec<usize> = (0..n)
+        .filter(|&i| {
+            let (prev, next) = (counts[(i + n - 1) % n], counts[(i + 1) % n]);
+            fraction(counts[i]) >= HUE_MIN_PEAK && counts[i] >= prev && counts[i] > next
+        })
+        .collect();
+    peaks.sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+    let peaks = peaks.iter().map(|&i| HUE_FAMILIES[i]);
+
+    let mut out = String::new();
+    match format {
+        HistogramFormat::Text => {
+            for (name, &count) in HUE_FAMILIES.iter().zip(&counts) {
+                writeln!(&mut out, "{name},{count},{:0.4}", fraction(count))?;
+            }
+            writeln!(&mut out, "gray,{gray},{:0.4}", fraction(gray))?;
+            writeln!(&mut out, "peaks,{}", peaks.collect::<Vec<_>>().join(","))?;
+        }
+        HistogramFormat::Json => {
+            let bins: Vec<String> = HUE_FAMILIES
+                .iter()
+                .zip(&counts)
+                .enumerate()
+                .map(|(i, (name, &count))| {
+                    format!(
+                        "{{\"hue\":\"{name}\",\"degrees\":{},\"pixels\":{count},\"percentage\":{:0.4}}}",
+                        i * 30,
+                        fraction(count)
+                    )
+                })
+                .collect();
+            let peaks: Vec<String> = peaks.map(|x| format!("\"{x}\"")).collect();
+            writeln!(
+                &mut out,
+                "{{\"bins\":[{}],\"gray\":{{\"pixels\":{gray},\"percentage\":{:0.4}}},\"peaks\":[{}]}}",
+                bins.join(","),
+                fraction(gray),
+                peaks.join(",")
+            )?;
+        }
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Prints the mean and maximum delta E of the pixels assigned to each color
+/// as `mean/max`, in the order of the centroid indices in `order`. `deltas`
+/// are pairs of a pixel's centroid index and its delta E.
+pub fn print_delta_e_stats(
+    order: &[usize],
+    deltas: impl Iterator<Item = (usize, f32)>,
+) -> Result<(), Box<dyn Error>> {
+    let len = order.iter().max().map_or(0, |&x| x + 1);
+    let mut stats = vec![(0.0f64, 0u64, 0.0f32); len];
+    for (idx, delta) in deltas.filter(|x| x.1.is_finite()) {
+        if let Some((sum, count, max)) = stats.get_mut(idx) {
+            *sum += f64::from(delta);
+            *count += 1;
+            *max = max.max(delta);
+        }
+    }
+
+    let mut out = String::new();
+    for (i, &idx) in order.iter().enumerate() {
+        let (sum, count, max) = stats[idx];
+        let mean = sum / count.max(1) as f64;
+        let sep = if i + 1 < order.len() { "," } else { "\n" };
+        write!(&mut out, "{mean:.2}/{max:.2}{sep}")?;
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Prints the dominant color followed by the accent color, the most saturated
+/// color making up at least `min_percentage` of the image.
+pub fn print_accent<C: Calculate + Copy + IntoColor<Srgb>>(
+    colors: &[CentroidData<C>],
+    min_percentage: f32,
+) -> Result<(), Box<dyn Error>> {
+    let dominant = colors
+        .iter()
+        .max_by(|a, b| (a.percentage).total_cmp(&b.percentage));
+    let accent = colors
+        .iter()
+        .filter(|x| x.percentage >= min_percentage)
+        .map(|x| (x, chroma(x.centroid.into_color())))
+        .max_by(|a, b| (a.1).total_cmp(&b.1))
+        .map(|x| x.0);
+
+    if let (Some(dominant), Some(accent)) = (dominant, accent) {
+        let hex = centroids_to_hex(&[dominant.clone(), accent.clone()]);
+        println!("{}", hex.join(","));
+    }
+
+    Ok(())
+}
+
+/// Prints the dominant background color followed by the dominant subject
+/// color. A color is left empty if no cluster was classified as such.
+pub fn print_background_split(background: Option<Srgb>, foreground: Option<Srgb>) {
+    let hex = |color: Option<Srgb>| {
+        color.map_or(String::new(), |x| format!("{:x}", x.into_format::<u8>()))
+    };
+    println!("{},{}", hex(background), hex(foreground));
+}
+
+/// Prints the number of contiguous regions of each color followed by the
+/// sizes of its largest regions in pixels, as `color,regions,sizes...`.
+pub fn print_regions(
+    regions: &Regions,
+    colors: &[Srgb<u8>],
+    largest: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = String::new();
+    for (i, color) in colors.iter().enumerate() {
+        let sizes = regions.sizes(i as u32);
+        if sizes.is_empty() {
+            continue;
+        }
+        write!(&mut out, "{color:x},{}", sizes.len())?;
+        for size in sizes.iter().take(largest) {
+            write!(&mut out, ",{size}")?;
+        }
+        out.push('\n');
+    }
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Prints the seed and score of each run, marking the run that was kept, and
+/// the range of the scores.
+pub fn print_runs(runs: &[RunSummary]) {
+    let best = runs.iter().map(|x| x.score).fold(f32::INFINITY, f32::min);
+    let worst = runs.iter().map(|x| x.score).fold(0.0, f32::max);
+    for (i, run) in runs.iter().enumerate() {
+        let mark = if run.score == best { " *" } else { "" };
+        println!(
+            "Run {}: seed {}, score {}{}",
+            i + 1,
+            run.seed,
+            run.score,
+            mark
+        );
+    }
+    println!("Score range: {best} - {worst}");
+}
+
+/// Returns the chroma of a color in the `Lab` color space.
+pub fn chroma(color: Srgb) -> f32 {
+    let lab: Lab<D65, f32> = color.into_linear().into_color();
+    (lab.a * lab.a + lab.b * lab.b).sqrt()
+}
+
+/// Returns `true` if the `Hsv` hue of a color lies in any of the ranges.
+/// Colors without saturation have no hue and are never excluded.
+pub fn hue_excluded(ranges: &[HueRange], color: &Srgba<u8>) -> bool {
+    if ranges.is_empty() {
+        return false;
+    }
+    let hsv: Hsv = color.color.into_format::<f32>().into_color();
+    hsv.saturation > 0.0
+        && ranges
+            .iter()
+            .any(|r| r.contains(hsv.hue.into_positive_degrees()))
+}
+
+/// Seed derived from the dimensions and pixel bytes of an image with 64-bit
+/// FNV-1a, which gives the same seed on every platform.
+pub fn content_seed(width: u32, height: u32, pixels: &[u8]) -> u64 {
+    let hash = fnv1a(FNV_OFFSET, &width.to_le_bytes());
+    let hash = fnv1a(hash, &height.to_le_bytes());
+    fnv1a(hash, pixels)
+}
+
+/// Initial value of a 64-bit FNV-1a hash.
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continue a 64-bit FNV-1a hash with more bytes.
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Adjust the exposure, in stops, and gamma of the pixels in linear light.
+///
+/// Both adjustments apply to each channel alone, so they're applied with a
+/// lookup table of the 256 channel values.
+#[allow(clippy::cast_possible_truncation)]
+pub fn adjust_tone(pixels: &mut [Srgba<u8>], exposure: f32, gamma: f32) {
+    let scale = exposure.exp2();
+    let mut table = [0u8; 256];
+    for (i, x) in table.iter_mut().enumerate() {
+        let linear = Srgb::new(i as u8, 0, 0).into_linear::<f32>().red;
+        let adjusted = (linear * scale).clamp(0.0, 1.0).powf(gamma.recip());
+        *x = Srgb::from_linear(LinSrgb::new(adjusted, 0.0, 0.0)).red;
+    }
+
+    for pixel in pixels {
+        pixel.red = table[pixel.red as usize];
+        pixel.green = table[pixel.green as usize];
+        pixel.blue = table[pixel.blue as usize];
+    }
+}
+
+/// Blend the pixels onto a solid `background` color, leaving them opaque.
+#[allow(clippy::cast_possible_truncation)]
+pub fn composite_background(pixels: &mut [Srgba<u8>], background: Srgb<u8>) {
+    for pixel in pixels {
+        let alpha = u16::from(pixel.alpha);
+        let blend = |x: u8, bg: u8| {
+            ((u16::from(x) * alpha + u16::from(bg) * (255 - alpha) + 127) / 255) as u8
+        };
+        pixel.red = blend(pixel.red, background.red);
+        pixel.green = blend(pixel.green, background.green);
+        pixel.blue = blend(pixel.blue, background.blue);
+        pixel.alpha = 255;
+    }
+}
+
+/// Returns `true` if the `Hsv` saturation of the color is below `min`.
+pub fn saturation_excluded(min: Option<f32>, color: &Srgba<u8>) -> bool {
+    match min {
+        Some(min) => {
+            let hsv: Hsv = color.color.into_format::<f32>().into_color();
+            hsv.saturation < min
+        }
+        None => false,
+    }
+}
+
+/// Returns `true` if the `Lab` lightness of the color is outside of `range`.
+pub fn lightness_excluded(range: Option<LightnessRange>, color: &Srgba<u8>) -> bool {
+    match range {
+        Some(range) => {
+            let lab: Lab<D65, f32> = color.color.into_linear().into_color();
+            !range.contains(lab.l)
+        }
+        None => false,
+    }
+}
+
+/// Naive conversion of a color to CMYK percentages without a color profile.
+pub fn srgb_to_cmyk(color: Srgb) -> [f32; 4] {
+    let Srgb {
+        red, green, blue, ..
+    } = color.into_format::<u8>().into_format::<f32>();
+    let k = 1.0 - red.max(green).max(blue);
+    if k >= 1.0 {
+        return [0.0, 0.0, 0.0, 100.0];
+    }
+    [
+        (1.0 - red - k) / (1.0 - k) * 100.0,
+        (1.0 - green - k) / (1.0 - k) * 100.0,
+        (1.0 - blue - k) / (1.0 - k) * 100.0,
+        k * 100.0,
+    ]
+}
+
 /// Saves image buffer to file.
 pub fn save_image(
     imgbuf: &[u8],
@@ -129,12 +930,58 @@ pub fn save_image_alpha(
     Ok(())
 }
 
-/// Save palette image file.
+/// Saves an indexed PNG whose palette is exactly `palette`, in order. PNG
+/// palettes hold at most 256 colors, so the indices must be below 256.
+#[allow(clippy::cast_possible_truncation)]
+pub fn save_indexed_png(
+    indices: &[u32],
+    palette: &[Srgb<u8>],
+    imgx: u32,
+    imgy: u32,
+    title: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if title.extension() != Some("png".as_ref()) {
+        return Err("Indexed output must be saved as a png".into());
+    }
+    let w = BufWriter::new(File::create(title)?);
+    let mut encoder = png::Encoder::new(w, imgx, imgy);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|x| [x.red, x.green, x.blue])
+            .collect::<Vec<u8>>(),
+    );
+
+    // Clean up if file is created but there's a problem writing to it
+    match encoder.write_header().and_then(|mut writer| {
+        writer.write_image_data(&indices.iter().map(|&x| x as u8).collect::<Vec<u8>>())
+    }) {
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("Error: {}.", err);
+            std::fs::remove_file(title)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Save palette image file. With `normalized`, a second strip of the colors
+/// at equal lightness is drawn below the colors.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
 pub fn save_palette<C: Calculate + Copy + IntoColor<Srgb>>(
     res: &[CentroidData<C>],
     proportional: bool,
     height: u32,
     width: Option<u32>,
+    normalized: bool,
     title: &Path,
 ) -> Result<(), Box<dyn Error>> {
     let len = res.len() as u32;
@@ -150,47 +997,163 @@ pub fn save_palette<C: Calculate + Copy + IntoColor<Srgb>>(
         None => height * len,
     };
 
-    let mut imgbuf: image::RgbImage = image::ImageBuffer::new(w, height);
-
+    // Index of the color of each column
+    let mut columns = vec![0; w as usize];
     if !proportional {
-        for (x, _, pixel) in imgbuf.enumerate_pixels_mut() {
-            let color = res
-                .get(
-                    (((x as f32 / w as f32) * len as f32 - 0.5)
-                        .max(0.0)
-                        .min(len as f32))
-                    .round() as usize,
-                )
-                .unwrap()
-                .centroid
-                .into_color()
-                .into_format()
-                .into();
-            *pixel = image::Rgb(color);
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = (((x as f32 / w as f32) * len as f32 - 0.5)
+                .max(0.0)
+                .min(len as f32))
+            .round() as usize;
         }
-    } else {
+    } else if !res.is_empty() {
         let mut curr_pos = 0;
-        if let Some((last, elements)) = res.split_last() {
-            for r in elements.iter() {
-                let pix: [u8; 3] = r.centroid.into_color().into_format().into();
-                // Clamp boundary to image width
-                let boundary =
-                    ((curr_pos as f32 + (r.percentage * w as f32)).round() as u32).min(w);
-                for y in 0..height {
-                    for x in curr_pos..boundary {
-                        imgbuf.put_pixel(x, y, image::Rgb(pix));
+        for (i, r) in res[..res.len() - 1].iter().enumerate() {
+            // Clamp boundary to image width
+            let boundary = ((curr_pos as f32 + (r.percentage * w as f32)).round() as u32).min(w);
+            columns[curr_pos as usize..boundary as usize].fill(i);
+            curr_pos = boundary;
+        }
+        columns[curr_pos as usize..].fill(res.len() - 1);
+    }
+
+    let colors: Vec<Srgb> = res.iter().map(|x| x.centroid.into_color()).collect();
+    let mut strips = vec![colors];
+    if normalized {
+        strips.push(equal_lightness(&strips[0]));
+    }
+
+    let mut imgbuf: image::RgbImage = image::ImageBuffer::new(w, height * strips.len() as u32);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let strip = &strips[(y / height) as usize];
+        *pixel = image::Rgb(strip[columns[x as usize]].into_format().into());
+    }
+
+    save_image(imgbuf.as_raw(), w, imgbuf.height(), title, true)
+}
+
+/// Sets the colors to their mean `Lab` lightness, keeping their hue. Chroma
+/// is reduced as needed to stay within the `Srgb` gamut.
+fn equal_lightness(colors: &[Srgb]) -> Vec<Srgb> {
+    let lab: Vec<Lab> = colors
+        .iter()
+        .map(|x| x.into_linear().into_color())
+        .collect();
+    let Some(mean) = average_color(&lab) else {
+        return Vec::new();
+    };
+
+    lab.iter()
+        .map(|x| {
+            // Search for the largest fraction of the chroma within the gamut
+            let in_gamut = |chroma: f32| {
+                let linear: LinSrgb = Lab::new(mean.l, x.a * chroma, x.b * chroma).into_color();
+                [linear.red, linear.green, linear.blue]
+                    .iter()
+                    .all(|c| (-1e-4..=1.0 + 1e-4).contains(c))
+            };
+            let (mut low, mut high) = (0.0, 1.0);
+            if !in_gamut(high) {
+                for _ in 0..16 {
+                    let mid = (low + high) / 2.0;
+                    if in_gamut(mid) {
+                        low = mid;
+                    } else {
+                        high = mid;
                     }
                 }
-                // If boundary has been clamped, return early
-                if boundary == w {
-                    return save_image(imgbuf.as_raw(), w, height, title, true);
+                high = low;
+            }
+            let linear: LinSrgb = Lab::new(mean.l, x.a * high, x.b * high).into_color();
+            Srgb::from_linear(linear.clamp())
+        })
+        .collect()
+}
+
+/// Save an image of a smooth horizontal gradient through the colors, in order.
+///
+/// If `width` is omitted, the gradient will be `height * 8` pixels wide.
+pub fn save_gradient(
+    res: &[CentroidData<Srgb>],
+    space: InterpolationSpace,
+    height: u32,
+    width: Option<u32>,
+    title: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let w = width.unwrap_or(height * 8).max(1);
+    let colors: Vec<Srgb> = res.iter().map(|x| x.centroid).collect();
+    let gradient = interpolate_colors(&colors, w as usize, space.into());
+
+    let mut imgbuf: image::RgbImage = image::ImageBuffer::new(w, height);
+    for (x, _, pixel) in imgbuf.enumerate_pixels_mut() {
+        if let Some(color) = gradient.get(x as usize) {
+            *pixel = image::Rgb(color.into_format().into());
+        }
+    }
+
+    save_image(imgbuf.as_raw(), w, height, title, true)
+}
+
+/// Converts sorted centroid data into a barcode stripe of colors and their
+/// percentages.
+pub fn barcode_stripe<C: Calculate + Copy + IntoColor<Srgb>>(
+    res: &[CentroidData<C>],
+) -> Vec<(Srgb<u8>, f32)> {
+    centroids_to_srgb(res)
+        .into_iter()
+        .zip(res.iter().map(|x| x.percentage))
+        .collect()
+}
+
+/// Save barcode image file, one stripe per input image.
+///
+/// If `proportional` is set, each stripe is divided vertically by the
+/// percentage of each color. Otherwise, the stripe is filled with the color
+/// having the largest percentage.
+pub fn save_barcode(
+    stripes: &[Vec<(Srgb<u8>, f32)>],
+    proportional: bool,
+    stripe_width: u32,
+    height: u32,
+    title: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let stripe_width = stripe_width.max(1);
+    let w = stripe_width * stripes.len() as u32;
+    let mut imgbuf: image::RgbImage = image::ImageBuffer::new(w, height);
+
+    for (i, stripe) in stripes.iter().enumerate() {
+        let x0 = i as u32 * stripe_width;
+        if !proportional {
+            if let Some(dominant) = stripe
+                .iter()
+                .max_by(|a, b| (a.1).total_cmp(&b.1))
+                .map(|x| x.0)
+            {
+                for y in 0..height {
+                    for x in x0..x0 + stripe_width {
+                        imgbuf.put_pixel(x, y, image::Rgb(dominant.into()));
+                    }
                 }
-                curr_pos = boundary;
             }
-            let pix: [u8; 3] = last.centroid.into_color().into_format().into();
-            for y in 0..height {
-                for x in curr_pos..w {
-                    imgbuf.put_pixel(x, y, image::Rgb(pix));
+        } else {
+            let mut curr_pos = 0;
+            if let Some((last, elements)) = stripe.split_last() {
+                for (color, percentage) in elements {
+                    // Clamp boundary to image height
+                    let boundary = ((curr_pos as f32 + (percentage * height as f32)).round()
+                        as u32)
+                        .min(height);
+                    for y in curr_pos..boundary {
+                        for x in x0..x0 + stripe_width {
+                            imgbuf.put_pixel(x, y, image::Rgb((*color).into()));
+                        }
+                    }
+                    curr_pos = boundary;
+                }
+                for y in curr_pos..height {
+                    for x in x0..x0 + stripe_width {
+                        imgbuf.put_pixel(x, y, image::Rgb(last.0.into()));
+                    }
                 }
             }
         }
@@ -199,19 +1162,143 @@ pub fn save_palette<C: Calculate + Copy + IntoColor<Srgb>>(
     save_image(imgbuf.as_raw(), w, height, title, true)
 }
 
-/// Optimized conversion of colors from Srgb to Lab using a hashmap for caching
-/// of expensive color conversions.
+/// Converts `Lab` with the reference white point `Wp` to linear sRGB.
+pub fn lab_to_linear<Wp: WhitePoint<f32>>(color: Lab<Wp, f32>) -> LinSrgb {
+    if std::any::TypeId::of::<Wp>() == std::any::TypeId::of::<D65>() {
+        Lab::<D65, f32>::new(color.l, color.a, color.b).into_color()
+    } else {
+        LinSrgb::adapt_from(color)
+    }
+}
+
+/// Converts sorted `Lab` centroid data to `Srgb` for printing and saving.
+pub fn lab_data_to_srgb<Wp: WhitePoint<f32>>(
+    res: &[CentroidData<Lab<Wp, f32>>],
+) -> Vec<CentroidData<Srgb>> {
+    res.iter()
+        .map(|x| CentroidData {
+            centroid: Srgb::from_linear(lab_to_linear(x.centroid)),
+            percentage: x.percentage,
+            index: x.index,
+        })
+        .collect()
+}
+
+/// `Oklch` chroma below which `--order hue` treats a color as gray.
+const GRAY_CHROMA: f32 = 0.02;
+
+/// Returns the sort key of a color for `--order hue`, placing grays before the
+/// colors ordered by hue angle.
+fn hue_key(color: Oklch) -> f32 {
+    if color.chroma < GRAY_CHROMA {
+        -1.0
+    } else {
+        color.hue.into_positive_degrees()
+    }
+}
+
+/// Reorder colors that are sorted from darkest to lightest, as returned by
+/// `sort_indexed_colors`. `to_oklch` converts the colors for ordering by
+/// `Oklab` lightness and by hue.
 ///
-/// Additionally, converting from Srgb to Linear Srgb is special-cased in
-/// `palette` to use a lookup table which is faster than the regular conversion
-/// using `color.into_format().into_color()`.
-pub fn cached_srgba_to_lab<'a>(
-    rgb: impl Iterator<Item = &'a Srgba<u8>>,
-    map: &mut fxhash::FxHashMap<[u8; 3], Lab<D65, f32>>,
-    lab_pixels: &mut Vec<Lab<D65, f32>>,
+/// Sorting is stable, so grays and colors with equal percentages stay ordered
+/// from darkest to lightest.
+pub fn order_colors<C: Calculate + Copy>(
+    res: &mut [CentroidData<C>],
+    order: Order,
+    lightness: SortLightness,
+    to_oklch: impl Fn(C) -> Oklch,
 ) {
-    lab_pixels.extend(rgb.map(|color| {
-        *map.entry([color.red, color.green, color.blue])
-            .or_insert_with(|| color.into_linear::<_, f32>().into_color())
-    }))
+    match order {
+        Order::Luminance => {
+            if lightness == SortLightness::Oklab {
+                res.sort_by(|a, b| to_oklch(a.centroid).l.total_cmp(&to_oklch(b.centroid).l));
+            }
+        }
+        Order::Percentage => res.sort_by(|a, b| b.percentage.total_cmp(&a.percentage)),
+        Order::Hue => res.sort_by(|a, b| {
+            hue_key(to_oklch(a.centroid)).total_cmp(&hue_key(to_oklch(b.centroid)))
+        }),
+        Order::Input => res.sort_by_key(|x| x.index),
+    }
+}
+
+/// Replace sorted colors with `n` colors interpolated between them, each
+/// given an equal share of the image.
+pub fn expand_palette(
+    res: &[CentroidData<Srgb>],
+    n: usize,
+    space: InterpolationSpace,
+) -> Vec<CentroidData<Srgb>> {
+    let colors: Vec<Srgb> = res.iter().map(|x| x.centroid).collect();
+    interpolate_colors(&colors, n, space.into())
+        .into_iter()
+        .enumerate()
+        .map(|(i, centroid)| CentroidData {
+            centroid,
+            percentage: 1.0 / n as f32,
+            index: i as u32,
+        })
+        .collect()
+}
+
+/// Returns the edge weights of the pixels of an image for which `keep` is
+/// `true`, calculated from the luma of the whole image.
+pub fn image_edge_weights(
+    img_vec: &[Srgba<u8>],
+    width: u32,
+    height: u32,
+    strength: f32,
+    keep: impl Fn(&Srgba<u8>) -> bool,
+) -> Vec<f32> {
+    let luma: Vec<f32> = img_vec
+        .iter()
+        .map(|x| {
+            let x: Srgb = x.color.into_format();
+            0.2126 * x.red + 0.7152 * x.green + 0.0722 * x.blue
+        })
+        .collect();
+
+    edge_weights(&luma, width as usize, height as usize, strength)
+        .into_iter()
+        .zip(img_vec)
+        .filter(|(_, x)| keep(x))
+        .map(|(w, _)| w)
+        .collect()
+}
+
+/// Quote and escape a string for json.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Print the path and message of each failed input file as json to standard
+/// error.
+pub fn print_error_summary(errors: &[CliError], total: usize) {
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|err| match err {
+            CliError::Input(path, err) => format!(
+                "{{\"input\":{},\"error\":{}}}",
+                json_string(&path.to_string_lossy()),
+                json_string(&err.to_string())
+            ),
+            err => format!("{{\"error\":{}}}", json_string(&err.to_string())),
+        })
+        .collect();
+    eprintln!(
+        "{{\"errors\":[{}],\"failed\":{},\"total\":{total}}}",
+        errors.join(","),
+        errors.len()
+    );
 }
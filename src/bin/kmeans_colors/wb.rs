@@ -0,0 +1,125 @@
+use palette::cast::{AsComponents, ComponentsAs};
+use palette::{white_point::D65, Lab, Srgb, Srgba};
+
+use crate::args::Command;
+use crate::filename::create_filename;
+use crate::utils::{lab_to_linear, save_image};
+use kmeans_colors::{get_kmeans, get_kmeans_hamerly, ConversionCache, Kmeans, Sort};
+
+/// Estimate the color cast of an image from its most neutral k-means color,
+/// optionally saving a corrected image.
+pub fn white_balance(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let Command::Wb {
+        input,
+        k,
+        max_iter,
+        factor,
+        runs,
+        seed,
+        max_chroma,
+        correct,
+        verbose,
+        output,
+    } = command
+    else {
+        unreachable!()
+    };
+
+    let seed = seed.unwrap_or(0);
+
+    // Cached results of Srgb<u8> -> Lab conversions; not cleared between runs
+    let mut lab_cache = ConversionCache::new();
+    // Vec of pixels converted to Lab; cleared and reused between runs
+    let mut lab_pixels: Vec<Lab<D65, f32>> = Vec::new();
+
+    for file in &input {
+        if input.len() > 1 {
+            println!("{}", &file.to_string_lossy());
+        }
+
+        let img = image::open(file)?.into_rgba8();
+        let (imgx, imgy) = img.dimensions();
+        let img_vec: &[Srgba<u8>] = img.as_raw().components_as();
+
+        lab_pixels.clear();
+        lab_cache.extend(img_vec.iter().map(|x| x.color), &mut lab_pixels);
+
+        // Iterate over amount of runs keeping best results
+        let mut result = Kmeans::new();
+        for i in 0..runs {
+            let run_result = if k > 1 {
+                get_kmeans_hamerly(
+                    k as usize,
+                    max_iter,
+                    factor,
+                    verbose,
+                    &lab_pixels,
+                    seed + i as u64,
+                )
+            } else {
+                get_kmeans(
+                    k as usize,
+                    max_iter,
+                    factor,
+                    verbose,
+                    &lab_pixels,
+                    seed + i as u64,
+                )
+            };
+            result.keep_best(run_result, seed + i as u64);
+        }
+
+        // The neutral color is the least saturated color below the threshold
+        let res = Lab::<D65, f32>::sort_indexed_colors(&result.centroids, &result.indices);
+        let neutral = res
+            .iter()
+            .map(|x| {
+                (
+                    x.centroid,
+                    (x.centroid.a * x.centroid.a + x.centroid.b * x.centroid.b).sqrt(),
+                )
+            })
+            .filter(|x| x.1 <= max_chroma)
+            .min_by(|a, b| (a.1).total_cmp(&b.1))
+            .map(|x| x.0);
+
+        let neutral = match neutral {
+            Some(x) => x,
+            None => {
+                eprintln!("No neutral color found in {}", file.to_string_lossy());
+                continue;
+            }
+        };
+
+        println!(
+            "{:x},{:.2},{:.2}",
+            Srgb::<u8>::from_linear(lab_to_linear(neutral)),
+            neutral.a,
+            neutral.b
+        );
+
+        if correct {
+            // Shift every pixel by the opposite of the color cast
+            let rgb: Vec<Srgb<u8>> = lab_pixels
+                .iter()
+                .map(|x| {
+                    Srgb::from_linear(lab_to_linear(Lab::<D65, f32>::new(
+                        x.l,
+                        x.a - neutral.a,
+                        x.b - neutral.b,
+                    )))
+                })
+                .collect();
+
+            save_image(
+                rgb.as_components(),
+                imgx,
+                imgy,
+                &create_filename(&input, &output, "png", None, file)?,
+                false,
+            )?;
+        }
+    }
+
+    Ok(())
+}
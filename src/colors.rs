@@ -1,4 +1,30 @@
+mod average;
+mod cache;
+mod histogram;
+#[cfg(feature = "image")]
+mod image;
+mod interpolate;
 mod kmeans;
+mod lut;
+#[cfg(feature = "raw")]
+mod raw;
+mod reduced;
 mod sort;
+mod ycbcr;
 
-pub use self::kmeans::MapColor;
+pub use self::average::{average_color, average_color_weighted};
+pub use self::cache::{ConversionCache, ConvertSrgb};
+pub use self::histogram::{dedup_weighted, ColorHistogram};
+#[cfg(feature = "image")]
+pub use self::image::{kmeans_from_image, kmeans_from_thumbnail, quantize_image};
+pub use self::interpolate::{interpolate_colors, Interpolation};
+pub use self::kmeans::{Assignment, MapColor};
+pub use self::lut::ConversionLut;
+#[cfg(feature = "raw")]
+pub use self::raw::{RawFormat, RawImage, RawImageError};
+pub use self::reduced::{ChromaOnly, HueOnly};
+pub use self::sort::{
+    centroids_from_hex, centroids_from_srgb, centroids_to_hex, centroids_to_srgb,
+    sort_by_oklab_lightness,
+};
+pub use self::ycbcr::{Bt601, Bt709, Subsampling, YCbCr, YCbCrRange, YCbCrStandard};
@@ -0,0 +1,90 @@
+use num_traits::{Float, FromPrimitive};
+use palette::cast::{self, ArrayCast};
+
+use crate::colors::kmeans::ClusterSum;
+
+/// Find the average color of a buffer, or `None` if it has no finite colors.
+///
+/// This is the centroid k-means finds with `k = 1`, computed exactly in a
+/// single pass without initialization or iterations. The mean is taken in the
+/// color space of the buffer, so averaging `Lab` and `Srgb` colors gives
+/// different results. Colors with non-finite components are skipped.
+///
+/// ```
+/// use kmeans_colors::average_color;
+/// use palette::Srgb;
+///
+/// let buf = [Srgb::new(0.0, 0.5, 1.0), Srgb::new(1.0, 0.0, 0.0)];
+/// assert_eq!(average_color(&buf), Some(Srgb::new(0.5, 0.25, 0.5)));
+/// assert_eq!(average_color::<Srgb, _>(&[]), None);
+/// ```
+pub fn average_color<C, T>(buf: &[C]) -> Option<C>
+where
+    C: ArrayCast<Array = [T; 3]> + Copy,
+    T: Float + FromPrimitive,
+{
+    let mut sum = ClusterSum::default();
+    for &color in buf {
+        sum.add(cast::into_array(color), 1.0);
+    }
+
+    sum.mean().map(cast::from_array)
+}
+
+/// Find the average color of a buffer of weighted colors, or `None` if it has
+/// no finite colors with a positive weight.
+///
+/// A color with weight `w` contributes as if it appeared `w` times, as in
+/// [`get_kmeans_weighted`](fn.get_kmeans_weighted.html), so the average of a
+/// [`ColorHistogram`](struct.ColorHistogram.html) is the average of the image.
+///
+/// # Panics
+///
+/// Panics if `weights` is not the same length as `buf`.
+pub fn average_color_weighted<C, T>(buf: &[C], weights: &[f32]) -> Option<C>
+where
+    C: ArrayCast<Array = [T; 3]> + Copy,
+    T: Float + FromPrimitive,
+{
+    assert_eq!(buf.len(), weights.len());
+
+    let mut sum = ClusterSum::default();
+    for (&color, &weight) in buf.iter().zip(weights) {
+        sum.add(cast::into_array(color), f64::from(weight));
+    }
+
+    sum.mean().map(cast::from_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_kmeans;
+    use palette::Srgb;
+
+    #[test]
+    fn average_matches_kmeans() {
+        let buf: Vec<Srgb> = (0..100u8)
+            .map(|i| Srgb::new(i, i / 2, 255 - i).into_format())
+            .collect();
+        let average = average_color(&buf).unwrap();
+        let result = get_kmeans(1, 20, 0.0, false, &buf, 0);
+        assert!((average.red - result.centroids[0].red).abs() < 1e-6);
+        assert!((average.green - result.centroids[0].green).abs() < 1e-6);
+        assert!((average.blue - result.centroids[0].blue).abs() < 1e-6);
+
+        // Non-finite colors and non-positive weights are skipped
+        let buf = [
+            Srgb::new(0.0, 0.0, 0.0),
+            Srgb::new(1.0, 1.0, 1.0),
+            Srgb::new(f32::NAN, 0.0, 0.0),
+            Srgb::new(0.5, 0.5, 0.5),
+        ];
+        let weights = [1.0, 3.0, 1.0, 0.0];
+        assert_eq!(
+            average_color_weighted(&buf, &weights),
+            Some(Srgb::new(0.75, 0.75, 0.75))
+        );
+        assert_eq!(average_color(&buf[2..3]), None);
+    }
+}
@@ -0,0 +1,121 @@
+use fxhash::FxHashMap;
+use palette::chromatic_adaptation::AdaptFrom;
+use palette::white_point::{WhitePoint, D65};
+use palette::{IntoColor, Lab, Oklab, Srgb};
+
+/// A trait for converting 8-bit `Srgb` colors into a color space used for
+/// k-means calculation.
+pub trait ConvertSrgb: Copy {
+    /// Convert an 8-bit `Srgb` color.
+    fn from_srgb(color: Srgb<u8>) -> Self;
+}
+
+impl<Wp: WhitePoint<f32>> ConvertSrgb for Lab<Wp, f32> {
+    /// Colors are adapted from the D65 white point of `Srgb` with the Bradford
+    /// method when `Wp` is not D65.
+    #[inline]
+    fn from_srgb(color: Srgb<u8>) -> Self {
+        // Converting from Srgb to linear Srgb is special-cased in `palette` to
+        // use a lookup table
+        let linear = color.into_linear::<f32>();
+        if core::any::TypeId::of::<Wp>() == core::any::TypeId::of::<D65>() {
+            let lab: Lab<D65, f32> = linear.into_color();
+            Lab::new(lab.l, lab.a, lab.b)
+        } else {
+            Lab::adapt_from(linear)
+        }
+    }
+}
+
+impl ConvertSrgb for Oklab<f32> {
+    #[inline]
+    fn from_srgb(color: Srgb<u8>) -> Self {
+        color.into_linear::<f32>().into_color()
+    }
+}
+
+impl ConvertSrgb for Srgb<f32> {
+    #[inline]
+    fn from_srgb(color: Srgb<u8>) -> Self {
+        color.into_format()
+    }
+}
+
+/// Cache of `Srgb<u8>` color conversions.
+///
+/// Images often contain far fewer unique colors than pixels, so caching the
+/// result of expensive conversions like `Srgb` to `Lab` is much faster than
+/// converting every pixel. The cache can be reused between images.
+///
+/// A capacity limit can be set to bound memory use for noisy images. Once the
+/// limit is reached, new colors are converted without being cached.
+///
+/// ```
+/// use kmeans_colors::ConversionCache;
+/// use palette::{Lab, Srgb};
+///
+/// let pixels = [Srgb::new(0u8, 0, 0), Srgb::new(255, 255, 255), Srgb::new(0, 0, 0)];
+/// let mut cache = ConversionCache::<Lab>::new();
+/// let mut lab = Vec::new();
+/// cache.extend(pixels.iter().copied(), &mut lab);
+/// assert_eq!(lab.len(), 3);
+/// assert_eq!(cache.len(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConversionCache<C> {
+    map: FxHashMap<[u8; 3], C>,
+    limit: Option<usize>,
+}
+
+impl<C: ConvertSrgb> ConversionCache<C> {
+    /// Create a new, unbounded `ConversionCache`.
+    pub fn new() -> Self {
+        ConversionCache {
+            map: FxHashMap::default(),
+            limit: None,
+        }
+    }
+
+    /// Create a new `ConversionCache` which stores at most `limit` colors.
+    pub fn with_limit(limit: usize) -> Self {
+        ConversionCache {
+            map: FxHashMap::default(),
+            limit: Some(limit),
+        }
+    }
+
+    /// Convert a color, using the cached result if it exists.
+    #[inline]
+    pub fn convert(&mut self, color: Srgb<u8>) -> C {
+        let key = [color.red, color.green, color.blue];
+        if let Some(&converted) = self.map.get(&key) {
+            return converted;
+        }
+
+        let converted = C::from_srgb(color);
+        if !matches!(self.limit, Some(limit) if self.map.len() >= limit) {
+            let _ = self.map.insert(key, converted);
+        }
+        converted
+    }
+
+    /// Convert colors and append them to `out`.
+    pub fn extend(&mut self, colors: impl IntoIterator<Item = Srgb<u8>>, out: &mut Vec<C>) {
+        out.extend(colors.into_iter().map(|color| self.convert(color)));
+    }
+
+    /// Returns the number of cached colors.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if no colors are cached.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Remove all cached colors.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
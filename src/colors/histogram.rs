@@ -0,0 +1,204 @@
+use fxhash::FxHashMap;
+use palette::cast::{self, ArrayCast};
+use palette::Srgb;
+
+use crate::colors::ConvertSrgb;
+use crate::kmeans::{get_kmeans_best, Hamerly, Kmeans, KmeansConfig, Weighted};
+
+/// Counts of the unique colors of an image, for clustering images too large to
+/// convert in full.
+///
+/// Pixels can be added in any number of strips or tiles, e.g. as they're
+/// decoded, and histograms of separate tiles can be merged. Memory use is
+/// bounded by the number of unique colors instead of the number of pixels, and
+/// the result doesn't depend on the order the pixels were added.
+///
+/// The unique colors are clustered with weighted k-means, where each color is
+/// weighted by its count, which finds the same centroids as clustering every
+/// pixel up to the initial choice of centroids. The strips can then be mapped
+/// to the centroids one at a time.
+///
+/// ```
+/// use kmeans_colors::{ColorHistogram, KmeansConfig};
+/// use palette::{Lab, Srgb};
+///
+/// let mut histogram = ColorHistogram::new();
+/// // Add each strip of the image
+/// histogram.add([Srgb::new(0u8, 0, 0), Srgb::new(255, 255, 255)]);
+/// histogram.add([Srgb::new(0u8, 0, 0), Srgb::new(0, 0, 0)]);
+/// assert_eq!(histogram.len(), 2);
+/// assert_eq!(histogram.pixel_count(), 4);
+///
+/// let config = KmeansConfig { k: 2, ..Default::default() };
+/// let result = histogram.kmeans::<Lab>(&config);
+/// assert_eq!(result.centroids.len(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ColorHistogram {
+    counts: FxHashMap<[u8; 3], u64>,
+    pixels: u64,
+}
+
+impl ColorHistogram {
+    /// Create a new, empty `ColorHistogram`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the colors of a strip or tile of pixels.
+    pub fn add(&mut self, pixels: impl IntoIterator<Item = Srgb<u8>>) {
+        for color in pixels {
+            *self
+                .counts
+                .entry([color.red, color.green, color.blue])
+                .or_insert(0) += 1;
+            self.pixels += 1;
+        }
+    }
+
+    /// Add the counts of another histogram, such as from a tile processed on
+    /// another thread.
+    pub fn merge(&mut self, other: &ColorHistogram) {
+        for (&key, &count) in &other.counts {
+            *self.counts.entry(key).or_insert(0) += count;
+        }
+        self.pixels += other.pixels;
+    }
+
+    /// Returns the number of unique colors.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns `true` if no pixels have been added.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Returns the number of pixels added.
+    pub fn pixel_count(&self) -> u64 {
+        self.pixels
+    }
+
+    /// Returns the unique colors converted to `C` and their counts, ordered by
+    /// their `Srgb` components.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn colors<C: ConvertSrgb>(&self) -> (Vec<C>, Vec<f32>) {
+        let mut entries: Vec<_> = self.counts.iter().collect();
+        entries.sort_unstable_by_key(|x| x.0);
+
+        entries
+            .into_iter()
+            .map(|(&[r, g, b], &count)| (C::from_srgb(Srgb::new(r, g, b)), count as f32))
+            .unzip()
+    }
+
+    /// Find the k-means centroids of the counted colors.
+    ///
+    /// The calculation is repeated `config.runs` times with incrementing seeds
+    /// and the result with the lowest score is returned, along with the score
+    /// of each run in `runs`, as in
+    /// [`get_kmeans_best`](fn.get_kmeans_best.html). The `indices` of the result correspond to the
+    /// colors returned by [`colors`](#method.colors), not to pixels.
+    pub fn kmeans<C>(&self, config: &KmeansConfig) -> Kmeans<C>
+    where
+        C: ConvertSrgb + Hamerly + Weighted + Clone,
+    {
+        if self.is_empty() {
+            return Kmeans::new();
+        }
+
+        let (colors, weights) = self.colors::<C>();
+        get_kmeans_best(config, &colors, Some(&weights))
+    }
+}
+
+/// Collapse a buffer into its unique points and the number of times each
+/// appears, to cluster with the weighted k-means functions such as
+/// [`get_kmeans_best`](fn.get_kmeans_best.html).
+///
+/// Photos usually have far fewer unique colors than pixels, so clustering the
+/// unique colors weighted by their counts is much faster and finds the same
+/// centroids, up to the initial choice of centroids. Points are unique if
+/// their components are bitwise equal, and are returned in the order they
+/// first appear. Use [`Calculate::get_closest_centroid`] to index the original
+/// buffer with the resulting centroids.
+///
+/// Unlike [`ColorHistogram`], this works on any color type with three
+/// components, such as `Srgb<u8>` or already converted `Lab` colors.
+///
+/// [`Calculate::get_closest_centroid`]: trait.Calculate.html#tymethod.get_closest_centroid
+///
+/// ```
+/// use kmeans_colors::{dedup_weighted, get_kmeans_best, KmeansConfig};
+/// use palette::Lab;
+///
+/// let black: Lab = Lab::new(0.0, 0.0, 0.0);
+/// let white: Lab = Lab::new(100.0, 0.0, 0.0);
+/// let (colors, counts) = dedup_weighted(&[black, white, white, black, white]);
+/// assert_eq!(colors, [black, white]);
+/// assert_eq!(counts, [2.0, 3.0]);
+///
+/// let config = KmeansConfig { k: 2, ..Default::default() };
+/// let result = get_kmeans_best(&config, &colors, Some(&counts));
+/// assert_eq!(result.centroids.len(), 2);
+/// ```
+#[allow(clippy::cast_precision_loss)]
+pub fn dedup_weighted<C, T>(buf: &[C]) -> (Vec<C>, Vec<f32>)
+where
+    C: ArrayCast<Array = [T; 3]> + Copy,
+    T: Into<f64> + Copy,
+{
+    let mut positions: FxHashMap<[u64; 3], usize> = FxHashMap::default();
+    let mut colors = Vec::new();
+    let mut counts: Vec<u64> = Vec::new();
+    for &color in buf {
+        let key = cast::into_array(color).map(|x| x.into().to_bits());
+        let position = *positions.entry(key).or_insert_with(|| {
+            colors.push(color);
+            counts.push(0);
+            colors.len() - 1
+        });
+        counts[position] += 1;
+    }
+
+    (colors, counts.into_iter().map(|x| x as f32).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_kmeans_hamerly, get_kmeans_hamerly_weighted};
+    use palette::Lab;
+
+    #[test]
+    fn dedup() {
+        let buf: Vec<Srgb<u8>> = (0..1000u32)
+            .map(|i| Srgb::new((i % 7) as u8 * 30, (i % 3) as u8 * 80, 0))
+            .collect();
+        let (colors, counts) = dedup_weighted(&buf);
+        assert_eq!(colors.len(), 21);
+        assert_eq!(counts.iter().sum::<f32>(), 1000.0);
+        assert_eq!(colors[0], buf[0]);
+
+        // Clustering the unique colors finds the centroids of every pixel
+        let lab: Vec<Lab> = buf.iter().map(|&x| Lab::from_srgb(x)).collect();
+        let (unique, weights) = dedup_weighted(&lab);
+        assert_eq!(unique.len(), 21);
+        let full = get_kmeans_hamerly(21, 20, 0.0, false, &lab, 0);
+        let weighted = get_kmeans_hamerly_weighted(21, 20, 0.0, false, &unique, &weights, 0);
+        let mut a: Vec<[u32; 3]> = full
+            .centroids
+            .iter()
+            .map(|&x| cast::into_array(x).map(|c: f32| (c * 100.0).round().to_bits()))
+            .collect();
+        let mut b: Vec<[u32; 3]> = weighted
+            .centroids
+            .iter()
+            .map(|&x| cast::into_array(x).map(|c: f32| (c * 100.0).round().to_bits()))
+            .collect();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,109 @@
+use image::{DynamicImage, RgbImage};
+use palette::cast::ComponentsAs;
+use palette::{white_point::D65, IntoColor, Lab, Srgb};
+
+use crate::colors::ConversionCache;
+use crate::kmeans::{get_kmeans_best, Calculate, Kmeans, KmeansConfig};
+
+/// Find the k-means colors of an image in the `Lab` color space.
+///
+/// The image is converted to 8-bit `Srgb`, ignoring any alpha channel. The
+/// calculation is repeated `config.runs` times with incrementing seeds and the
+/// result with the lowest score is returned, along with the score of each run
+/// in `runs`, as in [`get_kmeans_best`](fn.get_kmeans_best.html).
+///
+/// ```
+/// use kmeans_colors::{kmeans_from_image, quantize_image, KmeansConfig};
+///
+/// let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(
+///     2, 1, vec![0, 0, 0, 255, 255, 255]).unwrap());
+/// let config = KmeansConfig { k: 2, ..Default::default() };
+///
+/// let result = kmeans_from_image(&img, &config);
+/// let quantized = quantize_image(&img, &result);
+/// # assert_eq!(quantized.as_raw(), &[0, 0, 0, 255, 255, 255]);
+/// ```
+pub fn kmeans_from_image(img: &DynamicImage, config: &KmeansConfig) -> Kmeans<Lab<D65, f32>> {
+    get_kmeans_best(
+        config,
+        &image_to_lab(img, &mut ConversionCache::new()),
+        None,
+    )
+}
+
+/// Find the k-means colors of an image like
+/// [`kmeans_from_image`](fn.kmeans_from_image.html), clustering a thumbnail of
+/// at most `size` pixels on its longest side, then indexing every pixel of
+/// the full image with its closest color.
+///
+/// Clustering a thumbnail is much faster for large photos and finds nearly
+/// the same colors. Conversions of the thumbnail pixels are cached and reused
+/// for the full image. Images no larger than `size` are clustered directly.
+///
+/// ```
+/// use kmeans_colors::{kmeans_from_thumbnail, KmeansConfig};
+///
+/// let mut img = image::RgbImage::new(300, 200);
+/// for (x, _, pixel) in img.enumerate_pixels_mut() {
+///     *pixel = if x < 150 { image::Rgb([200, 30, 30]) } else { image::Rgb([20, 20, 220]) };
+/// }
+/// let img = image::DynamicImage::ImageRgb8(img);
+/// let config = KmeansConfig { k: 2, ..Default::default() };
+///
+/// let result = kmeans_from_thumbnail(&img, &config, 64);
+/// assert_eq!(result.centroids.len(), 2);
+/// assert_eq!(result.indices.len(), 300 * 200);
+/// ```
+pub fn kmeans_from_thumbnail(
+    img: &DynamicImage,
+    config: &KmeansConfig,
+    size: u32,
+) -> Kmeans<Lab<D65, f32>> {
+    if img.width().max(img.height()) <= size {
+        return kmeans_from_image(img, config);
+    }
+
+    let mut cache = ConversionCache::new();
+    let thumbnail = image_to_lab(&img.thumbnail(size, size), &mut cache);
+    let mut result = get_kmeans_best(config, &thumbnail, None);
+
+    let lab = image_to_lab(img, &mut cache);
+    result.indices.clear();
+    Lab::<D65, f32>::get_closest_centroid(&lab, &result.centroids, &mut result.indices);
+    result
+}
+
+/// Map each pixel of an image to its closest k-means color.
+///
+/// The image does not need to be the one the k-means were calculated from, so
+/// a full-size image can be quantized with colors found from a thumbnail.
+pub fn quantize_image(img: &DynamicImage, kmeans: &Kmeans<Lab<D65, f32>>) -> RgbImage {
+    let lab = image_to_lab(img, &mut ConversionCache::new());
+    let mut indices = Vec::with_capacity(lab.len());
+    Lab::<D65, f32>::get_closest_centroid(&lab, &kmeans.centroids, &mut indices);
+
+    let centroids: Vec<[u8; 3]> = kmeans
+        .centroids
+        .iter()
+        .map(|&x| Srgb::<u8>::from_linear(x.into_color()).into())
+        .collect();
+    let buffer: Vec<u8> = indices
+        .iter()
+        .flat_map(|&i| centroids.get(i as usize).copied().unwrap_or_default())
+        .collect();
+
+    RgbImage::from_raw(img.width(), img.height(), buffer).unwrap()
+}
+
+/// Convert an image to a buffer of `Lab` pixels, caching the conversions.
+fn image_to_lab(
+    img: &DynamicImage,
+    cache: &mut ConversionCache<Lab<D65, f32>>,
+) -> Vec<Lab<D65, f32>> {
+    let rgb = img.to_rgb8();
+    let pixels: &[Srgb<u8>] = rgb.as_raw().components_as();
+
+    let mut lab = Vec::with_capacity(pixels.len());
+    cache.extend(pixels.iter().copied(), &mut lab);
+    lab
+}
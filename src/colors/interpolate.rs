@@ -0,0 +1,101 @@
+use palette::{white_point::D65, Clamp, IntoColor, Lch, Mix, Oklab, Srgb};
+
+/// Color space used for interpolating between colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Interpolate in `Oklab`, which gives perceptually even steps without
+    /// hue shifts.
+    Oklab,
+    /// Interpolate in `Lch`, taking the shorter way around the hue circle,
+    /// which keeps colors saturated between distant hues.
+    Lch,
+}
+
+/// Generate `n` colors evenly spaced along a palette, passing through each
+/// color in order.
+///
+/// Each pair of neighboring colors gets an equal share of the `n` colors, so
+/// the palette should be sorted beforehand, e.g. by luminosity with
+/// [`Sort::sort_indexed_colors`](trait.Sort.html#tymethod.sort_indexed_colors).
+/// The first and last colors are always included when `n` is at least 2.
+/// Results are clamped to the `Srgb` gamut.
+///
+/// ```
+/// use kmeans_colors::{interpolate_colors, Interpolation};
+/// use palette::Srgb;
+///
+/// let palette = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)];
+/// let gradient = interpolate_colors(&palette, 5, Interpolation::Oklab);
+/// assert_eq!(gradient.len(), 5);
+/// ```
+pub fn interpolate_colors(colors: &[Srgb], n: usize, space: Interpolation) -> Vec<Srgb> {
+    match space {
+        Interpolation::Oklab => interpolate::<Oklab>(colors, n),
+        Interpolation::Lch => interpolate::<Lch<D65, f32>>(colors, n),
+    }
+}
+
+/// Interpolate colors in the space `C`.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn interpolate<C>(colors: &[Srgb], n: usize) -> Vec<Srgb>
+where
+    C: Mix<Scalar = f32> + IntoColor<palette::LinSrgb> + Copy,
+    palette::LinSrgb: IntoColor<C>,
+{
+    let converted: Vec<C> = colors
+        .iter()
+        .map(|x| x.into_linear().into_color())
+        .collect();
+    let (first, segments) = match converted.split_first() {
+        Some((&first, rest)) => (first, rest.len()),
+        None => return Vec::new(),
+    };
+
+    (0..n)
+        .map(|i| {
+            let color = if segments == 0 || n < 2 {
+                first
+            } else {
+                // Position along the palette, from 0 to the number of segments
+                let t = i as f32 / (n - 1) as f32 * segments as f32;
+                let segment = (t.floor() as usize).min(segments - 1);
+                converted[segment].mix(converted[segment + 1], t - segment as f32)
+            };
+            let linear: palette::LinSrgb = color.into_color();
+            Srgb::from_linear(linear.clamp())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_colors_endpoints() {
+        let palette = [
+            Srgb::new(0.0, 0.0, 0.0),
+            Srgb::new(1.0, 0.0, 0.0),
+            Srgb::new(1.0, 1.0, 1.0),
+        ];
+        for space in [Interpolation::Oklab, Interpolation::Lch] {
+            let colors = interpolate_colors(&palette, 9, space);
+            assert_eq!(colors.len(), 9);
+            for (color, expected) in [colors[0], colors[4], colors[8]].iter().zip(&palette) {
+                assert!((color.red - expected.red).abs() < 1e-3);
+                assert!((color.green - expected.green).abs() < 1e-3);
+                assert!((color.blue - expected.blue).abs() < 1e-3);
+            }
+        }
+
+        assert!(interpolate_colors(&[], 4, Interpolation::Oklab).is_empty());
+        assert_eq!(
+            interpolate_colors(&palette[..1], 3, Interpolation::Oklab).len(),
+            3
+        );
+    }
+}
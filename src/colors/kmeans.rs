@@ -1,11 +1,57 @@
 #[cfg(feature = "palette_color")]
 use num_traits::{Float, FromPrimitive, Zero};
 #[cfg(feature = "palette_color")]
-use palette::{rgb::Rgb, rgb::Rgba, Lab};
+use palette::{rgb::Rgb, white_point::WhitePoint, Lab, Xyz, Yxy};
 
 use rand::Rng;
 
-use crate::kmeans::{Calculate, Hamerly, HamerlyCentroids, HamerlyPoint};
+use crate::kdtree::Axes;
+use crate::kmeans::{Calculate, Elkan, Hamerly, HamerlyCentroids, HamerlyPoint, Weighted};
+
+/// Returns `true` if all components are finite. Points with NaN or infinite
+/// components are skipped when recalculating centroids.
+#[cfg(feature = "palette_color")]
+#[inline]
+fn is_finite<T: Float>(components: [T; 3]) -> bool {
+    components.iter().all(|x| x.is_finite())
+}
+
+/// Running sum of the components of a cluster and its total weight.
+///
+/// Sums are accumulated in `f64` so that large clusters don't lose precision
+/// and bias their centroids, as `f32` sums do past a few million points.
+#[cfg(feature = "palette_color")]
+#[derive(Clone, Copy, Default)]
+pub(super) struct ClusterSum {
+    sum: [f64; 3],
+    weight: f64,
+}
+
+#[cfg(feature = "palette_color")]
+impl ClusterSum {
+    /// Add a point with `weight`, skipping points with non-finite components
+    /// and weights that aren't positive and finite.
+    #[inline]
+    pub(super) fn add<T: Float>(&mut self, components: [T; 3], weight: f64) {
+        if weight > 0.0 && weight.is_finite() && is_finite(components) {
+            for (sum, x) in self.sum.iter_mut().zip(components) {
+                *sum += x.to_f64().unwrap_or(0.0) * weight;
+            }
+            self.weight += weight;
+        }
+    }
+
+    /// Returns the weighted mean of the points added, or `None` if no points
+    /// were added.
+    #[inline]
+    pub(super) fn mean<T: FromPrimitive>(&self) -> Option<[T; 3]> {
+        if self.weight > 0.0 {
+            Some(self.sum.map(|x| T::from_f64(x / self.weight).unwrap()))
+        } else {
+            None
+        }
+    }
+}
 
 #[cfg(feature = "palette_color")]
 impl<Wp, T> Calculate for Lab<Wp, T>
@@ -14,11 +60,11 @@ where
     Lab<Wp, T>: core::ops::AddAssign<Lab<Wp, T>> + Default,
 {
     #[allow(clippy::cast_possible_truncation)]
-    fn get_closest_centroid(lab: &[Lab<Wp, T>], centroids: &[Lab<Wp, T>], indices: &mut Vec<u8>) {
+    fn get_closest_centroid(lab: &[Lab<Wp, T>], centroids: &[Lab<Wp, T>], indices: &mut Vec<u32>) {
         for color in lab.iter() {
             let mut index = 0;
             let mut diff;
-            let mut min = core::f32::MAX;
+            let mut min = f32::MAX;
             for (idx, cent) in centroids.iter().enumerate() {
                 diff = Self::difference(color, cent);
                 if diff < min {
@@ -26,32 +72,66 @@ where
                     index = idx;
                 }
             }
-            indices.push(index as u8);
+            indices.push(index as u32);
         }
     }
 
-    #[allow(clippy::cast_precision_loss)]
     fn recalculate_centroids(
         mut rng: &mut impl Rng,
         buf: &[Lab<Wp, T>],
         centroids: &mut [Lab<Wp, T>],
-        indices: &[u8],
+        indices: &[u32],
     ) {
-        for (idx, cent) in centroids.iter_mut().enumerate() {
-            let mut temp = Lab::<Wp, T>::default();
-            let mut counter: u64 = 0;
-            for (&jdx, &color) in indices.iter().zip(buf) {
-                if jdx as usize == idx {
-                    temp += color;
-                    counter += 1;
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.l, color.a, color.b], 1.0);
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([l, a, b]) => Lab::new(l, a, b),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn assign_and_recalculate(
+        mut rng: &mut impl Rng,
+        buf: &[Lab<Wp, T>],
+        centroids: &mut [Lab<Wp, T>],
+        indices: &mut Vec<u32>,
+    ) {
+        // Index each color and accumulate the sum and count of its cluster in
+        // the same pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
                 }
             }
-            if counter != 0 {
-                *cent = temp / T::from_f64(counter as f64).unwrap();
-            } else {
-                *cent = Self::create_random(&mut rng);
+            indices.push(index as u32);
+
+            if let Some(sum) = sums.get_mut(index) {
+                sum.add([color.l, color.a, color.b], 1.0);
             }
         }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([l, a, b]) => Lab::new(l, a, b),
+                None => Self::create_random(&mut rng),
+            };
+        }
     }
 
     fn check_loop(centroids: &[Lab<Wp, T>], old_centroids: &[Lab<Wp, T>]) -> f32 {
@@ -60,7 +140,7 @@ where
             temp += c0 - c1;
         }
 
-        ((temp.l).powi(2) + (temp.a).powi(2) + (temp.b).powi(2))
+        (temp.l * temp.l + temp.a * temp.a + temp.b * temp.b)
             .to_f32()
             .unwrap_or(f32::MAX)
     }
@@ -76,9 +156,11 @@ where
 
     #[inline]
     fn difference(c1: &Lab<Wp, T>, c2: &Lab<Wp, T>) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
         let temp = *c1 - *c2;
 
-        ((temp.l).powi(2) + (temp.a).powi(2) + (temp.b).powi(2))
+        (temp.l * temp.l + temp.a * temp.a + temp.b * temp.b)
             .to_f32()
             .unwrap_or(f32::MAX)
     }
@@ -90,12 +172,14 @@ where
     T: Float + FromPrimitive + Zero,
     Rgb<S, T>: core::ops::AddAssign<Rgb<S, T>> + Default,
 {
+    const CONVERGE: f32 = 0.0025;
+
     #[allow(clippy::cast_possible_truncation)]
-    fn get_closest_centroid(rgb: &[Rgb<S, T>], centroids: &[Rgb<S, T>], indices: &mut Vec<u8>) {
+    fn get_closest_centroid(rgb: &[Rgb<S, T>], centroids: &[Rgb<S, T>], indices: &mut Vec<u32>) {
         for color in rgb.iter() {
             let mut index = 0;
             let mut diff;
-            let mut min = core::f32::MAX;
+            let mut min = f32::MAX;
             for (idx, cent) in centroids.iter().enumerate() {
                 diff = Self::difference(color, cent);
                 if diff < min {
@@ -103,32 +187,66 @@ where
                     index = idx;
                 }
             }
-            indices.push(index as u8);
+            indices.push(index as u32);
         }
     }
 
-    #[allow(clippy::cast_precision_loss)]
     fn recalculate_centroids(
         mut rng: &mut impl Rng,
         buf: &[Rgb<S, T>],
         centroids: &mut [Rgb<S, T>],
-        indices: &[u8],
+        indices: &[u32],
     ) {
-        for (idx, cent) in centroids.iter_mut().enumerate() {
-            let mut temp = Rgb::<S, T>::new(T::zero(), T::zero(), T::zero());
-            let mut counter: u64 = 0;
-            for (&jdx, &color) in indices.iter().zip(buf) {
-                if jdx as usize == idx {
-                    temp += color;
-                    counter += 1;
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.red, color.green, color.blue], 1.0);
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([red, green, blue]) => Rgb::new(red, green, blue),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn assign_and_recalculate(
+        mut rng: &mut impl Rng,
+        buf: &[Rgb<S, T>],
+        centroids: &mut [Rgb<S, T>],
+        indices: &mut Vec<u32>,
+    ) {
+        // Index each color and accumulate the sum and count of its cluster in
+        // the same pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
                 }
             }
-            if counter != 0 {
-                *cent = temp / T::from_f64(counter as f64).unwrap();
-            } else {
-                *cent = Self::create_random(&mut rng);
+            indices.push(index as u32);
+
+            if let Some(sum) = sums.get_mut(index) {
+                sum.add([color.red, color.green, color.blue], 1.0);
             }
         }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([red, green, blue]) => Rgb::new(red, green, blue),
+                None => Self::create_random(&mut rng),
+            };
+        }
     }
 
     fn check_loop(centroids: &[Rgb<S, T>], old_centroids: &[Rgb<S, T>]) -> f32 {
@@ -137,7 +255,7 @@ where
             temp += c0 - c1;
         }
 
-        ((temp.red).powi(2) + (temp.green).powi(2) + (temp.blue).powi(2))
+        (temp.red * temp.red + temp.green * temp.green + temp.blue * temp.blue)
             .to_f32()
             .unwrap_or(f32::MAX)
     }
@@ -153,14 +271,274 @@ where
 
     #[inline]
     fn difference(c1: &Rgb<S, T>, c2: &Rgb<S, T>) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
+        let temp = *c1 - *c2;
+
+        (temp.red * temp.red + temp.green * temp.green + temp.blue * temp.blue)
+            .to_f32()
+            .unwrap_or(f32::MAX)
+    }
+}
+
+#[cfg(feature = "palette_color")]
+impl<Wp, T> Calculate for Xyz<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Xyz<Wp, T>: core::ops::AddAssign<Xyz<Wp, T>> + Default,
+    Wp: WhitePoint<T>,
+{
+    const CONVERGE: f32 = 0.0025;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Xyz<Wp, T>], centroids: &[Xyz<Wp, T>], indices: &mut Vec<u32>) {
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Xyz<Wp, T>],
+        centroids: &mut [Xyz<Wp, T>],
+        indices: &[u32],
+    ) {
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.x, color.y, color.z], 1.0);
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([x, y, z]) => Xyz::new(x, y, z),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+
+    fn check_loop(centroids: &[Xyz<Wp, T>], old_centroids: &[Xyz<Wp, T>]) -> f32 {
+        let mut temp = Xyz::<Wp, T>::default();
+        for (&c0, &c1) in centroids.iter().zip(old_centroids) {
+            temp += c0 - c1;
+        }
+
+        (temp.x * temp.x + temp.y * temp.y + temp.z * temp.z)
+            .to_f32()
+            .unwrap_or(f32::MAX)
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Xyz<Wp, T> {
+        // Random points are within the box bounded by the white point
+        let white = Wp::get_xyz();
+        Xyz::<Wp, T>::new(
+            white.x * T::from_f64(rng.gen_range(0.0..=1.0)).unwrap(),
+            white.y * T::from_f64(rng.gen_range(0.0..=1.0)).unwrap(),
+            white.z * T::from_f64(rng.gen_range(0.0..=1.0)).unwrap(),
+        )
+    }
+
+    #[inline]
+    fn difference(c1: &Xyz<Wp, T>, c2: &Xyz<Wp, T>) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
+        let temp = *c1 - *c2;
+
+        (temp.x * temp.x + temp.y * temp.y + temp.z * temp.z)
+            .to_f32()
+            .unwrap_or(f32::MAX)
+    }
+}
+
+/// Distances are Euclidean over the `x` and `y` chromaticity and the `luma`,
+/// so chromaticity and luminance are weighted equally.
+#[cfg(feature = "palette_color")]
+impl<Wp, T> Calculate for Yxy<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Yxy<Wp, T>: core::ops::AddAssign<Yxy<Wp, T>> + Default,
+{
+    const CONVERGE: f32 = 0.0025;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Yxy<Wp, T>], centroids: &[Yxy<Wp, T>], indices: &mut Vec<u32>) {
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Yxy<Wp, T>],
+        centroids: &mut [Yxy<Wp, T>],
+        indices: &[u32],
+    ) {
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.x, color.y, color.luma], 1.0);
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([x, y, luma]) => Yxy::new(x, y, luma),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+
+    fn check_loop(centroids: &[Yxy<Wp, T>], old_centroids: &[Yxy<Wp, T>]) -> f32 {
+        let mut temp = Yxy::<Wp, T>::default();
+        for (&c0, &c1) in centroids.iter().zip(old_centroids) {
+            temp += c0 - c1;
+        }
+
+        (temp.x * temp.x + temp.y * temp.y + temp.luma * temp.luma)
+            .to_f32()
+            .unwrap_or(f32::MAX)
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Yxy<Wp, T> {
+        // Chromaticities are within the bounds of the spectral locus
+        Yxy::<Wp, T>::new(
+            T::from_f64(rng.gen_range(0.0036..=0.7347)).unwrap(),
+            T::from_f64(rng.gen_range(0.0048..=0.8338)).unwrap(),
+            T::from_f64(rng.gen_range(0.0..=1.0)).unwrap(),
+        )
+    }
+
+    #[inline]
+    fn difference(c1: &Yxy<Wp, T>, c2: &Yxy<Wp, T>) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
         let temp = *c1 - *c2;
 
-        ((temp.red).powi(2) + (temp.green).powi(2) + (temp.blue).powi(2))
+        (temp.x * temp.x + temp.y * temp.y + temp.luma * temp.luma)
             .to_f32()
             .unwrap_or(f32::MAX)
     }
 }
 
+#[cfg(feature = "palette_color")]
+impl<Wp, T> Axes for Lab<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Lab<Wp, T>: core::ops::AddAssign<Lab<Wp, T>> + Default,
+{
+    #[inline]
+    fn axes(&self) -> [f32; 3] {
+        [
+            self.l.to_f32().unwrap_or(f32::NAN),
+            self.a.to_f32().unwrap_or(f32::NAN),
+            self.b.to_f32().unwrap_or(f32::NAN),
+        ]
+    }
+}
+
+#[cfg(feature = "palette_color")]
+impl<S, T> Axes for Rgb<S, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Rgb<S, T>: core::ops::AddAssign<Rgb<S, T>> + Default,
+{
+    #[inline]
+    fn axes(&self) -> [f32; 3] {
+        [
+            self.red.to_f32().unwrap_or(f32::NAN),
+            self.green.to_f32().unwrap_or(f32::NAN),
+            self.blue.to_f32().unwrap_or(f32::NAN),
+        ]
+    }
+}
+
+#[cfg(feature = "palette_color")]
+impl<Wp, T> Weighted for Lab<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Lab<Wp, T>: core::ops::AddAssign<Lab<Wp, T>> + Default,
+{
+    fn recalculate_centroids_weighted(
+        mut rng: &mut impl Rng,
+        buf: &[Lab<Wp, T>],
+        weights: &[f32],
+        centroids: &mut [Lab<Wp, T>],
+        indices: &[u32],
+    ) {
+        // Accumulate the weighted sum and total weight of each cluster in a
+        // single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for ((&idx, color), &weight) in indices.iter().zip(buf).zip(weights) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.l, color.a, color.b], f64::from(weight));
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([l, a, b]) => Lab::new(l, a, b),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+}
+
+#[cfg(feature = "palette_color")]
+impl<S, T> Weighted for Rgb<S, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Rgb<S, T>: core::ops::AddAssign<Rgb<S, T>> + Default,
+{
+    fn recalculate_centroids_weighted(
+        mut rng: &mut impl Rng,
+        buf: &[Rgb<S, T>],
+        weights: &[f32],
+        centroids: &mut [Rgb<S, T>],
+        indices: &[u32],
+    ) {
+        // Accumulate the weighted sum and total weight of each cluster in a
+        // single pass
+        let mut sums = vec![ClusterSum::default(); centroids.len()];
+        for ((&idx, color), &weight) in indices.iter().zip(buf).zip(weights) {
+            if let Some(sum) = sums.get_mut(idx as usize) {
+                sum.add([color.red, color.green, color.blue], f64::from(weight));
+            }
+        }
+
+        for (cent, sum) in centroids.iter_mut().zip(sums) {
+            *cent = match sum.mean() {
+                Some([red, green, blue]) => Rgb::new(red, green, blue),
+                None => Self::create_random(&mut rng),
+            };
+        }
+    }
+}
+
 #[cfg(feature = "palette_color")]
 impl<Wp, T> Hamerly for Lab<Wp, T>
 where
@@ -198,14 +576,16 @@ where
         points: &mut [HamerlyPoint],
     ) {
         for (val, point) in buffer.iter().zip(points.iter_mut()) {
-            // Assign max of lower bound and half distance to z
+            // Assign max of lower bound and half distance to z. The bounds are
+            // compared strictly so that ties are resolved by the full search
+            // below, which picks the lowest index like `get_closest_centroid`.
             let z = centers
                 .half_distances
                 .get(point.index as usize)
                 .unwrap()
                 .max(point.lower_bound);
 
-            if point.upper_bound <= z {
+            if point.upper_bound < z {
                 continue;
             }
 
@@ -213,7 +593,7 @@ where
             point.upper_bound =
                 Self::difference(val, centers.centroids.get(point.index as usize).unwrap()).sqrt();
 
-            if point.upper_bound <= z {
+            if point.upper_bound < z {
                 continue;
             }
 
@@ -222,7 +602,7 @@ where
                 continue;
             }
 
-            let mut min1 = Self::difference(val, centers.centroids.get(0).unwrap());
+            let mut min1 = Self::difference(val, centers.centroids.first().unwrap());
             let mut min2 = f32::MAX;
             let mut c1 = 0;
             for j in 1..centers.centroids.len() {
@@ -238,44 +618,40 @@ where
                 }
             }
 
-            if c1 as u8 != point.index {
-                point.index = c1 as u8;
+            if c1 as u32 != point.index {
+                point.index = c1 as u32;
                 point.upper_bound = min1.sqrt();
             }
             point.lower_bound = min2.sqrt();
         }
     }
 
-    #[allow(clippy::cast_precision_loss)]
     fn recalculate_centroids_hamerly(
         mut rng: &mut impl Rng,
         buf: &[Self],
         centers: &mut HamerlyCentroids<Self>,
         points: &[HamerlyPoint],
     ) {
-        for ((idx, cent), delta) in centers
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centers.centroids.len()];
+        for (point, color) in points.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(point.index as usize) {
+                sum.add([color.l, color.a, color.b], 1.0);
+            }
+        }
+
+        for ((cent, delta), sum) in centers
             .centroids
             .iter_mut()
-            .enumerate()
             .zip(centers.deltas.iter_mut())
+            .zip(sums)
         {
-            let mut temp = Lab::<Wp, T>::default();
-            let mut counter: u64 = 0;
-            for (point, &color) in points.iter().zip(buf) {
-                if point.index as usize == idx {
-                    temp += color;
-                    counter += 1;
-                }
-            }
-            if counter != 0 {
-                let new_color = temp / T::from_f64(counter as f64).unwrap();
-                *delta = Self::difference(cent, &new_color).sqrt();
-                *cent = new_color;
-            } else {
-                let new_color = Self::create_random(&mut rng);
-                *delta = Self::difference(cent, &new_color).sqrt();
-                *cent = new_color;
-            }
+            let new_color = match sum.mean() {
+                Some([l, a, b]) => Lab::new(l, a, b),
+                None => Self::create_random(&mut rng),
+            };
+            *delta = Self::difference(cent, &new_color).sqrt();
+            *cent = new_color;
         }
     }
 
@@ -331,14 +707,16 @@ where
         points: &mut [HamerlyPoint],
     ) {
         for (val, point) in buffer.iter().zip(points.iter_mut()) {
-            // Assign max of lower bound and half distance to z
+            // Assign max of lower bound and half distance to z. The bounds are
+            // compared strictly so that ties are resolved by the full search
+            // below, which picks the lowest index like `get_closest_centroid`.
             let z = centers
                 .half_distances
                 .get(point.index as usize)
                 .unwrap()
                 .max(point.lower_bound);
 
-            if point.upper_bound <= z {
+            if point.upper_bound < z {
                 continue;
             }
 
@@ -346,7 +724,7 @@ where
             point.upper_bound =
                 Self::difference(val, centers.centroids.get(point.index as usize).unwrap()).sqrt();
 
-            if point.upper_bound <= z {
+            if point.upper_bound < z {
                 continue;
             }
 
@@ -355,7 +733,7 @@ where
                 continue;
             }
 
-            let mut min1 = Self::difference(val, centers.centroids.get(0).unwrap());
+            let mut min1 = Self::difference(val, centers.centroids.first().unwrap());
             let mut min2 = f32::MAX;
             let mut c1 = 0;
             for j in 1..centers.centroids.len() {
@@ -371,44 +749,40 @@ where
                 }
             }
 
-            if c1 as u8 != point.index {
-                point.index = c1 as u8;
+            if c1 as u32 != point.index {
+                point.index = c1 as u32;
                 point.upper_bound = min1.sqrt();
             }
             point.lower_bound = min2.sqrt();
         }
     }
 
-    #[allow(clippy::cast_precision_loss)]
     fn recalculate_centroids_hamerly(
         mut rng: &mut impl Rng,
         buf: &[Self],
         centers: &mut HamerlyCentroids<Self>,
         points: &[HamerlyPoint],
     ) {
-        for ((idx, cent), delta) in centers
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums = vec![ClusterSum::default(); centers.centroids.len()];
+        for (point, color) in points.iter().zip(buf) {
+            if let Some(sum) = sums.get_mut(point.index as usize) {
+                sum.add([color.red, color.green, color.blue], 1.0);
+            }
+        }
+
+        for ((cent, delta), sum) in centers
             .centroids
             .iter_mut()
-            .enumerate()
             .zip(centers.deltas.iter_mut())
+            .zip(sums)
         {
-            let mut temp = Rgb::<S, T>::default();
-            let mut counter: u64 = 0;
-            for (point, &color) in points.iter().zip(buf) {
-                if point.index as usize == idx {
-                    temp += color;
-                    counter += 1;
-                }
-            }
-            if counter != 0 {
-                let new_color = temp / T::from_f64(counter as f64).unwrap();
-                *delta = Self::difference(cent, &new_color).sqrt();
-                *cent = new_color;
-            } else {
-                let new_color = Self::create_random(&mut rng);
-                *delta = Self::difference(cent, &new_color).sqrt();
-                *cent = new_color;
-            }
+            let new_color = match sum.mean() {
+                Some([red, green, blue]) => Rgb::new(red, green, blue),
+                None => Self::create_random(&mut rng),
+            };
+            *delta = Self::difference(cent, &new_color).sqrt();
+            *cent = new_color;
         }
     }
 
@@ -427,56 +801,104 @@ where
     }
 }
 
-/// A trait for mapping colors to their corresponding centroids.
 #[cfg(feature = "palette_color")]
-pub trait MapColor: Sized {
-    /// Map pixel indices to each centroid for output buffer.
-    fn map_indices_to_centroids(centroids: &[Self], indices: &[u8]) -> Vec<Self>;
+impl<Wp, T> Elkan for Lab<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Lab<Wp, T>: core::ops::AddAssign<Lab<Wp, T>> + Default,
+{
 }
 
 #[cfg(feature = "palette_color")]
-impl<Wp, T> MapColor for Lab<Wp, T>
+impl<S, T> Elkan for Rgb<S, T>
 where
-    T: Copy,
+    T: Float + FromPrimitive + Zero,
+    Rgb<S, T>: core::ops::AddAssign<Rgb<S, T>> + Default,
 {
-    #[inline]
-    fn map_indices_to_centroids(centroids: &[Self], indices: &[u8]) -> Vec<Self> {
-        indices
-            .iter()
-            .map(|x| {
-                *centroids
-                    .get(*x as usize)
-                    .unwrap_or_else(|| centroids.last().unwrap())
-            })
-            .collect()
+}
+
+/// A pixel's nearest centroid and the distances to the nearest two centroids.
+#[cfg(feature = "palette_color")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Assignment {
+    /// Index of the nearest centroid.
+    pub index: u32,
+    /// Distance to the nearest centroid.
+    pub distance: f32,
+    /// Distance to the second nearest centroid, infinite if there is only one
+    /// centroid.
+    pub second_distance: f32,
+}
+
+#[cfg(feature = "palette_color")]
+impl Assignment {
+    /// Returns how ambiguous the assignment is, from 0 for a pixel on its
+    /// centroid to 1 for a pixel equally near two centroids.
+    pub fn ambiguity(&self) -> f32 {
+        if self.second_distance > 0.0 {
+            (self.distance / self.second_distance).min(1.0)
+        } else {
+            1.0
+        }
     }
 }
 
+/// A trait for mapping colors to their corresponding centroids.
+///
+/// Implemented for all `Copy` types, so centroids can be mapped to a different
+/// color type than they were calculated in, e.g. `Lab` centroids converted to
+/// `Srgba`. Indices past the end of the centroids are mapped to the last
+/// centroid.
 #[cfg(feature = "palette_color")]
-impl<Wp, T> MapColor for palette::Laba<Wp, T>
-where
-    T: Copy,
-{
-    #[inline]
-    fn map_indices_to_centroids(centroids: &[Self], indices: &[u8]) -> Vec<Self> {
-        indices
-            .iter()
-            .map(|x| {
-                *centroids
-                    .get(*x as usize)
-                    .unwrap_or_else(|| centroids.last().unwrap())
+pub trait MapColor: Sized {
+    /// Map pixel indices to each centroid for output buffer.
+    fn map_indices_to_centroids(centroids: &[Self], indices: &[u32]) -> Vec<Self>;
+
+    /// Find each pixel's nearest centroid along with the distances to the
+    /// nearest two centroids, which can be used to build confidence maps or
+    /// feather recoloring near cluster boundaries. Distances are the square
+    /// root of `Calculate::difference`, so for `Lab` they are the CIE76
+    /// color difference.
+    ///
+    /// When multiple centroids are equally near, the lowest index is used.
+    #[allow(clippy::cast_possible_truncation)]
+    fn map_to_centroids_with_distances(buf: &[Self], centroids: &[Self]) -> Vec<Assignment>
+    where
+        Self: Calculate,
+    {
+        buf.iter()
+            .map(|color| {
+                let mut index = 0;
+                let mut min = f32::MAX;
+                let mut second = f32::INFINITY;
+                for (idx, cent) in centroids.iter().enumerate() {
+                    let diff = Self::difference(color, cent);
+                    if diff < min {
+                        second = min;
+                        min = diff;
+                        index = idx;
+                    } else if diff < second {
+                        second = diff;
+                    }
+                }
+                if centroids.len() < 2 {
+                    second = f32::INFINITY;
+                }
+
+                Assignment {
+                    index: index as u32,
+                    distance: min.sqrt(),
+                    second_distance: second.sqrt(),
+                }
             })
             .collect()
     }
 }
 
 #[cfg(feature = "palette_color")]
-impl<S, T> MapColor for Rgb<S, T>
-where
-    T: Copy,
-{
+impl<C: Copy> MapColor for C {
     #[inline]
-    fn map_indices_to_centroids(centroids: &[Self], indices: &[u8]) -> Vec<Self> {
+    fn map_indices_to_centroids(centroids: &[Self], indices: &[u32]) -> Vec<Self> {
         indices
             .iter()
             .map(|x| {
@@ -488,20 +910,216 @@ where
     }
 }
 
-#[cfg(feature = "palette_color")]
-impl<S, T> MapColor for Rgba<S, T>
-where
-    T: Copy,
-{
-    #[inline]
-    fn map_indices_to_centroids(centroids: &[Self], indices: &[u8]) -> Vec<Self> {
-        indices
-            .iter()
-            .map(|x| {
-                *centroids
-                    .get(*x as usize)
-                    .unwrap_or_else(|| centroids.last().unwrap())
+#[cfg(test)]
+mod tests {
+    use crate::{get_kmeans, get_kmeans_hamerly};
+    use palette::{IntoColor, Lab, Srgb};
+    use rand::{Rng, SeedableRng};
+
+    /// Random 8-bit colors, quantized to few levels so that ties between
+    /// centroids are common.
+    fn random_colors(len: usize, seed: u64) -> Vec<Srgb> {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        (0..len)
+            .map(|_| {
+                Srgb::new(
+                    rng.gen_range(0..8u8) * 32,
+                    rng.gen_range(0..8u8) * 32,
+                    rng.gen_range(0..8u8) * 32,
+                )
+                .into_format()
             })
             .collect()
     }
+
+    #[test]
+    fn tie_lowest_index() {
+        use crate::{Calculate, Hamerly, HamerlyCentroids, HamerlyPoint};
+
+        // The point is equidistant to all centroids
+        let buf = [Srgb::new(0.5, 0.5, 0.5)];
+        let centroids = vec![
+            Srgb::new(0.75, 0.5, 0.5),
+            Srgb::new(0.25, 0.5, 0.5),
+            Srgb::new(0.5, 0.25, 0.5),
+        ];
+
+        let mut indices = Vec::new();
+        Srgb::get_closest_centroid(&buf, &centroids, &mut indices);
+        assert_eq!(indices, [0]);
+
+        let mut centers = HamerlyCentroids::new(centroids.len());
+        centers.centroids = centroids;
+        Srgb::compute_half_distances(&mut centers);
+        let mut points = [HamerlyPoint::new()];
+        Srgb::get_closest_centroid_hamerly(&buf, &centers, &mut points);
+        assert_eq!(points[0].index, 0);
+    }
+
+    #[test]
+    fn lloyd_hamerly_match() {
+        for seed in 0..4 {
+            let rgb = random_colors(1000, seed);
+            let lab: Vec<Lab> = rgb.iter().map(|x| x.into_linear().into_color()).collect();
+            for k in 2..10 {
+                let lloyd = get_kmeans(k, 20, 0.0, false, &rgb, seed);
+                let hamerly = get_kmeans_hamerly(k, 20, 0.0, false, &rgb, seed);
+                assert_eq!(lloyd.indices, hamerly.indices);
+                assert_eq!(lloyd.centroids, hamerly.centroids);
+                assert_eq!(lloyd.score.to_bits(), hamerly.score.to_bits());
+
+                let lloyd = get_kmeans(k, 20, 0.0, false, &lab, seed);
+                let hamerly = get_kmeans_hamerly(k, 20, 0.0, false, &lab, seed);
+                assert_eq!(lloyd.indices, hamerly.indices);
+                assert_eq!(lloyd.centroids, hamerly.centroids);
+                assert_eq!(lloyd.score.to_bits(), hamerly.score.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn lloyd_elkan_match() {
+        use crate::get_kmeans_elkan;
+
+        for seed in 0..4 {
+            let rgb = random_colors(1000, seed);
+            let lab: Vec<Lab> = rgb.iter().map(|x| x.into_linear().into_color()).collect();
+            for k in [2, 3, 5, 9, 32, 40] {
+                let lloyd = get_kmeans(k, 20, 0.0, false, &rgb, seed);
+                let elkan = get_kmeans_elkan(k, 20, 0.0, false, &rgb, seed);
+                assert_eq!(lloyd.indices, elkan.indices);
+                assert_eq!(lloyd.centroids, elkan.centroids);
+                assert_eq!(lloyd.score.to_bits(), elkan.score.to_bits());
+
+                let lloyd = get_kmeans(k, 20, 0.0, false, &lab, seed);
+                let elkan = get_kmeans_elkan(k, 20, 0.0, false, &lab, seed);
+                assert_eq!(lloyd.indices, elkan.indices);
+                assert_eq!(lloyd.centroids, elkan.centroids);
+                assert_eq!(lloyd.score.to_bits(), elkan.score.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        use crate::{get_kmeans_hamerly_weighted, get_kmeans_weighted};
+
+        let buf = [
+            Srgb::new(0.0, 0.0, 0.0),
+            Srgb::new(0.25, 0.25, 0.25),
+            Srgb::new(1.0, 1.0, 1.0),
+        ];
+        let result = get_kmeans_weighted(1, 20, 0.0, false, &buf, &[1.0, 3.0, 0.0], 0);
+        assert_eq!(result.centroids, [Srgb::new(0.1875, 0.1875, 0.1875)]);
+
+        for seed in 0..4 {
+            let rgb = random_colors(500, seed);
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+            let weights: Vec<f32> = (0..rgb.len())
+                .map(|_| f32::from(rng.gen_range(0..4u8)))
+                .collect();
+            for k in 2..8 {
+                let lloyd = get_kmeans_weighted(k, 20, 0.0, false, &rgb, &weights, seed);
+                let hamerly = get_kmeans_hamerly_weighted(k, 20, 0.0, false, &rgb, &weights, seed);
+                assert_eq!(lloyd.indices, hamerly.indices);
+                assert_eq!(lloyd.centroids, hamerly.centroids);
+            }
+        }
+    }
+
+    #[test]
+    fn large_cluster_precision() {
+        use crate::Calculate;
+
+        // An f32 sum of 0.1 drifts by about 1% over a million points
+        let buf: Vec<Srgb> = vec![Srgb::new(0.1, 0.1, 0.1); 1_000_000];
+        let indices = vec![0; buf.len()];
+        let mut centroids: [Srgb; 1] = [Srgb::new(0.0, 0.0, 0.0)];
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        Srgb::recalculate_centroids(&mut rng, &buf, &mut centroids, &indices);
+        assert_eq!(centroids, [Srgb::new(0.1, 0.1, 0.1)]);
+    }
+
+    #[test]
+    fn kdtree_matches_linear() {
+        use crate::{get_kmeans_kdtree, Calculate, CentroidTree};
+
+        let rgb = random_colors(2000, 0);
+        let lab: Vec<Lab> = rgb.iter().map(|x| x.into_linear().into_color()).collect();
+        for k in [1, 2, 7, 64, 256] {
+            let centroids = random_colors(k, 1);
+            let mut linear = Vec::new();
+            Srgb::get_closest_centroid(&rgb, &centroids, &mut linear);
+            let mut tree = Vec::new();
+            CentroidTree::new(&centroids).get_closest_centroid(&rgb, &mut tree);
+            assert_eq!(linear, tree);
+
+            let centroids: Vec<Lab> = centroids
+                .iter()
+                .map(|x| x.into_linear().into_color())
+                .collect();
+            linear.clear();
+            Lab::get_closest_centroid(&lab, &centroids, &mut linear);
+            tree.clear();
+            CentroidTree::new(&centroids).get_closest_centroid(&lab, &mut tree);
+            assert_eq!(linear, tree);
+        }
+
+        for k in [2, 9, 64] {
+            let lloyd = get_kmeans(k, 20, 0.0, false, &lab, 0);
+            let kdtree = get_kmeans_kdtree(k, 20, 0.0, false, &lab, 0);
+            assert_eq!(lloyd.indices, kdtree.indices);
+            assert_eq!(lloyd.centroids, kdtree.centroids);
+        }
+    }
+
+    #[test]
+    fn distances() {
+        use crate::MapColor;
+
+        let centroids = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 0.0, 0.0)];
+        let buf = [Srgb::new(0.25, 0.0, 0.0), Srgb::new(0.5, 0.0, 0.0)];
+        let assignments = Srgb::map_to_centroids_with_distances(&buf, &centroids);
+        assert_eq!(assignments[0].index, 0);
+        assert_eq!(assignments[0].distance, 0.25);
+        assert_eq!(assignments[0].second_distance, 0.75);
+        assert_eq!(assignments[1].index, 0);
+        assert_eq!(assignments[1].ambiguity(), 1.0);
+
+        let assignments = Srgb::map_to_centroids_with_distances(&buf, &centroids[..1]);
+        assert_eq!(assignments[1].second_distance, f32::INFINITY);
+    }
+
+    #[test]
+    fn map_indices() {
+        use crate::MapColor;
+
+        let centroids = [Srgb::new(0u8, 0, 0), Srgb::new(255, 255, 255)];
+        let mapped = Srgb::map_indices_to_centroids(&centroids, &[1, 0, 300]);
+        assert_eq!(mapped, [centroids[1], centroids[0], centroids[1]]);
+    }
+
+    #[test]
+    fn xyz_yxy() {
+        use crate::Calculate;
+        use palette::{white_point::D65, Xyz, Yxy};
+
+        let xyz: Vec<Xyz> = random_colors(500, 0)
+            .iter()
+            .map(|x| x.into_linear().into_color())
+            .collect();
+        let result = get_kmeans(4, 20, 0.0, false, &xyz, 0);
+        assert_eq!(result.centroids.len(), 4);
+        assert_eq!(result.indices.len(), xyz.len());
+
+        let yxy: Vec<Yxy> = xyz.iter().map(|&x| x.into_color()).collect();
+        let result = get_kmeans(4, 20, 0.0, false, &yxy, 0);
+        assert_eq!(result.indices.len(), yxy.len());
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            let random = Xyz::<D65, f32>::create_random(&mut rng);
+            assert!(random.x <= 0.95047 && random.z <= 1.08883);
+        }
+    }
 }
@@ -0,0 +1,157 @@
+use palette::Srgb;
+
+/// Channel layout of the pixels in a [`RawImage`](struct.RawImage.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawFormat {
+    /// 8-bit red, green, blue.
+    Rgb8,
+    /// 8-bit red, green, blue, alpha.
+    Rgba8,
+    /// 8-bit blue, green, red, alpha, common for frames from graphics APIs.
+    Bgra8,
+}
+
+impl RawFormat {
+    /// Returns the number of bytes in one pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            RawFormat::Rgb8 => 3,
+            RawFormat::Rgba8 | RawFormat::Bgra8 => 4,
+        }
+    }
+}
+
+/// Error for a raw buffer too small for its dimensions and stride.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawImageError {
+    /// Number of bytes required.
+    pub required: usize,
+    /// Length of the buffer.
+    pub len: usize,
+}
+
+impl core::fmt::Display for RawImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "raw buffer of {} bytes is too small, {} bytes required",
+            self.len, self.required
+        )
+    }
+}
+
+impl std::error::Error for RawImageError {}
+
+/// A borrowed view of a raw 8-bit image buffer with a row stride.
+///
+/// The buffer can be any `&[u8]`, such as a memory-mapped file or a frame
+/// shared by another process, and is read in place without being copied. Rows
+/// may be padded, so `stride` is the number of bytes from the start of one row
+/// to the start of the next.
+///
+/// Pixels can be converted with a [`ConversionCache`](struct.ConversionCache.html)
+/// or counted with a [`ColorHistogram`](struct.ColorHistogram.html) to find
+/// the k-means colors without converting the whole buffer at once.
+///
+/// ```
+/// use kmeans_colors::{ColorHistogram, RawFormat, RawImage};
+///
+/// // 1x2 image with 2 bytes of padding at the end of each row
+/// let data = [255u8, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 0];
+/// let raw = RawImage::new(&data, 1, 2, 6, RawFormat::Bgra8).unwrap();
+///
+/// let mut histogram = ColorHistogram::new();
+/// histogram.add(raw.pixels());
+/// assert_eq!(histogram.len(), 2);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RawImage<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: RawFormat,
+}
+
+impl<'a> RawImage<'a> {
+    /// Create a view of a raw buffer, checking that it's large enough for the
+    /// dimensions and stride.
+    ///
+    /// The last row doesn't need to include padding.
+    pub fn new(
+        data: &'a [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: RawFormat,
+    ) -> Result<Self, RawImageError> {
+        let row = width * format.bytes_per_pixel();
+        let required = match height {
+            0 => 0,
+            _ => stride.max(row) * (height - 1) + row,
+        };
+        if data.len() < required || (height > 1 && stride < row) {
+            return Err(RawImageError {
+                required,
+                len: data.len(),
+            });
+        }
+
+        Ok(RawImage {
+            data,
+            width,
+            height,
+            stride,
+            format,
+        })
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the bytes of each row, without padding.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let Self {
+            data,
+            width,
+            height,
+            stride,
+            format,
+        } = *self;
+        let row = width * format.bytes_per_pixel();
+        (0..height).map(move |y| &data[y * stride..y * stride + row])
+    }
+
+    /// Returns the color of each pixel, ignoring alpha.
+    pub fn pixels(&self) -> impl Iterator<Item = Srgb<u8>> + 'a {
+        let format = self.format;
+        self.rows().flat_map(move |row| {
+            row.chunks_exact(format.bytes_per_pixel())
+                .map(move |px| match format {
+                    RawFormat::Rgb8 | RawFormat::Rgba8 => Srgb::new(px[0], px[1], px[2]),
+                    RawFormat::Bgra8 => Srgb::new(px[2], px[1], px[0]),
+                })
+        })
+    }
+
+    /// Returns the color of each pixel that is fully opaque. All pixels are
+    /// returned for formats without alpha.
+    pub fn opaque_pixels(&self) -> impl Iterator<Item = Srgb<u8>> + 'a {
+        let format = self.format;
+        self.rows().flat_map(move |row| {
+            row.chunks_exact(format.bytes_per_pixel())
+                .filter_map(move |px| match format {
+                    RawFormat::Rgb8 => Some(Srgb::new(px[0], px[1], px[2])),
+                    RawFormat::Rgba8 => (px[3] == 255).then(|| Srgb::new(px[0], px[1], px[2])),
+                    RawFormat::Bgra8 => (px[3] == 255).then(|| Srgb::new(px[2], px[1], px[0])),
+                })
+        })
+    }
+}
@@ -0,0 +1,233 @@
+use palette::Lab;
+use rand::Rng;
+
+use crate::kmeans::Calculate;
+
+/// The hue angle of a `Lab` color, for clustering colors by hue alone.
+///
+/// Hues are compared on the color wheel, so 350° and 10° are 20° apart, and
+/// centroids are the circular mean of their hues. Neutral colors have no
+/// meaningful hue and should be filtered out beforehand.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HueOnly {
+    /// Hue angle in degrees, in `0.0..360.0`.
+    pub degrees: f32,
+}
+
+impl HueOnly {
+    /// Create a new `HueOnly` from an angle in degrees.
+    pub fn new(degrees: f32) -> Self {
+        HueOnly {
+            degrees: degrees.rem_euclid(360.0),
+        }
+    }
+
+    /// Returns the signed difference `self - other` on the color wheel, in
+    /// `-180.0..=180.0`.
+    #[inline]
+    fn delta(&self, other: &Self) -> f32 {
+        let d = (self.degrees - other.degrees).rem_euclid(360.0);
+        if d > 180.0 {
+            d - 360.0
+        } else {
+            d
+        }
+    }
+}
+
+impl<Wp> From<Lab<Wp, f32>> for HueOnly {
+    fn from(lab: Lab<Wp, f32>) -> Self {
+        HueOnly::new(lab.b.atan2(lab.a).to_degrees())
+    }
+}
+
+impl Calculate for HueOnly {
+    const DIMENSIONS: usize = 1;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Self], centroids: &[Self], indices: &mut Vec<u32>) {
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Self],
+        centroids: &mut [Self],
+        indices: &[u32],
+    ) {
+        // Accumulate the unit vectors of each cluster's hues in a single pass
+        let mut sums: Vec<(f32, f32, u64)> = centroids.iter().map(|_| (0.0, 0.0, 0)).collect();
+        for (&idx, hue) in indices.iter().zip(buf) {
+            if let Some((x, y, count)) = sums.get_mut(idx as usize) {
+                if hue.degrees.is_finite() {
+                    let (sin, cos) = hue.degrees.to_radians().sin_cos();
+                    *x += cos;
+                    *y += sin;
+                    *count += 1;
+                }
+            }
+        }
+
+        for (cent, (x, y, count)) in centroids.iter_mut().zip(sums) {
+            if count != 0 {
+                *cent = HueOnly::new(y.atan2(x).to_degrees());
+            } else {
+                *cent = Self::create_random(&mut rng);
+            }
+        }
+    }
+
+    fn check_loop(centroids: &[Self], old_centroids: &[Self]) -> f32 {
+        let temp: f32 = centroids
+            .iter()
+            .zip(old_centroids)
+            .map(|(c0, c1)| c0.delta(c1))
+            .sum();
+
+        temp * temp
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Self {
+        HueOnly::new(rng.gen_range(0.0..360.0))
+    }
+
+    #[inline]
+    fn difference(c1: &Self, c2: &Self) -> f32 {
+        let d = c1.delta(c2);
+        d * d
+    }
+}
+
+/// The `a*` and `b*` components of a `Lab` color, for clustering colors by
+/// chromaticity while ignoring lightness.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChromaOnly {
+    /// Green-red component.
+    pub a: f32,
+    /// Blue-yellow component.
+    pub b: f32,
+}
+
+impl ChromaOnly {
+    /// Create a new `ChromaOnly`.
+    pub fn new(a: f32, b: f32) -> Self {
+        ChromaOnly { a, b }
+    }
+}
+
+impl<Wp> From<Lab<Wp, f32>> for ChromaOnly {
+    fn from(lab: Lab<Wp, f32>) -> Self {
+        ChromaOnly::new(lab.a, lab.b)
+    }
+}
+
+impl Calculate for ChromaOnly {
+    const DIMENSIONS: usize = 2;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Self], centroids: &[Self], indices: &mut Vec<u32>) {
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Self],
+        centroids: &mut [Self],
+        indices: &[u32],
+    ) {
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums: Vec<(f32, f32, u64)> = centroids.iter().map(|_| (0.0, 0.0, 0)).collect();
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some((a, b, count)) = sums.get_mut(idx as usize) {
+                if color.a.is_finite() && color.b.is_finite() {
+                    *a += color.a;
+                    *b += color.b;
+                    *count += 1;
+                }
+            }
+        }
+
+        for (cent, (a, b, count)) in centroids.iter_mut().zip(sums) {
+            if count != 0 {
+                *cent = ChromaOnly::new(a / count as f32, b / count as f32);
+            } else {
+                *cent = Self::create_random(&mut rng);
+            }
+        }
+    }
+
+    fn check_loop(centroids: &[Self], old_centroids: &[Self]) -> f32 {
+        let (mut a, mut b) = (0.0, 0.0);
+        for (c0, c1) in centroids.iter().zip(old_centroids) {
+            a += c0.a - c1.a;
+            b += c0.b - c1.b;
+        }
+
+        a * a + b * b
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Self {
+        ChromaOnly::new(rng.gen_range(-128.0..=127.0), rng.gen_range(-128.0..=127.0))
+    }
+
+    #[inline]
+    fn difference(c1: &Self, c2: &Self) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
+        let (a, b) = (c1.a - c2.a, c1.b - c2.b);
+        a * a + b * b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hue_only() {
+        assert_eq!(
+            HueOnly::difference(&HueOnly::new(350.0), &HueOnly::new(10.0)),
+            400.0
+        );
+
+        // Hues on either side of red average to red, not cyan
+        let buf = [HueOnly::new(340.0), HueOnly::new(20.0), HueOnly::new(180.0)];
+        let result = crate::get_kmeans(2, 10, 0.0, false, &buf, 0);
+        assert_eq!(result.indices[0], result.indices[1]);
+        let red = result.centroids[result.indices[0] as usize];
+        assert!(red.degrees < 1e-3 || red.degrees > 360.0 - 1e-3);
+    }
+
+    #[test]
+    fn chroma_only() {
+        let lab: Lab = Lab::new(50.0, 20.0, -10.0);
+        assert_eq!(ChromaOnly::from(lab), ChromaOnly::new(20.0, -10.0));
+    }
+}
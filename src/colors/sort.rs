@@ -1,9 +1,13 @@
-use crate::sort::{CentroidData, Sort};
+use crate::sort::{CentroidData, Sort, Vivid};
 
 #[cfg(feature = "palette_color")]
 use num_traits::{Float, FromPrimitive, Zero};
 #[cfg(feature = "palette_color")]
-use palette::{luma::Luma, rgb::Rgb, IntoColor, Lab};
+use palette::{
+    luma::Luma,
+    rgb::{FromHexError, Rgb},
+    FromColor, IntoColor, Lab, Oklab, Srgb,
+};
 
 #[cfg(feature = "palette_color")]
 impl<Wp, T> Sort for Lab<Wp, T>
@@ -18,12 +22,12 @@ where
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
-    fn sort_indexed_colors(centroids: &[Self], indices: &[u8]) -> Vec<CentroidData<Self>> {
+    fn sort_indexed_colors(centroids: &[Self], indices: &[u32]) -> Vec<CentroidData<Self>> {
         // Count occurences of each color - "histogram"
-        let mut map: fxhash::FxHashMap<u8, u64> = centroids
+        let mut map: fxhash::FxHashMap<u32, u64> = centroids
             .iter()
             .enumerate()
-            .map(|(i, _)| (i as u8, 0))
+            .map(|(i, _)| (i as u32, 0))
             .collect();
 
         for i in indices {
@@ -33,18 +37,18 @@ where
 
         let len = indices.len();
         assert!(len > 0);
-        let mut colors: Vec<(u8, f32)> = Vec::with_capacity(centroids.len());
+        let mut colors: Vec<(u32, f32)> = Vec::with_capacity(centroids.len());
         for (i, _) in centroids.iter().enumerate() {
-            if let Some(&count) = map.get(&(i as u8)) {
-                colors.push((i as u8, (count as f32) / (len as f32)))
+            if let Some(&count) = map.get(&(i as u32)) {
+                colors.push((i as u32, (count as f32) / (len as f32)))
             }
         }
 
         // Sort by increasing luminosity
-        let mut lab: Vec<(u8, Self)> = centroids
+        let mut lab: Vec<(u32, Self)> = centroids
             .iter()
             .enumerate()
-            .map(|(i, x)| (i as u8, *x))
+            .map(|(i, x)| (i as u32, *x))
             .collect();
         lab.sort_unstable_by(|a, b| (a.1.l).partial_cmp(&b.1.l).unwrap());
 
@@ -62,7 +66,7 @@ where
                     .map(|y| CentroidData {
                         centroid: *(centroids.get(colors.get(y).unwrap().0 as usize).unwrap()),
                         percentage: colors.get(y).unwrap().1,
-                        index: y as u8,
+                        index: y as u32,
                     }),
                 None => None,
             })
@@ -70,6 +74,22 @@ where
     }
 }
 
+#[cfg(feature = "palette_color")]
+impl<Wp, T> Vivid for Lab<Wp, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Lab<Wp, T>: core::ops::AddAssign<Lab<Wp, T>> + Default,
+{
+    /// Chroma is the distance from the neutral axis, `sqrt(a^2 + b^2)`.
+    #[inline]
+    fn chroma(&self) -> f32 {
+        (self.a * self.a + self.b * self.b)
+            .sqrt()
+            .to_f32()
+            .unwrap_or(0.0)
+    }
+}
+
 #[cfg(feature = "palette_color")]
 impl<S, T> Sort for Rgb<S, T>
 where
@@ -83,12 +103,12 @@ where
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
-    fn sort_indexed_colors(centroids: &[Self], indices: &[u8]) -> Vec<CentroidData<Self>> {
+    fn sort_indexed_colors(centroids: &[Self], indices: &[u32]) -> Vec<CentroidData<Self>> {
         // Count occurences of each color - "histogram"
-        let mut map: fxhash::FxHashMap<u8, u64> = centroids
+        let mut map: fxhash::FxHashMap<u32, u64> = centroids
             .iter()
             .enumerate()
-            .map(|(i, _)| (i as u8, 0))
+            .map(|(i, _)| (i as u32, 0))
             .collect();
 
         for i in indices {
@@ -98,18 +118,18 @@ where
 
         let len = indices.len();
         assert!(len > 0);
-        let mut colors: Vec<(u8, f32)> = Vec::with_capacity(centroids.len());
+        let mut colors: Vec<(u32, f32)> = Vec::with_capacity(centroids.len());
         for (i, _) in centroids.iter().enumerate() {
-            if let Some(&count) = map.get(&(i as u8)) {
-                colors.push((i as u8, (count as f32) / (len as f32)))
+            if let Some(&count) = map.get(&(i as u32)) {
+                colors.push((i as u32, (count as f32) / (len as f32)))
             }
         }
 
         // Sort by increasing luminosity
-        let mut lab: Vec<(u8, Luma<S, T>)> = centroids
+        let mut lab: Vec<(u32, Luma<S, T>)> = centroids
             .iter()
             .enumerate()
-            .map(|(i, x)| (i as u8, x.into_format().into_color()))
+            .map(|(i, x)| (i as u32, x.into_format().into_color()))
             .collect();
         lab.sort_unstable_by(|a, b| (a.1.luma).partial_cmp(&b.1.luma).unwrap());
 
@@ -124,7 +144,7 @@ where
                     .map(|y| CentroidData {
                         centroid: *(centroids.get(colors.get(y).unwrap().0 as usize).unwrap()),
                         percentage: colors.get(y).unwrap().1,
-                        index: y as u8,
+                        index: y as u32,
                     }),
                 None => None,
             })
@@ -132,9 +152,156 @@ where
     }
 }
 
+#[cfg(feature = "palette_color")]
+impl<S, T> Vivid for Rgb<S, T>
+where
+    T: Float + FromPrimitive + Zero,
+    Rgb<S, T>: core::ops::AddAssign<Rgb<S, T>> + IntoColor<Luma<S, T>> + Default,
+{
+    /// Chroma is the difference between the largest and smallest component.
+    #[inline]
+    fn chroma(&self) -> f32 {
+        let max = self.red.max(self.green).max(self.blue);
+        let min = self.red.min(self.green).min(self.blue);
+        (max - min).to_f32().unwrap_or(0.0)
+    }
+}
+
+/// Sorts centroids from darkest to lightest by their `Oklab` lightness,
+/// regardless of the color space they were calculated in.
+///
+/// [`Sort::sort_indexed_colors`] orders `Lab` colors by `L*` and `Rgb` colors
+/// by luma, which both follow relative luminance. `Oklab` lightness ranks
+/// saturated colors, like a pure blue, closer to how bright they appear next
+/// to grays, keeping palette strips perceptually ordered.
+///
+/// ```
+/// use kmeans_colors::{sort_by_oklab_lightness, Sort};
+/// use palette::Srgb;
+///
+/// // Gray and blue of similar luminance
+/// let centroids = [Srgb::new(0.3, 0.3, 0.3), Srgb::new(0.0, 0.0, 1.0)];
+/// let mut res = Srgb::sort_indexed_colors(&centroids, &[0, 1]);
+/// assert_eq!(res[0].centroid, centroids[1]);
+///
+/// sort_by_oklab_lightness(&mut res);
+/// assert_eq!(res[0].centroid, centroids[0]);
+/// ```
+#[cfg(feature = "palette_color")]
+pub fn sort_by_oklab_lightness<C>(data: &mut [CentroidData<C>])
+where
+    C: crate::Calculate + IntoColor<Oklab> + Copy,
+{
+    let lightness = |x: &CentroidData<C>| {
+        let oklab: Oklab = x.centroid.into_color();
+        oklab.l
+    };
+    data.sort_by(|a, b| lightness(a).total_cmp(&lightness(b)));
+}
+
+/// Converts centroid data to 8-bit `Srgb` colors, keeping their order.
+///
+/// ```
+/// use kmeans_colors::{centroids_to_hex, centroids_to_srgb, Sort};
+/// use palette::{Lab, Srgb};
+///
+/// let centroids = [Lab::new(100.0, 0.0, 0.0), Lab::new(0.0, 0.0, 0.0)];
+/// let res = Lab::sort_indexed_colors(&centroids, &[0, 1, 1]);
+/// assert_eq!(
+///     centroids_to_srgb(&res),
+///     [Srgb::new(0u8, 0, 0), Srgb::new(255, 255, 255)]
+/// );
+/// assert_eq!(centroids_to_hex(&res), ["000000", "ffffff"]);
+/// ```
+#[cfg(feature = "palette_color")]
+pub fn centroids_to_srgb<C>(data: &[CentroidData<C>]) -> Vec<Srgb<u8>>
+where
+    C: crate::Calculate + IntoColor<Srgb> + Copy,
+{
+    data.iter()
+        .map(|x| x.centroid.into_color().into_format())
+        .collect()
+}
+
+/// Converts centroid data to lowercase hex codes without a leading `#`,
+/// keeping their order.
+#[cfg(feature = "palette_color")]
+pub fn centroids_to_hex<C>(data: &[CentroidData<C>]) -> Vec<String>
+where
+    C: crate::Calculate + IntoColor<Srgb> + Copy,
+{
+    centroids_to_srgb(data)
+        .iter()
+        .map(|x| format!("{x:x}"))
+        .collect()
+}
+
+/// Converts a palette of 8-bit `Srgb` colors to centroid data, such as a
+/// palette saved from an earlier result.
+///
+/// Each color is given an equal share of the percentages, and its position in
+/// the palette as its index.
+///
+/// ```
+/// use kmeans_colors::{centroids_from_srgb, CentroidData};
+/// use palette::{Lab, Srgb};
+///
+/// let res: Vec<CentroidData<Lab>> =
+///     centroids_from_srgb(&[Srgb::new(0, 0, 0), Srgb::new(255, 255, 255)]);
+/// assert_eq!(res[1].index, 1);
+/// assert_eq!(res[1].percentage, 0.5);
+/// assert!((res[1].centroid.l - 100.0).abs() < 1e-3);
+/// ```
+#[allow(clippy::cast_precision_loss)]
+#[cfg(feature = "palette_color")]
+pub fn centroids_from_srgb<C>(colors: &[Srgb<u8>]) -> Vec<CentroidData<C>>
+where
+    C: crate::Calculate + FromColor<Srgb>,
+{
+    let percentage = 1.0 / colors.len() as f32;
+    colors
+        .iter()
+        .zip(0..)
+        .map(|(x, index)| CentroidData {
+            centroid: C::from_color(x.into_format()),
+            percentage,
+            index,
+        })
+        .collect()
+}
+
+/// Parses hex codes, with or without a leading `#`, and converts them to
+/// centroid data like [`centroids_from_srgb`].
+///
+/// # Errors
+///
+/// Returns an error if a hex code isn't a 3 or 6 digit color.
+///
+/// ```
+/// use kmeans_colors::{centroids_from_hex, centroids_to_hex, CentroidData};
+/// use palette::Lab;
+///
+/// let res: Vec<CentroidData<Lab>> = centroids_from_hex(&["#2f393c", "ec8181"])?;
+/// assert_eq!(centroids_to_hex(&res), ["2f393c", "ec8181"]);
+/// assert!(centroids_from_hex::<Lab, _>(&["#12345"]).is_err());
+/// # Ok::<(), palette::rgb::FromHexError>(())
+/// ```
+#[cfg(feature = "palette_color")]
+pub fn centroids_from_hex<C, S>(hex: &[S]) -> Result<Vec<CentroidData<C>>, FromHexError>
+where
+    C: crate::Calculate + FromColor<Srgb>,
+    S: AsRef<str>,
+{
+    let colors = hex
+        .iter()
+        .map(|x| x.as_ref().parse())
+        .collect::<Result<Vec<Srgb<u8>>, _>>()?;
+    Ok(centroids_from_srgb(&colors))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{CentroidData, Sort};
+    use crate::{CentroidData, Sort, Vivid};
     #[cfg(feature = "palette_color")]
     use palette::Srgb;
 
@@ -163,4 +330,50 @@ mod tests {
             Srgb::new(0.5, 0.5, 0.5)
         );
     }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn most_vivid_color() {
+        let mut res = vec![
+            CentroidData::<Srgb> {
+                centroid: Srgb::new(0.5, 0.5, 0.5),
+                percentage: 0.7,
+                index: 0,
+            },
+            CentroidData::<Srgb> {
+                centroid: Srgb::new(0.9, 0.1, 0.1),
+                percentage: 0.2,
+                index: 1,
+            },
+            CentroidData::<Srgb> {
+                centroid: Srgb::new(0.1, 0.1, 1.0),
+                percentage: 0.1,
+                index: 2,
+            },
+        ];
+        assert_eq!(
+            Srgb::get_most_vivid_color(&res).unwrap(),
+            Srgb::new(0.9, 0.1, 0.1)
+        );
+
+        Srgb::sort_by_vividness(&mut res);
+        let order: Vec<u32> = res.iter().map(|x| x.index).collect();
+        assert_eq!(order, [1, 2, 0]);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn palette_round_trip() {
+        use crate::{centroids_from_srgb, centroids_to_srgb};
+        use palette::Lab;
+
+        // Every 8-bit color survives the conversion to `Lab` and back
+        let colors: Vec<Srgb<u8>> = (0..=255u8)
+            .map(|x| Srgb::new(x, x.wrapping_mul(7), 255 - x))
+            .collect();
+        let res: Vec<CentroidData<Lab>> = centroids_from_srgb(&colors);
+        assert_eq!(centroids_to_srgb(&res), colors);
+        assert_eq!(res.last().unwrap().index, 255);
+        assert!(centroids_from_srgb::<Lab>(&[]).is_empty());
+    }
 }
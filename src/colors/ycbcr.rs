@@ -0,0 +1,301 @@
+use core::marker::PhantomData;
+
+use palette::Srgb;
+use rand::Rng;
+
+use crate::kdtree::Axes;
+use crate::kmeans::Calculate;
+
+/// Luma coefficients of a `YCbCr` standard.
+pub trait YCbCrStandard: Copy + 'static {
+    /// Red coefficient.
+    const KR: f32;
+    /// Blue coefficient.
+    const KB: f32;
+}
+
+/// ITU-R BT.601 coefficients, used for standard definition video and JPEG.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bt601;
+
+impl YCbCrStandard for Bt601 {
+    const KR: f32 = 0.299;
+    const KB: f32 = 0.114;
+}
+
+/// ITU-R BT.709 coefficients, used for high definition video.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bt709;
+
+impl YCbCrStandard for Bt709 {
+    const KR: f32 = 0.2126;
+    const KB: f32 = 0.0722;
+}
+
+/// Range of 8-bit `YCbCr` samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YCbCrRange {
+    /// Samples use the full 0-255 range, as in JPEG.
+    Full,
+    /// Luma is in 16-235 and chroma in 16-240, as in most video.
+    Limited,
+}
+
+/// Chroma subsampling of planar `YCbCr` input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Chroma planes are full resolution.
+    Yuv444,
+    /// Chroma planes are half width.
+    Yuv422,
+    /// Chroma planes are half width and half height.
+    Yuv420,
+}
+
+impl Subsampling {
+    /// Returns the dimensions of the chroma planes for an image of `width`
+    /// and `height`.
+    pub fn chroma_dimensions(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Subsampling::Yuv444 => (width, height),
+            Subsampling::Yuv422 => (width.div_ceil(2), height),
+            Subsampling::Yuv420 => (width.div_ceil(2), height.div_ceil(2)),
+        }
+    }
+}
+
+/// A `Y'CbCr` color for clustering video frames in their native space.
+///
+/// Luma `y` is normalized to `0.0..=1.0` and the chroma components `cb` and
+/// `cr` to `-0.5..=0.5`. The standard `S` selects the BT.601 or BT.709 luma
+/// coefficients used for conversions with `Srgb`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct YCbCr<S = Bt709> {
+    /// Luma.
+    pub y: f32,
+    /// Blue-difference chroma.
+    pub cb: f32,
+    /// Red-difference chroma.
+    pub cr: f32,
+    standard: PhantomData<S>,
+}
+
+impl<S: YCbCrStandard> YCbCr<S> {
+    /// Create a new `YCbCr` color.
+    pub fn new(y: f32, cb: f32, cr: f32) -> Self {
+        YCbCr {
+            y,
+            cb,
+            cr,
+            standard: PhantomData,
+        }
+    }
+
+    /// Convert from a gamma encoded `Srgb` color.
+    pub fn from_rgb(rgb: Srgb<f32>) -> Self {
+        let y = S::KR * rgb.red + (1.0 - S::KR - S::KB) * rgb.green + S::KB * rgb.blue;
+        Self::new(
+            y,
+            0.5 * (rgb.blue - y) / (1.0 - S::KB),
+            0.5 * (rgb.red - y) / (1.0 - S::KR),
+        )
+    }
+
+    /// Convert to a gamma encoded `Srgb` color.
+    pub fn to_rgb(self) -> Srgb<f32> {
+        let red = self.y + 2.0 * (1.0 - S::KR) * self.cr;
+        let blue = self.y + 2.0 * (1.0 - S::KB) * self.cb;
+        let green = (self.y - S::KR * red - S::KB * blue) / (1.0 - S::KR - S::KB);
+        Srgb::new(red, green, blue)
+    }
+
+    /// Create a color from 8-bit samples.
+    pub fn from_u8(y: u8, cb: u8, cr: u8, range: YCbCrRange) -> Self {
+        let (y, cb, cr) = (f32::from(y), f32::from(cb), f32::from(cr));
+        match range {
+            YCbCrRange::Full => Self::new(y / 255.0, (cb - 128.0) / 255.0, (cr - 128.0) / 255.0),
+            YCbCrRange::Limited => Self::new(
+                (y - 16.0) / 219.0,
+                (cb - 128.0) / 224.0,
+                (cr - 128.0) / 224.0,
+            ),
+        }
+    }
+
+    /// Read a frame from 8-bit luma and chroma planes, pairing each luma
+    /// sample with the chroma sample covering it. Returns `None` if a plane is
+    /// too small for the dimensions.
+    pub fn from_planes(
+        y: &[u8],
+        cb: &[u8],
+        cr: &[u8],
+        width: usize,
+        height: usize,
+        subsampling: Subsampling,
+        range: YCbCrRange,
+    ) -> Option<Vec<Self>> {
+        let (chroma_width, chroma_height) = subsampling.chroma_dimensions(width, height);
+        if y.len() < width * height
+            || cb.len() < chroma_width * chroma_height
+            || cr.len() < chroma_width * chroma_height
+        {
+            return None;
+        }
+
+        let mut out = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let chroma_row = match subsampling {
+                Subsampling::Yuv420 => row / 2,
+                Subsampling::Yuv444 | Subsampling::Yuv422 => row,
+            };
+            for col in 0..width {
+                let chroma_col = match subsampling {
+                    Subsampling::Yuv444 => col,
+                    Subsampling::Yuv422 | Subsampling::Yuv420 => col / 2,
+                };
+                let c = chroma_row * chroma_width + chroma_col;
+                out.push(Self::from_u8(y[row * width + col], cb[c], cr[c], range));
+            }
+        }
+
+        Some(out)
+    }
+
+    #[inline]
+    fn is_finite(&self) -> bool {
+        self.y.is_finite() && self.cb.is_finite() && self.cr.is_finite()
+    }
+}
+
+impl<S: YCbCrStandard> Calculate for YCbCr<S> {
+    const CONVERGE: f32 = 0.0025;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid(buf: &[Self], centroids: &[Self], indices: &mut Vec<u32>) {
+        for color in buf.iter() {
+            let mut index = 0;
+            let mut diff;
+            let mut min = f32::MAX;
+            for (idx, cent) in centroids.iter().enumerate() {
+                diff = Self::difference(color, cent);
+                if diff < min {
+                    min = diff;
+                    index = idx;
+                }
+            }
+            indices.push(index as u32);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn recalculate_centroids(
+        mut rng: &mut impl Rng,
+        buf: &[Self],
+        centroids: &mut [Self],
+        indices: &[u32],
+    ) {
+        // Accumulate the sum and count of each cluster in a single pass
+        let mut sums: Vec<([f32; 3], u64)> = centroids.iter().map(|_| ([0.0; 3], 0)).collect();
+        for (&idx, color) in indices.iter().zip(buf) {
+            if let Some((sum, count)) = sums.get_mut(idx as usize) {
+                if color.is_finite() {
+                    sum[0] += color.y;
+                    sum[1] += color.cb;
+                    sum[2] += color.cr;
+                    *count += 1;
+                }
+            }
+        }
+
+        for (cent, (sum, count)) in centroids.iter_mut().zip(sums) {
+            if count != 0 {
+                let count = count as f32;
+                *cent = Self::new(sum[0] / count, sum[1] / count, sum[2] / count);
+            } else {
+                *cent = Self::create_random(&mut rng);
+            }
+        }
+    }
+
+    fn check_loop(centroids: &[Self], old_centroids: &[Self]) -> f32 {
+        let mut temp = [0.0f32; 3];
+        for (c0, c1) in centroids.iter().zip(old_centroids) {
+            temp[0] += c0.y - c1.y;
+            temp[1] += c0.cb - c1.cb;
+            temp[2] += c0.cr - c1.cr;
+        }
+
+        temp[0] * temp[0] + temp[1] * temp[1] + temp[2] * temp[2]
+    }
+
+    #[inline]
+    fn create_random(rng: &mut impl Rng) -> Self {
+        Self::new(
+            rng.gen_range(0.0..=1.0),
+            rng.gen_range(-0.5..=0.5),
+            rng.gen_range(-0.5..=0.5),
+        )
+    }
+
+    #[inline]
+    fn difference(c1: &Self, c2: &Self) -> f32 {
+        // Multiplication is used instead of `powi` which doesn't have
+        // guaranteed precision across platforms
+        let (y, cb, cr) = (c1.y - c2.y, c1.cb - c2.cb, c1.cr - c2.cr);
+        y * y + cb * cb + cr * cr
+    }
+}
+
+impl<S: YCbCrStandard> Axes for YCbCr<S> {
+    #[inline]
+    fn axes(&self) -> [f32; 3] {
+        [self.y, self.cb, self.cr]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ycbcr() {
+        let rgb = Srgb::new(0.8, 0.3, 0.1);
+        let bt601 = YCbCr::<Bt601>::from_rgb(rgb).to_rgb();
+        let bt709 = YCbCr::<Bt709>::from_rgb(rgb).to_rgb();
+        for converted in [bt601, bt709] {
+            assert!((converted.red - rgb.red).abs() < 1e-5);
+            assert!((converted.green - rgb.green).abs() < 1e-5);
+            assert!((converted.blue - rgb.blue).abs() < 1e-5);
+        }
+
+        // 3x2 frame with 4:2:0 chroma
+        let y = [16, 16, 235, 235, 16, 235];
+        let (cb, cr) = ([128, 240], [128, 16]);
+        let frame = YCbCr::<Bt709>::from_planes(
+            &y,
+            &cb,
+            &cr,
+            3,
+            2,
+            Subsampling::Yuv420,
+            YCbCrRange::Limited,
+        )
+        .unwrap();
+        assert_eq!(frame.len(), 6);
+        assert_eq!(frame[0], YCbCr::new(0.0, 0.0, 0.0));
+        assert_eq!(frame[5], YCbCr::new(1.0, 0.5, -0.5));
+        assert!(YCbCr::<Bt709>::from_planes(
+            &y,
+            &cb[..1],
+            &cr,
+            3,
+            2,
+            Subsampling::Yuv420,
+            YCbCrRange::Limited
+        )
+        .is_none());
+
+        let result = crate::get_kmeans(2, 20, 0.0, false, &frame, 0);
+        assert_eq!(result.centroids.len(), 2);
+    }
+}
@@ -0,0 +1,66 @@
+/// Calculate weights that favor pixels on edges, for use with
+/// [`get_kmeans_weighted`](fn.get_kmeans_weighted.html) and
+/// [`get_kmeans_hamerly_weighted`](fn.get_kmeans_hamerly_weighted.html).
+///
+/// `intensity` is a single channel of a `width` by `height` image in row-major
+/// order, such as luma or `Lab` lightness. The gradient magnitude of each
+/// pixel is found with a Sobel filter and scaled so the strongest edge is 1.0,
+/// and the weight of each pixel is `1.0 + strength * magnitude`. Flat areas
+/// keep a weight of 1.0, so large flat backgrounds count less relative to
+/// detailed regions without being ignored.
+///
+/// All weights are 1.0 if `intensity` is shorter than `width * height`.
+///
+/// ```
+/// use kmeans_colors::edge_weights;
+///
+/// // A 3x3 image with a vertical edge between the first and second columns
+/// let intensity = [0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0];
+/// let weights = edge_weights(&intensity, 3, 3, 4.0);
+/// assert_eq!(weights[4], 5.0);
+/// assert_eq!(weights[5], 1.0);
+/// ```
+pub fn edge_weights(intensity: &[f32], width: usize, height: usize, strength: f32) -> Vec<f32> {
+    let mut weights = vec![1.0; intensity.len()];
+    if width == 0 || height == 0 || intensity.len() < width * height {
+        return weights;
+    }
+
+    // Sample with coordinates clamped to the image
+    let at = |x: usize, dx: isize, y: usize, dy: isize| {
+        let x = x.saturating_add_signed(dx).min(width - 1);
+        let y = y.saturating_add_signed(dy).min(height - 1);
+        intensity[y * width + x]
+    };
+
+    let mut max = 0.0f32;
+    for y in 0..height {
+        for x in 0..width {
+            let gx = at(x, 1, y, -1) + 2.0 * at(x, 1, y, 0) + at(x, 1, y, 1)
+                - at(x, -1, y, -1)
+                - 2.0 * at(x, -1, y, 0)
+                - at(x, -1, y, 1);
+            let gy = at(x, -1, y, 1) + 2.0 * at(x, 0, y, 1) + at(x, 1, y, 1)
+                - at(x, -1, y, -1)
+                - 2.0 * at(x, 0, y, -1)
+                - at(x, 1, y, -1);
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            if magnitude.is_finite() {
+                weights[y * width + x] = magnitude;
+                max = max.max(magnitude);
+            } else {
+                weights[y * width + x] = 0.0;
+            }
+        }
+    }
+
+    for weight in weights.iter_mut().take(width * height) {
+        *weight = if max > 0.0 {
+            1.0 + strength * *weight / max
+        } else {
+            1.0
+        };
+    }
+
+    weights
+}
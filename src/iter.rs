@@ -0,0 +1,281 @@
+use rand::{Rng, SeedableRng};
+
+use crate::kmeans::{Calculate, Kmeans, Plateau, Weighted};
+use crate::plus_plus::{InitResult, InitStatus};
+
+/// Number of points buffered at a time.
+const CHUNK_SIZE: usize = 4096;
+
+/// Find the k-means centroids of the points produced by an iterator, such as
+/// a decoder or network stream, without collecting them into a buffer.
+///
+/// Takes the same arguments as [`get_kmeans`](fn.get_kmeans.html), except the
+/// points are read by cloning `points` once for each pass of the calculation.
+/// Only a small chunk of points is buffered at a time, along with the index
+/// of every point for the returned `Kmeans`.
+///
+/// The centroids are the same averages as `get_kmeans` finds, up to rounding
+/// from combining the averages of each chunk, but the initial centroids are
+/// chosen in a different order of the random number generator, so the results
+/// differ from `get_kmeans` for the same seed. Non-finite points are handled
+/// the same way.
+pub fn get_kmeans_iter<C, I>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    points: I,
+    seed: u64,
+) -> Kmeans<C>
+where
+    C: Weighted + Clone,
+    I: Iterator<Item = C> + Clone,
+{
+    // Initialize the random centroids
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids: Vec<C> = Vec::with_capacity(k);
+    let init = init_plus_plus_iter(k, &mut rng, points.clone(), &mut centroids);
+    if init.status == InitStatus::NoFinitePoints {
+        return Kmeans::new();
+    }
+
+    // Initialize indexed buffer and convergence variables
+    let mut iterations = 0;
+    let mut score;
+    let mut plateau = Plateau::new(max_iter);
+    let mut old_centroids = centroids.clone();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut chunk: Vec<C> = Vec::with_capacity(CHUNK_SIZE);
+    let mut chunk_indices: Vec<u32> = Vec::with_capacity(CHUNK_SIZE);
+    // Empty clusters of partial means are re-initialized with this generator
+    // so that `rng` is only used for the final centroids
+    let mut scratch_rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+
+    // Main loop: find nearest centroids and recalculate means until convergence
+    loop {
+        indices.clear();
+        let mut means = centroids.clone();
+        let mut counts: Vec<f32> = centroids.iter().map(|_| 0.0).collect();
+
+        // Index each chunk and merge its cluster means into the running means
+        let mut iter = points.clone();
+        loop {
+            chunk.clear();
+            chunk.extend(iter.by_ref().take(CHUNK_SIZE));
+            if chunk.is_empty() {
+                break;
+            }
+
+            chunk_indices.clear();
+            C::get_closest_centroid(&chunk, &centroids, &mut chunk_indices);
+            indices.extend_from_slice(&chunk_indices);
+
+            let mut chunk_means = centroids.clone();
+            C::recalculate_centroids(&mut scratch_rng, &chunk, &mut chunk_means, &chunk_indices);
+            let mut chunk_counts: Vec<f32> = centroids.iter().map(|_| 0.0).collect();
+            for (&idx, point) in chunk_indices.iter().zip(&chunk) {
+                if C::difference(point, point).is_finite() {
+                    chunk_counts[idx as usize] += 1.0;
+                }
+            }
+
+            merge_means(
+                &mut scratch_rng,
+                &mut means,
+                &mut counts,
+                chunk_means,
+                &chunk_counts,
+            );
+        }
+
+        // Clusters without points are re-initialized with a random point
+        for (cent, (mean, &count)) in centroids.iter_mut().zip(means.into_iter().zip(&counts)) {
+            *cent = if count > 0.0 {
+                mean
+            } else {
+                C::create_random(&mut rng)
+            };
+        }
+
+        score = C::check_loop(&centroids, &old_centroids);
+        if verbose {
+            println!("Score: {}", score);
+        }
+
+        // Verify that either the maximum iteration count has been met, the
+        // centroids haven't moved beyond a certain threshold since the
+        // previous iteration, or the score has stopped improving.
+        if iterations >= max_iter || score <= converge || plateau.stop(iterations, score) {
+            if verbose {
+                println!("Iterations: {}", iterations);
+            }
+            break;
+        }
+
+        iterations += 1;
+        old_centroids.clone_from(&centroids);
+    }
+
+    let mut result = Kmeans {
+        score,
+        centroids,
+        indices,
+        runs: Vec::new(),
+    };
+    let _ = result.dedup_centroids();
+    if verbose && result.effective_k() < k {
+        println!("Effective k: {}", result.effective_k());
+    }
+
+    result
+}
+
+/// Combine the running cluster means with the means of a chunk, weighting
+/// each by its number of points.
+#[allow(clippy::cast_possible_truncation)]
+fn merge_means<C: Weighted + Clone>(
+    rng: &mut impl Rng,
+    means: &mut [C],
+    counts: &mut [f32],
+    chunk_means: Vec<C>,
+    chunk_counts: &[f32],
+) {
+    let k = means.len();
+    let mut points = means.to_vec();
+    points.extend(chunk_means);
+    let weights: Vec<f32> = counts.iter().chain(chunk_counts).copied().collect();
+    let point_indices: Vec<u32> = (0..k).chain(0..k).map(|i| i as u32).collect();
+
+    C::recalculate_centroids_weighted(rng, &points, &weights, means, &point_indices);
+    counts
+        .iter_mut()
+        .zip(chunk_counts)
+        .for_each(|(count, chunk_count)| *count += chunk_count);
+}
+
+/// k-means++ initialization over the points of an iterator, making two passes
+/// for each centroid.
+fn init_plus_plus_iter<C, I>(
+    k: usize,
+    rng: &mut impl Rng,
+    points: I,
+    centroids: &mut Vec<C>,
+) -> InitResult
+where
+    C: Calculate + Clone,
+    I: Iterator<Item = C> + Clone,
+{
+    let start = centroids.len();
+    let result = |centroids: &Vec<C>, status| InitResult {
+        count: centroids.len() - start,
+        status,
+    };
+    if k == 0 {
+        return result(centroids, InitStatus::Complete);
+    }
+
+    // Choose first centroid uniformly from the finite points
+    let weight = |x: &C, _: &[C]| {
+        if C::difference(x, x).is_finite() {
+            1.0
+        } else {
+            0.0
+        }
+    };
+    match sample(rng, points.clone(), centroids, weight) {
+        Some(first) => centroids.push(first),
+        None => return result(centroids, InitStatus::NoFinitePoints),
+    }
+
+    // Pick a new centroid with weighted probability of `D(x)^2 / sum(D(x)^2)`,
+    // where `D(x)^2` is the distance to the closest centroid
+    let weight = |x: &C, centroids: &[C]| {
+        if !C::difference(x, x).is_finite() {
+            return 0.0;
+        }
+        let mut min = f32::MAX;
+        for cent in centroids {
+            let diff = C::difference(x, cent);
+            if diff < min {
+                min = diff;
+            }
+        }
+        min
+    };
+    for _ in 1..k {
+        match sample(rng, points.clone(), centroids, weight) {
+            Some(next) => centroids.push(next),
+            None => return result(centroids, InitStatus::FewerUniquePoints),
+        }
+    }
+
+    result(centroids, InitStatus::Complete)
+}
+
+/// Sample a point with probability proportional to its weight, returning
+/// `None` if the weights sum to zero.
+fn sample<C, I>(
+    rng: &mut impl Rng,
+    points: I,
+    centroids: &[C],
+    weight: impl Fn(&C, &[C]) -> f32,
+) -> Option<C>
+where
+    C: Clone,
+    I: Iterator<Item = C> + Clone,
+{
+    let sum: f32 = points.clone().map(|x| weight(&x, centroids)).sum();
+    if !sum.is_normal() {
+        return None;
+    }
+
+    // The last point with a positive weight is used if rounding keeps the
+    // running sum below the target
+    let target = rng.gen_range(0.0..sum);
+    let mut running = 0.0;
+    let mut last = None;
+    for point in points {
+        let w = weight(&point, centroids);
+        if w > 0.0 {
+            running += w;
+            if running > target {
+                return Some(point);
+            }
+            last = Some(point);
+        }
+    }
+
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn iterator_input() {
+        use crate::get_kmeans_iter;
+        use palette::Srgb;
+
+        let buf: Vec<Srgb> = (0..10_000)
+            .map(|i| match i % 3 {
+                0 => Srgb::new(0.0, 0.0, 0.0),
+                1 => Srgb::new(1.0, 1.0, 1.0),
+                _ => Srgb::new(f32::NAN, 0.0, 0.0),
+            })
+            .collect();
+
+        let result = get_kmeans_iter(1, 10, 0.0, false, buf.iter().copied(), 0);
+        assert_eq!(result.centroids.len(), 1);
+        assert!((result.centroids[0].red - 0.5).abs() < 1e-3);
+        assert_eq!(result.indices.len(), buf.len());
+
+        let result = get_kmeans_iter(3, 10, 0.0, false, buf.iter().copied(), 0);
+        assert_eq!(result.effective_k(), 2);
+        assert_ne!(result.indices[0], result.indices[1]);
+        assert!(result
+            .indices
+            .iter()
+            .step_by(3)
+            .all(|&i| i == result.indices[0]));
+    }
+}
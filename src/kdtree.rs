@@ -0,0 +1,123 @@
+use crate::kmeans::Calculate;
+
+/// A trait for points which can be split along coordinate axes, enabling
+/// nearest centroid search with a [`CentroidTree`](struct.CentroidTree.html).
+pub trait Axes: Calculate {
+    /// Returns the coordinates of the point.
+    ///
+    /// `difference` must be the sum of the squared differences of these
+    /// coordinates, otherwise the tree may skip the nearest centroid.
+    fn axes(&self) -> [f32; 3];
+}
+
+/// A k-d tree over a set of centroids for finding the nearest centroid of a
+/// point faster than comparing against every centroid.
+///
+/// Building the tree is `O(k log² k)` and a search is close to `O(log k)` for
+/// well-spread centroids, which is much faster than the linear scan of
+/// [`Calculate::get_closest_centroid`] for high `k`, e.g. 64 to 256 colors.
+/// The tree can be built once from the result of a k-means calculation and
+/// used to index other buffers against those centroids.
+///
+/// Results are identical to the linear scan: when multiple centroids are
+/// equally near, the lowest index is used.
+#[derive(Clone, Debug)]
+pub struct CentroidTree<C: Axes> {
+    /// Centroid points.
+    centroids: Vec<C>,
+    /// Coordinates of each centroid.
+    axes: Vec<[f32; 3]>,
+    /// Centroid indices, ordered so that the median of each range is the
+    /// splitting node of that subtree.
+    order: Vec<usize>,
+}
+
+impl<C: Axes + Clone> CentroidTree<C> {
+    /// Build a tree from a set of centroids.
+    pub fn new(centroids: &[C]) -> Self {
+        let axes: Vec<[f32; 3]> = centroids.iter().map(Axes::axes).collect();
+        let mut order: Vec<usize> = (0..centroids.len()).collect();
+        build(&axes, &mut order, 0);
+
+        CentroidTree {
+            centroids: centroids.to_vec(),
+            axes,
+            order,
+        }
+    }
+
+    /// Returns the centroids the tree was built from.
+    pub fn centroids(&self) -> &[C] {
+        &self.centroids
+    }
+
+    /// Find the index of the nearest centroid to a point. Returns 0 if the
+    /// distance to every centroid is NaN or `f32::MAX` or greater, like the
+    /// linear scan.
+    pub fn nearest(&self, point: &C) -> usize {
+        let mut best = (0, f32::MAX);
+        self.search(point, &point.axes(), 0, self.order.len(), 0, &mut best);
+        best.0
+    }
+
+    /// Find each point's nearest centroid, index the point with that centroid.
+    /// Equivalent to [`Calculate::get_closest_centroid`].
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn get_closest_centroid(&self, buffer: &[C], indices: &mut Vec<u32>) {
+        indices.extend(buffer.iter().map(|x| self.nearest(x) as u32));
+    }
+
+    /// Search the subtree of `order[lo..hi]`, updating the best index and
+    /// distance found.
+    fn search(
+        &self,
+        point: &C,
+        point_axes: &[f32; 3],
+        lo: usize,
+        hi: usize,
+        depth: usize,
+        best: &mut (usize, f32),
+    ) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        let idx = self.order[mid];
+        let diff = C::difference(point, &self.centroids[idx]);
+        if diff < best.1 || (diff == best.1 && idx < best.0) {
+            *best = (idx, diff);
+        }
+
+        // Search the side of the splitting plane containing the point first,
+        // the other side can only hold a nearer centroid if the plane is
+        // nearer than the best distance. Equal distances are still searched
+        // for a lower index.
+        let axis = depth % 3;
+        let plane = point_axes[axis] - self.axes[idx][axis];
+        let (near, far) = if plane < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(point, point_axes, near.0, near.1, depth + 1, best);
+        if plane * plane <= best.1 {
+            self.search(point, point_axes, far.0, far.1, depth + 1, best);
+        }
+    }
+}
+
+/// Order the indices so the median of each range splits the range along the
+/// axis for its depth.
+fn build(axes: &[[f32; 3]], order: &mut [usize], depth: usize) {
+    if order.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % 3;
+    order.sort_by(|&a, &b| axes[a][axis].total_cmp(&axes[b][axis]));
+    let mid = order.len() / 2;
+    let (left, right) = order.split_at_mut(mid);
+    build(axes, left, depth + 1);
+    build(axes, &mut right[1..], depth + 1);
+}
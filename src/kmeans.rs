@@ -1,20 +1,63 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
 use rand::{Rng, SeedableRng};
 
+use crate::array::Projection;
+use crate::kdtree::{Axes, CentroidTree};
+use crate::plus_plus::InitStatus;
+
 /// A trait for enabling k-means calculation of a data type.
 pub trait Calculate: Sized {
+    /// Default convergence threshold in units of `check_loop`, calibrated for
+    /// `k = 8` on an image of 2<sup>18</sup> pixels. Scale it to other sizes
+    /// with [`default_converge`](fn.default_converge.html).
+    ///
+    /// The default of `5.0` suits components spanning about `0.0..100.0`, as
+    /// in `Lab`; types with components in `0.0..1.0` should use a much smaller
+    /// value such as `0.0025`.
+    const CONVERGE: f32 = 5.0;
+
+    /// Number of components of a point, used to model the spread of clusters
+    /// in [`get_kmeans_shrink`](fn.get_kmeans_shrink.html).
+    const DIMENSIONS: usize = 3;
+
     /// Find a points's nearest centroid, index the point with that centroid.
-    fn get_closest_centroid(buffer: &[Self], centroids: &[Self], indices: &mut Vec<u8>);
+    /// When multiple centroids are equally near, the lowest index is used.
+    fn get_closest_centroid(buffer: &[Self], centroids: &[Self], indices: &mut Vec<u32>);
 
     /// Find the new centroid locations based on the average of the points that
     /// correspond to the centroid. If no points correspond, the centroid is
     /// re-initialized with a random point.
+    ///
+    /// Implementations should accumulate the sum of each cluster in a single
+    /// pass over the buffer rather than one pass per centroid, which is much
+    /// faster at high `k`.
     fn recalculate_centroids(
         rng: &mut impl Rng,
         buf: &[Self],
         centroids: &mut [Self],
-        indices: &[u8],
+        indices: &[u32],
     );
 
+    /// Index each point with its nearest centroid like `get_closest_centroid`,
+    /// then recalculate the centroids like `recalculate_centroids`.
+    ///
+    /// The default implementation calls both functions. Implementations can
+    /// override it to accumulate the cluster sums while assigning points, so
+    /// each iteration of [`get_kmeans`](fn.get_kmeans.html) only passes over
+    /// the buffer once.
+    fn assign_and_recalculate(
+        rng: &mut impl Rng,
+        buf: &[Self],
+        centroids: &mut [Self],
+        indices: &mut Vec<u32>,
+    ) {
+        Self::get_closest_centroid(buf, centroids, indices);
+        Self::recalculate_centroids(rng, buf, centroids, indices);
+    }
+
     /// Calculate the distance metric for convergence comparison.
     fn check_loop(centroids: &[Self], old_centroids: &[Self]) -> f32;
 
@@ -23,9 +66,28 @@ pub trait Calculate: Sized {
 
     /// Calculate the geometric distance between two points, the square root is
     /// omitted.
+    ///
+    /// For results to be reproducible across platforms, implementations should
+    /// avoid operations without guaranteed precision such as `powi` or `powf`.
     fn difference(c1: &Self, c2: &Self) -> f32;
 }
 
+/// A trait for enabling k-means calculation of weighted points, such as the
+/// unique colors of an image weighted by how often they occur.
+pub trait Weighted: Calculate {
+    /// Find the new centroid locations based on the weighted average of the
+    /// points that correspond to the centroid. Points with a weight of zero or
+    /// less are ignored. If no points correspond, the centroid is
+    /// re-initialized with a random point.
+    fn recalculate_centroids_weighted(
+        rng: &mut impl Rng,
+        buf: &[Self],
+        weights: &[f32],
+        centroids: &mut [Self],
+        indices: &[u32],
+    );
+}
+
 /// Struct result of k-means calculation with convergence score, centroids, and
 /// indexed buffer.
 #[derive(Clone, Debug, Default)]
@@ -35,17 +97,616 @@ pub struct Kmeans<C: Calculate> {
     /// Points determined to be centroids of input buffer.
     pub centroids: Vec<C>,
     /// Buffer of points indexed to centroids.
-    pub indices: Vec<u8>,
+    pub indices: Vec<u32>,
+    /// Seed and score of every run when the result is the best of several
+    /// runs, in the order they ran. Empty for a single calculation.
+    pub runs: Vec<RunSummary>,
+}
+
+/// Outcome of one run of a k-means calculation repeated with different seeds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunSummary {
+    /// Seed of the run.
+    pub seed: u64,
+    /// Final score of the run, as in [`Kmeans::score`].
+    pub score: f32,
 }
 
 impl<C: Calculate> Kmeans<C> {
     /// Create a new `Kmeans` struct to contain k-means results.
     pub fn new() -> Self {
         Kmeans {
-            score: core::f32::MAX,
+            score: f32::MAX,
             centroids: Vec::new(),
             indices: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Keep the result of `run` if its score is lower, recording its seed and
+    /// score in [`runs`](#structfield.runs).
+    ///
+    /// Starting from [`Kmeans::new`], this finds the best of several runs of
+    /// the calculation while keeping the scores of the others, to judge how
+    /// much the result depends on the seed.
+    ///
+    /// ```
+    /// use kmeans_colors::{get_kmeans, Kmeans};
+    /// # use palette::Srgb;
+    /// # let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0f32, 1.0, 1.0)];
+    ///
+    /// let mut result = Kmeans::new();
+    /// for seed in 0..3 {
+    ///     result.keep_best(get_kmeans(2, 20, 0.0025, false, &buf, seed), seed);
+    /// }
+    /// assert_eq!(result.runs.len(), 3);
+    /// ```
+    pub fn keep_best(&mut self, run: Kmeans<C>, seed: u64) {
+        let mut runs = core::mem::take(&mut self.runs);
+        runs.push(RunSummary {
+            seed,
+            score: run.score,
+        });
+        if run.score < self.score {
+            *self = run;
+        }
+        self.runs = runs;
+    }
+
+    /// Sort the centroids with a comparator function and remap the indices to
+    /// match the new order.
+    ///
+    /// The centroid order of a result depends on the random initialization, so
+    /// the same data with different seeds may produce permuted centroids.
+    /// Sorting them gives a canonical order for caching and comparing results,
+    /// e.g. by luminance for colors.
+    pub fn sort_centroids_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&C, &C) -> core::cmp::Ordering,
+    {
+        let mut order: Vec<usize> = (0..self.centroids.len()).collect();
+        order.sort_by(|&a, &b| compare(&self.centroids[a], &self.centroids[b]));
+        self.reorder(&order);
+    }
+
+    /// Sort the centroids from most to least points and remap the indices to
+    /// match the new order. Centroids with equal counts keep their relative
+    /// order.
+    pub fn sort_centroids_by_population(&mut self) {
+        let counts = self.counts();
+        let mut order: Vec<usize> = (0..self.centroids.len()).collect();
+        order.sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+        self.reorder(&order);
+    }
+
+    /// Merge centroids that are identical to an earlier centroid and remap the
+    /// indices, returning the number of centroids removed.
+    ///
+    /// `get_kmeans` and `get_kmeans_hamerly` call this before returning, so
+    /// the length of `centroids` is the effective number of clusters found.
+    pub fn dedup_centroids(&mut self) -> usize {
+        let len = self.centroids.len();
+        let mut keep: Vec<usize> = Vec::with_capacity(len);
+        let mut remap: Vec<usize> = Vec::with_capacity(len);
+        for (i, cent) in self.centroids.iter().enumerate() {
+            match keep
+                .iter()
+                .position(|&j| C::difference(cent, &self.centroids[j]) == 0.0)
+            {
+                Some(pos) => remap.push(pos),
+                None => {
+                    remap.push(keep.len());
+                    keep.push(i);
+                }
+            }
+        }
+
+        if keep.len() < len {
+            self.remap(&keep, &remap);
+        }
+        len - keep.len()
+    }
+
+    /// Returns the effective number of clusters, which may be less than the
+    /// requested `k` if the buffer had fewer than `k` unique points.
+    pub fn effective_k(&self) -> usize {
+        self.centroids.len()
+    }
+
+    /// Returns the number of points in each cluster.
+    pub fn counts(&self) -> Vec<usize> {
+        let mut counts = vec![0usize; self.centroids.len()];
+        for &i in &self.indices {
+            if let Some(count) = counts.get_mut(i as usize) {
+                *count += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Returns the positions in the input buffer of the points in cluster `i`.
+    pub fn cluster_members(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        self.indices
+            .iter()
+            .enumerate()
+            .filter(move |(_, &idx)| idx as usize == i)
+            .map(|(pos, _)| pos)
+    }
+
+    /// Returns the indices as one-hot rows, one per point, with a 1 in the
+    /// column of the point's cluster and 0 in the others.
+    pub fn to_one_hot(&self) -> Vec<Vec<u8>> {
+        self.indices
+            .iter()
+            .map(|&idx| {
+                let mut row = vec![0; self.centroids.len()];
+                if let Some(x) = row.get_mut(idx as usize) {
+                    *x = 1;
+                }
+                row
+            })
+            .collect()
+    }
+
+    /// Move each centroid with `snap`, e.g. to the nearest color of a fixed
+    /// grid, then reindex the points of `buf` to the moved centroids and merge
+    /// centroids that moved to the same point.
+    ///
+    /// `buf` must be the buffer the result was calculated from. The `score` is
+    /// left unchanged.
+    pub fn snap_centroids<F>(&mut self, buf: &[C], snap: F)
+    where
+        F: FnMut(&C) -> C,
+    {
+        self.centroids = self.centroids.iter().map(snap).collect();
+        self.indices.clear();
+        C::get_closest_centroid(buf, &self.centroids, &mut self.indices);
+        let _ = self.dedup_centroids();
+    }
+
+    /// Returns the point of each cluster nearest to its centroid, or `None` for
+    /// clusters without points.
+    ///
+    /// `buf` must be the buffer the result was calculated from. The centroid
+    /// is the average of the cluster, which may not be a color in the image;
+    /// the representative is an actual input point. When multiple points are
+    /// equally near, the first is used.
+    pub fn representatives(&self, buf: &[C]) -> Vec<Option<C>>
+    where
+        C: Clone,
+    {
+        let mut best: Vec<Option<(usize, f32)>> = self.centroids.iter().map(|_| None).collect();
+        for (i, (&idx, point)) in self.indices.iter().zip(buf).enumerate() {
+            if let (Some(slot), Some(cent)) =
+                (best.get_mut(idx as usize), self.centroids.get(idx as usize))
+            {
+                let diff = C::difference(point, cent);
+                if diff.is_finite() && slot.is_none_or(|(_, min)| diff < min) {
+                    *slot = Some((i, diff));
+                }
+            }
+        }
+
+        best.into_iter()
+            .map(|x| x.map(|(i, _)| buf[i].clone()))
+            .collect()
+    }
+
+    /// Classify the clusters of an image into probable background and subject
+    /// from how often they appear along the border of the image.
+    ///
+    /// The indices must be the pixels of a `width` by `height` image in
+    /// row-major order. The border is a margin of 5% of the smaller dimension,
+    /// at least 1 pixel wide. A cluster is background if it covers a larger
+    /// fraction of the border than of the whole image, since the subject of a
+    /// photo rarely touches its edges.
+    pub fn split_background(&self, width: usize, height: usize) -> BackgroundSplit<C>
+    where
+        C: Clone,
+    {
+        let margin = (width.min(height) / 20).max(1);
+        let mut border = vec![0usize; self.centroids.len()];
+        for (i, &idx) in self.indices.iter().enumerate().take(width * height) {
+            let (x, y) = (i % width, i / width);
+            if x < margin || y < margin || x + margin >= width || y + margin >= height {
+                if let Some(count) = border.get_mut(idx as usize) {
+                    *count += 1;
+                }
+            }
+        }
+        let counts = self.counts();
+        let border_total: usize = border.iter().sum();
+        let total: usize = counts.iter().sum();
+
+        // Compare border / border_total > count / total without dividing
+        let is_background: Vec<bool> = border
+            .iter()
+            .zip(&counts)
+            .map(|(&b, &c)| (b as u128) * (total as u128) > (c as u128) * (border_total as u128))
+            .collect();
+        let dominant = |background: bool| {
+            counts
+                .iter()
+                .zip(&is_background)
+                .enumerate()
+                .filter(|(_, (&count, &bg))| bg == background && count > 0)
+                .max_by_key(|(i, (&count, _))| (count, core::cmp::Reverse(*i)))
+                .map(|(i, _)| self.centroids[i].clone())
+        };
+
+        BackgroundSplit {
+            background: dominant(true),
+            foreground: dominant(false),
+            is_background,
+        }
+    }
+
+    /// Find the contiguous regions of each cluster of an image, to tell a
+    /// large object of one color from speckles scattered across the image.
+    ///
+    /// The indices must be the pixels of a `width` by `height` image in
+    /// row-major order. Pixels are connected to the pixels above, below, and
+    /// to either side of them with the same index.
+    pub fn regions(&self, width: usize, height: usize) -> Regions {
+        Regions::new(&self.indices, width, height)
+    }
+
+    /// Reorder centroids so that the `i`th centroid is the old centroid at
+    /// `order[i]`, and remap the indices.
+    fn reorder(&mut self, order: &[usize]) {
+        let mut remap = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            remap[old] = new;
+        }
+        self.remap(order, &remap);
+    }
+
+    /// Keep the centroids at the indices in `keep`, in order, and replace each
+    /// index `i` with `remap[i]`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn remap(&mut self, keep: &[usize], remap: &[usize]) {
+        let mut old: Vec<Option<C>> = self.centroids.drain(..).map(Some).collect();
+        self.centroids = keep.iter().filter_map(|&i| old[i].take()).collect();
+        self.indices.iter_mut().for_each(|x| {
+            *x = remap.get(*x as usize).map_or(*x, |&new| new as u32);
+        });
+    }
+}
+
+/// Clusters of an image classified as background or subject, returned by
+/// [`Kmeans::split_background`](struct.Kmeans.html#method.split_background).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BackgroundSplit<C> {
+    /// Whether each centroid is probably part of the background.
+    pub is_background: Vec<bool>,
+    /// The background centroid covering the most pixels.
+    pub background: Option<C>,
+    /// The subject centroid covering the most pixels.
+    pub foreground: Option<C>,
+}
+
+/// Contiguous regions of the clusters of an image, returned by
+/// [`Kmeans::regions`](struct.Kmeans.html#method.regions).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Regions {
+    /// Region of each pixel, in row-major order.
+    pub labels: Vec<u32>,
+    /// Centroid index and number of pixels of each region, in the order of
+    /// the labels.
+    pub regions: Vec<(u32, usize)>,
+}
+
+impl Regions {
+    /// Find the contiguous regions of the indices of a `width` by `height`
+    /// image in row-major order, as in
+    /// [`Kmeans::regions`](struct.Kmeans.html#method.regions).
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(indices: &[u32], width: usize, height: usize) -> Self {
+        let len = indices.len().min(width * height);
+        let mut labels = vec![u32::MAX; len];
+        let mut regions = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..len {
+            if labels[start] != u32::MAX {
+                continue;
+            }
+            let label = regions.len() as u32;
+            let index = indices[start];
+            labels[start] = label;
+            stack.push(start);
+            let mut size = 0;
+            while let Some(i) = stack.pop() {
+                size += 1;
+                for &j in neighbors(i, width, height).iter().flatten() {
+                    if j < len && labels[j] == u32::MAX && indices[j] == index {
+                        labels[j] = label;
+                        stack.push(j);
+                    }
+                }
+            }
+            regions.push((index, size));
+        }
+
+        Regions { labels, regions }
+    }
+
+    /// Returns the sizes of the regions of a centroid, from largest to
+    /// smallest.
+    pub fn sizes(&self, centroid: u32) -> Vec<usize> {
+        let mut sizes: Vec<usize> = self
+            .regions
+            .iter()
+            .filter(|x| x.0 == centroid)
+            .map(|x| x.1)
+            .collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        sizes
+    }
+}
+
+/// Pixels above, below, and to either side of pixel `i` of a `width` by
+/// `height` image.
+fn neighbors(i: usize, width: usize, height: usize) -> [Option<usize>; 4] {
+    let (x, y) = (i % width, i / width);
+    [
+        (x > 0).then(|| i - 1),
+        (x + 1 < width).then(|| i + 1),
+        (y > 0).then(|| i - width),
+        (y + 1 < height).then(|| i + width),
+    ]
+}
+
+/// Reassign contiguous regions smaller than `min_size` pixels to the index
+/// that surrounds them the most, removing speckles from a quantized image.
+///
+/// The indices must be the pixels of a `width` by `height` image in
+/// row-major order, with pixels connected as in
+/// [`Kmeans::regions`](struct.Kmeans.html#method.regions). Regions are merged
+/// from smallest to largest until every region has at least `min_size`
+/// pixels or covers the whole image.
+///
+/// ```
+/// use kmeans_colors::despeckle;
+///
+/// let mut indices = [0, 0, 0, 0, 1, 0, 0, 0, 0];
+/// despeckle(&mut indices, 3, 3, 2);
+/// assert_eq!(indices, [0; 9]);
+/// ```
+pub fn despeckle(indices: &mut [u32], width: usize, height: usize, min_size: usize) {
+    let len = indices.len().min(width * height);
+    loop {
+        let regions = Regions::new(indices, width, height);
+        let mut small: Vec<usize> = (0..regions.regions.len())
+            .filter(|&r| regions.regions[r].1 < min_size)
+            .collect();
+        if small.is_empty() || regions.regions.len() == 1 {
+            return;
+        }
+        small.sort_by_key(|&r| regions.regions[r].1);
+
+        let mut members = vec![Vec::new(); regions.regions.len()];
+        for (i, &label) in regions.labels.iter().enumerate() {
+            if regions.regions[label as usize].1 < min_size {
+                members[label as usize].push(i);
+            }
+        }
+
+        let mut changed = false;
+        for r in small {
+            // Count the neighboring pixels of each other index, using the
+            // indices already merged in this pass
+            let mut votes: HashMap<u32, usize> = HashMap::new();
+            let index = indices[members[r][0]];
+            for &i in &members[r] {
+                for &j in neighbors(i, width, height).iter().flatten() {
+                    if j < len && indices[j] != index {
+                        *votes.entry(indices[j]).or_insert(0) += 1;
+                    }
+                }
+            }
+            let best = votes
+                .into_iter()
+                .max_by_key(|&(x, count)| (count, core::cmp::Reverse(x)))
+                .map(|(x, _)| x);
+            if let Some(best) = best {
+                for &i in &members[r] {
+                    indices[i] = best;
+                }
+                changed = true;
+            }
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+/// Convergence threshold for clustering `len` points into `k` clusters,
+/// scaled from the calibrated [`Calculate::CONVERGE`] of `C`.
+///
+/// `check_loop` sums the movement of every centroid, so the threshold grows
+/// linearly with `k`. Reassigning a few points moves the centroids of small
+/// buffers further, so the threshold also grows with the inverse square root
+/// of `len`, within half to twice the calibrated value.
+///
+/// ```
+/// use kmeans_colors::{default_converge, Calculate};
+/// use palette::{Lab, Srgb};
+///
+/// assert_eq!(default_converge::<Lab>(8, 1 << 18), <Lab as Calculate>::CONVERGE);
+/// assert!(default_converge::<Srgb>(16, 1 << 18) > default_converge::<Srgb>(8, 1 << 18));
+/// ```
+#[allow(clippy::cast_precision_loss)]
+pub fn default_converge<C: Calculate>(k: usize, len: usize) -> f32 {
+    const CALIBRATED_K: f32 = 8.0;
+    const CALIBRATED_LEN: f32 = (1 << 18) as f32;
+
+    let size = (CALIBRATED_LEN / len.max(1) as f32).sqrt().clamp(0.5, 2.0);
+    C::CONVERGE * k.max(1) as f32 / CALIBRATED_K * size
+}
+
+/// `max_iter` that stops the calculation once the score stops improving
+/// instead of after a fixed number of iterations.
+///
+/// The calculation still stops when the score reaches `converge`. Otherwise,
+/// it stops when the score has improved by less than 5% of its previous value
+/// for 3 iterations in a row, or after 1000 iterations.
+///
+/// ```
+/// use kmeans_colors::{get_kmeans_hamerly, AUTO_MAX_ITER};
+/// # use palette::Srgb;
+/// # let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0f32, 1.0, 1.0)];
+///
+/// let result = get_kmeans_hamerly(2, AUTO_MAX_ITER, 0.0025, false, &buf, 0);
+/// # assert_eq!(result.indices.len(), buf.len());
+/// ```
+pub const AUTO_MAX_ITER: usize = usize::MAX;
+
+/// Tracks the score trajectory of a calculation run with [`AUTO_MAX_ITER`].
+pub(crate) struct Plateau {
+    enabled: bool,
+    previous: f32,
+    stalled: usize,
+}
+
+impl Plateau {
+    /// Minimum improvement of the score relative to the previous iteration.
+    const EPSILON: f32 = 0.05;
+    /// Iterations in a row without improvement before stopping.
+    const PATIENCE: usize = 3;
+    /// Iterations after which to stop regardless of the score.
+    const LIMIT: usize = 1000;
+
+    pub(crate) fn new(max_iter: usize) -> Self {
+        Plateau {
+            enabled: max_iter == AUTO_MAX_ITER,
+            previous: f32::INFINITY,
+            stalled: 0,
+        }
+    }
+
+    /// Record the score of an iteration, returning whether to stop.
+    pub(crate) fn stop(&mut self, iterations: usize, score: f32) -> bool {
+        if !self.enabled {
+            return false;
         }
+
+        if self.previous - score < Self::EPSILON * self.previous {
+            self.stalled += 1;
+        } else {
+            self.stalled = 0;
+        }
+        self.previous = score;
+        self.stalled >= Self::PATIENCE || iterations >= Self::LIMIT
+    }
+}
+
+/// Parameters for calculating k-means over multiple runs.
+#[derive(Clone, Copy, Debug)]
+pub struct KmeansConfig {
+    /// Number of clusters.
+    pub k: usize,
+    /// Maximum number of iterations.
+    pub max_iter: usize,
+    /// Threshold for convergence.
+    pub converge: f32,
+    /// Number of times to run the calculation, keeping the lowest score.
+    pub runs: usize,
+    /// Seed for the random number generator, incremented for each run.
+    pub seed: u64,
+    /// Flag for printing convergence information to console.
+    pub verbose: bool,
+    /// Abandon runs that are this many times worse than the best completed
+    /// run after a few iterations, see
+    /// [`get_kmeans_best`](fn.get_kmeans_best.html).
+    pub prune: Option<f32>,
+    /// Called with the diagnostics of every iteration of every run, such as
+    /// to log them in a structured format. Applies to
+    /// [`get_kmeans_best`](fn.get_kmeans_best.html).
+    pub trace: Option<fn(&Iteration)>,
+}
+
+impl Default for KmeansConfig {
+    fn default() -> Self {
+        KmeansConfig {
+            k: 8,
+            max_iter: 20,
+            converge: 5.0,
+            runs: 1,
+            seed: 0,
+            verbose: false,
+            prune: None,
+            trace: None,
+        }
+    }
+}
+
+/// Time spent in each phase of a k-means calculation.
+///
+/// Returned by [`get_kmeans_timed`](fn.get_kmeans_timed.html) and
+/// [`get_kmeans_hamerly_timed`](fn.get_kmeans_hamerly_timed.html) for
+/// evaluating performance and choosing between algorithms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// k-means++ initialization.
+    pub init: Duration,
+    /// Finding the nearest centroid of each point, including maintaining the
+    /// bounds of the Hamerly algorithm.
+    pub assignment: Duration,
+    /// Recalculating the centroids.
+    pub recalculation: Duration,
+    /// Comparing the centroids with the previous iteration.
+    pub convergence: Duration,
+    /// Number of iterations completed.
+    pub iterations: usize,
+}
+
+/// Diagnostics of one iteration of a k-means calculation, passed to
+/// [`KmeansConfig::trace`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Iteration {
+    /// Seed of the run.
+    pub seed: u64,
+    /// Number of the iteration in the run, starting from 1.
+    pub iteration: usize,
+    /// Convergence score of the iteration.
+    pub score: f32,
+    /// Number of clusters without points, which were re-initialized to a
+    /// random point.
+    pub reseeded: usize,
+    /// Time spent on the iteration.
+    pub elapsed: Duration,
+}
+
+/// Callback for the diagnostics of each iteration of a run.
+#[derive(Clone, Copy)]
+struct Trace {
+    seed: u64,
+    callback: fn(&Iteration),
+}
+
+impl Trace {
+    /// Report an iteration, counting the clusters that no point is indexed to.
+    fn record(
+        &self,
+        iterations: usize,
+        score: f32,
+        k: usize,
+        indices: impl Iterator<Item = u32>,
+        start: Instant,
+    ) {
+        let mut used = vec![false; k];
+        for i in indices {
+            used[i as usize] = true;
+        }
+        (self.callback)(&Iteration {
+            seed: self.seed,
+            iteration: iterations + 1,
+            score,
+            reseeded: used.iter().filter(|&&x| !x).count(),
+            elapsed: start.elapsed(),
+        });
     }
 }
 
@@ -53,7 +714,8 @@ impl<C: Calculate> Kmeans<C> {
 ///
 /// `max_iter` and `converge` are used together to determine when the k-means
 /// calculation has converged. When the `score` is less than `converge` or the
-/// number of iterations reaches `max_iter`, the calculation is complete.
+/// number of iterations reaches `max_iter`, the calculation is complete. Use
+/// [`AUTO_MAX_ITER`] to stop once the score stops improving instead.
 ///
 /// - `k` - number of clusters.
 /// - `max_iter` - maximum number of iterations.
@@ -61,6 +723,26 @@ impl<C: Calculate> Kmeans<C> {
 /// - `verbose` - flag for printing convergence information to console.
 /// - `buf` - array of points.
 /// - `seed` - seed for the random number generator.
+///
+/// If the buffer has fewer than `k` unique points, fewer centroids are
+/// returned and identical centroids are merged. The number of centroids found
+/// is available from [`Kmeans::effective_k`].
+///
+/// ## Non-finite points
+///
+/// Points with NaN or infinite components are skipped: they are never chosen
+/// as initial centroids and don't contribute to the centroid averages, so they
+/// can't poison the result. They are still indexed, to the first centroid, so
+/// `indices` matches the length of `buf`. If there are no finite points, an
+/// empty result from [`Kmeans::new`] is returned. Use
+/// [`check_finite`](fn.check_finite.html) beforehand to treat them as an error
+/// instead.
+///
+/// ## Reproducibility
+///
+/// The calculation runs on the calling thread and sums the points of each
+/// cluster in the order of `buf`, so the same buffer and seed give the same
+/// result down to the bit on a platform.
 pub fn get_kmeans<C: Calculate + Clone>(
     k: usize,
     max_iter: usize,
@@ -68,47 +750,1245 @@ pub fn get_kmeans<C: Calculate + Clone>(
     verbose: bool,
     buf: &[C],
     seed: u64,
+) -> Kmeans<C> {
+    kmeans_lloyd(
+        k, max_iter, converge, verbose, buf, seed, None, None, None, None,
+    )
+}
+
+/// Find the k-means centroids of a buffer like
+/// [`get_kmeans`](fn.get_kmeans.html), also returning the time spent in each
+/// phase of the calculation.
+///
+/// Points are assigned and centroids recalculated in separate passes so they
+/// can be timed, which may be slightly slower than `get_kmeans`. The results
+/// are identical.
+pub fn get_kmeans_timed<C: Calculate + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> (Kmeans<C>, Timings) {
+    let mut timings = Timings::default();
+    let result = kmeans_lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        seed,
+        None,
+        Some(&mut timings),
+        None,
+        None,
+    );
+    (result, timings)
+}
+
+/// Lloyd's algorithm with k-means++ initialization or `initial` centroids,
+/// optionally timed or pruned.
+#[allow(clippy::too_many_arguments)]
+fn kmeans_lloyd<C: Calculate + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+    initial: Option<&[C]>,
+    mut timings: Option<&mut Timings>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
 ) -> Kmeans<C> {
     // Initialize the random centroids
+    let start = Instant::now();
     let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
     let mut centroids: Vec<C> = Vec::with_capacity(k);
-    crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centroids);
+    let initialized = init_centroids(k, &mut rng, buf, None, initial, &mut centroids);
+    if let Some(timings) = timings.as_deref_mut() {
+        timings.init += start.elapsed();
+    }
+    if !initialized {
+        return Kmeans::new();
+    }
+
+    lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centroids,
+        |rng, centroids, indices, timings| match timings {
+            None => C::assign_and_recalculate(rng, buf, centroids, indices),
+            Some(timings) => {
+                let start = Instant::now();
+                C::get_closest_centroid(buf, centroids, indices);
+                let assigned = Instant::now();
+                C::recalculate_centroids(rng, buf, centroids, indices);
+                timings.assignment += assigned - start;
+                timings.recalculation += assigned.elapsed();
+            }
+        },
+        timings,
+        prune,
+        trace,
+    )
+}
+
+/// Find the k-means centroids of a buffer, indexing points with a
+/// [`CentroidTree`](struct.CentroidTree.html) rebuilt each iteration. Takes
+/// the same arguments as [`get_kmeans`](fn.get_kmeans.html) and produces the
+/// same results.
+///
+/// The nearest centroid search of `get_kmeans` compares every point with
+/// every centroid, which dominates the calculation at high `k`. Searching a
+/// k-d tree is much faster for `k` of 64 or more, while the cost of building
+/// it makes this slower for small `k`.
+pub fn get_kmeans_kdtree<C: Axes + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> Kmeans<C> {
+    // Initialize the random centroids
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids: Vec<C> = Vec::with_capacity(k);
+    let init = crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centroids);
+    if init.status == InitStatus::NoFinitePoints {
+        return Kmeans::new();
+    }
+
+    lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centroids,
+        |rng, centroids, indices, _| {
+            CentroidTree::new(centroids).get_closest_centroid(buf, indices);
+            C::recalculate_centroids(rng, buf, centroids, indices);
+        },
+        None,
+        None,
+        None,
+    )
+}
+
+/// Find the k-means centroids of a buffer of arrays, such as embeddings, with
+/// an approximate nearest centroid search. Takes the same arguments as
+/// [`get_kmeans`](fn.get_kmeans.html) and the number of `candidates` checked
+/// for each point.
+///
+/// Comparing every point with every centroid costs `N` operations per pair,
+/// which dominates the calculation for large `N`. Instead, the points and
+/// centroids are projected onto `min(N, 16)` random directions chosen from
+/// `seed`, and each point is only compared in full with the `candidates`
+/// centroids nearest to it in the projection. A point can be assigned to a
+/// centroid that isn't its nearest when the projection ranks that centroid
+/// outside the candidates, so results may differ slightly from `get_kmeans`.
+/// With `candidates` of `k` or more, every centroid is checked and the result
+/// is the same as `get_kmeans`.
+///
+/// ```
+/// use kmeans_colors::get_kmeans_approx;
+///
+/// let mut buf = vec![[0.0f32; 64]; 50];
+/// buf.extend(vec![[1.0; 64]; 50]);
+/// let result = get_kmeans_approx(2, 20, 0.0025, false, &buf, 0, 1);
+/// assert_eq!(result.effective_k(), 2);
+/// ```
+pub fn get_kmeans_approx<const N: usize>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[[f32; N]],
+    seed: u64,
+    candidates: usize,
+) -> Kmeans<[f32; N]> {
+    // Initialize the random centroids
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids: Vec<[f32; N]> = Vec::with_capacity(k);
+    let init = crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centroids);
+    if init.status == InitStatus::NoFinitePoints {
+        return Kmeans::new();
+    }
+
+    // The directions use their own stream so the centroids are the same as
+    // those of `get_kmeans`
+    let mut projection_rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    projection_rng.set_stream(1);
+    let projection = Projection::new(&mut projection_rng, buf);
+
+    lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centroids,
+        |rng, centroids, indices, _| {
+            projection.get_closest_centroid(buf, centroids, candidates, indices);
+            <[f32; N]>::recalculate_centroids(rng, buf, centroids, indices);
+        },
+        None,
+        None,
+        None,
+    )
+}
+
+/// Find the k-means centroids of a buffer like
+/// [`get_kmeans`](fn.get_kmeans.html), merging centroids that split a
+/// natural cluster so that fewer than `k` centroids may be returned.
+///
+/// Whenever the score stops improving by at least 5% per iteration while
+/// still above `converge`, the two closest centroids are merged if that
+/// lowers the Bayesian information criterion of the clustering, which weighs
+/// how well the clusters fit the points against the number of clusters.
+/// Iterations then continue with one centroid fewer. Once a merge is
+/// rejected, no more merges are tried. This helps images whose natural
+/// palette is smaller than the requested `k`.
+///
+/// The criterion models each cluster as a spherical Gaussian with a variance
+/// shared by all clusters, in [`Calculate::DIMENSIONS`] dimensions.
+///
+/// ```
+/// use kmeans_colors::get_kmeans_shrink;
+/// use palette::Srgb;
+///
+/// // Two colors with some noise
+/// let mut state = 1u32;
+/// let mut noise = || {
+///     state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+///     (state >> 16) as f32 / 65536.0 * 0.05
+/// };
+/// let buf: Vec<Srgb> = (0..1000)
+///     .map(|i| {
+///         let v = if i % 2 == 0 { 0.2 } else { 0.8 };
+///         Srgb::new(v + noise(), v + noise(), v + noise())
+///     })
+///     .collect();
+/// let result = get_kmeans_shrink(6, 50, 0.0, false, &buf, 0);
+/// assert_eq!(result.effective_k(), 2);
+/// ```
+pub fn get_kmeans_shrink<C: Calculate + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> Kmeans<C> {
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids: Vec<C> = Vec::with_capacity(k);
+    let init = crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centroids);
+    if init.status == InitStatus::NoFinitePoints {
+        return Kmeans::new();
+    }
+
+    let mut iterations = 0;
+    let mut score;
+    let mut previous = f32::INFINITY;
+    let mut shrinking = true;
+    let mut plateau = Plateau::new(max_iter);
+    let mut old_centroids = centroids.clone();
+    let mut indices: Vec<u32> = Vec::with_capacity(buf.len());
+    loop {
+        C::assign_and_recalculate(&mut rng, buf, &mut centroids, &mut indices);
+        score = C::check_loop(&centroids, &old_centroids);
+        if verbose {
+            println!("Score: {}", score);
+        }
+
+        // Try merging once the centroids have settled, continuing with the
+        // merged centroids instead of stopping if it's an improvement
+        let converged = score <= converge || plateau.stop(iterations, score);
+        let mut merged = false;
+        if shrinking
+            && iterations < max_iter
+            && centroids.len() > 1
+            && (converged || previous - score < Plateau::EPSILON * previous)
+        {
+            match merge_closest(&mut rng, buf, &centroids) {
+                Some(fewer) => {
+                    if verbose {
+                        println!("Merged centroids, k: {}", fewer.len());
+                    }
+                    centroids = fewer;
+                    merged = true;
+                    previous = f32::INFINITY;
+                    plateau = Plateau::new(max_iter);
+                }
+                None => shrinking = false,
+            }
+        }
+        if !merged {
+            if iterations >= max_iter || converged {
+                if verbose {
+                    println!("Iterations: {}", iterations);
+                }
+                break;
+            }
+            previous = score;
+        }
 
+        indices.clear();
+        iterations += 1;
+        old_centroids.clone_from(&centroids);
+    }
+
+    let mut result = Kmeans {
+        score,
+        centroids,
+        indices,
+        runs: Vec::new(),
+    };
+    let _ = result.dedup_centroids();
+    if verbose && result.effective_k() < k {
+        println!("Effective k: {}", result.effective_k());
+    }
+
+    result
+}
+
+/// Returns the centroids with the closest two merged into their mean, if that
+/// lowers the Bayesian information criterion of the clustering.
+fn merge_closest<C: Calculate + Clone>(
+    rng: &mut impl Rng,
+    buf: &[C],
+    centroids: &[C],
+) -> Option<Vec<C>> {
+    let mut indices = Vec::with_capacity(buf.len());
+    C::get_closest_centroid(buf, centroids, &mut indices);
+
+    let (mut a, mut b) = (0, 1);
+    let mut closest = f32::INFINITY;
+    for (i, x) in centroids.iter().enumerate() {
+        for (j, y) in centroids.iter().enumerate().skip(i + 1) {
+            let distance = C::difference(x, y);
+            if distance < closest {
+                closest = distance;
+                (a, b) = (i, j);
+            }
+        }
+    }
+
+    // Move the points of `b` to `a` and shift the indices above `b` down
+    #[allow(clippy::cast_possible_truncation)]
+    let (a, b) = (a as u32, b as u32);
+    let merged_indices: Vec<u32> = indices
+        .iter()
+        .map(|&i| match i {
+            i if i == b => a,
+            i if i > b => i - 1,
+            i => i,
+        })
+        .collect();
+    let mut merged = centroids.to_vec();
+    let _ = merged.remove(b as usize);
+    C::recalculate_centroids(rng, buf, &mut merged, &merged_indices);
+
+    (bic(buf, &merged, &merged_indices) < bic(buf, centroids, &indices)).then_some(merged)
+}
+
+/// Bayesian information criterion of a clustering, modeling each cluster as a
+/// spherical Gaussian with a shared variance. Lower is better.
+#[allow(clippy::cast_precision_loss)]
+fn bic<C: Calculate>(buf: &[C], centroids: &[C], indices: &[u32]) -> f64 {
+    let mut counts = vec![0usize; centroids.len()];
+    let mut sse = 0.0f64;
+    for (x, &i) in buf.iter().zip(indices) {
+        let distance = C::difference(x, &centroids[i as usize]);
+        // Non-finite points don't belong to any cluster
+        if distance.is_finite() {
+            counts[i as usize] += 1;
+            sse += f64::from(distance);
+        }
+    }
+
+    let n: usize = counts.iter().sum();
+    if n == 0 {
+        return f64::INFINITY;
+    }
+    let (n, d, k) = (n as f64, C::DIMENSIONS as f64, centroids.len() as f64);
+    let variance = (sse / (n * d)).max(f64::MIN_POSITIVE);
+    let log_likelihood = counts
+        .iter()
+        .filter(|&&x| x > 0)
+        .map(|&x| x as f64 * (x as f64 / n).ln())
+        .sum::<f64>()
+        - n * d / 2.0 * ((2.0 * std::f64::consts::PI * variance).ln() + 1.0);
+    // Mixture weights, means, and the shared variance
+    let parameters = k * (d + 1.0);
+
+    parameters * n.ln() - 2.0 * log_likelihood
+}
+
+/// Find the k-means centroids of a buffer of weighted points.
+///
+/// Takes the same arguments as [`get_kmeans`](fn.get_kmeans.html) along with
+/// a weight for each point. A point with weight `w` contributes to the
+/// centroids as if it appeared `w` times, so a histogram of unique colors and
+/// their counts can be clustered instead of every pixel. Points with a weight
+/// of zero or less are indexed but otherwise ignored.
+///
+/// # Panics
+///
+/// Panics if `weights` is not the same length as `buf`.
+pub fn get_kmeans_weighted<C: Weighted + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    weights: &[f32],
+    seed: u64,
+) -> Kmeans<C> {
+    assert_eq!(buf.len(), weights.len());
+    kmeans_lloyd_weighted(
+        k, max_iter, converge, verbose, buf, weights, seed, None, None, None,
+    )
+}
+
+/// Lloyd's algorithm for weighted points with k-means++ initialization or
+/// `initial` centroids, optionally pruned.
+#[allow(clippy::too_many_arguments)]
+fn kmeans_lloyd_weighted<C: Weighted + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    weights: &[f32],
+    seed: u64,
+    initial: Option<&[C]>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
+) -> Kmeans<C> {
+    // Initialize the random centroids
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centroids: Vec<C> = Vec::with_capacity(k);
+    if !init_centroids(k, &mut rng, buf, Some(weights), initial, &mut centroids) {
+        return Kmeans::new();
+    }
+
+    lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centroids,
+        |rng, centroids, indices, _| {
+            C::get_closest_centroid(buf, centroids, indices);
+            C::recalculate_centroids_weighted(rng, buf, weights, centroids, indices);
+        },
+        None,
+        prune,
+        trace,
+    )
+}
+
+/// Start from a copy of the `initial` centroids, or choose `k` centroids with
+/// k-means++ initialization. Returns `false` if there are no centroids to
+/// start from, such as when the buffer has no finite points.
+fn init_centroids<C: Calculate + Clone>(
+    k: usize,
+    rng: &mut impl Rng,
+    buf: &[C],
+    weights: Option<&[f32]>,
+    initial: Option<&[C]>,
+    centroids: &mut Vec<C>,
+) -> bool {
+    if let Some(initial) = initial {
+        centroids.extend_from_slice(initial);
+        return !centroids.is_empty();
+    }
+    let init = match weights {
+        Some(weights) => crate::plus_plus::init_plus_plus_weighted(k, rng, buf, weights, centroids),
+        None => crate::plus_plus::init_plus_plus(k, rng, buf, centroids),
+    };
+
+    init.status != InitStatus::NoFinitePoints
+}
+
+/// Threshold for abandoning a run that is clearly worse than the best
+/// completed run.
+#[derive(Clone, Copy)]
+struct Prune<'a> {
+    /// Sum of squared distances above which the run is abandoned.
+    limit: f32,
+    /// Weights of the points, if any.
+    weights: Option<&'a [f32]>,
+}
+
+impl Prune<'_> {
+    /// Iterations after which a run is compared with the best completed run.
+    const AFTER: usize = 3;
+
+    /// Whether the points are further from their centroids than the limit.
+    fn exceeded<C: Calculate>(
+        &self,
+        buf: &[C],
+        centroids: &[C],
+        indices: impl Iterator<Item = u32>,
+    ) -> bool {
+        inertia(buf, centroids, indices, self.weights) > self.limit
+    }
+}
+
+/// Sum of the squared distances of the finite points to their centroids,
+/// multiplied by the weight of each point if there are weights.
+fn inertia<C: Calculate>(
+    buf: &[C],
+    centroids: &[C],
+    indices: impl Iterator<Item = u32>,
+    weights: Option<&[f32]>,
+) -> f32 {
+    buf.iter()
+        .zip(indices)
+        .enumerate()
+        .map(|(i, (x, idx))| {
+            let weight = weights.map_or(1.0, |w| w[i]);
+            let distance = C::difference(x, &centroids[idx as usize]);
+            if distance.is_finite() && weight > 0.0 {
+                distance * weight
+            } else {
+                0.0
+            }
+        })
+        .sum()
+}
+
+impl<C: Calculate> Kmeans<C> {
+    /// Result of an abandoned run, with a score that is never kept.
+    fn pruned(verbose: bool, centroids: Vec<C>, indices: Vec<u32>) -> Self {
+        if verbose {
+            println!("Pruned after {} iterations", Prune::AFTER);
+        }
+
+        Kmeans {
+            score: f32::INFINITY,
+            centroids,
+            indices,
+            runs: Vec::new(),
+        }
+    }
+}
+
+/// Cluster a buffer with at most `k` unique points exactly, making each unique
+/// point a centroid without calculating k-means.
+///
+/// Returns `None` if the buffer is empty, or has more than `k` unique points
+/// or a point that isn't finite. Points are compared with
+/// [`Calculate::difference`] in a single pass that stops at the first point
+/// past `k` unique points, so it gives up quickly on photos with many colors.
+/// Centroids are in the order their points first appear, and the score is 0.
+///
+/// ```
+/// use kmeans_colors::get_kmeans_exact;
+/// use palette::Srgb;
+///
+/// let black = Srgb::new(0.0f32, 0.0, 0.0);
+/// let white = Srgb::new(1.0, 1.0, 1.0);
+/// let result = get_kmeans_exact(8, &[white, black, black, white]).unwrap();
+/// assert_eq!(result.centroids, [white, black]);
+/// assert_eq!(result.indices, [0, 1, 1, 0]);
+/// assert!(get_kmeans_exact(1, &[white, black]).is_none());
+/// ```
+#[allow(clippy::cast_possible_truncation)]
+pub fn get_kmeans_exact<C: Calculate + Clone>(k: usize, buf: &[C]) -> Option<Kmeans<C>> {
+    let mut centroids: Vec<C> = Vec::new();
+    let mut indices: Vec<u32> = Vec::with_capacity(buf.len());
+    for x in buf {
+        // Neighboring pixels are often the same color
+        let index = indices
+            .last()
+            .filter(|&&i| C::difference(x, &centroids[i as usize]) == 0.0)
+            .copied()
+            .or_else(|| {
+                centroids
+                    .iter()
+                    .position(|c| C::difference(x, c) == 0.0)
+                    .map(|i| i as u32)
+            });
+        match index {
+            Some(i) => indices.push(i),
+            // A point that isn't finite isn't even equal to itself
+            None if centroids.len() < k && C::difference(x, x) == 0.0 => {
+                indices.push(centroids.len() as u32);
+                centroids.push(x.clone());
+            }
+            None => return None,
+        }
+    }
+    if centroids.is_empty() {
+        return None;
+    }
+
+    Some(Kmeans {
+        score: 0.0,
+        centroids,
+        indices,
+        runs: Vec::new(),
+    })
+}
+
+/// Find the k-means centroids of a buffer, keeping the best of `config.runs`
+/// runs with incrementing seeds as in [`Kmeans::keep_best`].
+///
+/// Hamerly's algorithm is used when `k` is greater than 1. With `weights`,
+/// each point contributes to the centroids as in
+/// [`get_kmeans_weighted`](fn.get_kmeans_weighted.html).
+///
+/// If the buffer has no more than `k` unique points, and every weight is
+/// positive, the exact result of
+/// [`get_kmeans_exact`](fn.get_kmeans_exact.html) is returned without running
+/// the calculation, so the colors and their percentages are exact.
+///
+/// With `config.prune`, a run is abandoned after 3 iterations if the sum of
+/// squared distances of the points to their centroids is more than `prune`
+/// times that of the best completed run. k-means++ initialization keeps the
+/// runs within a few percent of each other, so a factor around 1.1 prunes
+/// the runs that are clearly heading for a worse local minimum. Abandoned runs
+/// are recorded in `runs` with an infinite score.
+///
+/// # Panics
+///
+/// Panics if `weights` is not the same length as `buf`.
+///
+/// ```
+/// use kmeans_colors::{get_kmeans_best, KmeansConfig};
+/// # use palette::Srgb;
+/// # let buf = [
+/// #     Srgb::new(0.0, 0.0, 0.0),
+/// #     Srgb::new(0.5, 0.5, 0.5),
+/// #     Srgb::new(1.0f32, 1.0, 1.0),
+/// # ];
+///
+/// let config = KmeansConfig {
+///     k: 2,
+///     runs: 5,
+///     prune: Some(1.1),
+///     ..Default::default()
+/// };
+/// let result = get_kmeans_best(&config, &buf, None);
+/// assert_eq!(result.runs.len(), 5);
+/// ```
+pub fn get_kmeans_best<C: Hamerly + Weighted + Clone>(
+    config: &KmeansConfig,
+    buf: &[C],
+    weights: Option<&[f32]>,
+) -> Kmeans<C> {
+    kmeans_best(config, buf, weights, None)
+}
+
+/// Find the k-means centroids of a buffer like
+/// [`get_kmeans_best`](fn.get_kmeans_best.html), starting the first run from
+/// the `initial` centroids instead of k-means++ initialization.
+///
+/// Starting from the centroids of a similar buffer, such as the previous frame
+/// of a video, usually converges in far fewer iterations. `k` is the number of
+/// initial centroids and `config.k` is ignored. Any further runs of
+/// `config.runs` use k-means++ initialization, so the initial centroids are
+/// only kept as a starting point if no run finds a better result.
+///
+/// # Panics
+///
+/// Panics if `weights` is not the same length as `buf`.
+///
+/// ```
+/// use kmeans_colors::{get_kmeans_best, get_kmeans_best_from, KmeansConfig};
+/// use palette::Srgb;
+///
+/// let config = KmeansConfig {
+///     k: 2,
+///     converge: 0.0025,
+///     ..Default::default()
+/// };
+/// let mut frame = vec![Srgb::new(0.1f32, 0.1, 0.1); 50];
+/// frame.extend(vec![Srgb::new(0.9, 0.9, 0.9); 50]);
+/// let first = get_kmeans_best(&config, &frame, None);
+///
+/// frame[0] = Srgb::new(0.2, 0.2, 0.2);
+/// let next = get_kmeans_best_from(&config, &first.centroids, &frame, None);
+/// assert_eq!(next.effective_k(), 2);
+/// ```
+pub fn get_kmeans_best_from<C: Hamerly + Weighted + Clone>(
+    config: &KmeansConfig,
+    initial: &[C],
+    buf: &[C],
+    weights: Option<&[f32]>,
+) -> Kmeans<C> {
+    let config = KmeansConfig {
+        k: initial.len(),
+        ..*config
+    };
+    kmeans_best(&config, buf, weights, Some(initial))
+}
+
+/// Keep the best of `config.runs` runs, the first starting from the `initial`
+/// centroids if there are any.
+fn kmeans_best<C: Hamerly + Weighted + Clone>(
+    config: &KmeansConfig,
+    buf: &[C],
+    weights: Option<&[f32]>,
+    initial: Option<&[C]>,
+) -> Kmeans<C> {
+    if let Some(weights) = weights {
+        assert_eq!(buf.len(), weights.len());
+    }
+
+    let KmeansConfig {
+        k,
+        max_iter,
+        converge,
+        verbose,
+        ..
+    } = *config;
+    if weights.is_none_or(|w| w.iter().all(|&x| x > 0.0)) {
+        if let Some(result) = get_kmeans_exact(k, buf) {
+            if verbose {
+                println!("Exact colors: {}", result.centroids.len());
+            }
+            return result;
+        }
+    }
+
+    let mut result = Kmeans::new();
+    let mut best = f32::INFINITY;
+    for i in 0..config.runs {
+        let seed = config.seed + i as u64;
+        let prune = config
+            .prune
+            .filter(|_| best.is_finite())
+            .map(|factor| Prune {
+                limit: best * factor,
+                weights,
+            });
+        let trace = config.trace.map(|callback| Trace { seed, callback });
+        let initial = initial.filter(|_| i == 0);
+        let run_result = match (weights, k > 1) {
+            (Some(w), true) => kmeans_hamerly_weighted(
+                k, max_iter, converge, verbose, buf, w, seed, initial, prune, trace,
+            ),
+            (Some(w), false) => kmeans_lloyd_weighted(
+                k, max_iter, converge, verbose, buf, w, seed, initial, prune, trace,
+            ),
+            (None, true) => kmeans_hamerly(
+                k, max_iter, converge, verbose, buf, seed, initial, None, prune, trace,
+            ),
+            (None, false) => kmeans_lloyd(
+                k, max_iter, converge, verbose, buf, seed, initial, None, prune, trace,
+            ),
+        };
+        if config.prune.is_some() && run_result.score.is_finite() {
+            let run_inertia = inertia(
+                buf,
+                &run_result.centroids,
+                run_result.indices.iter().copied(),
+                weights,
+            );
+            best = best.min(run_inertia);
+        }
+        result.keep_best(run_result, seed);
+    }
+
+    result
+}
+
+/// Find the k-means centroids of a buffer by clustering tiles of it in
+/// parallel, then clustering the centroids of every tile.
+///
+/// The buffer is split into contiguous tiles of `tile_len` points, such as
+/// bands of rows of an image, which are clustered independently with
+/// [`get_kmeans_best`](fn.get_kmeans_best.html) on up to `threads` threads.
+/// The centroids of all tiles, weighted by the number of points in their
+/// cluster, are clustered again with the same `config` to find the final
+/// centroids, and every point is indexed with its nearest final centroid.
+///
+/// Each thread only works on one tile at a time, bounding the memory used per
+/// thread, and the time scales almost linearly with the number of threads.
+/// The result is close to but not the same as clustering the whole buffer.
+/// `runs` of the result are those of the final clustering.
+///
+/// Every tile is clustered on a single thread and the tile centroids are
+/// combined in the order of the tiles, so the result for a seed is the same
+/// for any number of `threads`.
+///
+/// ```
+/// use kmeans_colors::{get_kmeans_tiled, KmeansConfig};
+/// use palette::Srgb;
+///
+/// let mut buf = vec![Srgb::new(0.0f32, 0.0, 0.0); 1000];
+/// buf[500..].fill(Srgb::new(1.0, 1.0, 1.0));
+/// let config = KmeansConfig {
+///     k: 2,
+///     converge: 0.0025,
+///     ..Default::default()
+/// };
+/// let result = get_kmeans_tiled(&config, &buf, 256, 4);
+/// assert_eq!(result.effective_k(), 2);
+/// assert_eq!(result.indices.len(), buf.len());
+/// ```
+#[allow(clippy::cast_precision_loss)]
+pub fn get_kmeans_tiled<C>(
+    config: &KmeansConfig,
+    buf: &[C],
+    tile_len: usize,
+    threads: usize,
+) -> Kmeans<C>
+where
+    C: Hamerly + Weighted + Clone + Send + Sync,
+{
+    let tiles: Vec<&[C]> = buf.chunks(tile_len.max(1)).collect();
+    let tile_config = KmeansConfig {
+        verbose: false,
+        trace: None,
+        ..*config
+    };
+    let tile_results = parallel_map(threads, tiles.len(), |i| {
+        get_kmeans_best(&tile_config, tiles[i], None)
+    });
+
+    let mut points = Vec::new();
+    let mut weights = Vec::new();
+    for tile in &tile_results {
+        for (centroid, count) in tile.centroids.iter().zip(tile.counts()) {
+            if count > 0 {
+                points.push(centroid.clone());
+                weights.push(count as f32);
+            }
+        }
+    }
+    // No finite points, as in `get_kmeans`
+    if points.is_empty() {
+        return Kmeans::new();
+    }
+    let mut result = get_kmeans_best(config, &points, Some(&weights));
+
+    let indices = parallel_map(threads, tiles.len(), |i| {
+        let mut indices = Vec::with_capacity(tiles[i].len());
+        C::get_closest_centroid(tiles[i], &result.centroids, &mut indices);
+        indices
+    });
+    result.indices = indices.concat();
+
+    result
+}
+
+/// Result of one seed of [`sweep_seeds`](fn.sweep_seeds.html).
+#[derive(Clone, Debug)]
+pub struct SeedResult<C: Calculate> {
+    /// Seed of the run.
+    pub seed: u64,
+    /// Sum of the squared distances of the points to their centroids, to
+    /// compare how well the results fit the buffer.
+    pub inertia: f32,
+    /// Result of the run.
+    pub result: Kmeans<C>,
+}
+
+/// Find the k-means centroids of a buffer once for every seed, returning every
+/// result in the order of `seeds`.
+///
+/// Each run is the same as [`get_kmeans_best`](fn.get_kmeans_best.html) with a
+/// single run and that seed, ignoring `config.runs`, `config.seed`, and
+/// `config.prune`. Comparing the results shows how much they vary between
+/// seeds, and the result with the lowest inertia is the best fit found. With
+/// the `rayon` feature, the seeds are run in parallel. Each seed still runs on
+/// a single thread, so the results are the same for any number of threads.
+///
+/// ```
+/// use kmeans_colors::{sweep_seeds, KmeansConfig};
+/// # use palette::Srgb;
+/// # let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0f32, 1.0, 1.0)];
+///
+/// let config = KmeansConfig {
+///     k: 2,
+///     ..Default::default()
+/// };
+/// let results = sweep_seeds(&config, &buf, &[0, 1, 2]);
+/// let best = results
+///     .iter()
+///     .min_by(|a, b| a.inertia.total_cmp(&b.inertia))
+///     .unwrap();
+/// assert_eq!(best.result.effective_k(), 2);
+/// ```
+pub fn sweep_seeds<C>(config: &KmeansConfig, buf: &[C], seeds: &[u64]) -> Vec<SeedResult<C>>
+where
+    C: Hamerly + Weighted + Clone + Send + Sync,
+{
+    let run = |&seed: &u64| {
+        let run_config = KmeansConfig {
+            runs: 1,
+            seed,
+            prune: None,
+            ..*config
+        };
+        let result = get_kmeans_best(&run_config, buf, None);
+        SeedResult {
+            seed,
+            inertia: inertia(buf, &result.centroids, result.indices.iter().copied(), None),
+            result,
+        }
+    };
+
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        seeds.par_iter().map(run).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        seeds.iter().map(run).collect()
+    }
+}
+
+/// Call `f` with each index up to `len` on up to `threads` threads, returning
+/// the results in order.
+fn parallel_map<T: Send>(threads: usize, len: usize, f: impl Fn(usize) -> T + Sync) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, T)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.clamp(1, len.max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= len {
+                            break results;
+                        }
+                        results.push((i, f(i)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    results.sort_unstable_by_key(|&(i, _)| i);
+
+    results.into_iter().map(|(_, x)| x).collect()
+}
+
+/// Main loop of Lloyd's algorithm: find nearest centroids and recalculate
+/// means with `step` until convergence. `step` records its phases in
+/// `timings` if they're provided.
+#[allow(clippy::too_many_arguments)]
+fn lloyd<C: Calculate + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    mut rng: rand_chacha::ChaCha8Rng,
+    mut centroids: Vec<C>,
+    mut step: impl FnMut(&mut rand_chacha::ChaCha8Rng, &mut [C], &mut Vec<u32>, Option<&mut Timings>),
+    mut timings: Option<&mut Timings>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
+) -> Kmeans<C> {
     // Initialize indexed buffer and convergence variables
     let mut iterations = 0;
     let mut score;
+    let mut plateau = Plateau::new(max_iter);
     let mut old_centroids = centroids.clone();
-    let mut indices: Vec<u8> = Vec::with_capacity(buf.len());
+    let mut indices: Vec<u32> = Vec::with_capacity(buf.len());
 
     // Main loop: find nearest centroids and recalculate means until convergence
     loop {
-        C::get_closest_centroid(buf, &centroids, &mut indices);
-        C::recalculate_centroids(&mut rng, buf, &mut centroids, &indices);
+        let iteration_start = Instant::now();
+        step(
+            &mut rng,
+            &mut centroids,
+            &mut indices,
+            timings.as_deref_mut(),
+        );
 
+        let start = Instant::now();
         score = C::check_loop(&centroids, &old_centroids);
+        if let Some(timings) = timings.as_deref_mut() {
+            timings.convergence += start.elapsed();
+            timings.iterations = iterations + 1;
+        }
+        if let Some(trace) = trace {
+            let indices = indices.iter().copied();
+            trace.record(iterations, score, centroids.len(), indices, iteration_start);
+        }
         if verbose {
             println!("Score: {}", score);
         }
 
-        // Verify that either the maximum iteration count has been met or the
+        // Verify that either the maximum iteration count has been met, the
         // centroids haven't moved beyond a certain threshold since the
-        // previous iteration.
-        if iterations >= max_iter || score <= converge {
+        // previous iteration, or the score has stopped improving.
+        if iterations >= max_iter || score <= converge || plateau.stop(iterations, score) {
             if verbose {
                 println!("Iterations: {}", iterations);
             }
             break;
         }
 
+        if iterations == Prune::AFTER
+            && prune.is_some_and(|x| x.exceeded(buf, &centroids, indices.iter().copied()))
+        {
+            return Kmeans::pruned(verbose, centroids, indices);
+        }
+
         indices.clear();
         iterations += 1;
         old_centroids.clone_from(&centroids);
     }
 
-    Kmeans {
+    let mut result = Kmeans {
         score,
         centroids,
         indices,
+        runs: Vec::new(),
+    };
+    let _ = result.dedup_centroids();
+    if verbose && result.effective_k() < k {
+        println!("Effective k: {}", result.effective_k());
+    }
+
+    result
+}
+
+/// State of a k-means calculation which is advanced one iteration at a time
+/// with [`kmeans_step`](fn.kmeans_step.html).
+///
+/// This allows custom convergence logic, or interleaving the calculation with
+/// other work like rendering progress, without reimplementing the main loop of
+/// [`get_kmeans`](fn.get_kmeans.html).
+#[derive(Clone, Debug)]
+pub struct KmeansState<C: Calculate> {
+    /// Current centroids.
+    pub centroids: Vec<C>,
+    /// Buffer of points indexed to the centroids of the previous iteration.
+    pub indices: Vec<u32>,
+    /// Convergence score of the last iteration.
+    pub score: f32,
+    /// Number of iterations performed.
+    pub iterations: usize,
+    /// Random number generator for re-initializing empty clusters.
+    rng: rand_chacha::ChaCha8Rng,
+}
+
+impl<C: Calculate + Clone> KmeansState<C> {
+    /// Create a new state with centroids chosen by k-means++ initialization,
+    /// the same as `get_kmeans` with the same `seed`.
+    ///
+    /// If the buffer has no finite points, the state has no centroids and
+    /// stepping it does nothing.
+    pub fn new(k: usize, buf: &[C], seed: u64) -> Self {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let mut centroids: Vec<C> = Vec::with_capacity(k);
+        if !buf.is_empty() {
+            let _ = crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centroids);
+        }
+        Self::with_rng(centroids, rng)
+    }
+
+    /// Create a new state from existing centroids, such as the result of a
+    /// previous calculation.
+    pub fn from_centroids(centroids: Vec<C>, seed: u64) -> Self {
+        Self::with_rng(centroids, rand_chacha::ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    fn with_rng(centroids: Vec<C>, rng: rand_chacha::ChaCha8Rng) -> Self {
+        KmeansState {
+            centroids,
+            indices: Vec::new(),
+            score: f32::MAX,
+            iterations: 0,
+            rng,
+        }
+    }
+
+    /// Convert the state into a `Kmeans` result, merging identical centroids.
+    pub fn into_kmeans(self) -> Kmeans<C> {
+        let mut result = Kmeans {
+            score: self.score,
+            centroids: self.centroids,
+            indices: self.indices,
+            runs: Vec::new(),
+        };
+        let _ = result.dedup_centroids();
+        result
+    }
+}
+
+/// Perform exactly one iteration of Lloyd's algorithm: index each point with
+/// its nearest centroid, then recalculate the centroids. Returns the
+/// convergence score of the iteration.
+///
+/// Stepping until `score <= converge` or `iterations > max_iter` produces the
+/// same result as [`get_kmeans`](fn.get_kmeans.html).
+///
+/// ```
+/// use kmeans_colors::{kmeans_step, KmeansState};
+/// # use palette::Srgb;
+/// # let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0f32, 1.0, 1.0)];
+///
+/// let mut state = KmeansState::new(2, &buf, 0);
+/// while state.iterations < 20 {
+///     let score = kmeans_step(&mut state, &buf);
+///     if score <= 0.0025 {
+///         break;
+///     }
+/// }
+/// let result = state.into_kmeans();
+/// # assert_eq!(result.indices.len(), buf.len());
+/// ```
+pub fn kmeans_step<C: Calculate + Clone>(state: &mut KmeansState<C>, buf: &[C]) -> f32 {
+    if state.centroids.is_empty() {
+        return state.score;
+    }
+
+    let old_centroids = state.centroids.clone();
+    state.indices.clear();
+    C::assign_and_recalculate(
+        &mut state.rng,
+        buf,
+        &mut state.centroids,
+        &mut state.indices,
+    );
+    state.score = C::check_loop(&state.centroids, &old_centroids);
+    state.iterations += 1;
+
+    state.score
+}
+
+/// Update a previous result for a buffer that differs from the clustered
+/// buffer only at the points flagged in `changed`, such as after an edit to
+/// part of an image.
+///
+/// The previous centroids are the starting point: changed points are indexed
+/// with their nearest centroid while the other points keep their index, then
+/// the centroids are recalculated and iterations continue as in
+/// [`get_kmeans`](fn.get_kmeans.html) until convergence. A small edit usually
+/// converges within a few iterations, skipping the k-means++ initialization
+/// and the many iterations of a full calculation.
+///
+/// - `previous` - result for the buffer before the changes.
+/// - `changed` - flag for each point of `buf` that changed.
+/// - `seed` - seed for re-initializing empty clusters.
+///
+/// The other arguments are the same as for `get_kmeans`. If `previous` has no
+/// centroids, it is returned unchanged.
+///
+/// # Panics
+///
+/// Panics if `changed` or the indices of `previous` aren't the same length as
+/// `buf`.
+///
+/// ```
+/// use kmeans_colors::{get_kmeans, update_kmeans};
+/// use palette::Srgb;
+///
+/// let mut buf = vec![Srgb::new(0.0f32, 0.0, 0.0); 8];
+/// buf[4..].fill(Srgb::new(1.0, 1.0, 1.0));
+/// let result = get_kmeans(2, 20, 0.0025, false, &buf, 0);
+///
+/// // Paint one black point white
+/// buf[0] = Srgb::new(1.0, 1.0, 1.0);
+/// let mut changed = vec![false; buf.len()];
+/// changed[0] = true;
+/// let updated = update_kmeans(result, &changed, 20, 0.0025, false, &buf, 0);
+/// assert_eq!(updated.indices[0], updated.indices[7]);
+/// ```
+pub fn update_kmeans<C: Calculate + Clone>(
+    previous: Kmeans<C>,
+    changed: &[bool],
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> Kmeans<C> {
+    assert_eq!(changed.len(), buf.len());
+    assert_eq!(previous.indices.len(), buf.len());
+    if previous.centroids.is_empty() {
+        return previous;
+    }
+
+    let k = previous.centroids.len();
+    let mut previous_indices = Some(previous.indices);
+    lloyd(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rand_chacha::ChaCha8Rng::seed_from_u64(seed),
+        previous.centroids,
+        |rng, centroids, indices, _| match previous_indices.take() {
+            // The centroids haven't moved yet, so only the changed points can
+            // have a different nearest centroid
+            Some(previous_indices) => {
+                let points: Vec<C> = buf
+                    .iter()
+                    .zip(changed)
+                    .filter(|(_, &changed)| changed)
+                    .map(|(x, _)| x.clone())
+                    .collect();
+                let mut nearest = Vec::with_capacity(points.len());
+                C::get_closest_centroid(&points, centroids, &mut nearest);
+
+                *indices = previous_indices;
+                let changed_indices = indices
+                    .iter_mut()
+                    .zip(changed)
+                    .filter(|(_, &changed)| changed);
+                for ((index, _), nearest) in changed_indices.zip(nearest) {
+                    *index = nearest;
+                }
+                C::recalculate_centroids(rng, buf, centroids, indices);
+            }
+            None => C::assign_and_recalculate(rng, buf, centroids, indices),
+        },
+        None,
+        None,
+        None,
+    )
+}
+
+/// Error for a buffer containing a point with NaN or infinite components.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NonFiniteError {
+    /// Index of the first non-finite point.
+    pub index: usize,
+}
+
+impl core::fmt::Display for NonFiniteError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "point at index {} is not finite", self.index)
+    }
+}
+
+impl std::error::Error for NonFiniteError {}
+
+/// Check that every point in a buffer has finite components, returning the
+/// index of the first point that doesn't.
+///
+/// A point is considered finite if its distance to itself is finite.
+pub fn check_finite<C: Calculate>(buf: &[C]) -> Result<(), NonFiniteError> {
+    match buf.iter().position(|x| !C::difference(x, x).is_finite()) {
+        Some(index) => Err(NonFiniteError { index }),
+        None => Ok(()),
     }
 }
 
@@ -136,6 +2016,34 @@ pub trait Hamerly: Calculate {
 
     /// Update the lower and upper bounds of each point.
     fn update_bounds(centroids: &HamerlyCentroids<Self>, points: &mut [HamerlyPoint]);
+
+    /// Find the new centroid locations based on the weighted average of the
+    /// points that correspond to the centroid, and the distances the centroids
+    /// moved.
+    ///
+    /// The default implementation uses
+    /// [`Weighted::recalculate_centroids_weighted`].
+    fn recalculate_centroids_hamerly_weighted(
+        rng: &mut impl Rng,
+        buf: &[Self],
+        weights: &[f32],
+        centers: &mut HamerlyCentroids<Self>,
+        points: &[HamerlyPoint],
+    ) where
+        Self: Weighted + Clone,
+    {
+        let indices: Vec<u32> = points.iter().map(|x| x.index).collect();
+        let old_centroids = centers.centroids.clone();
+        Self::recalculate_centroids_weighted(rng, buf, weights, &mut centers.centroids, &indices);
+        for ((delta, old), new) in centers
+            .deltas
+            .iter_mut()
+            .zip(&old_centroids)
+            .zip(&centers.centroids)
+        {
+            *delta = Self::difference(old, new).sqrt();
+        }
+    }
 }
 
 /// Struct used for caching data required to compute k-means with the Hamerly
@@ -166,7 +2074,7 @@ impl<C: Hamerly> HamerlyCentroids<C> {
 #[derive(Copy, Clone, Debug)]
 pub struct HamerlyPoint {
     /// Index of this point's centroid.
-    pub index: u8,
+    pub index: u32,
     /// Closest centroid's distance to this point.
     pub upper_bound: f32,
     /// Minimum distance that any centroid beyond the closest centroid can be
@@ -195,6 +2103,13 @@ impl Default for HamerlyPoint {
 /// the same arguments as [`get_kmeans`](fn.get_kmeans.html) and produces the
 /// same results.
 ///
+/// Both functions consume the random number generator in the same order and
+/// assign points equidistant to multiple centroids to the one with the lowest
+/// index, so the same seed gives identical centroids and indices. Custom
+/// `Hamerly` implementations should skip the nearest centroid search only when
+/// a point's upper bound is strictly less than its lower bound to preserve
+/// this.
+///
 /// Hamerly uses the triangle inequality and caches one lower and upper bound
 /// for each point, which allows it to skip the inner loop of distance
 /// calculation for each point more often. Asymptotically, this algorithm
@@ -209,6 +2124,8 @@ impl Default for HamerlyPoint {
 ///
 /// [hamerly]: ../src/kmeans_colors/colors/kmeans.rs.html#165
 ///
+/// Non-finite points are handled the same way as in `get_kmeans`.
+///
 /// ## Reference
 ///
 /// Hamerly, G., & Drake, J. (2017). Chapter 2 Accelerating Lloyd's Algorithm
@@ -223,47 +2140,1139 @@ pub fn get_kmeans_hamerly<C: Hamerly + Clone>(
     verbose: bool,
     buf: &[C],
     seed: u64,
+) -> Kmeans<C> {
+    kmeans_hamerly(
+        k, max_iter, converge, verbose, buf, seed, None, None, None, None,
+    )
+}
+
+/// Find the k-means centroids of a buffer using the Hamerly algorithm like
+/// [`get_kmeans_hamerly`](fn.get_kmeans_hamerly.html), also returning the
+/// time spent in each phase of the calculation.
+pub fn get_kmeans_hamerly_timed<C: Hamerly + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> (Kmeans<C>, Timings) {
+    let mut timings = Timings::default();
+    let result = kmeans_hamerly(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        seed,
+        None,
+        Some(&mut timings),
+        None,
+        None,
+    );
+    (result, timings)
+}
+
+/// The Hamerly algorithm with k-means++ initialization or `initial`
+/// centroids, optionally timed or pruned.
+#[allow(clippy::too_many_arguments)]
+fn kmeans_hamerly<C: Hamerly + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+    initial: Option<&[C]>,
+    mut timings: Option<&mut Timings>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
+) -> Kmeans<C> {
+    // Initialize the random centroids
+    let start = Instant::now();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centers: HamerlyCentroids<C> = HamerlyCentroids::new(k);
+    let initialized = init_centroids(k, &mut rng, buf, None, initial, &mut centers.centroids);
+    if let Some(timings) = timings.as_deref_mut() {
+        timings.init += start.elapsed();
+    }
+    if !initialized {
+        return Kmeans::new();
+    }
+
+    hamerly(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centers,
+        |rng, centers, points| C::recalculate_centroids_hamerly(rng, buf, centers, points),
+        timings,
+        prune,
+        trace,
+    )
+}
+
+/// Find the k-means centroids of a buffer of weighted points using the Hamerly
+/// algorithm. Takes the same arguments as
+/// [`get_kmeans_weighted`](fn.get_kmeans_weighted.html) and produces the same
+/// results.
+///
+/// # Panics
+///
+/// Panics if `weights` is not the same length as `buf`.
+pub fn get_kmeans_hamerly_weighted<C: Hamerly + Weighted + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    weights: &[f32],
+    seed: u64,
+) -> Kmeans<C> {
+    assert_eq!(buf.len(), weights.len());
+    kmeans_hamerly_weighted(
+        k, max_iter, converge, verbose, buf, weights, seed, None, None, None,
+    )
+}
+
+/// The Hamerly algorithm for weighted points with k-means++ initialization or
+/// `initial` centroids, optionally pruned.
+#[allow(clippy::too_many_arguments)]
+fn kmeans_hamerly_weighted<C: Hamerly + Weighted + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    weights: &[f32],
+    seed: u64,
+    initial: Option<&[C]>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
 ) -> Kmeans<C> {
     // Initialize the random centroids
     let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
     let mut centers: HamerlyCentroids<C> = HamerlyCentroids::new(k);
-    crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centers.centroids);
+    let initialized = init_centroids(
+        k,
+        &mut rng,
+        buf,
+        Some(weights),
+        initial,
+        &mut centers.centroids,
+    );
+    if !initialized {
+        return Kmeans::new();
+    }
+
+    hamerly(
+        k,
+        max_iter,
+        converge,
+        verbose,
+        buf,
+        rng,
+        centers,
+        |rng, centers, points| {
+            C::recalculate_centroids_hamerly_weighted(rng, buf, weights, centers, points)
+        },
+        None,
+        prune,
+        trace,
+    )
+}
 
+/// Main loop of the Hamerly algorithm: find nearest centroids and recalculate
+/// means with `recalculate` until convergence, recording the time spent in
+/// each phase in `timings` if they're provided.
+#[allow(clippy::too_many_arguments)]
+fn hamerly<C: Hamerly + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    mut rng: rand_chacha::ChaCha8Rng,
+    mut centers: HamerlyCentroids<C>,
+    mut recalculate: impl FnMut(&mut rand_chacha::ChaCha8Rng, &mut HamerlyCentroids<C>, &[HamerlyPoint]),
+    mut timings: Option<&mut Timings>,
+    prune: Option<Prune<'_>>,
+    trace: Option<Trace>,
+) -> Kmeans<C> {
     // Initialize points buffer and convergence variables
     let mut iterations = 0;
     let mut score;
+    let mut plateau = Plateau::new(max_iter);
     let mut old_centers = centers.centroids.clone();
     let mut points: Vec<HamerlyPoint> = (0..buf.len()).map(|_| HamerlyPoint::new()).collect();
 
     // Main loop: find nearest centroids and recalculate means until convergence
     loop {
+        let start = Instant::now();
         C::compute_half_distances(&mut centers);
         C::get_closest_centroid_hamerly(buf, &centers, &mut points);
-        C::recalculate_centroids_hamerly(&mut rng, buf, &mut centers, &points);
+        let assigned = Instant::now();
+        recalculate(&mut rng, &mut centers, &points);
+        let recalculated = Instant::now();
 
         score = Calculate::check_loop(&centers.centroids, &old_centers);
+        if let Some(timings) = timings.as_deref_mut() {
+            timings.assignment += assigned - start;
+            timings.recalculation += recalculated - assigned;
+            timings.convergence += recalculated.elapsed();
+            timings.iterations = iterations + 1;
+        }
+        if let Some(trace) = trace {
+            let indices = points.iter().map(|p| p.index);
+            trace.record(iterations, score, centers.centroids.len(), indices, start);
+        }
         if verbose {
             println!("Score: {}", score);
         }
 
-        // Verify that either the maximum iteration count has been met or the
+        // Verify that either the maximum iteration count has been met, the
         // centroids haven't moved beyond a certain threshold since the
-        // previous iteration.
-        if iterations >= max_iter || score <= converge {
+        // previous iteration, or the score has stopped improving.
+        if iterations >= max_iter || score <= converge || plateau.stop(iterations, score) {
             if verbose {
                 println!("Iterations: {}", iterations);
             }
             break;
         }
 
+        if iterations == Prune::AFTER
+            && prune.is_some_and(|x| {
+                x.exceeded(buf, &centers.centroids, points.iter().map(|p| p.index))
+            })
+        {
+            let indices = points.iter().map(|p| p.index).collect();
+            return Kmeans::pruned(verbose, centers.centroids, indices);
+        }
+
+        let start = Instant::now();
         C::update_bounds(&centers, &mut points);
+        if let Some(timings) = timings.as_deref_mut() {
+            timings.assignment += start.elapsed();
+        }
         old_centers.clone_from(&centers.centroids);
         iterations += 1;
     }
 
-    Kmeans {
+    let mut result = Kmeans {
         score,
         centroids: centers.centroids,
         indices: points.iter().map(|x| x.index).collect(),
+        runs: Vec::new(),
+    };
+    let _ = result.dedup_centroids();
+    if verbose && result.effective_k() < k {
+        println!("Effective k: {}", result.effective_k());
+    }
+
+    result
+}
+
+/// A trait for calculating k-means with the Elkan algorithm.
+///
+/// Every method has a default implementation built on
+/// [`Calculate`](trait.Calculate.html), so implementing the trait only takes
+/// an empty `impl` block. Implementations can override the methods with
+/// faster versions for their type.
+pub trait Elkan: Calculate {
+    /// Find the half-distances between each pair of centers and from each
+    /// center to its nearest center.
+    fn compute_center_distances(centers: &mut ElkanCentroids<Self>) {
+        let k = centers.centroids.len();
+        for (i, ci) in centers.centroids.iter().enumerate() {
+            let mut min = f32::MAX;
+            for (j, cj) in centers.centroids.iter().enumerate() {
+                // Don't compare centroid to itself
+                if i == j {
+                    centers.half_distances[i * k + j] = 0.0;
+                    continue;
+                }
+                let half_dist = Self::difference(ci, cj).sqrt() * 0.5;
+                centers.half_distances[i * k + j] = half_dist;
+                if half_dist < min {
+                    min = half_dist;
+                }
+            }
+            centers.nearest[i] = min;
+        }
+    }
+
+    /// Find a point's nearest centroid, index the point with that centroid.
+    ///
+    /// Centroids are skipped only when a bound shows they're strictly farther
+    /// than the point's centroid, and equally near centroids are resolved to
+    /// the lowest index like `get_closest_centroid`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_closest_centroid_elkan(
+        buffer: &[Self],
+        centers: &ElkanCentroids<Self>,
+        points: &mut ElkanPoints,
+    ) {
+        let k = centers.centroids.len();
+        for ((val, (index, upper)), lower) in buffer
+            .iter()
+            .zip(points.indices.iter_mut().zip(&mut points.upper_bounds))
+            .zip(points.lower_bounds.chunks_exact_mut(k))
+        {
+            let mut c = *index as usize;
+            if *upper < centers.nearest[c] {
+                continue;
+            }
+
+            // Tighten upper bound. The squared distance to the centroid is
+            // compared directly like `get_closest_centroid` does.
+            let mut min = Self::difference(val, &centers.centroids[c]);
+            *upper = min.sqrt();
+            lower[c] = *upper;
+            if *upper < centers.nearest[c] {
+                continue;
+            }
+
+            for (j, cj) in centers.centroids.iter().enumerate() {
+                if j == c || *upper < lower[j].max(centers.half_distances[c * k + j]) {
+                    continue;
+                }
+                let diff = Self::difference(val, cj);
+                lower[j] = diff.sqrt();
+                if diff < min || (diff == min && j < c) {
+                    c = j;
+                    *upper = lower[j];
+                    min = diff;
+                }
+            }
+            *index = c as u32;
+        }
+    }
+
+    /// Find the new centroid locations based on the average of the points that
+    /// correspond to the centroid, and the distances the centroids moved.
+    ///
+    /// The default implementation uses
+    /// [`Calculate::recalculate_centroids`].
+    fn recalculate_centroids_elkan(
+        rng: &mut impl Rng,
+        buf: &[Self],
+        centers: &mut ElkanCentroids<Self>,
+        points: &ElkanPoints,
+    ) where
+        Self: Clone,
+    {
+        let old_centroids = centers.centroids.clone();
+        Self::recalculate_centroids(rng, buf, &mut centers.centroids, &points.indices);
+        for ((delta, old), new) in centers
+            .deltas
+            .iter_mut()
+            .zip(&old_centroids)
+            .zip(&centers.centroids)
+        {
+            *delta = Self::difference(old, new).sqrt();
+        }
+    }
+
+    /// Update the upper bound and lower bounds of each point.
+    fn update_bounds_elkan(centers: &ElkanCentroids<Self>, points: &mut ElkanPoints) {
+        let k = centers.centroids.len();
+        for (upper, &index) in points.upper_bounds.iter_mut().zip(&points.indices) {
+            *upper += centers.deltas[index as usize];
+        }
+        for lower in points.lower_bounds.chunks_exact_mut(k) {
+            for (bound, delta) in lower.iter_mut().zip(&centers.deltas) {
+                *bound = (*bound - delta).max(0.0);
+            }
+        }
+    }
+}
+
+/// Struct used for caching data required to compute k-means with the Elkan
+/// algorithm.
+#[derive(Clone, Debug)]
+pub struct ElkanCentroids<C: Elkan> {
+    /// Centroid points.
+    pub centroids: Vec<C>,
+    /// Distances the centroids have moved since the previous iteration.
+    pub deltas: Vec<f32>,
+    /// Half-distances between each pair of centroids, row by row.
+    pub half_distances: Vec<f32>,
+    /// Half-distances to nearest centroid.
+    pub nearest: Vec<f32>,
+}
+
+impl<C: Elkan> ElkanCentroids<C> {
+    /// Create a new `ElkanCentroids` with capacity.
+    pub fn new(capacity: usize) -> Self {
+        ElkanCentroids {
+            centroids: Vec::with_capacity(capacity),
+            deltas: vec![0.0; capacity],
+            half_distances: vec![0.0; capacity * capacity],
+            nearest: vec![0.0; capacity],
+        }
+    }
+}
+
+/// Struct that holds the necessary caching information for points in the
+/// Elkan algorithm implementation.
+#[derive(Clone, Debug)]
+pub struct ElkanPoints {
+    /// Index of each point's centroid.
+    pub indices: Vec<u32>,
+    /// Each point's distance to its closest centroid.
+    pub upper_bounds: Vec<f32>,
+    /// Minimum distance from each point to each centroid, `k` values per
+    /// point.
+    pub lower_bounds: Vec<f32>,
+}
+
+impl ElkanPoints {
+    /// Create a new `ElkanPoints` for `len` points and `k` centroids.
+    pub fn new(len: usize, k: usize) -> Self {
+        ElkanPoints {
+            indices: vec![0; len],
+            upper_bounds: vec![f32::MAX; len],
+            lower_bounds: vec![0.0; len * k],
+        }
+    }
+}
+
+/// Find the k-means centroids of a buffer using the Elkan algorithm. Takes
+/// the same arguments as [`get_kmeans`](fn.get_kmeans.html) and produces the
+/// same results.
+///
+/// Elkan keeps a lower bound for the distance from every point to every
+/// centroid, along with the distances between each pair of centroids, so it
+/// calculates fewer distances than
+/// [`get_kmeans_hamerly`](fn.get_kmeans_hamerly.html). Updating the bounds
+/// costs `k` operations and `k` floats of memory per point each iteration,
+/// which is more than the distances it saves for three component colors: with
+/// the `bench` subcommand of the binary on photos in `Lab`, Elkan was 5-20%
+/// slower than Hamerly for `k` from 8 to 64. It can pay off for points with an
+/// expensive `difference`; benchmark both for your use case.
+///
+/// Non-finite points are handled the same way as in `get_kmeans`.
+///
+/// ## Reference
+///
+/// Elkan, C. (2003). Using the triangle inequality to accelerate k-means. In:
+/// Proceedings of the Twentieth International Conference on Machine Learning.
+pub fn get_kmeans_elkan<C: Elkan + Clone>(
+    k: usize,
+    max_iter: usize,
+    converge: f32,
+    verbose: bool,
+    buf: &[C],
+    seed: u64,
+) -> Kmeans<C> {
+    // Initialize the random centroids
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    let mut centers: ElkanCentroids<C> = ElkanCentroids::new(k);
+    let init = crate::plus_plus::init_plus_plus(k, &mut rng, buf, &mut centers.centroids);
+    if init.status == InitStatus::NoFinitePoints {
+        return Kmeans::new();
+    }
+
+    // Initialize points buffer and convergence variables
+    let mut iterations = 0;
+    let mut score;
+    let mut plateau = Plateau::new(max_iter);
+    let mut old_centers = centers.centroids.clone();
+    let mut points = ElkanPoints::new(buf.len(), centers.centroids.len());
+
+    // Main loop: find nearest centroids and recalculate means until convergence
+    loop {
+        C::compute_center_distances(&mut centers);
+        C::get_closest_centroid_elkan(buf, &centers, &mut points);
+        C::recalculate_centroids_elkan(&mut rng, buf, &mut centers, &points);
+
+        score = Calculate::check_loop(&centers.centroids, &old_centers);
+        if verbose {
+            println!("Score: {}", score);
+        }
+
+        // Verify that either the maximum iteration count has been met, the
+        // centroids haven't moved beyond a certain threshold since the
+        // previous iteration, or the score has stopped improving.
+        if iterations >= max_iter || score <= converge || plateau.stop(iterations, score) {
+            if verbose {
+                println!("Iterations: {}", iterations);
+            }
+            break;
+        }
+
+        C::update_bounds_elkan(&centers, &mut points);
+        old_centers.clone_from(&centers.centroids);
+        iterations += 1;
+    }
+
+    let mut result = Kmeans {
+        score,
+        centroids: centers.centroids,
+        indices: points.indices,
+        runs: Vec::new(),
+    };
+    let _ = result.dedup_centroids();
+    if verbose && result.effective_k() < k {
+        println!("Effective k: {}", result.effective_k());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "palette_color")]
+    use crate::{
+        check_finite, get_kmeans, get_kmeans_hamerly, get_kmeans_shrink, get_kmeans_tiled,
+        update_kmeans, Calculate, Kmeans, KmeansConfig, NonFiniteError,
+    };
+    #[cfg(feature = "palette_color")]
+    use palette::Srgb;
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn sort_centroids() {
+        let mut result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![
+                Srgb::new(1.0, 1.0, 1.0),
+                Srgb::new(0.0, 0.0, 0.0),
+                Srgb::new(0.5, 0.5, 0.5),
+            ],
+            indices: vec![0, 1, 1, 2, 2, 2],
+            runs: Vec::new(),
+        };
+
+        result.sort_centroids_by_population();
+        assert_eq!(result.centroids[0], Srgb::new(0.5, 0.5, 0.5));
+        assert_eq!(result.indices, [2, 1, 1, 0, 0, 0]);
+
+        result.sort_centroids_by(|a, b| a.red.total_cmp(&b.red));
+        assert_eq!(
+            result.centroids,
+            [
+                Srgb::new(0.0, 0.0, 0.0),
+                Srgb::new(0.5, 0.5, 0.5),
+                Srgb::new(1.0, 1.0, 1.0)
+            ]
+        );
+        assert_eq!(result.indices, [2, 0, 0, 1, 1, 1]);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn dedup_centroids() {
+        let mut result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![
+                Srgb::new(1.0, 1.0, 1.0),
+                Srgb::new(0.0, 0.0, 0.0),
+                Srgb::new(1.0, 1.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 2],
+            runs: Vec::new(),
+        };
+        assert_eq!(result.dedup_centroids(), 1);
+        assert_eq!(result.effective_k(), 2);
+        assert_eq!(result.indices, [0, 1, 0, 0]);
+
+        // Fewer unique points than k
+        let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)];
+        let result = get_kmeans(4, 10, 0.0, false, &buf, 0);
+        assert_eq!(result.effective_k(), 2);
+        assert!(result.indices.iter().all(|&i| (i as usize) < 2));
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn update_changed_points() {
+        let mut buf: Vec<Srgb> = (0..300u16)
+            .map(|i| {
+                let v = f32::from(i % 3) * 0.4 + f32::from(i % 7) * 0.01;
+                Srgb::new(v, v, v)
+            })
+            .collect();
+        let result = get_kmeans(3, 50, 0.0, false, &buf, 0);
+
+        let mut changed = vec![false; buf.len()];
+        for i in (0..buf.len()).step_by(10) {
+            buf[i] = Srgb::new(0.95, 0.95, 0.95);
+            changed[i] = true;
+        }
+        let updated = update_kmeans(result, &changed, 50, 0.0, false, &buf, 0);
+
+        // Same centroids as a full calculation, up to order
+        let full = get_kmeans(3, 50, 0.0, false, &buf, 0);
+        let sorted = |result: &Kmeans<Srgb>| {
+            let mut red: Vec<f32> = result.centroids.iter().map(|x| x.red).collect();
+            red.sort_by(f32::total_cmp);
+            red
+        };
+        for (a, b) in sorted(&updated).iter().zip(&sorted(&full)) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        // Every point is indexed with its nearest centroid
+        let mut nearest = Vec::new();
+        Srgb::get_closest_centroid(&buf, &updated.centroids, &mut nearest);
+        assert_eq!(nearest, updated.indices);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn tiled() {
+        let buf: Vec<Srgb> = (0..600u16)
+            .map(|i| {
+                let v = f32::from(i % 3) * 0.4 + f32::from(i % 7) * 0.01;
+                Srgb::new(v, v, v)
+            })
+            .collect();
+        let config = KmeansConfig {
+            k: 3,
+            max_iter: 50,
+            converge: 0.0,
+            ..Default::default()
+        };
+        let result = get_kmeans_tiled(&config, &buf, 64, 3);
+        let full = get_kmeans(3, 50, 0.0, false, &buf, 0);
+
+        let mut red: Vec<f32> = result.centroids.iter().map(|x| x.red).collect();
+        let mut full_red: Vec<f32> = full.centroids.iter().map(|x| x.red).collect();
+        red.sort_by(f32::total_cmp);
+        full_red.sort_by(f32::total_cmp);
+        for (a, b) in red.iter().zip(&full_red) {
+            assert!((a - b).abs() < 1e-3);
+        }
+
+        // Indices of every tile are joined in order
+        let mut nearest = Vec::new();
+        Srgb::get_closest_centroid(&buf, &result.centroids, &mut nearest);
+        assert_eq!(nearest, result.indices);
+
+        assert!(get_kmeans_tiled::<Srgb>(&config, &[], 64, 3)
+            .indices
+            .is_empty());
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn shrink() {
+        // Hashed noise from -0.5 to 0.5, independent between channels
+        let noise = |i: u32, channel: u32| {
+            let mut x = i.wrapping_mul(0x9e37_79b9) ^ (channel + 1).wrapping_mul(0x85eb_ca6b);
+            x ^= x >> 15;
+            x = x.wrapping_mul(0x2c1b_3c6d);
+            x ^= x >> 12;
+            f32::from((x % 1000) as u16) / 1000.0 - 0.5
+        };
+        let palette = [
+            Srgb::new(0.1, 0.2, 0.3),
+            Srgb::new(0.8, 0.5, 0.2),
+            Srgb::new(0.4, 0.9, 0.6),
+        ];
+        let buf: Vec<Srgb> = (0..3000u32)
+            .map(|i| {
+                let color = palette[(i % 3) as usize];
+                Srgb::new(
+                    color.red + noise(i, 0) * 0.05,
+                    color.green + noise(i, 1) * 0.05,
+                    color.blue + noise(i, 2) * 0.05,
+                )
+            })
+            .collect();
+
+        // Extra centroids are merged down to the natural palette
+        let result = get_kmeans_shrink(8, 100, 0.0, false, &buf, 0);
+        assert_eq!(result.effective_k(), 3);
+        assert_eq!(get_kmeans(8, 100, 0.0, false, &buf, 0).effective_k(), 8);
+
+        // Distinct colors aren't merged
+        let result = get_kmeans_shrink(3, 100, 0.0, false, &buf, 0);
+        assert_eq!(result.effective_k(), 3);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn non_finite_points() {
+        let buf = [
+            Srgb::new(0.0, 0.0, 0.0),
+            Srgb::new(f32::NAN, 0.0, 0.0),
+            Srgb::new(1.0, 1.0, 1.0),
+            Srgb::new(0.0, f32::INFINITY, 0.0),
+        ];
+        assert_eq!(check_finite(&buf), Err(NonFiniteError { index: 1 }));
+        assert_eq!(check_finite(&buf[2..3]), Ok(()));
+
+        for seed in 0..8 {
+            let result = get_kmeans(2, 10, 0.0, false, &buf, seed);
+            assert_eq!(result.indices.len(), buf.len());
+            assert!(check_finite(&result.centroids).is_ok());
+            let result = get_kmeans_hamerly(2, 10, 0.0, false, &buf, seed);
+            assert_eq!(result.indices.len(), buf.len());
+            assert!(check_finite(&result.centroids).is_ok());
+            let result = crate::get_kmeans_elkan(2, 10, 0.0, false, &buf, seed);
+            assert_eq!(result.indices.len(), buf.len());
+            assert!(check_finite(&result.centroids).is_ok());
+        }
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn timed() {
+        use crate::{get_kmeans_hamerly_timed, get_kmeans_timed};
+
+        let buf: Vec<Srgb> = (0..100u8)
+            .map(|i| Srgb::new(i, i / 2, 255 - i).into_format())
+            .collect();
+        let result = get_kmeans(3, 10, 0.0, false, &buf, 0);
+        let (timed, timings) = get_kmeans_timed(3, 10, 0.0, false, &buf, 0);
+        assert_eq!(result.centroids, timed.centroids);
+        assert_eq!(result.indices, timed.indices);
+        assert!(timings.iterations > 0);
+
+        let result = get_kmeans_hamerly(3, 10, 0.0, false, &buf, 0);
+        let (timed, timings) = get_kmeans_hamerly_timed(3, 10, 0.0, false, &buf, 0);
+        assert_eq!(result.centroids, timed.centroids);
+        assert!(timings.iterations > 0);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn more_than_256_clusters() {
+        use crate::Sort;
+
+        // Every point is its own cluster, so every index is used
+        let buf: Vec<Srgb> = (0..300u16)
+            .map(|i| {
+                Srgb::new(
+                    f32::from(i % 10),
+                    f32::from(i / 10 % 10),
+                    f32::from(i / 100),
+                ) / 10.0
+            })
+            .collect();
+        for result in [
+            get_kmeans(300, 20, 0.0, false, &buf, 0),
+            get_kmeans_hamerly(300, 20, 0.0, false, &buf, 0),
+        ] {
+            let mut indices = result.indices.clone();
+            indices.sort_unstable();
+            indices.dedup();
+            assert_eq!(indices.len(), 300);
+            assert_eq!(indices.last(), Some(&299));
+
+            let res = Srgb::sort_indexed_colors(&result.centroids, &result.indices);
+            assert_eq!(res.len(), 300);
+        }
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn step() {
+        use crate::{kmeans_step, KmeansState};
+
+        let buf: Vec<Srgb> = (0..100u8)
+            .map(|i| Srgb::new(i, i / 2, 255 - i).into_format())
+            .collect();
+        let (max_iter, converge) = (10, 0.0);
+        let mut state = KmeansState::new(4, &buf, 1);
+        loop {
+            let score = kmeans_step(&mut state, &buf);
+            if state.iterations > max_iter || score <= converge {
+                break;
+            }
+        }
+        let stepped = state.into_kmeans();
+        let result = get_kmeans(4, max_iter, converge, false, &buf, 1);
+        assert_eq!(result.centroids, stepped.centroids);
+        assert_eq!(result.indices, stepped.indices);
+        assert_eq!(result.score.to_bits(), stepped.score.to_bits());
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn auto_max_iter() {
+        use crate::{get_kmeans_hamerly_timed, AUTO_MAX_ITER};
+
+        let buf: Vec<Srgb> = (0..1000u32)
+            .map(|i| Srgb::new(i % 256, i * 7 % 256, i * 13 % 256).into_format())
+            .collect();
+        // A convergence threshold of zero is never reached, so the calculation
+        // stops when the score plateaus
+        let (result, timings) = get_kmeans_hamerly_timed(8, AUTO_MAX_ITER, 0.0, false, &buf, 0);
+        assert!(timings.iterations < 1000);
+        let lloyd = get_kmeans(8, AUTO_MAX_ITER, 0.0, false, &buf, 0);
+        assert_eq!(result.centroids, lloyd.centroids);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn prune_runs() {
+        use crate::{get_kmeans_best, KmeansConfig};
+
+        let buf: Vec<Srgb> = (0..1000u32)
+            .map(|i| Srgb::new(i % 256, i * 7 % 256, i * 13 % 256).into_format())
+            .collect();
+        let config = KmeansConfig {
+            k: 8,
+            converge: 0.0,
+            runs: 4,
+            ..Default::default()
+        };
+        let result = get_kmeans_best(&config, &buf, None);
+        let first = get_kmeans_hamerly(8, config.max_iter, 0.0, false, &buf, 0);
+        assert_eq!(result.runs.len(), 4);
+        assert_eq!(result.runs[0].score, first.score);
+
+        // Every run after the first is further from its centroids than zero
+        let config = KmeansConfig {
+            prune: Some(0.0),
+            ..config
+        };
+        let result = get_kmeans_best(&config, &buf, None);
+        assert_eq!(result.centroids, first.centroids);
+        assert!(result.runs[1..].iter().all(|x| x.score == f32::INFINITY));
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn exact_colors() {
+        use crate::{get_kmeans_best, get_kmeans_exact};
+
+        let colors = [
+            Srgb::new(1.0, 0.0, 0.0),
+            Srgb::new(0.0, 1.0, 0.0),
+            Srgb::new(0.0, 0.0, 1.0),
+        ];
+        let buf: Vec<Srgb> = (0..100).map(|i| colors[i * i % 7 % 3]).collect();
+        let config = KmeansConfig {
+            k: 8,
+            runs: 3,
+            ..Default::default()
+        };
+        let result = get_kmeans_best(&config, &buf, None);
+        assert_eq!(result.score, 0.0);
+        assert!(result.runs.is_empty());
+        for (x, &i) in buf.iter().zip(&result.indices) {
+            assert_eq!(*x, result.centroids[i as usize]);
+        }
+        let counts = result.counts();
+        for (color, count) in result.centroids.iter().zip(counts) {
+            assert_eq!(buf.iter().filter(|&x| x == color).count(), count);
+        }
+
+        // Too many colors, points that aren't finite, and ignored points
+        assert!(get_kmeans_exact(2, &buf).is_none());
+        assert!(get_kmeans_exact(3, &[colors[0], Srgb::new(f32::NAN, 0.0, 0.0)]).is_none());
+        let mut weights = vec![1.0; buf.len()];
+        weights[0] = 0.0;
+        let result = get_kmeans_best(&config, &buf, Some(&weights));
+        assert!(!result.runs.is_empty());
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn sweep() {
+        use crate::sweep_seeds;
+
+        let buf: Vec<Srgb> = (0..1000u32)
+            .map(|i| Srgb::new(i % 256, i * 7 % 256, i * 13 % 256).into_format())
+            .collect();
+        let config = KmeansConfig {
+            k: 8,
+            converge: 0.0,
+            runs: 4,
+            seed: 10,
+            ..Default::default()
+        };
+        let results = sweep_seeds(&config, &buf, &[3, 0, 7]);
+        assert_eq!(
+            results.iter().map(|x| x.seed).collect::<Vec<_>>(),
+            [3, 0, 7]
+        );
+        for x in &results {
+            let run = get_kmeans_hamerly(8, config.max_iter, 0.0, false, &buf, x.seed);
+            assert_eq!(x.result.centroids, run.centroids);
+            assert_eq!(x.result.runs.len(), 1);
+            let inertia: f32 = buf
+                .iter()
+                .zip(&run.indices)
+                .map(|(p, &i)| Srgb::difference(p, &run.centroids[i as usize]))
+                .sum();
+            assert!((x.inertia - inertia).abs() <= 1e-3 * inertia);
+        }
+        assert!(sweep_seeds(&config, &buf, &[]).is_empty());
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn trace_iterations() {
+        use crate::{get_kmeans_best, Iteration};
+        use std::sync::Mutex;
+
+        static TRACE: Mutex<Vec<(u64, usize)>> = Mutex::new(Vec::new());
+
+        let buf: Vec<Srgb> = (0..1000u32)
+            .map(|i| Srgb::new(i % 256, i * 7 % 256, i * 13 % 256).into_format())
+            .collect();
+        let config = KmeansConfig {
+            k: 8,
+            max_iter: 5,
+            converge: 0.0,
+            runs: 2,
+            trace: Some(|x: &Iteration| TRACE.lock().unwrap().push((x.seed, x.iteration))),
+            ..Default::default()
+        };
+        let result = get_kmeans_best(&config, &buf, None);
+        // Both runs stop at `max_iter` after 6 iterations, numbered from 1
+        let expected: Vec<(u64, usize)> = (0..2)
+            .flat_map(|seed| (1..=6).map(move |i| (seed, i)))
+            .collect();
+        assert_eq!(*TRACE.lock().unwrap(), expected);
+        assert_eq!(result.runs.len(), 2);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn representatives() {
+        let buf = [
+            Srgb::new(0.0, 0.0, 0.0),
+            Srgb::new(0.2, 0.2, 0.2),
+            Srgb::new(1.0, 1.0, 1.0),
+        ];
+        let result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![
+                Srgb::new(0.15, 0.15, 0.15),
+                Srgb::new(1.0, 1.0, 1.0),
+                Srgb::new(0.5, 0.5, 0.5),
+            ],
+            indices: vec![0, 0, 1],
+            runs: Vec::new(),
+        };
+        assert_eq!(
+            result.representatives(&buf),
+            [Some(buf[1]), Some(buf[2]), None]
+        );
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn membership() {
+        let result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)],
+            indices: vec![1, 0, 1, 1],
+            runs: Vec::new(),
+        };
+        assert_eq!(result.counts(), [1, 3]);
+        assert_eq!(result.cluster_members(1).collect::<Vec<_>>(), [0, 2, 3]);
+        assert_eq!(result.cluster_members(2).count(), 0);
+        assert_eq!(result.to_one_hot(), [[0, 1], [1, 0], [0, 1], [0, 1]]);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn split_background() {
+        // A 4x4 image with a 2x2 subject in the center
+        let (bg, fg) = (Srgb::new(1.0, 1.0, 1.0), Srgb::new(1.0, 0.0, 0.0));
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 0, 0, 0,
+            0, 1, 1, 0,
+            0, 1, 1, 0,
+            0, 0, 0, 0,
+        ];
+        let result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![bg, fg],
+            indices,
+            runs: Vec::new(),
+        };
+        let split = result.split_background(4, 4);
+        assert_eq!(split.is_background, [true, false]);
+        assert_eq!(split.background, Some(bg));
+        assert_eq!(split.foreground, Some(fg));
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn regions() {
+        // Two blocks of 0 split by a column of 1, and a speck of 1
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 1, 0, 0,
+            0, 1, 0, 1,
+            0, 1, 0, 0,
+        ];
+        let result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![Srgb::default(); 2],
+            indices,
+            runs: Vec::new(),
+        };
+        let regions = result.regions(4, 3);
+        assert_eq!(regions.regions.len(), 4);
+        assert_eq!(regions.sizes(0), [5, 3]);
+        assert_eq!(regions.sizes(1), [3, 1]);
+        assert_eq!(regions.labels[0], regions.labels[8]);
+        assert_ne!(regions.labels[0], regions.labels[2]);
+    }
+
+    #[test]
+    fn despeckle_regions() {
+        #[rustfmt::skip]
+        let mut indices = vec![
+            0, 0, 1, 1, 1,
+            0, 2, 1, 1, 1,
+            0, 0, 1, 3, 1,
+            0, 0, 1, 1, 1,
+        ];
+        super::despeckle(&mut indices, 5, 4, 2);
+        #[rustfmt::skip]
+        assert_eq!(indices, [
+            0, 0, 1, 1, 1,
+            0, 0, 1, 1, 1,
+            0, 0, 1, 1, 1,
+            0, 0, 1, 1, 1,
+        ]);
+
+        // A region covering the image is kept
+        let mut indices = vec![4; 6];
+        super::despeckle(&mut indices, 3, 2, 10);
+        assert_eq!(indices, [4; 6]);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn snap_centroids() {
+        let buf = [
+            Srgb::new(0.1, 0.1, 0.1),
+            Srgb::new(0.2, 0.2, 0.2),
+            Srgb::new(0.9, 0.9, 0.9),
+        ];
+        let mut result = Kmeans::<Srgb> {
+            score: 0.0,
+            centroids: vec![
+                Srgb::new(0.1, 0.1, 0.1),
+                Srgb::new(0.2, 0.2, 0.2),
+                Srgb::new(0.9, 0.9, 0.9),
+            ],
+            indices: vec![0, 1, 2],
+            runs: Vec::new(),
+        };
+        result.snap_centroids(&buf, |x| {
+            let snap = |c: f32| (c * 2.0).round() / 2.0;
+            Srgb::new(snap(x.red), snap(x.green), snap(x.blue))
+        });
+        assert_eq!(
+            result.centroids,
+            [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)]
+        );
+        assert_eq!(result.indices, [0, 0, 1]);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn warm_start_centroids() {
+        let buf: Vec<Srgb> = (0..60u8)
+            .map(|i| {
+                let x = f32::from(i % 20) / 100.0 + f32::from(i / 20) * 0.4;
+                Srgb::new(x, x, x)
+            })
+            .collect();
+        let config = KmeansConfig {
+            k: 3,
+            max_iter: crate::AUTO_MAX_ITER,
+            converge: 0.0,
+            ..Default::default()
+        };
+        let result = crate::get_kmeans_best(&config, &buf, None);
+
+        // Starting from converged centroids stops without a max_iter bound
+        let weights = vec![1.0; buf.len()];
+        let warm = crate::get_kmeans_best_from(&config, &result.centroids, &buf, Some(&weights));
+        assert_eq!(warm.centroids.len(), 3);
+        assert_eq!(warm.indices, result.indices);
+        assert!((warm.score - result.score).abs() < 1e-6);
+
+        // The number of initial centroids sets k
+        let warm = crate::get_kmeans_best_from(&config, &result.centroids[..2], &buf, None);
+        assert_eq!(warm.centroids.len(), 2);
+    }
+
+    #[cfg(feature = "palette_color")]
+    fn gradient() -> Vec<Srgb> {
+        (0..=255u8)
+            .map(|i| {
+                let x = f32::from(i) / 255.0;
+                Srgb::new(x, (x * 7.0).fract(), 1.0 - x)
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn same_seed_same_result() {
+        let buf = gradient();
+        let a = get_kmeans(6, 20, 0.0, false, &buf, 3);
+        let b = get_kmeans(6, 20, 0.0, false, &buf, 3);
+        assert_eq!(a.centroids, b.centroids);
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.score.to_bits(), b.score.to_bits());
+
+        let a = get_kmeans_hamerly(6, 20, 0.0, false, &buf, 3);
+        let b = get_kmeans_hamerly(6, 20, 0.0, false, &buf, 3);
+        assert_eq!(a.centroids, b.centroids);
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.score.to_bits(), b.score.to_bits());
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn tiled_thread_count() {
+        let buf = gradient();
+        let config = KmeansConfig {
+            k: 4,
+            converge: 0.0,
+            runs: 2,
+            ..Default::default()
+        };
+        let single = get_kmeans_tiled(&config, &buf, 40, 1);
+        for threads in [2, 3, 8] {
+            let result = get_kmeans_tiled(&config, &buf, 40, threads);
+            assert_eq!(result.centroids, single.centroids);
+            assert_eq!(result.indices, single.indices);
+            assert_eq!(result.score.to_bits(), single.score.to_bits());
+        }
+    }
+
+    #[cfg(all(feature = "palette_color", feature = "rayon"))]
+    #[test]
+    fn sweep_seeds_thread_count() {
+        let buf = gradient();
+        let config = KmeansConfig {
+            k: 4,
+            converge: 0.0,
+            ..Default::default()
+        };
+        let sweep = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| crate::sweep_seeds(&config, &buf, &[0, 1, 2, 3, 4, 5]))
+        };
+        let single = sweep(1);
+        let parallel = sweep(4);
+        for (a, b) in single.iter().zip(&parallel) {
+            assert_eq!(a.seed, b.seed);
+            assert_eq!(a.inertia.to_bits(), b.inertia.to_bits());
+            assert_eq!(a.result.centroids, b.result.centroids);
+        }
     }
 }
@@ -12,10 +12,20 @@
 //! When using the library, set `default-features = false` in the Cargo.toml to
 //! avoid bringing in the binary dependencies. If working with colors,
 //! implementations have been provided for the [`palette`][palette] `Lab` and
-//! `Rgb` color types behind the `palette_color` feature.
+//! `Rgb` color types behind the `palette_color` feature. `Calculate` is also
+//! implemented for `Xyz` and `Yxy`, and for the crate's `YCbCr` type for
+//! clustering video frames. Arrays `[f32; N]` such as embeddings implement
+//! `Calculate` without any feature, and [`get_kmeans_approx`] speeds up their
+//! nearest centroid search for large `N`.
 //!
 //! The binary located in `src/bin/kmeans_colors` shows examples of crate
-//! usage.
+//! usage. The `image` feature adds `kmeans_from_image`,
+//! `kmeans_from_thumbnail`, and `quantize_image` for working with
+//! [`image`][image] buffers directly. The `raw` feature adds `RawImage` for
+//! reading pixels from borrowed buffers, such as memory-mapped frames, without
+//! copying them. The `rayon` feature runs [`sweep_seeds`] in parallel.
+//!
+//! [image]: https://github.com/image-rs/image
 //!
 //! [palette]: https://github.com/Ogeon/palette/
 //!
@@ -27,8 +37,12 @@
 //! `Rgb` implementations in [`colors/kmeans.rs`][kmeans] for examples. These
 //! implementations can be used as groundwork for implementing with other types
 //! and should not require much modification beyond the distance calculations.
+//! [`Elkan`](trait.Elkan.html) only needs an empty `impl` to enable
+//! [`get_kmeans_elkan`][elkan], which calculates fewer distances than Hamerly
+//! but was 5-20% slower for three component colors in benchmarks.
 //!
 //! [hamerly]: fn.get_kmeans_hamerly.html
+//! [elkan]: fn.get_kmeans_elkan.html
 //! [kmeans]: ../src/kmeans_colors/colors/kmeans.rs.html#9
 //!
 //! ## Calculating k-means with `palette_color`
@@ -85,9 +99,7 @@
 //!         &lab,
 //!         seed + i as u64,
 //!     );
-//!     if run_result.score < result.score {
-//!         result = run_result;
-//!     }
+//!     result.keep_best(run_result, seed + i as u64);
 //! }
 //!
 //! // Convert indexed colors back to Srgb<u8> for output
@@ -108,9 +120,7 @@
 //! #         &lab,
 //! #         seed + i as u64,
 //! #     );
-//! #     if run_result.score < result.score {
-//! #         result = run_result;
-//! #     }
+//! #     result.keep_best(run_result, seed + i as u64);
 //! # }
 //! # // Convert indexed colors back to Srgb<u8> for output
 //! # let rgb = &result.centroids
@@ -128,7 +138,10 @@
 //! the convergence threshold has been met.
 //!
 //! The binary uses `8` as the default `k`. The iteration limit is set to `20`.
-//! The convergence factor defaults to `5.0` for `Lab` and `0.0025` for `Rgb`.
+//! The convergence factor defaults to `5.0` for `Lab` and `0.0025` for `Rgb`
+//! at `k = 8` on a 512x512 image, scaled to other sizes with
+//! [`default_converge`]. Other color spaces set their calibrated default with
+//! [`Calculate::CONVERGE`].
 //! The number of runs defaults to `3` for one of the binary subcommands.
 //! If the results do not appear correct, raise the iteration limit as
 //! convergence was probably not met.
@@ -167,9 +180,7 @@
 //! #         &lab,
 //! #         seed + i as u64,
 //! #     );
-//! #     if run_result.score < result.score {
-//! #         result = run_result;
-//! #     }
+//! #     result.keep_best(run_result, seed + i as u64);
 //! # }
 //! // Using the results from the previous example, process the centroid data
 //! let mut res = Lab::sort_indexed_colors(&result.centroids, &result.indices);
@@ -186,6 +197,12 @@
 //! res.sort_unstable_by(|a, b| (b.percentage).total_cmp(&a.percentage));
 //! let dominant_color = res.first().unwrap().centroid;
 //! ```
+//!
+//! The sorted colors can be converted to 8-bit `Srgb` colors or hex codes
+//! with [`centroids_to_srgb`](fn.centroids_to_srgb.html) and
+//! [`centroids_to_hex`](fn.centroids_to_hex.html), and palettes saved that way
+//! can be read back with [`centroids_from_srgb`](fn.centroids_from_srgb.html)
+//! and [`centroids_from_hex`](fn.centroids_from_hex.html).
 #![forbid(
     absolute_paths_not_starting_with_crate,
     missing_docs,
@@ -204,17 +221,44 @@
 )]
 
 #[cfg(feature = "palette_color")]
+mod array;
 mod colors;
 
+mod edges;
+mod iter;
+mod kdtree;
 mod kmeans;
+mod metrics;
 mod plus_plus;
 mod sort;
 
 #[cfg(feature = "palette_color")]
-pub use colors::MapColor;
+pub use colors::{
+    average_color, average_color_weighted, centroids_from_hex, centroids_from_srgb,
+    centroids_to_hex, centroids_to_srgb, dedup_weighted, interpolate_colors,
+    sort_by_oklab_lightness, Assignment, Bt601, Bt709, ChromaOnly, ColorHistogram, ConversionCache,
+    ConversionLut, ConvertSrgb, HueOnly, Interpolation, MapColor, Subsampling, YCbCr, YCbCrRange,
+    YCbCrStandard,
+};
+
+#[cfg(feature = "image")]
+pub use colors::{kmeans_from_image, kmeans_from_thumbnail, quantize_image};
+
+#[cfg(feature = "raw")]
+pub use colors::{RawFormat, RawImage, RawImageError};
 
+pub use edges::edge_weights;
+pub use iter::get_kmeans_iter;
+pub use kdtree::{Axes, CentroidTree};
 pub use kmeans::{
-    get_kmeans, get_kmeans_hamerly, Calculate, Hamerly, HamerlyCentroids, HamerlyPoint, Kmeans,
+    check_finite, default_converge, despeckle, get_kmeans, get_kmeans_approx, get_kmeans_best,
+    get_kmeans_best_from, get_kmeans_elkan, get_kmeans_exact, get_kmeans_hamerly,
+    get_kmeans_hamerly_timed, get_kmeans_hamerly_weighted, get_kmeans_kdtree, get_kmeans_shrink,
+    get_kmeans_tiled, get_kmeans_timed, get_kmeans_weighted, kmeans_step, sweep_seeds,
+    update_kmeans, BackgroundSplit, Calculate, Elkan, ElkanCentroids, ElkanPoints, Hamerly,
+    HamerlyCentroids, HamerlyPoint, Iteration, Kmeans, KmeansConfig, KmeansState, NonFiniteError,
+    Regions, RunSummary, SeedResult, Timings, Weighted, AUTO_MAX_ITER,
 };
-pub use plus_plus::init_plus_plus;
-pub use sort::{CentroidData, Sort};
+pub use metrics::{adjusted_rand_index, match_centroids, normalized_mutual_information};
+pub use plus_plus::{init_plus_plus, init_plus_plus_weighted, InitResult, InitStatus};
+pub use sort::{CentroidData, Sort, Vivid};
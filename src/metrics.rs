@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::kmeans::Calculate;
+
+/// Counts of each pair of cluster index and label, and the counts of each
+/// cluster index and each label alone.
+struct Contingency {
+    pairs: Vec<u64>,
+    clusters: Vec<u64>,
+    labels: Vec<u64>,
+    total: u64,
+}
+
+impl Contingency {
+    fn new<L: Eq + Hash>(indices: &[u32], labels: &[L]) -> Self {
+        let mut pairs: HashMap<(u32, &L), u64> = HashMap::new();
+        let mut clusters: HashMap<u32, u64> = HashMap::new();
+        let mut label_counts: HashMap<&L, u64> = HashMap::new();
+        let mut total = 0;
+        for (&idx, label) in indices.iter().zip(labels) {
+            *pairs.entry((idx, label)).or_insert(0) += 1;
+            *clusters.entry(idx).or_insert(0) += 1;
+            *label_counts.entry(label).or_insert(0) += 1;
+            total += 1;
+        }
+
+        Contingency {
+            pairs: pairs.into_values().collect(),
+            clusters: clusters.into_values().collect(),
+            labels: label_counts.into_values().collect(),
+            total,
+        }
+    }
+}
+
+/// Number of unordered pairs among `n` items.
+#[allow(clippy::cast_precision_loss)]
+fn pairs(n: u64) -> f64 {
+    (n * n.saturating_sub(1) / 2) as f64
+}
+
+/// Entropy of a clustering from the number of points in each cluster.
+#[allow(clippy::cast_precision_loss)]
+fn entropy(counts: &[u64], total: u64) -> f64 {
+    let total = total as f64;
+    counts
+        .iter()
+        .filter(|&&x| x > 0)
+        .map(|&x| {
+            let p = x as f64 / total;
+            -p * p.ln()
+        })
+        .sum()
+}
+
+/// Calculate the Adjusted Rand Index between the cluster indices of a k-means
+/// result and ground-truth labels.
+///
+/// The index is 1.0 when the clusterings match up to renaming of the
+/// clusters, close to 0.0 for random assignments, and can be negative for
+/// worse than random agreement. Indices and labels are paired in order; extra
+/// elements of the longer slice are ignored.
+///
+/// ```
+/// use kmeans_colors::adjusted_rand_index;
+///
+/// let indices = [0, 0, 1, 1];
+/// assert_eq!(adjusted_rand_index(&indices, &["b", "b", "a", "a"]), 1.0);
+/// ```
+#[allow(clippy::cast_possible_truncation)]
+pub fn adjusted_rand_index<L: Eq + Hash>(indices: &[u32], labels: &[L]) -> f32 {
+    let table = Contingency::new(indices, labels);
+    let index: f64 = table.pairs.iter().map(|&x| pairs(x)).sum();
+    let clusters: f64 = table.clusters.iter().map(|&x| pairs(x)).sum();
+    let labels: f64 = table.labels.iter().map(|&x| pairs(x)).sum();
+
+    let expected = match pairs(table.total) {
+        x if x > 0.0 => clusters * labels / x,
+        _ => 0.0,
+    };
+    let max = (clusters + labels) / 2.0;
+    // Identical trivial clusterings, such as a single cluster or all points in
+    // their own cluster, are a perfect match
+    if max == expected {
+        return 1.0;
+    }
+
+    ((index - expected) / (max - expected)) as f32
+}
+
+/// Calculate the Normalized Mutual Information between the cluster indices of
+/// a k-means result and ground-truth labels.
+///
+/// The mutual information is normalized by the arithmetic mean of the
+/// entropies of both clusterings, giving 1.0 when the clusterings match up to
+/// renaming of the clusters and 0.0 when they are independent. Indices and
+/// labels are paired in order; extra elements of the longer slice are
+/// ignored.
+///
+/// ```
+/// use kmeans_colors::normalized_mutual_information;
+///
+/// let indices = [0, 0, 1, 1];
+/// assert_eq!(normalized_mutual_information(&indices, &[2, 2, 7, 7]), 1.0);
+/// ```
+#[allow(clippy::cast_possible_truncation)]
+pub fn normalized_mutual_information<L: Eq + Hash>(indices: &[u32], labels: &[L]) -> f32 {
+    let table = Contingency::new(indices, labels);
+    let h_clusters = entropy(&table.clusters, table.total);
+    let h_labels = entropy(&table.labels, table.total);
+    // Both clusterings have a single cluster
+    if h_clusters + h_labels == 0.0 {
+        return 1.0;
+    }
+
+    // Mutual information from the joint entropy, I(U, V) = H(U) + H(V) - H(U, V)
+    let mutual = h_clusters + h_labels - entropy(&table.pairs, table.total);
+
+    (2.0 * mutual / (h_clusters + h_labels)).clamp(0.0, 1.0) as f32
+}
+
+/// Pair the centroids of two results by minimal distance, such as results of
+/// the same buffer with different seeds.
+///
+/// Pairs are chosen greedily, starting from the closest pair of centroids and
+/// continuing with the closest pair of unpaired centroids, so each centroid is
+/// paired at most once. Returns the index of the centroid in `b` paired with
+/// each centroid of `a`, which is `None` for leftover centroids when `b` has
+/// fewer centroids than `a`.
+///
+/// ```
+/// use kmeans_colors::match_centroids;
+/// use palette::Srgb;
+///
+/// let a = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)];
+/// let b = [Srgb::new(0.9, 0.9, 0.9), Srgb::new(0.1, 0.1, 0.1)];
+/// assert_eq!(match_centroids(&a, &b), [Some(1), Some(0)]);
+/// ```
+pub fn match_centroids<C: Calculate>(a: &[C], b: &[C]) -> Vec<Option<usize>> {
+    let mut pairs: Vec<(f32, usize, usize)> = a
+        .iter()
+        .enumerate()
+        .flat_map(|(i, x)| {
+            b.iter()
+                .enumerate()
+                .map(move |(j, y)| (C::difference(x, y), i, j))
+        })
+        .collect();
+    pairs.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut matches = vec![None; a.len()];
+    let mut used = vec![false; b.len()];
+    for (_, i, j) in pairs {
+        if matches[i].is_none() && !used[j] {
+            matches[i] = Some(j);
+            used[j] = true;
+        }
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluation() {
+        // Relabeled clusters match perfectly
+        let indices = [0, 0, 0, 1, 1, 1];
+        let labels = ['x', 'x', 'x', 'y', 'y', 'y'];
+        assert_eq!(adjusted_rand_index(&indices, &labels), 1.0);
+        assert!((normalized_mutual_information(&indices, &labels) - 1.0).abs() < 1e-6);
+
+        // Reference values from scikit-learn
+        let indices = [0, 0, 1, 1];
+        let labels = [0, 0, 1, 2];
+        assert!((adjusted_rand_index(&indices, &labels) - 0.571_428_6).abs() < 1e-6);
+        assert!((normalized_mutual_information(&indices, &labels) - 0.8).abs() < 1e-6);
+
+        // Independent clusterings
+        let indices = [0, 1, 0, 1];
+        let labels = [0, 0, 1, 1];
+        assert!(adjusted_rand_index(&indices, &labels) < 0.0);
+        assert!(normalized_mutual_information(&indices, &labels).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn match_centroids_greedy() {
+        use palette::Srgb;
+
+        // The closest pair is matched first even if another centroid of `a`
+        // is closer to it than to its own match
+        let a = [Srgb::new(0.5, 0.5, 0.5), Srgb::new(0.0, 0.0, 0.0)];
+        let b = [Srgb::new(0.4, 0.4, 0.4), Srgb::new(1.0, 1.0, 1.0)];
+        assert_eq!(match_centroids(&a, &b), [Some(0), Some(1)]);
+
+        // Leftover centroids are unmatched
+        assert_eq!(match_centroids(&a, &b[..1]), [Some(0), None]);
+        assert!(match_centroids::<Srgb>(&[], &b).is_empty());
+    }
+}
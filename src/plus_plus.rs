@@ -1,8 +1,42 @@
 use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
 
+/// Reason that k-means++ initialization stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitStatus {
+    /// All `k` centroids were chosen.
+    Complete,
+    /// Every point is identical to a chosen centroid, so the buffer has fewer
+    /// than `k` unique points.
+    FewerUniquePoints,
+    /// The buffer has no points with finite components, or for weighted
+    /// initialization, no finite points with a positive weight.
+    NoFinitePoints,
+}
+
+/// Result of k-means++ initialization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InitResult {
+    /// Number of centroids that were chosen.
+    pub count: usize,
+    /// Reason that initialization stopped.
+    pub status: InitStatus,
+}
+
 /// k-means++ centroid initialization.
 ///
+/// Centroids are chosen with the provided random number generator only, so the
+/// same seed always produces the same centroids. Distances to the nearest
+/// centroid are calculated in index order and don't depend on which of several
+/// equally near centroids is found first.
+///
+/// Points with NaN or infinite components are never chosen as centroids. If
+/// there are no finite points, no centroids are produced.
+///
+/// Returns the number of centroids chosen, which is less than `k` when the
+/// buffer doesn't have enough unique points, and the reason initialization
+/// stopped.
+///
 /// # Panics
 ///
 /// Panics if buffer is empty.
@@ -12,43 +46,118 @@ use rand::Rng;
 /// Based on Section 2.2 from `k-means++: The Advantages of Careful Seeding` by
 /// Arthur and Vassilvitskii (2007).
 pub fn init_plus_plus<C: crate::Calculate + Clone>(
+    k: usize,
+    rng: &mut impl Rng,
+    buf: &[C],
+    centroids: &mut Vec<C>,
+) -> InitResult {
+    init(k, rng, buf, None, centroids)
+}
+
+/// Weighted k-means++ centroid initialization.
+///
+/// Behaves like [`init_plus_plus`](fn.init_plus_plus.html), except the first
+/// centroid is sampled in proportion to the point weights and later centroids
+/// in proportion to `w(x) * D(x)^2`. Points with a weight of zero or less are
+/// never chosen.
+///
+/// # Panics
+///
+/// Panics if buffer is empty or `weights` is not the same length as `buf`.
+pub fn init_plus_plus_weighted<C: crate::Calculate + Clone>(
+    k: usize,
+    rng: &mut impl Rng,
+    buf: &[C],
+    weights: &[f32],
+    centroids: &mut Vec<C>,
+) -> InitResult {
+    assert_eq!(buf.len(), weights.len());
+    init(k, rng, buf, Some(weights), centroids)
+}
+
+/// k-means++ initialization with optional point weights.
+fn init<C: crate::Calculate + Clone>(
     k: usize,
     mut rng: &mut impl Rng,
     buf: &[C],
+    point_weights: Option<&[f32]>,
     centroids: &mut Vec<C>,
-) {
+) -> InitResult {
+    let start = centroids.len();
+    let result = |centroids: &Vec<C>, status| InitResult {
+        count: centroids.len() - start,
+        status,
+    };
     if k == 0 {
-        return;
+        return result(centroids, InitStatus::Complete);
     }
     let len = buf.len();
     assert!(len > 0);
 
     let mut weights: Vec<f32> = (0..len).map(|_| 0.0).collect();
 
-    // Choose first centroid at random, uniform sampling from input buffer
-    centroids.push(buf.get(rng.gen_range(0..len)).unwrap().to_owned());
+    // A point is finite if its distance to itself is finite, weighted points
+    // must also have a positive weight to be chosen
+    let finite: Vec<bool> = buf
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            C::difference(x, x).is_finite()
+                && point_weights.is_none_or(|w| w[i].is_finite() && w[i] > 0.0)
+        })
+        .collect();
+    let finite_count = finite.iter().filter(|&&x| x).count();
+    if finite_count == 0 {
+        return result(centroids, InitStatus::NoFinitePoints);
+    }
+
+    // Choose first centroid at random, uniform sampling from finite points of
+    // the input buffer, or sampling by point weight
+    match point_weights {
+        None => {
+            let nth = rng.gen_range(0..finite_count);
+            let (first, _) = buf.iter().zip(&finite).filter(|x| *x.1).nth(nth).unwrap();
+            centroids.push(first.to_owned());
+        }
+        Some(point_weights) => {
+            for ((dist, &w), &valid) in weights.iter_mut().zip(point_weights).zip(&finite) {
+                *dist = if valid { w } else { 0.0 };
+            }
+            let sampler = WeightedIndex::new(&weights).unwrap();
+            centroids.push(buf.get(sampler.sample(&mut rng)).unwrap().to_owned());
+        }
+    }
 
     // Pick a new centroid with weighted probability of `D(x)^2 / sum(D(x)^2)`,
     // where `D(x)^2` is the distance to the closest centroid
     for _ in 1..k {
         // Calculate the distances to nearest centers, accumulate a sum
         let mut sum = 0.0;
-        for (b, dist) in buf.iter().zip(weights.iter_mut()) {
+        for (i, ((b, dist), _)) in buf
+            .iter()
+            .zip(weights.iter_mut())
+            .zip(&finite)
+            .enumerate()
+            .filter(|x| *(x.1).1)
+        {
             let mut diff;
-            let mut min = core::f32::MAX;
+            let mut min = f32::MAX;
             for cent in centroids.iter() {
                 diff = C::difference(b, cent);
                 if diff < min {
                     min = diff;
                 }
             }
+            if let Some(w) = point_weights {
+                min *= w[i];
+            }
             *dist = min;
             sum += min;
         }
 
         // If centroids match all colors, return early
         if !sum.is_normal() {
-            return;
+            return result(centroids, InitStatus::FewerUniquePoints);
         }
 
         // Divide distances by sum to find D^2 weighting for distribution
@@ -58,4 +167,45 @@ pub fn init_plus_plus<C: crate::Calculate + Clone>(
         let sampler = WeightedIndex::new(&weights).unwrap();
         centroids.push(buf.get(sampler.sample(&mut rng)).unwrap().to_owned());
     }
+
+    result(centroids, InitStatus::Complete)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "palette_color")]
+    #[test]
+    fn init_status() {
+        use crate::{init_plus_plus, InitResult, InitStatus};
+        use palette::Srgb;
+        use rand::SeedableRng;
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        let buf = [Srgb::new(0.0, 0.0, 0.0), Srgb::new(1.0, 1.0, 1.0)];
+
+        let mut centroids = Vec::new();
+        let result = init_plus_plus(2, &mut rng, &buf, &mut centroids);
+        assert_eq!(
+            result,
+            InitResult {
+                count: 2,
+                status: InitStatus::Complete
+            }
+        );
+
+        let mut centroids = Vec::new();
+        let result = init_plus_plus(3, &mut rng, &buf, &mut centroids);
+        assert_eq!(result.count, 2);
+        assert_eq!(result.status, InitStatus::FewerUniquePoints);
+
+        let mut centroids = Vec::new();
+        let result = init_plus_plus(
+            3,
+            &mut rng,
+            &[Srgb::new(f32::NAN, 0.0, 0.0)],
+            &mut centroids,
+        );
+        assert_eq!(result.count, 0);
+        assert_eq!(result.status, InitStatus::NoFinitePoints);
+    }
 }
@@ -7,7 +7,7 @@ pub struct CentroidData<C: crate::Calculate> {
     /// The percentage a centroid appears in a buffer.
     pub percentage: f32,
     /// The centroid's index.
-    pub index: u8,
+    pub index: u32,
 }
 
 /// A trait for sorting indexed k-means colors.
@@ -18,5 +18,38 @@ pub trait Sort: Sized + crate::Calculate {
     /// Sorts centroids by luminosity and calculates the percentage of each
     /// color in the buffer. Returns a Vec of `CentroidData` sorted from darkest
     /// to lightest.
-    fn sort_indexed_colors(centroids: &[Self], indices: &[u8]) -> Vec<CentroidData<Self>>;
+    fn sort_indexed_colors(centroids: &[Self], indices: &[u32]) -> Vec<CentroidData<Self>>;
+}
+
+/// An extension of [`Sort`] for ranking k-means colors by how vivid they
+/// are.
+pub trait Vivid: Sort {
+    /// Returns the chroma, or colorfulness, of a centroid. Values are only
+    /// compared between centroids of the same type, so the scale doesn't
+    /// matter.
+    fn chroma(&self) -> f32;
+
+    /// Sorts centroids from most to least vivid, where vividness is the
+    /// chroma of a centroid multiplied by its percentage.
+    ///
+    /// The dominant color of a photo is often a gray background; this ranks
+    /// the standout colors first while still ignoring tiny specks of color.
+    fn sort_by_vividness(data: &mut [CentroidData<Self>]) {
+        data.sort_by(|a, b| {
+            (b.centroid.chroma() * b.percentage).total_cmp(&(a.centroid.chroma() * a.percentage))
+        });
+    }
+
+    /// Returns the centroid with the largest chroma multiplied by percentage.
+    fn get_most_vivid_color(data: &[CentroidData<Self>]) -> Option<Self>
+    where
+        Self: Clone,
+    {
+        data.iter()
+            .max_by(|a, b| {
+                (a.centroid.chroma() * a.percentage)
+                    .total_cmp(&(b.centroid.chroma() * b.percentage))
+            })
+            .map(|res| res.centroid.clone())
+    }
 }